@@ -0,0 +1,272 @@
+use std::path::Path;
+
+use anchor_lang::{
+   prelude::Pubkey,
+   solana_program::instruction::Instruction,
+};
+use anyhow::{anyhow, bail, Context as _, Result};
+use lending_client::{
+   lending::constants::{DEFAULT_BORROW_WEIGHT, DEFAULT_LIQUIDATION_BONUS, DEFAULT_LIQUIDATION_CLOSE_FACTOR, DEFAULT_ORACLE_MAX_AGE, HEALTH_FACTOR_ONE},
+   BankAccounts, BankConfig, BorrowBuilder, DepositBuilder, InitBankBuilder, InitUserBuilder, LiquidationMode, RepayBuilder, SetBankPausedBuilder,
+   UpdateBankConfigBuilder, WithdrawBuilder,
+};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::rpc_config::{RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig};
+use solana_sdk::{account::Account, signature::Keypair, signer::Signer, transaction::Transaction};
+
+use crate::{config::BankConfigFile, diff, market, Context};
+
+pub enum Action {
+   Deposit,
+   Withdraw,
+   Borrow,
+   Repay,
+}
+
+fn percent(bps: u64) -> String {
+   format!("{}.{:02}%", bps / 100, bps % 100)
+}
+
+fn cap(cap: u64) -> String {
+   if cap == 0 { "none".to_string() } else { cap.to_string() }
+}
+
+// 1.0 is HEALTH_FACTOR_ONE, printed with its 4 decimals
+fn health_factor(health_factor: u64) -> String {
+   if health_factor == u64::MAX {
+      return "no debt".to_string();
+   }
+   format!("{}.{:04}", health_factor / HEALTH_FACTOR_ONE, health_factor % HEALTH_FACTOR_ONE)
+}
+
+/*
+   Every command that changes something ends here. With --dry-run the transaction is only simulated:
+   the writable accounts are read before, returned by the simulation after, and the differences printed.
+*/
+fn send(context: &Context, payer: &Keypair, instructions: &[Instruction]) -> Result<()> {
+   let blockhash = context.rpc.get_latest_blockhash()?;
+   let transaction = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), &[payer], blockhash);
+
+   if !context.dry_run {
+      let signature = context.rpc.send_and_confirm_transaction(&transaction)?;
+      println!("Signature: {signature}");
+      return Ok(());
+   }
+
+   let message = &transaction.message;
+   let writable: Vec<Pubkey> =
+      message.account_keys.iter().enumerate().filter(|(index, _)| message.is_writable(*index)).map(|(_, key)| *key).collect();
+   let before = context.rpc.get_multiple_accounts(&writable)?;
+
+   let config = RpcSimulateTransactionConfig {
+      sig_verify: false,
+      replace_recent_blockhash: true,
+      commitment: Some(context.rpc.commitment()),
+      accounts: Some(RpcSimulateTransactionAccountsConfig {
+         encoding: Some(UiAccountEncoding::Base64),
+         addresses: writable.iter().map(Pubkey::to_string).collect(),
+      }),
+      ..Default::default()
+   };
+   let result = context.rpc.simulate_transaction_with_config(&transaction, config)?.value;
+
+   for log in result.logs.iter().flatten() {
+      println!("{log}");
+   }
+   if let Some(err) = result.err {
+      bail!("simulation failed: {err}");
+   }
+   if let Some(units) = result.units_consumed {
+      println!("Compute units: {units}");
+   }
+
+   let after = result.accounts.unwrap_or_default();
+   println!("Dry run, nothing was sent. Account changes:");
+   for (index, key) in writable.iter().enumerate() {
+      let after: Option<Account> = after.get(index).cloned().flatten().and_then(|account| account.decode());
+      // An account left without lamports is deleted at the end of the transaction
+      let after = after.filter(|account| account.lamports > 0);
+      for line in diff::diff(key, before[index].as_ref(), after.as_ref()) {
+         println!("{line}");
+      }
+   }
+   Ok(())
+}
+
+pub fn init_bank(context: &Context, mint: Pubkey, oracle: Pubkey, config_path: &Path) -> Result<()> {
+   let authority = context.keypair()?;
+   let file = BankConfigFile::load(config_path)?;
+   let (Some(liquidation_threshold), Some(max_ltv), Some(interest_rate)) = (file.liquidation_threshold, file.max_ltv, file.interest_rate)
+   else {
+      bail!("{} needs liquidation_threshold, max_ltv and interest_rate to create a bank", config_path.display());
+   };
+
+   let mint_account = context.rpc.get_account(&mint).with_context(|| format!("no mint at {mint}"))?;
+   let accounts = BankAccounts::new(mint, oracle).with_token_program(mint_account.owner);
+   let config = file.apply(BankConfig {
+      liquidation_threshold,
+      liquidation_bonus: DEFAULT_LIQUIDATION_BONUS,
+      liquidation_close_factor: DEFAULT_LIQUIDATION_CLOSE_FACTOR,
+      max_ltv,
+      interest_rate,
+      oracle_max_age: DEFAULT_ORACLE_MAX_AGE,
+      deposit_cap: 0,
+      borrow_cap: 0,
+      borrow_weight_bps: DEFAULT_BORROW_WEIGHT,
+   });
+
+   // init_bank only takes the main parameters, the rest of the file is set in the same transaction
+   let instructions = [
+      InitBankBuilder::new(authority.pubkey(), &accounts, liquidation_threshold, max_ltv, interest_rate).instruction(),
+      UpdateBankConfigBuilder::new(authority.pubkey(), accounts.bank, config).instruction(),
+   ];
+   println!("Bank of {mint}: {}", accounts.bank);
+   send(context, &authority, &instructions)
+}
+
+pub fn update_bank(context: &Context, mint: Pubkey, config_path: &Path) -> Result<()> {
+   let authority = context.keypair()?;
+   let file = BankConfigFile::load(config_path)?;
+   let (accounts, bank) = market::bank_accounts(&context.rpc, &mint)?;
+
+   let config = file.apply(BankConfig {
+      liquidation_threshold: bank.liquidation_threshold,
+      liquidation_bonus: bank.liquidation_bonus,
+      liquidation_close_factor: bank.liquidation_close_factor,
+      max_ltv: bank.max_ltv,
+      interest_rate: bank.interest_rate,
+      oracle_max_age: bank.oracle_max_age,
+      deposit_cap: bank.deposit_cap,
+      borrow_cap: bank.borrow_cap,
+      borrow_weight_bps: bank.borrow_weight_bps,
+   });
+   send(context, &authority, &[UpdateBankConfigBuilder::new(authority.pubkey(), accounts.bank, config).instruction()])
+}
+
+pub fn set_paused(context: &Context, mint: Pubkey, paused: bool) -> Result<()> {
+   let authority = context.keypair()?;
+   let bank = lending_client::bank_address(&mint);
+   send(context, &authority, &[SetBankPausedBuilder::new(authority.pubkey(), bank, paused).instruction()])
+}
+
+pub fn list_banks(context: &Context) -> Result<()> {
+   let banks = market::banks(&context.rpc)?;
+   if banks.is_empty() {
+      println!("No banks");
+   }
+   for (key, bank) in banks {
+      println!("Bank {key}{}", if bank.paused { " (paused)" } else { "" });
+      println!("  mint:               {} ({} decimals)", bank.mint_address, bank.decimals);
+      println!("  oracle:             {} (max age {}s)", bank.oracle, bank.oracle_max_age);
+      for secondary_oracle in bank.oracles().iter().skip(1) {
+         println!("  secondary oracle:   {secondary_oracle}");
+      }
+      if bank.oracle_tolerance_bps > 0 {
+         println!("  oracle tolerance:   {}", percent(bank.oracle_tolerance_bps));
+      }
+      if bank.last_price > 0 {
+         println!("  cached price:       {} (published at {})", bank.last_price, bank.last_price_updated);
+      }
+      if bank.ema_window > 0 {
+         println!("  moving average:     {} over {}s (updated at {})", bank.ema_price, bank.ema_window, bank.ema_updated);
+      }
+      println!("  last accrual:       {}", bank.last_updated);
+      println!("  deposits:           {} (cap {})", bank.total_deposits, cap(bank.deposit_cap));
+      println!("  borrowed:           {} (cap {})", bank.total_borrowed, cap(bank.borrow_cap));
+      println!("  utilization:        {}", percent(lending_client::utilization_bps(&bank)?));
+      println!("  APY borrow/supply: {} / {}", percent(lending_client::borrow_apy_bps(&bank)?), percent(lending_client::supply_apy_bps(&bank)?));
+      println!("  max LTV:            {}", percent(bank.max_ltv));
+      println!("  borrow weight:      {}", percent(bank.borrow_weight_bps));
+      if bank.isolated {
+         println!("  isolated:           debt {} (ceiling {})", bank.isolated_debt, cap(bank.debt_ceiling));
+      }
+      if bank.borrowable_in_isolation {
+         println!("  borrowable in isolation");
+      }
+      println!("  liquidation:        threshold {}, bonus {}, close factor {}",
+         percent(bank.liquidation_threshold), percent(bank.liquidation_bonus), percent(bank.liquidation_close_factor));
+      if bank.liquidation_mode == LiquidationMode::DutchAuction {
+         println!("  dutch auction:      bonus from {} over {}s", percent(bank.min_liquidation_bonus), bank.liquidation_auction_duration);
+      }
+      if bank.soft_liquidation_health > 0 {
+         println!("  self liquidation:   below health factor {}, penalty {}",
+            percent(bank.soft_liquidation_health), percent(bank.self_liquidation_penalty));
+      }
+      if bank.insurance_fee_bps > 0 || bank.insurance_shares > 0 {
+         println!("  insurance:          fee {}, funds {} + fees {} (unstake cooldown {}s)",
+            percent(bank.insurance_fee_bps), bank.insurance_funds, bank.insurance_fees, bank.insurance_cooldown);
+      }
+   }
+   Ok(())
+}
+
+pub fn list_users(context: &Context) -> Result<()> {
+   let banks = market::banks(&context.rpc)?;
+   let users = market::users(&context.rpc)?;
+   if users.is_empty() {
+      println!("No users");
+   }
+   // Amounts at the last accrual of each bank, `health` gives the current ones
+   for (key, user) in users {
+      println!("User {key}");
+      println!("  owner:         {}", user.owner);
+      println!("  health factor: {} (at {})", health_factor(user.health_factor), user.last_updated);
+      for position in user.positions.iter().filter(|position| position.is_active()) {
+         let Some((_, bank)) = banks.iter().find(|(key, _)| *key == position.bank) else {
+            println!("  bank {}: unknown", position.bank);
+            continue;
+         };
+         println!("  {}: deposited {}, borrowed {}", bank.mint_address,
+            bank.deposit_shares_to_amount(position.deposit_shares)?, bank.borrowed_shares_to_amount(position.borrowed_shares)?);
+      }
+   }
+   Ok(())
+}
+
+pub fn bank_action(context: &Context, action: Action, mint: Pubkey, amount: u64) -> Result<()> {
+   let payer = context.keypair()?;
+   let wallet = payer.pubkey();
+   let (accounts, _) = market::bank_accounts(&context.rpc, &mint)?;
+   let user = market::user(&context.rpc, &wallet)?;
+   let positions = market::position_accounts(&context.rpc, user.as_ref(), &[accounts.bank])?;
+   let isolated_bank = market::isolated_bank(&context.rpc, user.as_ref())?;
+
+   let mut instructions = Vec::new();
+   if user.is_none() {
+      instructions.push(InitUserBuilder::new(wallet).instruction());
+   }
+   instructions.push(match action {
+      Action::Deposit => DepositBuilder::new(wallet, &accounts, amount).positions(positions).instruction(),
+      Action::Withdraw => WithdrawBuilder::new(wallet, &accounts, amount).positions(positions).instruction(),
+      Action::Borrow => BorrowBuilder::new(wallet, &accounts, amount).positions(positions).isolated_bank(isolated_bank).instruction(),
+      Action::Repay => RepayBuilder::new(wallet, &accounts, amount).positions(positions).isolated_bank(isolated_bank).instruction(),
+   });
+   send(context, &payer, &instructions)
+}
+
+pub fn health(context: &Context, wallet: Pubkey) -> Result<()> {
+   let user = market::user(&context.rpc, &wallet)?.ok_or_else(|| anyhow!("{wallet} has no user account"))?;
+   let banks = market::priced_banks(&context.rpc, &user)?;
+   let emode = market::emode_category(&context.rpc, user.emode_category)?;
+   let health = lending_client::compute_health(&user, &banks, emode.as_ref())?;
+
+   // Values are in the quote currency of the oracles with 8 decimals, amounts in base units of their mint
+   println!("Wallet {wallet}");
+   if let Some(category) = &emode {
+      println!("  e-mode category {}: max ltv {}, liquidation threshold {}", category.id, category.max_ltv, category.liquidation_threshold);
+   }
+   for priced in &banks {
+      let Some(position) = user.position(&priced.key) else { continue };
+      println!("  {}: deposited {}, borrowed {} (price {}, average {})", priced.bank.mint_address,
+         priced.bank.deposit_shares_to_amount(position.deposit_shares)?, priced.bank.borrowed_shares_to_amount(position.borrowed_shares)?,
+         priced.price, priced.ema_price);
+   }
+   println!("  collateral value:    {}", health.collateral_value);
+   println!("  weighted collateral: {}", health.weighted_collateral);
+   println!("  borrow limit:        {}", health.borrow_limit);
+   println!("  debt value:          {}", health.debt_value);
+   println!("  weighted debt:       {}", health.weighted_debt);
+   println!("  health factor:       {}", health_factor(health.health_factor()));
+   Ok(())
+}
+
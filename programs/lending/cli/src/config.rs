@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use lending_client::BankConfig;
+use serde::Deserialize;
+
+/*
+   The RPC URL and the keypair come from the config file of the Solana CLI (`solana config set ...`),
+   unless they are given on the command line.
+*/
+#[derive(Debug, Default, Deserialize)]
+pub struct SolanaConfig {
+   #[serde(default)]
+   pub json_rpc_url: Option<String>,
+   #[serde(default)]
+   pub keypair_path: Option<String>,
+   #[serde(default)]
+   pub commitment: Option<String>,
+}
+
+fn home() -> PathBuf {
+   std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default()
+}
+
+impl SolanaConfig {
+   pub fn default_path() -> PathBuf {
+      home().join(".config/solana/cli/config.yml")
+   }
+
+   // A missing default config file is fine, the Solana CLI defaults are used instead
+   pub fn load(path: Option<&Path>) -> Result<Self> {
+      let (path, required) = match path {
+         Some(path) => (path.to_path_buf(), true),
+         None => (Self::default_path(), false),
+      };
+      if !required && !path.exists() {
+         return Ok(SolanaConfig::default());
+      }
+      let text = std::fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+      serde_yaml::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+   }
+
+   pub fn rpc_url(&self, url: Option<&str>) -> String {
+      let url = url.or(self.json_rpc_url.as_deref()).unwrap_or("http://localhost:8899");
+      // Same monikers as `solana --url`
+      match url {
+         "l" | "localhost" => "http://localhost:8899",
+         "d" | "devnet" => "https://api.devnet.solana.com",
+         "t" | "testnet" => "https://api.testnet.solana.com",
+         "m" | "mainnet-beta" => "https://api.mainnet-beta.solana.com",
+         url => url,
+      }
+      .to_string()
+   }
+
+   pub fn keypair_path(&self, keypair: Option<&Path>) -> PathBuf {
+      keypair
+         .map(Path::to_path_buf)
+         .or_else(|| self.keypair_path.as_ref().map(PathBuf::from))
+         .unwrap_or_else(|| home().join(".config/solana/id.json"))
+   }
+}
+
+/*
+   Risk parameters of a bank, in a TOML file:
+
+      liquidation_threshold = 8000   # bps
+      max_ltv = 7500                 # bps
+      interest_rate = 5              # yearly percentage
+      liquidation_bonus = 500        # bps
+      liquidation_close_factor = 5000
+      oracle_max_age = 60            # seconds
+      deposit_cap = 0                # 0 means no cap
+      borrow_cap = 0
+      borrow_weight_bps = 10000      # bps, 10000 counts the debt at its value
+
+   init-bank needs the first three, the others start with the defaults of the program.
+   update-bank only changes the parameters that are in the file.
+*/
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BankConfigFile {
+   pub liquidation_threshold: Option<u64>,
+   pub max_ltv: Option<u64>,
+   pub interest_rate: Option<u64>,
+   pub liquidation_bonus: Option<u64>,
+   pub liquidation_close_factor: Option<u64>,
+   pub oracle_max_age: Option<u64>,
+   pub deposit_cap: Option<u64>,
+   pub borrow_cap: Option<u64>,
+   pub borrow_weight_bps: Option<u64>,
+}
+
+impl BankConfigFile {
+   pub fn load(path: &Path) -> Result<Self> {
+      let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+      toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+   }
+
+   pub fn apply(&self, base: BankConfig) -> BankConfig {
+      BankConfig {
+         liquidation_threshold: self.liquidation_threshold.unwrap_or(base.liquidation_threshold),
+         liquidation_bonus: self.liquidation_bonus.unwrap_or(base.liquidation_bonus),
+         liquidation_close_factor: self.liquidation_close_factor.unwrap_or(base.liquidation_close_factor),
+         max_ltv: self.max_ltv.unwrap_or(base.max_ltv),
+         interest_rate: self.interest_rate.unwrap_or(base.interest_rate),
+         oracle_max_age: self.oracle_max_age.unwrap_or(base.oracle_max_age),
+         deposit_cap: self.deposit_cap.unwrap_or(base.deposit_cap),
+         borrow_cap: self.borrow_cap.unwrap_or(base.borrow_cap),
+         borrow_weight_bps: self.borrow_weight_bps.unwrap_or(base.borrow_weight_bps),
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn bank_config_file_only_changes_what_it_contains() {
+      let file: BankConfigFile = toml::from_str("max_ltv = 6000\nborrow_cap = 1000000").unwrap();
+      let base = BankConfig {
+         liquidation_threshold: 8_000,
+         liquidation_bonus: 500,
+         liquidation_close_factor: 5_000,
+         max_ltv: 7_500,
+         interest_rate: 5,
+         oracle_max_age: 60,
+         deposit_cap: 0,
+         borrow_cap: 0,
+         borrow_weight_bps: 10_000,
+      };
+      let config = file.apply(base);
+      assert_eq!((config.max_ltv, config.borrow_cap), (6_000, 1_000_000));
+      assert_eq!((config.liquidation_threshold, config.interest_rate), (8_000, 5));
+
+      assert!(toml::from_str::<BankConfigFile>("max_ltw = 6000").is_err());
+   }
+
+   #[test]
+   fn solana_config_fills_what_the_command_line_does_not_give() {
+      let config: SolanaConfig = serde_yaml::from_str("json_rpc_url: devnet\nkeypair_path: /keys/admin.json\ncommitment: confirmed").unwrap();
+      assert_eq!(config.rpc_url(None), "https://api.devnet.solana.com");
+      assert_eq!(config.rpc_url(Some("http://127.0.0.1:8899")), "http://127.0.0.1:8899");
+      assert_eq!(config.keypair_path(None), PathBuf::from("/keys/admin.json"));
+   }
+}
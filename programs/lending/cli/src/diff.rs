@@ -0,0 +1,145 @@
+use anchor_lang::{prelude::Pubkey, solana_program::program_pack::Pack, Discriminator};
+use anchor_spl::token::spl_token;
+use lending_client::{decode_bank, decode_user, lending, Bank, User};
+use solana_sdk::account::Account;
+
+/*
+   --dry-run prints what a transaction would change: every writable account is described as a list of
+   (field, value) before and after the simulation, and only the fields that differ are printed.
+*/
+pub type Fields = Vec<(String, String)>;
+
+fn field(fields: &mut Fields, name: impl Into<String>, value: impl ToString) {
+   fields.push((name.into(), value.to_string()));
+}
+
+fn bank_fields(bank: &Bank, fields: &mut Fields) {
+   field(fields, "total_deposits", bank.total_deposits);
+   field(fields, "total_deposit_shares", bank.total_deposit_shares);
+   field(fields, "total_borrowed", bank.total_borrowed);
+   field(fields, "total_borrowed_shares", bank.total_borrowed_shares);
+   field(fields, "liquidation_threshold", bank.liquidation_threshold);
+   field(fields, "liquidation_bonus", bank.liquidation_bonus);
+   field(fields, "liquidation_close_factor", bank.liquidation_close_factor);
+   field(fields, "max_ltv", bank.max_ltv);
+   field(fields, "interest_rate", bank.interest_rate);
+   field(fields, "oracle", bank.oracle);
+   field(fields, "oracle_max_age", bank.oracle_max_age);
+   field(fields, "deposit_cap", bank.deposit_cap);
+   field(fields, "borrow_cap", bank.borrow_cap);
+   field(fields, "paused", bank.paused);
+   field(fields, "last_updated", bank.last_updated);
+   field(fields, "last_price", bank.last_price);
+   field(fields, "last_price_updated", bank.last_price_updated);
+}
+
+fn user_fields(user: &User, fields: &mut Fields) {
+   field(fields, "owner", user.owner);
+   for (index, position) in user.positions.iter().enumerate().filter(|(_, position)| position.is_active()) {
+      field(fields, format!("positions[{index}].bank"), position.bank);
+      field(fields, format!("positions[{index}].deposit_shares"), position.deposit_shares);
+      field(fields, format!("positions[{index}].borrowed_shares"), position.borrowed_shares);
+   }
+   field(fields, "health_factor", user.health_factor);
+   field(fields, "last_updated", user.last_updated);
+}
+
+pub fn describe(account: Option<&Account>) -> Fields {
+   let mut fields = Fields::new();
+   let Some(account) = account else {
+      return fields;
+   };
+   field(&mut fields, "lamports", account.lamports);
+
+   let data = &account.data[..];
+   if account.owner == lending::ID && data.starts_with(&Bank::DISCRIMINATOR) {
+      if let Ok(bank) = decode_bank(data) {
+         bank_fields(&bank, &mut fields);
+         return fields;
+      }
+   }
+   if account.owner == lending::ID && data.starts_with(&User::DISCRIMINATOR) {
+      if let Ok(user) = decode_user(data) {
+         user_fields(&user, &mut fields);
+         return fields;
+      }
+   }
+   // Token accounts of both token programs start with the spl_token layout
+   if data.len() >= spl_token::state::Account::LEN && account.owner != lending::ID {
+      if let Ok(token_account) = spl_token::state::Account::unpack_from_slice(&data[..spl_token::state::Account::LEN]) {
+         field(&mut fields, "mint", token_account.mint);
+         field(&mut fields, "token_owner", token_account.owner);
+         field(&mut fields, "amount", token_account.amount);
+         return fields;
+      }
+   }
+   field(&mut fields, "owner", account.owner);
+   field(&mut fields, "data_len", account.data.len());
+   fields
+}
+
+// The lines to print for one account, nothing when it doesn't change
+pub fn diff(key: &Pubkey, before: Option<&Account>, after: Option<&Account>) -> Vec<String> {
+   let (before_fields, after_fields) = (describe(before), describe(after));
+   let value = |fields: &Fields, name: &str| fields.iter().find(|(field, _)| field == name).map(|(_, value)| value.clone());
+
+   let mut names: Vec<&str> = before_fields.iter().map(|(name, _)| name.as_str()).collect();
+   for (name, _) in &after_fields {
+      if !names.contains(&name.as_str()) {
+         names.push(name);
+      }
+   }
+
+   let mut lines = Vec::new();
+   for name in names {
+      let (old, new) = (value(&before_fields, name), value(&after_fields, name));
+      if old != new {
+         let show = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+         lines.push(format!("  {name}: {} -> {}", show(old), show(new)));
+      }
+   }
+   if !lines.is_empty() {
+      let status = match (before, after) {
+         (None, Some(_)) => " (created)",
+         (Some(_), None) => " (closed)",
+         _ => "",
+      };
+      lines.insert(0, format!("{key}{status}"));
+   }
+   lines
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use anchor_lang::AccountSerialize;
+
+   fn bank_account(bank: &Bank) -> Account {
+      let mut data = Vec::new();
+      bank.try_serialize(&mut data).unwrap();
+      Account { lamports: 1_000, data, owner: lending::ID, executable: false, rent_epoch: 0 }
+   }
+
+   #[test]
+   fn diff_prints_only_the_fields_that_change() {
+      let before = Bank { total_deposits: 1_000, total_deposit_shares: 1_000, ..Default::default() };
+      let after = Bank { total_deposits: 1_500, total_deposit_shares: 1_500, ..Default::default() };
+      let key = Pubkey::new_unique();
+
+      let lines = diff(&key, Some(&bank_account(&before)), Some(&bank_account(&after)));
+      assert_eq!(lines, vec![
+         key.to_string(),
+         "  total_deposits: 1000 -> 1500".to_string(),
+         "  total_deposit_shares: 1000 -> 1500".to_string(),
+      ]);
+      assert!(diff(&key, Some(&bank_account(&before)), Some(&bank_account(&before))).is_empty());
+   }
+
+   #[test]
+   fn diff_shows_created_accounts() {
+      let key = Pubkey::new_unique();
+      let lines = diff(&key, None, Some(&bank_account(&Bank::default())));
+      assert_eq!(lines[0], format!("{key} (created)"));
+      assert_eq!(lines[1], "  lamports: - -> 1000");
+   }
+}
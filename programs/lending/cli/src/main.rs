@@ -0,0 +1,152 @@
+use std::path::PathBuf;
+
+use anchor_lang::prelude::Pubkey;
+use anyhow::{Context as _, Result};
+use clap::{Parser, Subcommand};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+   commitment_config::CommitmentConfig,
+   signature::{read_keypair_file, Keypair},
+};
+
+mod commands;
+mod config;
+mod diff;
+mod market;
+
+use config::SolanaConfig;
+
+/*
+   Admin and user tool of the lending program. Every command that sends a transaction can be run with --dry-run,
+   which only simulates it and prints how every account it writes would change.
+*/
+#[derive(Parser)]
+#[command(name = "lending-cli", version, about = "Admin and user tool of the lending program")]
+struct Cli {
+   /// Solana CLI config file [default: ~/.config/solana/cli/config.yml]
+   #[arg(long, short = 'C', global = true)]
+   config: Option<PathBuf>,
+
+   /// RPC URL or moniker (localhost, devnet, testnet, mainnet-beta) [default: from the Solana config]
+   #[arg(long, short = 'u', global = true)]
+   url: Option<String>,
+
+   /// Keypair that signs and pays [default: from the Solana config]
+   #[arg(long, short = 'k', global = true)]
+   keypair: Option<PathBuf>,
+
+   /// Simulate the transaction and print the account changes instead of sending it
+   #[arg(long, global = true)]
+   dry_run: bool,
+
+   #[command(subcommand)]
+   command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+   /// Create the bank of a mint, with the risk parameters of a TOML config file (signed by the upgrade authority of the program)
+   InitBank {
+      #[arg(long)]
+      mint: Pubkey,
+      /// Price account of the mint (Pyth, Switchboard-style or AMM TWAP layout)
+      #[arg(long)]
+      oracle: Pubkey,
+      #[arg(long)]
+      config: PathBuf,
+   },
+   /// Change the risk parameters of a bank that are in a TOML config file
+   UpdateBank {
+      #[arg(long)]
+      mint: Pubkey,
+      #[arg(long)]
+      config: PathBuf,
+   },
+   /// Stop deposits, withdrawals and borrows of a bank
+   Pause {
+      #[arg(long)]
+      mint: Pubkey,
+   },
+   /// Resume a paused bank
+   Unpause {
+      #[arg(long)]
+      mint: Pubkey,
+   },
+   /// List every bank with its state, utilization and APYs
+   Banks,
+   /// List every user account with its positions
+   Users,
+   /// Deposit tokens of the keypair
+   Deposit(AmountArgs),
+   /// Withdraw deposited tokens to the keypair
+   Withdraw(AmountArgs),
+   /// Borrow tokens against the deposits of the keypair
+   Borrow(AmountArgs),
+   /// Repay borrowed tokens from the keypair
+   Repay(AmountArgs),
+   /// Print the health of a wallet [default: the keypair]
+   Health {
+      #[arg(long)]
+      wallet: Option<Pubkey>,
+   },
+}
+
+#[derive(clap::Args)]
+struct AmountArgs {
+   /// Mint of the bank
+   #[arg(long)]
+   mint: Pubkey,
+   /// Amount in base units of the mint
+   #[arg(long)]
+   amount: u64,
+}
+
+// What every command needs: the RPC connection, the signer and whether to only simulate
+pub struct Context {
+   pub rpc: RpcClient,
+   pub keypair_path: PathBuf,
+   pub dry_run: bool,
+}
+
+impl Context {
+   pub fn keypair(&self) -> Result<Keypair> {
+      read_keypair_file(&self.keypair_path).map_err(|err| anyhow::anyhow!("reading keypair {}: {err}", self.keypair_path.display()))
+   }
+}
+
+fn commitment(config: &SolanaConfig) -> Result<CommitmentConfig> {
+   match config.commitment.as_deref() {
+      None => Ok(CommitmentConfig::confirmed()),
+      Some(commitment) => commitment.parse().with_context(|| format!("invalid commitment {commitment}")),
+   }
+}
+
+fn main() -> Result<()> {
+   let cli = Cli::parse();
+   let config = SolanaConfig::load(cli.config.as_deref())?;
+   let context = Context {
+      rpc: RpcClient::new_with_commitment(config.rpc_url(cli.url.as_deref()), commitment(&config)?),
+      keypair_path: config.keypair_path(cli.keypair.as_deref()),
+      dry_run: cli.dry_run,
+   };
+
+   match cli.command {
+      Command::InitBank { mint, oracle, config } => commands::init_bank(&context, mint, oracle, &config),
+      Command::UpdateBank { mint, config } => commands::update_bank(&context, mint, &config),
+      Command::Pause { mint } => commands::set_paused(&context, mint, true),
+      Command::Unpause { mint } => commands::set_paused(&context, mint, false),
+      Command::Banks => commands::list_banks(&context),
+      Command::Users => commands::list_users(&context),
+      Command::Deposit(args) => commands::bank_action(&context, commands::Action::Deposit, args.mint, args.amount),
+      Command::Withdraw(args) => commands::bank_action(&context, commands::Action::Withdraw, args.mint, args.amount),
+      Command::Borrow(args) => commands::bank_action(&context, commands::Action::Borrow, args.mint, args.amount),
+      Command::Repay(args) => commands::bank_action(&context, commands::Action::Repay, args.mint, args.amount),
+      Command::Health { wallet } => {
+         let wallet = match wallet {
+            Some(wallet) => wallet,
+            None => solana_sdk::signer::Signer::pubkey(&context.keypair()?),
+         };
+         commands::health(&context, wallet)
+      }
+   }
+}
@@ -0,0 +1,126 @@
+use anchor_lang::{prelude::Pubkey, solana_program::instruction::AccountMeta, AccountDeserialize, Discriminator};
+use anyhow::{anyhow, Context, Result};
+use lending_client::{decode_bank, decode_emode_category, decode_user, user_address, Bank, BankAccounts, EModeCategory, PricedBank, User};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+   rpc_client::RpcClient,
+   rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+   rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{clock::Clock, sysvar};
+
+/*
+   Reads of the program accounts over RPC, decoded with lending-client.
+*/
+
+// Every account of type T of the program, found by its discriminator
+fn program_accounts<T: AccountDeserialize + Discriminator>(rpc: &RpcClient) -> Result<Vec<(Pubkey, T)>> {
+   let config = RpcProgramAccountsConfig {
+      filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, &T::DISCRIMINATOR))]),
+      account_config: RpcAccountInfoConfig { encoding: Some(UiAccountEncoding::Base64), ..Default::default() },
+      ..Default::default()
+   };
+   let accounts = rpc.get_program_accounts_with_config(&lending_client::lending::ID, config)?;
+   accounts
+      .into_iter()
+      .map(|(key, account)| Ok((key, T::try_deserialize(&mut &account.data[..])?)))
+      .collect()
+}
+
+pub fn banks(rpc: &RpcClient) -> Result<Vec<(Pubkey, Bank)>> {
+   program_accounts(rpc)
+}
+
+pub fn users(rpc: &RpcClient) -> Result<Vec<(Pubkey, User)>> {
+   program_accounts(rpc)
+}
+
+pub fn bank(rpc: &RpcClient, key: &Pubkey) -> Result<Bank> {
+   let account = rpc.get_account(key).with_context(|| format!("no bank at {key}"))?;
+   Ok(decode_bank(&account.data)?)
+}
+
+// None until the wallet has a user account
+pub fn user(rpc: &RpcClient, wallet: &Pubkey) -> Result<Option<User>> {
+   let account = rpc.get_account_with_commitment(&user_address(wallet), rpc.commitment())?.value;
+   account.map(|account| decode_user(&account.data).map_err(Into::into)).transpose()
+}
+
+// The e-mode category a user or a bank is in, None for 0
+pub fn emode_category(rpc: &RpcClient, id: u8) -> Result<Option<EModeCategory>> {
+   if id == 0 {
+      return Ok(None);
+   }
+   let key = lending_client::emode_category_address(id);
+   let account = rpc.get_account(&key).with_context(|| format!("no e-mode category at {key}"))?;
+   Ok(Some(decode_emode_category(&account.data)?))
+}
+
+// The accounts of the bank of a mint, with the token program that owns the mint
+pub fn bank_accounts(rpc: &RpcClient, mint: &Pubkey) -> Result<(BankAccounts, Bank)> {
+   let bank_key = lending_client::bank_address(mint);
+   let bank = bank(rpc, &bank_key)?;
+   let mint_account = rpc.get_account(mint).with_context(|| format!("no mint at {mint}"))?;
+   Ok((BankAccounts::from_bank(&bank).with_token_program(mint_account.owner), bank))
+}
+
+pub fn now(rpc: &RpcClient) -> Result<i64> {
+   let account = rpc.get_account(&sysvar::clock::ID)?;
+   let clock: Clock = solana_sdk::account::from_account(&account).ok_or_else(|| anyhow!("invalid clock sysvar"))?;
+   Ok(clock.unix_timestamp)
+}
+
+// Every bank the user has a position in, accrued and priced like the program would do it now
+pub fn priced_banks(rpc: &RpcClient, user: &User) -> Result<Vec<PricedBank>> {
+   let keys: Vec<Pubkey> = user.positions.iter().filter(|position| position.is_active()).map(|position| position.bank).collect();
+   let banks = rpc.get_multiple_accounts(&keys)?;
+   let now = now(rpc)?;
+
+   let mut priced = Vec::new();
+   for (key, account) in keys.iter().zip(banks) {
+      let bank = decode_bank(&account.with_context(|| format!("no bank at {key}"))?.data)?;
+      let oracles = bank
+         .oracles()
+         .iter()
+         .map(|oracle| rpc.get_account(oracle).with_context(|| format!("no oracle at {oracle}")))
+         .collect::<Result<Vec<_>>>()?;
+      let oracle_data: Vec<&[u8]> = oracles.iter().map(|oracle| &oracle.data[..]).collect();
+      priced.push(lending_client::priced_bank(*key, &bank, &oracle_data, now)?);
+   }
+   Ok(priced)
+}
+
+// The remaining accounts for the other positions of the user
+pub fn position_accounts(rpc: &RpcClient, user: Option<&User>, acted: &[Pubkey]) -> Result<Vec<AccountMeta>> {
+   let Some(user) = user else {
+      return Ok(Vec::new());
+   };
+   let keys: Vec<Pubkey> = user.positions.iter().filter(|position| position.is_active()).map(|position| position.bank).collect();
+   let banks: Vec<(Pubkey, Bank)> = keys
+      .iter()
+      .zip(rpc.get_multiple_accounts(&keys)?)
+      .filter_map(|(key, account)| Some((*key, decode_bank(&account?.data).ok()?)))
+      .collect();
+   let oracles_of = |key: &Pubkey| banks.iter().find(|(bank, _)| bank == key).map(|(_, bank)| bank.oracles());
+   Ok(lending_client::position_accounts(user, acted, oracles_of)?)
+}
+
+// The isolated bank backing the debt of the user (before its first borrow the one it has collateral in),
+// borrow and repay count the debt it backs on it
+pub fn isolated_bank(rpc: &RpcClient, user: Option<&User>) -> Result<Option<Pubkey>> {
+   let Some(user) = user else {
+      return Ok(None);
+   };
+   if user.isolated_bank != Pubkey::default() {
+      return Ok(Some(user.isolated_bank));
+   }
+   let keys: Vec<Pubkey> =
+      user.positions.iter().filter(|position| position.is_active() && position.deposit_shares > 0).map(|position| position.bank).collect();
+   for (key, account) in keys.iter().zip(rpc.get_multiple_accounts(&keys)?) {
+      let bank = decode_bank(&account.with_context(|| format!("no bank at {key}"))?.data)?;
+      if bank.isolated {
+         return Ok(Some(*key));
+      }
+   }
+   Ok(None)
+}
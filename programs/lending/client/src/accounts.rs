@@ -0,0 +1,107 @@
+use anchor_lang::{prelude::*, AccountDeserialize};
+use anchor_spl::token::spl_token;
+use lending::error::ErrCode;
+use lending::oracle::aggregate_price;
+use lending::state::{Bank, EModeCategory, User};
+
+use crate::pda::{bank_address, emode_category_address, insurance_vault_address, treasury_address};
+
+// Account data as returned by getAccountInfo, the discriminator is checked
+pub fn decode_bank(data: &[u8]) -> Result<Bank> {
+   Bank::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_user(data: &[u8]) -> Result<User> {
+   User::try_deserialize(&mut &data[..])
+}
+
+pub fn decode_emode_category(data: &[u8]) -> Result<EModeCategory> {
+   EModeCategory::try_deserialize(&mut &data[..])
+}
+
+// The price the program would use at `now`, with PRICE_DECIMALS decimals, from the data of Bank::oracles in that order
+pub fn oracle_price(bank: &Bank, oracle_data: &[&[u8]], now: i64) -> Result<u64> {
+   Ok(aggregate_price(bank, oracle_data, now)?.0)
+}
+
+/*
+   The accounts of a bank every instruction needs. The token program can't be read from the bank,
+   it is the one of the mint (spl_token unless the mint is a token-2022 mint).
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BankAccounts {
+   pub mint: Pubkey,
+   pub bank: Pubkey,
+   pub treasury: Pubkey,
+   pub insurance_vault: Pubkey,
+   pub oracle: Pubkey,
+   pub secondary_oracles: [Option<Pubkey>; 2],
+   pub token_program: Pubkey,
+}
+
+impl BankAccounts {
+   pub fn new(mint: Pubkey, oracle: Pubkey) -> Self {
+      BankAccounts {
+         mint,
+         bank: bank_address(&mint),
+         treasury: treasury_address(&mint),
+         insurance_vault: insurance_vault_address(&mint),
+         oracle,
+         secondary_oracles: [None; 2],
+         token_program: spl_token::ID,
+      }
+   }
+
+   pub fn from_bank(bank: &Bank) -> Self {
+      let secondary_oracle = |oracle: Pubkey| (oracle != Pubkey::default()).then_some(oracle);
+      Self::new(bank.mint_address, bank.oracle)
+         .with_secondary_oracles(bank.secondary_oracles.map(secondary_oracle))
+   }
+
+   pub fn with_secondary_oracles(mut self, secondary_oracles: [Option<Pubkey>; 2]) -> Self {
+      self.secondary_oracles = secondary_oracles;
+      self
+   }
+
+   pub fn with_token_program(mut self, token_program: Pubkey) -> Self {
+      self.token_program = token_program;
+      self
+   }
+}
+
+/*
+   The remaining accounts of every instruction that checks the health of the user: its e-mode category if it has one,
+   then for every other position of the user, in the order of the positions, its bank followed by the oracles of the bank
+   (see health::load_emode_category and health::load_priced_banks).
+   `acted` are the banks the instruction already gets, `oracles_of` returns Bank::oracles of a bank.
+*/
+pub fn position_accounts(
+   user: &User,
+   acted: &[Pubkey],
+   oracles_of: impl Fn(&Pubkey) -> Option<Vec<Pubkey>>,
+) -> Result<Vec<AccountMeta>> {
+   let mut metas = Vec::new();
+   if user.emode_category != 0 {
+      metas.push(AccountMeta::new_readonly(emode_category_address(user.emode_category), false));
+   }
+   for position in user.positions.iter().filter(|position| position.is_active()) {
+      if acted.contains(&position.bank) {
+         continue;
+      }
+      let oracles = oracles_of(&position.bank).ok_or(ErrCode::MissingPositionAccounts)?;
+      metas.push(AccountMeta::new_readonly(position.bank, false));
+      metas.extend(oracles.into_iter().map(|oracle| AccountMeta::new_readonly(oracle, false)));
+   }
+   Ok(metas)
+}
+
+// The remaining accounts of set_user_emode: position_accounts with the banks the user borrows from writable (see Bank::add_emode_debt)
+pub fn emode_position_accounts(user: &User, oracles_of: impl Fn(&Pubkey) -> Option<Vec<Pubkey>>) -> Result<Vec<AccountMeta>> {
+   let mut metas = position_accounts(user, &[], oracles_of)?;
+   for meta in metas.iter_mut() {
+      if user.position(&meta.pubkey).is_some_and(|position| position.borrowed_shares > 0) {
+         meta.is_writable = true;
+      }
+   }
+   Ok(metas)
+}
@@ -0,0 +1,1439 @@
+use anchor_lang::{
+   prelude::*,
+   solana_program::{instruction::Instruction, system_program},
+   InstructionData,
+};
+use anchor_spl::associated_token::{self, get_associated_token_address_with_program_id};
+use lending::instructions::{
+   BankConfig, EModeConfig, EmaConfig, InsuranceConfig, IsolationConfig, LiquidationModeConfig, OracleConfig, SelfLiquidationConfig,
+};
+
+use crate::accounts::BankAccounts;
+use crate::pda::{emode_category_address, event_authority, insurance_stake_address, program_data_address, user_address};
+
+/*
+   One builder per instruction of the program. `new` takes everything the instruction can't work without,
+   the rest has defaults that can be changed before calling `instruction()`:
+   - token accounts of the signer default to its associated token account for the mint
+   - positions are the remaining accounts of the other positions of the user, see accounts::position_accounts
+*/
+
+fn instruction(accounts: impl ToAccountMetas, data: impl InstructionData, remaining: &[AccountMeta]) -> Instruction {
+   let mut metas = accounts.to_account_metas(None);
+   metas.extend_from_slice(remaining);
+   Instruction { program_id: lending::ID, accounts: metas, data: data.data() }
+}
+
+fn associated_token_account(wallet: &Pubkey, bank: &BankAccounts) -> Pubkey {
+   get_associated_token_address_with_program_id(wallet, &bank.mint, &bank.token_program)
+}
+
+// ---------- admin ----------
+
+pub struct InitBankBuilder {
+   authority: Pubkey,
+   bank: BankAccounts,
+   liquidation_threshold: u64,
+   max_ltv: u64,
+   interest_rate: u64,
+}
+
+impl InitBankBuilder {
+   // The rest of the config (bonus, close factor, oracle max age, caps) starts with the defaults of the program
+   pub fn new(authority: Pubkey, bank: &BankAccounts, liquidation_threshold: u64, max_ltv: u64, interest_rate: u64) -> Self {
+      InitBankBuilder { authority, bank: *bank, liquidation_threshold, max_ltv, interest_rate }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::InitBank {
+            signer: self.authority,
+            bank: self.bank.bank,
+            mint: self.bank.mint,
+            bank_token_account: self.bank.treasury,
+            insurance_vault: self.bank.insurance_vault,
+            oracle: self.bank.oracle,
+            program_data: program_data_address(),
+            token_program: self.bank.token_program,
+            associated_token_account: associated_token::ID,
+            system_program: system_program::ID,
+         },
+         lending::instruction::InitBank {
+            liquidation_threshold: self.liquidation_threshold,
+            max_ltv: self.max_ltv,
+            interest_rate: self.interest_rate,
+         },
+         &[],
+      )
+   }
+}
+
+pub struct UpdateBankConfigBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   config: BankConfig,
+}
+
+impl UpdateBankConfigBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, config: BankConfig) -> Self {
+      UpdateBankConfigBuilder { authority, bank, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::UpdateBankConfig {
+            authority: self.authority,
+            bank: self.bank,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::UpdateBankConfig { config: self.config },
+         &[],
+      )
+   }
+}
+
+pub struct SetBankPausedBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   paused: bool,
+}
+
+impl SetBankPausedBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, paused: bool) -> Self {
+      SetBankPausedBuilder { authority, bank, paused }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetBankPaused {
+            authority: self.authority,
+            bank: self.bank,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetBankPaused { paused: self.paused },
+         &[],
+      )
+   }
+}
+
+pub struct InitEModeCategoryBuilder {
+   authority: Pubkey,
+   id: u8,
+   config: EModeConfig,
+}
+
+impl InitEModeCategoryBuilder {
+   pub fn new(authority: Pubkey, id: u8, config: EModeConfig) -> Self {
+      InitEModeCategoryBuilder { authority, id, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::InitEModeCategory {
+            signer: self.authority,
+            emode_category: emode_category_address(self.id),
+            program_data: program_data_address(),
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::InitEmodeCategory { id: self.id, config: self.config },
+         &[],
+      )
+   }
+}
+
+pub struct UpdateEModeCategoryBuilder {
+   authority: Pubkey,
+   id: u8,
+   config: EModeConfig,
+}
+
+impl UpdateEModeCategoryBuilder {
+   pub fn new(authority: Pubkey, id: u8, config: EModeConfig) -> Self {
+      UpdateEModeCategoryBuilder { authority, id, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::UpdateEModeCategory {
+            authority: self.authority,
+            emode_category: emode_category_address(self.id),
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::UpdateEmodeCategory { config: self.config },
+         &[],
+      )
+   }
+}
+
+// A category of 0 takes the bank out of its category
+pub struct SetBankEModeCategoryBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   category: u8,
+}
+
+impl SetBankEModeCategoryBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, category: u8) -> Self {
+      SetBankEModeCategoryBuilder { authority, bank, category }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetBankEModeCategory {
+            authority: self.authority,
+            bank: self.bank,
+            emode_category: (self.category != 0).then(|| emode_category_address(self.category)),
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetBankEmodeCategory {},
+         &[],
+      )
+   }
+}
+
+pub struct SetBankIsolationBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   config: IsolationConfig,
+}
+
+impl SetBankIsolationBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, config: IsolationConfig) -> Self {
+      SetBankIsolationBuilder { authority, bank, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetBankIsolation {
+            authority: self.authority,
+            bank: self.bank,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetBankIsolation { config: self.config },
+         &[],
+      )
+   }
+}
+
+pub struct SetBankLiquidationModeBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   config: LiquidationModeConfig,
+}
+
+impl SetBankLiquidationModeBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, config: LiquidationModeConfig) -> Self {
+      SetBankLiquidationModeBuilder { authority, bank, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetBankLiquidationMode {
+            authority: self.authority,
+            bank: self.bank,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetBankLiquidationMode { config: self.config },
+         &[],
+      )
+   }
+}
+
+pub struct SetBankInsuranceBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   config: InsuranceConfig,
+}
+
+impl SetBankInsuranceBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, config: InsuranceConfig) -> Self {
+      SetBankInsuranceBuilder { authority, bank, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetBankInsurance {
+            authority: self.authority,
+            bank: self.bank,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetBankInsurance { config: self.config },
+         &[],
+      )
+   }
+}
+
+pub struct SetBankSelfLiquidationBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   config: SelfLiquidationConfig,
+}
+
+impl SetBankSelfLiquidationBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, config: SelfLiquidationConfig) -> Self {
+      SetBankSelfLiquidationBuilder { authority, bank, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetBankSelfLiquidation {
+            authority: self.authority,
+            bank: self.bank,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetBankSelfLiquidation { config: self.config },
+         &[],
+      )
+   }
+}
+
+// The secondary oracles replace the current ones of the bank, none removes them
+pub struct SetBankOraclesBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   secondary_oracles: [Option<Pubkey>; 2],
+   config: OracleConfig,
+}
+
+impl SetBankOraclesBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, secondary_oracles: [Option<Pubkey>; 2], config: OracleConfig) -> Self {
+      SetBankOraclesBuilder { authority, bank, secondary_oracles, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetBankOracles {
+            authority: self.authority,
+            bank: self.bank,
+            secondary_oracle_1: self.secondary_oracles[0],
+            secondary_oracle_2: self.secondary_oracles[1],
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetBankOracles { config: self.config },
+         &[],
+      )
+   }
+}
+
+pub struct SetBankEmaBuilder {
+   authority: Pubkey,
+   bank: Pubkey,
+   config: EmaConfig,
+}
+
+impl SetBankEmaBuilder {
+   pub fn new(authority: Pubkey, bank: Pubkey, config: EmaConfig) -> Self {
+      SetBankEmaBuilder { authority, bank, config }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetBankEma {
+            authority: self.authority,
+            bank: self.bank,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetBankEma { config: self.config },
+         &[],
+      )
+   }
+}
+
+// ---------- users ----------
+
+pub struct InitUserBuilder {
+   wallet: Pubkey,
+}
+
+impl InitUserBuilder {
+   pub fn new(wallet: Pubkey) -> Self {
+      InitUserBuilder { wallet }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::InitUser {
+            signer: self.wallet,
+            user_account: user_address(&self.wallet),
+            system_program: system_program::ID,
+         },
+         lending::instruction::InitUser {},
+         &[],
+      )
+   }
+}
+
+// A category of 0 leaves e-mode. The positions are the ones of the user before the change, with no acted bank
+pub struct SetUserEModeBuilder {
+   wallet: Pubkey,
+   category: u8,
+   positions: Vec<AccountMeta>,
+}
+
+impl SetUserEModeBuilder {
+   pub fn new(wallet: Pubkey, category: u8) -> Self {
+      SetUserEModeBuilder { wallet, category, positions: Vec::new() }
+   }
+
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SetUserEMode {
+            signer: self.wallet,
+            user_account: user_address(&self.wallet),
+            emode_category: (self.category != 0).then(|| emode_category_address(self.category)),
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SetUserEmode {},
+         &self.positions,
+      )
+   }
+}
+
+/*
+   Deposit, withdraw, borrow and repay all move `amount` tokens of one bank between the wallet and the treasury,
+   so their builders take the same arguments. Borrow and repay also take the isolated bank of a user whose collateral
+   is isolated (see math::isolated_collateral), the debt it backs is counted on it.
+*/
+macro_rules! bank_action_builder {
+   ($builder:ident $(, $optional:ident)*) => {
+      pub struct $builder {
+         wallet: Pubkey,
+         bank: BankAccounts,
+         amount: u64,
+         user_token_account: Pubkey,
+         positions: Vec<AccountMeta>,
+         $($optional: Option<Pubkey>,)*
+      }
+
+      impl $builder {
+         pub fn new(wallet: Pubkey, bank: &BankAccounts, amount: u64) -> Self {
+            $builder {
+               wallet,
+               bank: *bank,
+               amount,
+               user_token_account: associated_token_account(&wallet, bank),
+               positions: Vec::new(),
+               $($optional: None,)*
+            }
+         }
+
+         pub fn user_token_account(mut self, user_token_account: Pubkey) -> Self {
+            self.user_token_account = user_token_account;
+            self
+         }
+
+         pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+            self.positions = positions;
+            self
+         }
+
+         $(
+            pub fn $optional(mut self, $optional: Option<Pubkey>) -> Self {
+               self.$optional = $optional;
+               self
+            }
+         )*
+      }
+   };
+}
+
+bank_action_builder!(DepositBuilder);
+bank_action_builder!(WithdrawBuilder);
+bank_action_builder!(BorrowBuilder, isolated_bank);
+bank_action_builder!(RepayBuilder, isolated_bank);
+
+impl DepositBuilder {
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::Deposit {
+            signer: self.wallet,
+            mint: self.bank.mint,
+            bank: self.bank.bank,
+            bank_token_account: self.bank.treasury,
+            oracle: self.bank.oracle,
+            secondary_oracle_1: self.bank.secondary_oracles[0],
+            secondary_oracle_2: self.bank.secondary_oracles[1],
+            user_account: user_address(&self.wallet),
+            user_token_account: self.user_token_account,
+            token_program: self.bank.token_program,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::Deposit { amount: self.amount },
+         &self.positions,
+      )
+   }
+}
+
+impl WithdrawBuilder {
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::Withdraw {
+            signer: self.wallet,
+            mint: self.bank.mint,
+            bank: self.bank.bank,
+            bank_token_account: self.bank.treasury,
+            oracle: self.bank.oracle,
+            secondary_oracle_1: self.bank.secondary_oracles[0],
+            secondary_oracle_2: self.bank.secondary_oracles[1],
+            user_account: user_address(&self.wallet),
+            user_token_account: self.user_token_account,
+            token_program: self.bank.token_program,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::Withdraw { amount: self.amount },
+         &self.positions,
+      )
+   }
+}
+
+impl BorrowBuilder {
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::Borrow {
+            signer: self.wallet,
+            mint: self.bank.mint,
+            bank: self.bank.bank,
+            bank_token_account: self.bank.treasury,
+            oracle: self.bank.oracle,
+            secondary_oracle_1: self.bank.secondary_oracles[0],
+            secondary_oracle_2: self.bank.secondary_oracles[1],
+            user: user_address(&self.wallet),
+            user_token_account: self.user_token_account,
+            isolated_bank: self.isolated_bank,
+            token_program: self.bank.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::Borrow { amount: self.amount },
+         &self.positions,
+      )
+   }
+}
+
+impl RepayBuilder {
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::Repay {
+            signer: self.wallet,
+            mint: self.bank.mint,
+            bank: self.bank.bank,
+            bank_token_account: self.bank.treasury,
+            oracle: self.bank.oracle,
+            secondary_oracle_1: self.bank.secondary_oracles[0],
+            secondary_oracle_2: self.bank.secondary_oracles[1],
+            user_account: user_address(&self.wallet),
+            user_token_account: self.user_token_account,
+            isolated_bank: self.isolated_bank,
+            token_program: self.bank.token_program,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::Repay { amount: self.amount },
+         &self.positions,
+      )
+   }
+}
+
+// The liquidator repays `amount` of the debt of `user` (a wallet) in `borrowed` and receives collateral of `collateral`
+pub struct LiquidateBuilder {
+   liquidator: Pubkey,
+   user: Pubkey,
+   collateral: BankAccounts,
+   borrowed: BankAccounts,
+   amount: u64,
+   liquidator_collateral_token_account: Pubkey,
+   liquidator_borrowed_token_account: Pubkey,
+   positions: Vec<AccountMeta>,
+}
+
+impl LiquidateBuilder {
+   pub fn new(liquidator: Pubkey, user: Pubkey, collateral: &BankAccounts, borrowed: &BankAccounts, amount: u64) -> Self {
+      LiquidateBuilder {
+         liquidator,
+         user,
+         collateral: *collateral,
+         borrowed: *borrowed,
+         amount,
+         liquidator_collateral_token_account: associated_token_account(&liquidator, collateral),
+         liquidator_borrowed_token_account: associated_token_account(&liquidator, borrowed),
+         positions: Vec::new(),
+      }
+   }
+
+   pub fn liquidator_token_accounts(mut self, collateral: Pubkey, borrowed: Pubkey) -> Self {
+      self.liquidator_collateral_token_account = collateral;
+      self.liquidator_borrowed_token_account = borrowed;
+      self
+   }
+
+   // The other positions of the liquidated user
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::Liquidate {
+            liquidator: self.liquidator,
+            collateral_mint: self.collateral.mint,
+            borrowed_mint: self.borrowed.mint,
+            collateral_bank: self.collateral.bank,
+            borrowed_bank: self.borrowed.bank,
+            collateral_bank_token_account: self.collateral.treasury,
+            borrowed_bank_token_account: self.borrowed.treasury,
+            collateral_oracle: self.collateral.oracle,
+            collateral_secondary_oracle_1: self.collateral.secondary_oracles[0],
+            collateral_secondary_oracle_2: self.collateral.secondary_oracles[1],
+            borrowed_oracle: self.borrowed.oracle,
+            borrowed_secondary_oracle_1: self.borrowed.secondary_oracles[0],
+            borrowed_secondary_oracle_2: self.borrowed.secondary_oracles[1],
+            user_account: user_address(&self.user),
+            liquidator_collateral_token_account: self.liquidator_collateral_token_account,
+            liquidator_borrowed_token_account: self.liquidator_borrowed_token_account,
+            token_program: self.borrowed.token_program,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::Liquidate { amount: self.amount },
+         &self.positions,
+      )
+   }
+}
+
+/*
+   The user of `wallet` repays `amount` of its own debt in `borrowed` with the same value of collateral of `collateral`,
+   minus the penalty, swapped with `swap_program`. `swap_accounts` are the accounts the adapter needs,
+   like for RepayWithCollateralBuilder.
+*/
+pub struct SelfLiquidateBuilder {
+   wallet: Pubkey,
+   collateral: BankAccounts,
+   borrowed: BankAccounts,
+   amount: u64,
+   min_amount_out: u64,
+   swap_program: Pubkey,
+   user_collateral_token_account: Pubkey,
+   user_borrowed_token_account: Pubkey,
+   positions: Vec<AccountMeta>,
+   swap_accounts: Vec<AccountMeta>,
+}
+
+impl SelfLiquidateBuilder {
+   pub fn new(
+      wallet: Pubkey,
+      collateral: &BankAccounts,
+      borrowed: &BankAccounts,
+      amount: u64,
+      min_amount_out: u64,
+      swap_program: Pubkey,
+   ) -> Self {
+      SelfLiquidateBuilder {
+         wallet,
+         collateral: *collateral,
+         borrowed: *borrowed,
+         amount,
+         min_amount_out,
+         swap_program,
+         user_collateral_token_account: associated_token_account(&wallet, collateral),
+         user_borrowed_token_account: associated_token_account(&wallet, borrowed),
+         positions: Vec::new(),
+         swap_accounts: Vec::new(),
+      }
+   }
+
+   pub fn user_token_accounts(mut self, collateral: Pubkey, borrowed: Pubkey) -> Self {
+      self.user_collateral_token_account = collateral;
+      self.user_borrowed_token_account = borrowed;
+      self
+   }
+
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn swap_accounts(mut self, swap_accounts: Vec<AccountMeta>) -> Self {
+      self.swap_accounts = swap_accounts;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      let mut remaining = self.positions.clone();
+      remaining.extend_from_slice(&self.swap_accounts);
+      instruction(
+         lending::accounts::SelfLiquidate {
+            signer: self.wallet,
+            collateral_mint: self.collateral.mint,
+            borrowed_mint: self.borrowed.mint,
+            collateral_bank: self.collateral.bank,
+            borrowed_bank: self.borrowed.bank,
+            collateral_bank_token_account: self.collateral.treasury,
+            borrowed_bank_token_account: self.borrowed.treasury,
+            collateral_oracle: self.collateral.oracle,
+            collateral_secondary_oracle_1: self.collateral.secondary_oracles[0],
+            collateral_secondary_oracle_2: self.collateral.secondary_oracles[1],
+            borrowed_oracle: self.borrowed.oracle,
+            borrowed_secondary_oracle_1: self.borrowed.secondary_oracles[0],
+            borrowed_secondary_oracle_2: self.borrowed.secondary_oracles[1],
+            user_account: user_address(&self.wallet),
+            user_collateral_token_account: self.user_collateral_token_account,
+            user_borrowed_token_account: self.user_borrowed_token_account,
+            swap_program: self.swap_program,
+            token_program: self.borrowed.token_program,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SelfLiquidate { amount: self.amount, min_amount_out: self.min_amount_out },
+         &remaining,
+      )
+   }
+}
+
+/*
+   Withdraws collateral, swaps it with `swap_program` (see lending::swap for the interface of an adapter)
+   and repays the debt with what it returns. `swap_accounts` are the accounts the adapter needs after
+   the three the program passes itself (the wallet and the two token accounts of the user).
+*/
+pub struct RepayWithCollateralBuilder {
+   wallet: Pubkey,
+   collateral: BankAccounts,
+   borrowed: BankAccounts,
+   collateral_amount: u64,
+   min_amount_out: u64,
+   swap_program: Pubkey,
+   user_collateral_token_account: Pubkey,
+   user_borrowed_token_account: Pubkey,
+   positions: Vec<AccountMeta>,
+   swap_accounts: Vec<AccountMeta>,
+}
+
+impl RepayWithCollateralBuilder {
+   pub fn new(
+      wallet: Pubkey,
+      collateral: &BankAccounts,
+      borrowed: &BankAccounts,
+      collateral_amount: u64,
+      min_amount_out: u64,
+      swap_program: Pubkey,
+   ) -> Self {
+      RepayWithCollateralBuilder {
+         wallet,
+         collateral: *collateral,
+         borrowed: *borrowed,
+         collateral_amount,
+         min_amount_out,
+         swap_program,
+         user_collateral_token_account: associated_token_account(&wallet, collateral),
+         user_borrowed_token_account: associated_token_account(&wallet, borrowed),
+         positions: Vec::new(),
+         swap_accounts: Vec::new(),
+      }
+   }
+
+   pub fn user_token_accounts(mut self, collateral: Pubkey, borrowed: Pubkey) -> Self {
+      self.user_collateral_token_account = collateral;
+      self.user_borrowed_token_account = borrowed;
+      self
+   }
+
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn swap_accounts(mut self, swap_accounts: Vec<AccountMeta>) -> Self {
+      self.swap_accounts = swap_accounts;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      // The program expects the position pairs first and the accounts of the adapter after them
+      let mut remaining = self.positions.clone();
+      remaining.extend_from_slice(&self.swap_accounts);
+      instruction(
+         lending::accounts::RepayWithCollateral {
+            signer: self.wallet,
+            collateral_mint: self.collateral.mint,
+            borrowed_mint: self.borrowed.mint,
+            collateral_bank: self.collateral.bank,
+            borrowed_bank: self.borrowed.bank,
+            collateral_bank_token_account: self.collateral.treasury,
+            borrowed_bank_token_account: self.borrowed.treasury,
+            collateral_oracle: self.collateral.oracle,
+            collateral_secondary_oracle_1: self.collateral.secondary_oracles[0],
+            collateral_secondary_oracle_2: self.collateral.secondary_oracles[1],
+            borrowed_oracle: self.borrowed.oracle,
+            borrowed_secondary_oracle_1: self.borrowed.secondary_oracles[0],
+            borrowed_secondary_oracle_2: self.borrowed.secondary_oracles[1],
+            user_account: user_address(&self.wallet),
+            user_collateral_token_account: self.user_collateral_token_account,
+            user_borrowed_token_account: self.user_borrowed_token_account,
+            swap_program: self.swap_program,
+            token_program: self.borrowed.token_program,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::RepayWithCollateral { collateral_amount: self.collateral_amount, min_amount_out: self.min_amount_out },
+         &remaining,
+      )
+   }
+}
+
+/*
+   Borrows until the LTV of the wallet reaches `target_ltv` (at most the max LTV of the collateral bank), swaps the
+   borrowed tokens into the collateral mint with `swap_program` and deposits them, `min_amount_out` is in collateral tokens.
+*/
+pub struct LeverageBuilder {
+   wallet: Pubkey,
+   collateral: BankAccounts,
+   borrowed: BankAccounts,
+   target_ltv: u64,
+   min_amount_out: u64,
+   swap_program: Pubkey,
+   user_collateral_token_account: Pubkey,
+   user_borrowed_token_account: Pubkey,
+   positions: Vec<AccountMeta>,
+   swap_accounts: Vec<AccountMeta>,
+}
+
+impl LeverageBuilder {
+   pub fn new(
+      wallet: Pubkey,
+      collateral: &BankAccounts,
+      borrowed: &BankAccounts,
+      target_ltv: u64,
+      min_amount_out: u64,
+      swap_program: Pubkey,
+   ) -> Self {
+      LeverageBuilder {
+         wallet,
+         collateral: *collateral,
+         borrowed: *borrowed,
+         target_ltv,
+         min_amount_out,
+         swap_program,
+         user_collateral_token_account: associated_token_account(&wallet, collateral),
+         user_borrowed_token_account: associated_token_account(&wallet, borrowed),
+         positions: Vec::new(),
+         swap_accounts: Vec::new(),
+      }
+   }
+
+   pub fn user_token_accounts(mut self, collateral: Pubkey, borrowed: Pubkey) -> Self {
+      self.user_collateral_token_account = collateral;
+      self.user_borrowed_token_account = borrowed;
+      self
+   }
+
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn swap_accounts(mut self, swap_accounts: Vec<AccountMeta>) -> Self {
+      self.swap_accounts = swap_accounts;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      let mut remaining = self.positions.clone();
+      remaining.extend_from_slice(&self.swap_accounts);
+      instruction(
+         lending::accounts::Leverage {
+            signer: self.wallet,
+            collateral_mint: self.collateral.mint,
+            borrowed_mint: self.borrowed.mint,
+            collateral_bank: self.collateral.bank,
+            borrowed_bank: self.borrowed.bank,
+            collateral_bank_token_account: self.collateral.treasury,
+            borrowed_bank_token_account: self.borrowed.treasury,
+            collateral_oracle: self.collateral.oracle,
+            collateral_secondary_oracle_1: self.collateral.secondary_oracles[0],
+            collateral_secondary_oracle_2: self.collateral.secondary_oracles[1],
+            borrowed_oracle: self.borrowed.oracle,
+            borrowed_secondary_oracle_1: self.borrowed.secondary_oracles[0],
+            borrowed_secondary_oracle_2: self.borrowed.secondary_oracles[1],
+            user_account: user_address(&self.wallet),
+            user_collateral_token_account: self.user_collateral_token_account,
+            user_borrowed_token_account: self.user_borrowed_token_account,
+            swap_program: self.swap_program,
+            token_program: self.borrowed.token_program,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::Leverage { target_ltv: self.target_ltv, min_amount_out: self.min_amount_out },
+         &remaining,
+      )
+   }
+}
+
+// The inverse of leverage, with the accounts of repay_with_collateral. A target of 0 closes the loop
+pub struct DeleverageBuilder {
+   wallet: Pubkey,
+   collateral: BankAccounts,
+   borrowed: BankAccounts,
+   target_ltv: u64,
+   min_amount_out: u64,
+   swap_program: Pubkey,
+   user_collateral_token_account: Pubkey,
+   user_borrowed_token_account: Pubkey,
+   positions: Vec<AccountMeta>,
+   swap_accounts: Vec<AccountMeta>,
+}
+
+impl DeleverageBuilder {
+   pub fn new(
+      wallet: Pubkey,
+      collateral: &BankAccounts,
+      borrowed: &BankAccounts,
+      target_ltv: u64,
+      min_amount_out: u64,
+      swap_program: Pubkey,
+   ) -> Self {
+      DeleverageBuilder {
+         wallet,
+         collateral: *collateral,
+         borrowed: *borrowed,
+         target_ltv,
+         min_amount_out,
+         swap_program,
+         user_collateral_token_account: associated_token_account(&wallet, collateral),
+         user_borrowed_token_account: associated_token_account(&wallet, borrowed),
+         positions: Vec::new(),
+         swap_accounts: Vec::new(),
+      }
+   }
+
+   pub fn user_token_accounts(mut self, collateral: Pubkey, borrowed: Pubkey) -> Self {
+      self.user_collateral_token_account = collateral;
+      self.user_borrowed_token_account = borrowed;
+      self
+   }
+
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn swap_accounts(mut self, swap_accounts: Vec<AccountMeta>) -> Self {
+      self.swap_accounts = swap_accounts;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      let mut remaining = self.positions.clone();
+      remaining.extend_from_slice(&self.swap_accounts);
+      instruction(
+         lending::accounts::RepayWithCollateral {
+            signer: self.wallet,
+            collateral_mint: self.collateral.mint,
+            borrowed_mint: self.borrowed.mint,
+            collateral_bank: self.collateral.bank,
+            borrowed_bank: self.borrowed.bank,
+            collateral_bank_token_account: self.collateral.treasury,
+            borrowed_bank_token_account: self.borrowed.treasury,
+            collateral_oracle: self.collateral.oracle,
+            collateral_secondary_oracle_1: self.collateral.secondary_oracles[0],
+            collateral_secondary_oracle_2: self.collateral.secondary_oracles[1],
+            borrowed_oracle: self.borrowed.oracle,
+            borrowed_secondary_oracle_1: self.borrowed.secondary_oracles[0],
+            borrowed_secondary_oracle_2: self.borrowed.secondary_oracles[1],
+            user_account: user_address(&self.wallet),
+            user_collateral_token_account: self.user_collateral_token_account,
+            user_borrowed_token_account: self.user_borrowed_token_account,
+            swap_program: self.swap_program,
+            token_program: self.borrowed.token_program,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::Deleverage { target_ltv: self.target_ltv, min_amount_out: self.min_amount_out },
+         &remaining,
+      )
+   }
+}
+
+// swap_collateral and swap_debt share their accounts
+fn swap_position_accounts(
+   wallet: Pubkey,
+   from: &BankAccounts,
+   to: &BankAccounts,
+   user_from_token_account: Pubkey,
+   user_to_token_account: Pubkey,
+   swap_program: Pubkey,
+) -> lending::accounts::SwapPosition {
+   lending::accounts::SwapPosition {
+      signer: wallet,
+      from_mint: from.mint,
+      to_mint: to.mint,
+      from_bank: from.bank,
+      to_bank: to.bank,
+      from_bank_token_account: from.treasury,
+      to_bank_token_account: to.treasury,
+      from_oracle: from.oracle,
+      from_secondary_oracle_1: from.secondary_oracles[0],
+      from_secondary_oracle_2: from.secondary_oracles[1],
+      to_oracle: to.oracle,
+      to_secondary_oracle_1: to.secondary_oracles[0],
+      to_secondary_oracle_2: to.secondary_oracles[1],
+      user_account: user_address(&wallet),
+      user_from_token_account,
+      user_to_token_account,
+      swap_program,
+      token_program: to.token_program,
+      event_authority: event_authority(),
+      program: lending::ID,
+   }
+}
+
+// Moves `amount` of collateral from the `from` bank into the `to` bank, swapped with `swap_program`
+pub struct SwapCollateralBuilder {
+   wallet: Pubkey,
+   from: BankAccounts,
+   to: BankAccounts,
+   amount: u64,
+   min_amount_out: u64,
+   swap_program: Pubkey,
+   user_from_token_account: Pubkey,
+   user_to_token_account: Pubkey,
+   positions: Vec<AccountMeta>,
+   swap_accounts: Vec<AccountMeta>,
+}
+
+impl SwapCollateralBuilder {
+   pub fn new(wallet: Pubkey, from: &BankAccounts, to: &BankAccounts, amount: u64, min_amount_out: u64, swap_program: Pubkey) -> Self {
+      SwapCollateralBuilder {
+         wallet,
+         from: *from,
+         to: *to,
+         amount,
+         min_amount_out,
+         swap_program,
+         user_from_token_account: associated_token_account(&wallet, from),
+         user_to_token_account: associated_token_account(&wallet, to),
+         positions: Vec::new(),
+         swap_accounts: Vec::new(),
+      }
+   }
+
+   pub fn user_token_accounts(mut self, from: Pubkey, to: Pubkey) -> Self {
+      self.user_from_token_account = from;
+      self.user_to_token_account = to;
+      self
+   }
+
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn swap_accounts(mut self, swap_accounts: Vec<AccountMeta>) -> Self {
+      self.swap_accounts = swap_accounts;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      let mut remaining = self.positions.clone();
+      remaining.extend_from_slice(&self.swap_accounts);
+      instruction(
+         swap_position_accounts(self.wallet, &self.from, &self.to, self.user_from_token_account, self.user_to_token_account, self.swap_program),
+         lending::instruction::SwapCollateral { amount: self.amount, min_amount_out: self.min_amount_out },
+         &remaining,
+      )
+   }
+}
+
+// Borrows `borrow_amount` from the `to` bank and swaps it with `swap_program` to repay the debt in the `from` bank
+pub struct SwapDebtBuilder {
+   wallet: Pubkey,
+   from: BankAccounts,
+   to: BankAccounts,
+   borrow_amount: u64,
+   min_amount_out: u64,
+   swap_program: Pubkey,
+   user_from_token_account: Pubkey,
+   user_to_token_account: Pubkey,
+   positions: Vec<AccountMeta>,
+   swap_accounts: Vec<AccountMeta>,
+}
+
+impl SwapDebtBuilder {
+   pub fn new(wallet: Pubkey, from: &BankAccounts, to: &BankAccounts, borrow_amount: u64, min_amount_out: u64, swap_program: Pubkey) -> Self {
+      SwapDebtBuilder {
+         wallet,
+         from: *from,
+         to: *to,
+         borrow_amount,
+         min_amount_out,
+         swap_program,
+         user_from_token_account: associated_token_account(&wallet, from),
+         user_to_token_account: associated_token_account(&wallet, to),
+         positions: Vec::new(),
+         swap_accounts: Vec::new(),
+      }
+   }
+
+   pub fn user_token_accounts(mut self, from: Pubkey, to: Pubkey) -> Self {
+      self.user_from_token_account = from;
+      self.user_to_token_account = to;
+      self
+   }
+
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn swap_accounts(mut self, swap_accounts: Vec<AccountMeta>) -> Self {
+      self.swap_accounts = swap_accounts;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      let mut remaining = self.positions.clone();
+      remaining.extend_from_slice(&self.swap_accounts);
+      instruction(
+         swap_position_accounts(self.wallet, &self.from, &self.to, self.user_from_token_account, self.user_to_token_account, self.swap_program),
+         lending::instruction::SwapDebt { borrow_amount: self.borrow_amount, min_amount_out: self.min_amount_out },
+         &remaining,
+      )
+   }
+}
+
+// ---------- insurance ----------
+
+pub struct StakeInsuranceBuilder {
+   staker: Pubkey,
+   bank: BankAccounts,
+   amount: u64,
+   staker_token_account: Pubkey,
+}
+
+impl StakeInsuranceBuilder {
+   pub fn new(staker: Pubkey, bank: &BankAccounts, amount: u64) -> Self {
+      StakeInsuranceBuilder { staker, bank: *bank, amount, staker_token_account: associated_token_account(&staker, bank) }
+   }
+
+   pub fn staker_token_account(mut self, staker_token_account: Pubkey) -> Self {
+      self.staker_token_account = staker_token_account;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::StakeInsurance {
+            staker: self.staker,
+            mint: self.bank.mint,
+            bank: self.bank.bank,
+            insurance_vault: self.bank.insurance_vault,
+            stake: insurance_stake_address(&self.bank.bank, &self.staker),
+            staker_token_account: self.staker_token_account,
+            token_program: self.bank.token_program,
+            system_program: system_program::ID,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::StakeInsurance { amount: self.amount },
+         &[],
+      )
+   }
+}
+
+// Starts the cooldown, after which UnstakeBuilder withdraws the value of the shares
+pub struct RequestUnstakeBuilder {
+   staker: Pubkey,
+   bank: Pubkey,
+   shares: u64,
+}
+
+impl RequestUnstakeBuilder {
+   pub fn new(staker: Pubkey, bank: Pubkey, shares: u64) -> Self {
+      RequestUnstakeBuilder { staker, bank, shares }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::RequestUnstake {
+            staker: self.staker,
+            bank: self.bank,
+            stake: insurance_stake_address(&self.bank, &self.staker),
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::RequestUnstake { shares: self.shares },
+         &[],
+      )
+   }
+}
+
+pub struct UnstakeBuilder {
+   staker: Pubkey,
+   bank: BankAccounts,
+   staker_token_account: Pubkey,
+}
+
+impl UnstakeBuilder {
+   pub fn new(staker: Pubkey, bank: &BankAccounts) -> Self {
+      UnstakeBuilder { staker, bank: *bank, staker_token_account: associated_token_account(&staker, bank) }
+   }
+
+   pub fn staker_token_account(mut self, staker_token_account: Pubkey) -> Self {
+      self.staker_token_account = staker_token_account;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::Unstake {
+            staker: self.staker,
+            mint: self.bank.mint,
+            bank: self.bank.bank,
+            bank_token_account: self.bank.treasury,
+            insurance_vault: self.bank.insurance_vault,
+            stake: insurance_stake_address(&self.bank.bank, &self.staker),
+            staker_token_account: self.staker_token_account,
+            token_program: self.bank.token_program,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::Unstake {},
+         &[],
+      )
+   }
+}
+
+// ---------- cranks ----------
+
+// Needs no signer, the fee payer of the transaction can be anyone
+pub struct RefreshBankBuilder {
+   bank: BankAccounts,
+}
+
+impl RefreshBankBuilder {
+   pub fn new(bank: &BankAccounts) -> Self {
+      RefreshBankBuilder { bank: *bank }
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::RefreshBank {
+            bank: self.bank.bank,
+            oracle: self.bank.oracle,
+            secondary_oracle_1: self.bank.secondary_oracles[0],
+            secondary_oracle_2: self.bank.secondary_oracles[1],
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::RefreshBank {},
+         &[],
+      )
+   }
+}
+
+// Writes off the debt in `bank` of the user of `owner`, which has no collateral left
+pub struct SocializeLossBuilder {
+   bank: BankAccounts,
+   owner: Pubkey,
+   isolated_bank: Option<Pubkey>,
+}
+
+impl SocializeLossBuilder {
+   pub fn new(bank: &BankAccounts, owner: Pubkey) -> Self {
+      SocializeLossBuilder { bank: *bank, owner, isolated_bank: None }
+   }
+
+   // User::isolated_bank of the user, when it isn't the default
+   pub fn isolated_bank(mut self, isolated_bank: Option<Pubkey>) -> Self {
+      self.isolated_bank = isolated_bank;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::SocializeLoss {
+            mint: self.bank.mint,
+            bank: self.bank.bank,
+            bank_token_account: self.bank.treasury,
+            insurance_vault: self.bank.insurance_vault,
+            user_account: user_address(&self.owner),
+            isolated_bank: self.isolated_bank,
+            token_program: self.bank.token_program,
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::SocializeLoss {},
+         &[],
+      )
+   }
+}
+
+// Refreshes the health of the user of `owner`, the positions are all of its positions (nothing is acted on)
+pub struct RefreshUserBuilder {
+   owner: Pubkey,
+   positions: Vec<AccountMeta>,
+}
+
+impl RefreshUserBuilder {
+   pub fn new(owner: Pubkey) -> Self {
+      RefreshUserBuilder { owner, positions: Vec::new() }
+   }
+
+   pub fn positions(mut self, positions: Vec<AccountMeta>) -> Self {
+      self.positions = positions;
+      self
+   }
+
+   pub fn instruction(&self) -> Instruction {
+      instruction(
+         lending::accounts::RefreshUser {
+            user_account: user_address(&self.owner),
+            event_authority: event_authority(),
+            program: lending::ID,
+         },
+         lending::instruction::RefreshUser {},
+         &self.positions,
+      )
+   }
+}
+
+// ---------- devnet faucet ----------
+
+#[cfg(feature = "devnet-faucet")]
+pub use faucet::*;
+
+#[cfg(feature = "devnet-faucet")]
+mod faucet {
+   use super::*;
+   use crate::pda::{faucet_address, faucet_claim_address};
+
+   // Hands the mint authority of `mint` (held by `authority`) over to the faucet
+   pub struct InitFaucetBuilder {
+      authority: Pubkey,
+      mint: Pubkey,
+      token_program: Pubkey,
+      max_amount: u64,
+      cooldown: u64,
+   }
+
+   impl InitFaucetBuilder {
+      pub fn new(authority: Pubkey, mint: Pubkey, max_amount: u64, cooldown: u64) -> Self {
+         InitFaucetBuilder { authority, mint, token_program: anchor_spl::token::spl_token::ID, max_amount, cooldown }
+      }
+
+      pub fn token_program(mut self, token_program: Pubkey) -> Self {
+         self.token_program = token_program;
+         self
+      }
+
+      pub fn instruction(&self) -> Instruction {
+         instruction(
+            lending::accounts::InitFaucet {
+               signer: self.authority,
+               mint: self.mint,
+               faucet: faucet_address(&self.mint),
+               token_program: self.token_program,
+               system_program: system_program::ID,
+            },
+            lending::instruction::InitFaucet { max_amount: self.max_amount, cooldown: self.cooldown },
+            &[],
+         )
+      }
+   }
+
+   pub struct UpdateFaucetBuilder {
+      authority: Pubkey,
+      mint: Pubkey,
+      max_amount: u64,
+      cooldown: u64,
+   }
+
+   impl UpdateFaucetBuilder {
+      pub fn new(authority: Pubkey, mint: Pubkey, max_amount: u64, cooldown: u64) -> Self {
+         UpdateFaucetBuilder { authority, mint, max_amount, cooldown }
+      }
+
+      pub fn instruction(&self) -> Instruction {
+         instruction(
+            lending::accounts::UpdateFaucet { authority: self.authority, faucet: faucet_address(&self.mint) },
+            lending::instruction::UpdateFaucet { max_amount: self.max_amount, cooldown: self.cooldown },
+            &[],
+         )
+      }
+   }
+
+   pub struct MintTokensBuilder {
+      wallet: Pubkey,
+      mint: Pubkey,
+      token_program: Pubkey,
+      user_token_account: Pubkey,
+      quantity: u64,
+   }
+
+   impl MintTokensBuilder {
+      pub fn new(wallet: Pubkey, mint: Pubkey, quantity: u64) -> Self {
+         let token_program = anchor_spl::token::spl_token::ID;
+         MintTokensBuilder {
+            wallet,
+            mint,
+            token_program,
+            user_token_account: get_associated_token_address_with_program_id(&wallet, &mint, &token_program),
+            quantity,
+         }
+      }
+
+      // Also moves the default token account to the associated token account of that program
+      pub fn token_program(mut self, token_program: Pubkey) -> Self {
+         self.token_program = token_program;
+         self.user_token_account = get_associated_token_address_with_program_id(&self.wallet, &self.mint, &token_program);
+         self
+      }
+
+      pub fn user_token_account(mut self, user_token_account: Pubkey) -> Self {
+         self.user_token_account = user_token_account;
+         self
+      }
+
+      pub fn instruction(&self) -> Instruction {
+         instruction(
+            lending::accounts::MintTokens {
+               signer: self.wallet,
+               mint: self.mint,
+               faucet: faucet_address(&self.mint),
+               claim: faucet_claim_address(&self.mint, &self.wallet),
+               user_token_account: self.user_token_account,
+               token_program: self.token_program,
+               system_program: system_program::ID,
+            },
+            lending::instruction::Minter { quantity: self.quantity },
+            &[],
+         )
+      }
+   }
+}
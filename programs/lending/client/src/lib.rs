@@ -0,0 +1,23 @@
+/*
+   Everything an off-chain service needs to talk to the lending program without assembling accounts by hand:
+   pda            -> addresses of banks, treasuries, insurance vaults and users, derived from the seeds the program uses
+   accounts       -> decoding of Bank, User and EModeCategory accounts and oracle prices, and the accounts of a bank
+   instructions   -> one builder per instruction
+   math           -> health, max borrow / withdraw and APYs, computed with the same code as the program
+*/
+pub mod accounts;
+pub mod instructions;
+pub mod math;
+pub mod pda;
+
+pub use accounts::*;
+pub use instructions::*;
+pub use math::*;
+pub use pda::*;
+
+pub use lending;
+pub use lending::error::ErrCode;
+pub use lending::instructions::{
+   BankConfig, EModeConfig, EmaConfig, InsuranceConfig, IsolationConfig, LiquidationModeConfig, OracleConfig, SelfLiquidationConfig,
+};
+pub use lending::state::{Bank, EModeCategory, InsuranceStake, LiquidationMode, Position, User};
@@ -0,0 +1,278 @@
+use anchor_lang::prelude::*;
+use lending::constants::BPS;
+use lending::error::ErrCode;
+use lending::math::{checked_add, checked_sub, mul_div, SECONDS_PER_YEAR};
+use lending::state::{Bank, EModeCategory, User};
+
+pub use lending::health::{compute_health, isolated_collateral, Health, PricedBank};
+
+/*
+   The program accrues interest and reads the oracle before every action, and these helpers do the same
+   with the on-chain code, so a UI or a bot gets exactly the numbers the program will compute.
+   Every function takes banks that are already accrued and priced at the time of the transaction (see priced_bank),
+   and the e-mode category of the user when it has one (see accounts::decode_emode_category).
+*/
+
+// The bank as an instruction sent at `now` would see it
+pub fn accrued(bank: &Bank, now: i64) -> Result<Bank> {
+   let mut bank = bank.clone();
+   bank.accrue_interest(now)?;
+   Ok(bank)
+}
+
+pub fn priced_bank(key: Pubkey, bank: &Bank, oracle_data: &[&[u8]], now: i64) -> Result<PricedBank> {
+   let price = crate::accounts::oracle_price(bank, oracle_data, now)?;
+   Ok(PricedBank::new(key, accrued(bank, now)?, price, now))
+}
+
+// weighted collateral / debt, HEALTH_FACTOR_ONE (1.0) and below can be liquidated, u64::MAX without debt
+pub fn health_factor(user: &User, banks: &[PricedBank], emode: Option<&EModeCategory>) -> Result<u64> {
+   Ok(compute_health(user, banks, emode)?.health_factor())
+}
+
+fn find(banks: &[PricedBank], bank: &Pubkey) -> Result<usize> {
+   banks.iter().position(|priced| priced.key == *bank).ok_or_else(|| error!(ErrCode::MissingPositionAccounts))
+}
+
+/*
+   The bonus a liquidation sent at `now` gets for seizing collateral of `collateral`. A user nobody flagged yet
+   is flagged by the liquidation itself, so a dutch auction starts at its minimum.
+*/
+pub fn liquidation_bonus(user: &User, collateral: &Bank, emode: Option<&EModeCategory>, now: i64) -> Result<u64> {
+   let unhealthy_since = if user.unhealthy_since == 0 { now } else { user.unhealthy_since };
+   collateral.liquidation_bonus(emode, unhealthy_since, now)
+}
+
+// Whether the user can self_liquidate with the collateral of `collateral`, its health factor is below the soft threshold of the bank
+pub fn can_self_liquidate(user: &User, banks: &[PricedBank], emode: Option<&EModeCategory>, collateral: &Pubkey) -> Result<bool> {
+   let soft_liquidation_health = banks[find(banks, collateral)?].bank.soft_liquidation_health;
+   Ok(soft_liquidation_health != 0 && health_factor(user, banks, emode)? < soft_liquidation_health)
+}
+
+// The health of the user after borrowing `amount`, or the error the borrow would fail with
+pub fn borrow_health(user: &User, banks: &[PricedBank], emode: Option<&EModeCategory>, bank: &Pubkey, amount: u64) -> Result<Health> {
+   require!(amount > 0, ErrCode::InvalidAmount);
+   let (mut user, mut banks) = (user.clone(), banks.to_vec());
+   let index = find(&banks, bank)?;
+   let priced = &mut banks[index];
+   user.check_emode_borrow(&priced.bank)?;
+   priced.bank.check_liquidity(amount)?;
+
+   let shares = priced.bank.borrow_amount_to_shares(amount)?;
+   priced.bank.total_borrowed = checked_add(priced.bank.total_borrowed, amount)?;
+   if priced.bank.borrow_cap > 0 && priced.bank.total_borrowed > priced.bank.borrow_cap {
+      return Err(ErrCode::BorrowCapExceeded.into());
+   }
+   priced.bank.total_borrowed_shares = checked_add(priced.bank.total_borrowed_shares, shares)?;
+
+   let position = user.position_or_insert(bank)?;
+   position.borrowed_shares = checked_add(position.borrowed_shares, shares)?;
+
+   let health = compute_health(&user, &banks, emode)?;
+   if let Some(isolated) = isolated_collateral(&user, &banks)? {
+      let (borrowed_bank, isolated) = (banks[index].bank.clone(), find(&banks, &isolated)?);
+      banks[isolated].bank.add_isolated_debt(&borrowed_bank, amount)?;
+   }
+   require!(health.is_within_borrow_limit(), ErrCode::BorrowLimitExceeded);
+   Ok(health)
+}
+
+// The health of the user after withdrawing `amount`, or the error the withdrawal would fail with
+pub fn withdraw_health(user: &User, banks: &[PricedBank], emode: Option<&EModeCategory>, bank: &Pubkey, amount: u64) -> Result<Health> {
+   require!(amount > 0, ErrCode::InvalidAmount);
+   let (mut user, mut banks) = (user.clone(), banks.to_vec());
+   let index = find(&banks, bank)?;
+   let priced = &mut banks[index];
+
+   let deposit_shares = user.position(bank).map_or(0, |position| position.deposit_shares);
+   let deposited = priced.bank.deposit_shares_to_amount(deposit_shares)?;
+   require!(amount <= deposited, ErrCode::InsufficientFunds);
+   priced.bank.check_liquidity(amount)?;
+
+   let shares = if amount == deposited {
+      deposit_shares
+   } else {
+      priced.bank.withdraw_amount_to_shares(amount)?.min(deposit_shares)
+   };
+   let position = user.position_mut(bank).ok_or(ErrCode::InsufficientFunds)?;
+   position.deposit_shares = checked_sub(position.deposit_shares, shares)?;
+   priced.bank.total_deposits = checked_sub(priced.bank.total_deposits, amount)?;
+   priced.bank.total_deposit_shares = checked_sub(priced.bank.total_deposit_shares, shares)?;
+   user.close_empty_positions();
+
+   let health = compute_health(&user, &banks, emode)?;
+   require!(health.is_within_borrow_limit(), ErrCode::WithdrawExceedsBorrowLimit);
+   Ok(health)
+}
+
+// The largest amount in [0, upper] the check accepts, the checks only get harder as the amount grows
+fn max_amount(upper: u64, accepts: impl Fn(u64) -> bool) -> u64 {
+   let (mut low, mut high) = (0, upper);
+   while low < high {
+      let middle = low + (high - low).div_ceil(2);
+      if accepts(middle) {
+         low = middle;
+      } else {
+         high = middle - 1;
+      }
+   }
+   low
+}
+
+// The most the user can borrow from `bank` in one borrow
+pub fn max_borrow(user: &User, banks: &[PricedBank], emode: Option<&EModeCategory>, bank: &Pubkey) -> Result<u64> {
+   let upper = banks[find(banks, bank)?].bank.available_liquidity();
+   Ok(max_amount(upper, |amount| borrow_health(user, banks, emode, bank, amount).is_ok()))
+}
+
+// The most the user can withdraw from `bank` in one withdrawal
+pub fn max_withdraw(user: &User, banks: &[PricedBank], emode: Option<&EModeCategory>, bank: &Pubkey) -> Result<u64> {
+   let priced = &banks[find(banks, bank)?];
+   let deposit_shares = user.position(bank).map_or(0, |position| position.deposit_shares);
+   let upper = priced.bank.deposit_shares_to_amount(deposit_shares)?;
+   Ok(max_amount(upper, |amount| withdraw_health(user, banks, emode, bank, amount).is_ok()))
+}
+
+// total_borrowed / total_deposits in bps
+pub fn utilization_bps(bank: &Bank) -> Result<u64> {
+   if bank.total_deposits == 0 {
+      return Ok(0);
+   }
+   mul_div(bank.total_borrowed, BPS, bank.total_deposits)
+}
+
+// What the debt grows by in one year, in bps, with the compounding of accrue_interest
+pub fn borrow_apy_bps(bank: &Bank) -> Result<u64> {
+   let mut one_unit = Bank { total_borrowed: BPS, total_borrowed_shares: BPS, ..bank.clone() };
+   one_unit.accrue_interest(bank.last_updated + SECONDS_PER_YEAR as i64)
+}
+
+// What the deposits earn in one year at the current utilization: the interest of the borrowers minus the insurance fee, in bps of the deposits
+pub fn supply_apy_bps(bank: &Bank) -> Result<u64> {
+   if bank.total_deposits == 0 {
+      return Ok(0);
+   }
+   let mut in_a_year = bank.clone();
+   in_a_year.accrue_interest(bank.last_updated + SECONDS_PER_YEAR as i64)?;
+   mul_div(in_a_year.total_deposits - bank.total_deposits, BPS, bank.total_deposits)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use lending::constants::STABLE_PRICE;
+   use lending::health::test_utils::{test_bank, test_user};
+   use lending::state::Position;
+
+   #[test]
+   fn max_borrow_is_the_last_amount_within_the_borrow_limit() {
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let banks = [test_bank(sol, 1_000, 0, 150), test_bank(usdc, 100_000, 3_000, 1)];
+      let user = test_user(&[Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 }]);
+
+      // 100 SOL at 150 with a 75% max ltv
+      assert_eq!(max_borrow(&user, &banks, None, &usdc).unwrap(), 11_250);
+      assert!(borrow_health(&user, &banks, None, &usdc, 11_250).is_ok());
+      assert_eq!(borrow_health(&user, &banks, None, &usdc, 11_251).unwrap_err(), ErrCode::BorrowLimitExceeded.into());
+   }
+
+   #[test]
+   fn self_liquidation_starts_below_the_soft_threshold_of_the_collateral() {
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [test_bank(sol, 1_000, 0, 150), test_bank(usdc, 100_000, 11_000, 1)];
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 },
+         Position { bank: usdc, deposit_shares: 0, borrowed_shares: 11_000 },
+      ]);
+
+      // 100 SOL * $150 * 80% / $11_000 = 1.09
+      assert!(!can_self_liquidate(&user, &banks, None, &sol).unwrap());
+      banks[0].bank.soft_liquidation_health = 10_500;
+      assert!(!can_self_liquidate(&user, &banks, None, &sol).unwrap());
+      banks[0].bank.soft_liquidation_health = 11_000;
+      assert!(can_self_liquidate(&user, &banks, None, &sol).unwrap());
+   }
+
+   #[test]
+   fn max_borrow_is_limited_by_liquidity_and_cap() {
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [test_bank(sol, 1_000, 0, 150), test_bank(usdc, 5_000, 3_000, 1)];
+      let user = test_user(&[Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 }]);
+      assert_eq!(max_borrow(&user, &banks, None, &usdc).unwrap(), 2_000);
+      assert_eq!(borrow_health(&user, &banks, None, &usdc, 2_001).unwrap_err(), ErrCode::InsufficientLiquidity.into());
+
+      banks[1].bank.borrow_cap = 3_500;
+      assert_eq!(max_borrow(&user, &banks, None, &usdc).unwrap(), 500);
+   }
+
+   #[test]
+   fn max_borrow_counts_the_debt_with_its_borrow_weight() {
+      let (sol, usdc, bonk) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [test_bank(sol, 1_000, 0, 150), test_bank(usdc, 100_000, 0, 1), test_bank(bonk, 100_000, 0, 1)];
+      banks[2].bank.borrow_weight_bps = 15_000;
+      let user = test_user(&[Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 }]);
+
+      // 11_250 of borrowing power, a dollar of BONK takes 1.5 of it
+      assert_eq!(max_borrow(&user, &banks, None, &usdc).unwrap(), 11_250);
+      assert_eq!(max_borrow(&user, &banks, None, &bonk).unwrap(), 7_500);
+   }
+
+   #[test]
+   fn max_withdraw_keeps_the_debt_covered() {
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let banks = [test_bank(sol, 1_000, 0, 150), test_bank(usdc, 100_000, 7_500, 1)];
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 },
+         Position { bank: usdc, deposit_shares: 0, borrowed_shares: 7_500 },
+      ]);
+
+      // 7_500 of debt needs 10_000 of collateral at 75%, so 66.67 SOL stay
+      assert_eq!(max_withdraw(&user, &banks, None, &sol).unwrap(), 33);
+      assert_eq!(withdraw_health(&user, &banks, None, &sol, 34).unwrap_err(), ErrCode::WithdrawExceedsBorrowLimit.into());
+   }
+
+   #[test]
+   fn max_borrow_in_emode_uses_the_category_and_stays_in_it() {
+      let (jitosol, sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [test_bank(jitosol, 1_000, 0, 150), test_bank(sol, 1_000, 0, 150), test_bank(usdc, 100_000, 0, 1)];
+      banks[0].bank.emode_category = 1;
+      banks[1].bank.emode_category = 1;
+      let mut user = test_user(&[Position { bank: jitosol, deposit_shares: 100, borrowed_shares: 0 }]);
+      user.emode_category = 1;
+      let category = EModeCategory { id: 1, max_ltv: 9_000, liquidation_threshold: 9_500, ..Default::default() };
+
+      // 90% of 100 jitoSOL, in SOL
+      assert_eq!(max_borrow(&user, &banks, Some(&category), &sol).unwrap(), 90);
+      assert_eq!(borrow_health(&user, &banks, Some(&category), &usdc, 1).unwrap_err(), ErrCode::EModeCategoryMismatch.into());
+      assert_eq!(max_borrow(&user, &banks, Some(&category), &usdc).unwrap(), 0);
+   }
+
+   #[test]
+   fn max_borrow_against_isolated_collateral_stops_at_the_debt_ceiling() {
+      let (bonk, sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [test_bank(bonk, 100_000, 0, 1), test_bank(sol, 1_000, 0, 150), test_bank(usdc, 100_000, 0, 1)];
+      banks[0].bank.isolated = true;
+      banks[0].bank.debt_ceiling = 5_000 * STABLE_PRICE;
+      banks[0].bank.isolated_debt = 4_000 * STABLE_PRICE;
+      banks[2].bank.borrowable_in_isolation = true;
+      let user = test_user(&[Position { bank: bonk, deposit_shares: 10_000, borrowed_shares: 0 }]);
+
+      // 7_500 would be within the borrow limit, but only 1_000 are left under the ceiling
+      assert_eq!(max_borrow(&user, &banks, None, &usdc).unwrap(), 1_000);
+      assert_eq!(borrow_health(&user, &banks, None, &usdc, 1_001).unwrap_err(), ErrCode::DebtCeilingExceeded.into());
+      assert_eq!(borrow_health(&user, &banks, None, &sol, 1).unwrap_err(), ErrCode::NotBorrowableInIsolation.into());
+   }
+
+   #[test]
+   fn apys_follow_the_interest_accrual() {
+      // 5% a year compounded -> exp(0.05) - 1 = 5.127%
+      let bank = test_bank(Pubkey::new_unique(), 2_000_000, 1_000_000, 1).bank;
+      assert_eq!(borrow_apy_bps(&bank).unwrap(), 512);
+      assert_eq!(utilization_bps(&bank).unwrap(), 5_000);
+      // half of the deposits are lent, so they earn half of it
+      assert_eq!(supply_apy_bps(&bank).unwrap(), 256);
+      assert_eq!(supply_apy_bps(&Bank::default()).unwrap(), 0);
+      // a 10% insurance fee keeps 10% of it
+      assert_eq!(supply_apy_bps(&Bank { insurance_fee_bps: 1_000, ..bank }).unwrap(), 230);
+   }
+}
@@ -0,0 +1,54 @@
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+
+// The seeds are the ones of the #[account(seeds = ...)] constraints in the instructions of the program
+
+// [mint], see InitBank
+pub fn bank_address(mint: &Pubkey) -> Pubkey {
+   Pubkey::find_program_address(&[mint.as_ref()], &lending::ID).0
+}
+
+// [b"treasury", mint], the token account of the bank, it is its own authority
+pub fn treasury_address(mint: &Pubkey) -> Pubkey {
+   Pubkey::find_program_address(&[b"treasury", mint.as_ref()], &lending::ID).0
+}
+
+// [b"insurance", mint], the vault of the insurance fund of the bank, it is its own authority
+pub fn insurance_vault_address(mint: &Pubkey) -> Pubkey {
+   Pubkey::find_program_address(&[b"insurance", mint.as_ref()], &lending::ID).0
+}
+
+// [b"insurance_stake", bank, staker], the shares of a staker in the insurance fund of a bank
+pub fn insurance_stake_address(bank: &Pubkey, staker: &Pubkey) -> Pubkey {
+   Pubkey::find_program_address(&[b"insurance_stake", bank.as_ref(), staker.as_ref()], &lending::ID).0
+}
+
+// [wallet], one user account per wallet holds its positions in every bank
+pub fn user_address(wallet: &Pubkey) -> Pubkey {
+   Pubkey::find_program_address(&[wallet.as_ref()], &lending::ID).0
+}
+
+// [b"emode", id], one account per e-mode category, see InitEModeCategory
+pub fn emode_category_address(id: u8) -> Pubkey {
+   Pubkey::find_program_address(&[b"emode", &[id]], &lending::ID).0
+}
+
+// [program id] of the upgradeable loader, holds the upgrade authority of the program that lists banks, see InitBank
+pub fn program_data_address() -> Pubkey {
+   Pubkey::find_program_address(&[lending::ID.as_ref()], &bpf_loader_upgradeable::ID).0
+}
+
+// Signs the self CPI of the instructions that emit events with emit_cpi!
+pub fn event_authority() -> Pubkey {
+   Pubkey::find_program_address(&[b"__event_authority"], &lending::ID).0
+}
+
+#[cfg(feature = "devnet-faucet")]
+pub fn faucet_address(mint: &Pubkey) -> Pubkey {
+   Pubkey::find_program_address(&[b"faucet", mint.as_ref()], &lending::ID).0
+}
+
+#[cfg(feature = "devnet-faucet")]
+pub fn faucet_claim_address(mint: &Pubkey, wallet: &Pubkey) -> Pubkey {
+   Pubkey::find_program_address(&[b"faucet_claim", mint.as_ref(), wallet.as_ref()], &lending::ID).0
+}
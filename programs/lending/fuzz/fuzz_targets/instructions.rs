@@ -0,0 +1,151 @@
+#![no_main]
+
+use anchor_lang::prelude::Pubkey;
+use anchor_lang::solana_program::instruction::Instruction;
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use lending_tests::*;
+
+/*
+   Decodes the fuzzer input into a sequence of user instructions, with any of their accounts
+   replaced by another account of the market, and runs them in the in-process runtime.
+
+   A crash is either a panic of the program (overflow checks are on, so this includes arithmetic
+   overflow), or a broken accounting invariant after a step (see lending_tests::check_accounting).
+   Rejected transactions are fine: they are atomic and leave nothing behind.
+*/
+
+const PRICES: [u64; 3] = [150, 1, 2_000];
+const USERS: usize = 3;
+
+#[derive(Arbitrary, Debug)]
+enum Action {
+   Deposit { user: u8, bank: u8, amount: u64 },
+   Withdraw { user: u8, bank: u8, amount: u64 },
+   Borrow { user: u8, bank: u8, amount: u64 },
+   Repay { user: u8, bank: u8, amount: u64 },
+   Liquidate { liquidator: u8, user: u8, collateral: u8, borrowed: u8, amount: u64 },
+   SocializeLoss { caller: u8, user: u8, bank: u8 },
+   Accrue { seconds: u32 },
+   SetPrice { bank: u8, price: u32 },
+}
+
+// Replaces the account at `slot` of the instruction with account number `account` of the market
+#[derive(Arbitrary, Debug)]
+struct Substitution {
+   slot: u8,
+   account: u8,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Step {
+   action: Action,
+   substitutions: Vec<Substitution>,
+}
+
+struct Market {
+   env: TestEnv,
+   banks: Vec<TestBank>,
+   users: Vec<TestUser>,
+   // Every account an instruction could be pointed at instead of the right one
+   accounts: Vec<Pubkey>,
+}
+
+impl Market {
+   fn new() -> Self {
+      let mut env = TestEnv::new();
+      let banks: Vec<TestBank> = PRICES.iter().map(|price| env.bank().price(*price).interest_rate(20).build()).collect();
+      let users: Vec<TestUser> = (0..USERS).map(|_| env.user()).collect();
+
+      let mut accounts = vec![env.admin, event_authority(), lending::ID, Pubkey::new_unique()];
+      for bank in &banks {
+         accounts.extend([bank.mint, bank.bank, bank.treasury, bank.oracle]);
+      }
+      for user in &users {
+         accounts.extend([user.wallet, user.account]);
+         for bank in &banks {
+            accounts.push(env.token_account(&user.wallet, &bank.mint));
+         }
+      }
+      Market { env, banks, users, accounts }
+   }
+
+   fn user(&self, index: u8) -> TestUser {
+      self.users[index as usize % self.users.len()]
+   }
+
+   fn bank(&self, index: u8) -> TestBank {
+      self.banks[index as usize % self.banks.len()]
+   }
+
+   // Mints what the user pays in, as far as the supply of the mint allows
+   fn fund(&mut self, user: &TestUser, bank: &TestBank, amount: u64) {
+      let amount = amount.min(u64::MAX - self.env.supply(&bank.mint));
+      self.env.fund(user, bank, amount);
+   }
+
+   // The instruction of the action and the wallet that signs it
+   fn instruction(&mut self, action: &Action) -> Option<(Instruction, Pubkey)> {
+      let ix = match *action {
+         Action::Deposit { user, bank, amount } => {
+            let (user, bank) = (self.user(user), self.bank(bank));
+            self.fund(&user, &bank, amount);
+            (self.env.deposit_ix(&user, &bank, amount), user.wallet)
+         }
+         Action::Withdraw { user, bank, amount } => {
+            let (user, bank) = (self.user(user), self.bank(bank));
+            (self.env.withdraw_ix(&user, &bank, amount), user.wallet)
+         }
+         Action::Borrow { user, bank, amount } => {
+            let (user, bank) = (self.user(user), self.bank(bank));
+            (self.env.borrow_ix(&user, &bank, amount), user.wallet)
+         }
+         Action::Repay { user, bank, amount } => {
+            let (user, bank) = (self.user(user), self.bank(bank));
+            self.fund(&user, &bank, amount);
+            (self.env.repay_ix(&user, &bank, amount), user.wallet)
+         }
+         Action::Liquidate { liquidator, user, collateral, borrowed, amount } => {
+            let (liquidator, user) = (self.user(liquidator), self.user(user));
+            let (collateral, borrowed) = (self.bank(collateral), self.bank(borrowed));
+            self.fund(&liquidator, &borrowed, amount);
+            (self.env.liquidate_ix(&liquidator, &user, &collateral, &borrowed, amount), liquidator.wallet)
+         }
+         Action::SocializeLoss { caller, user, bank } => {
+            let (caller, user, bank) = (self.user(caller), self.user(user), self.bank(bank));
+            (self.env.socialize_loss_ix(&bank, &user), caller.wallet)
+         }
+         Action::Accrue { seconds } => {
+            self.env.warp(seconds as i64);
+            return None;
+         }
+         Action::SetPrice { bank, price } => {
+            let bank = self.bank(bank);
+            self.env.set_oracle_price(bank.oracle, price as u64);
+            return None;
+         }
+      };
+      Some(ix)
+   }
+
+   fn run(&mut self, step: &Step) {
+      let Some((mut ix, signer)) = self.instruction(&step.action) else {
+         return;
+      };
+      for substitution in &step.substitutions {
+         let slot = substitution.slot as usize % ix.accounts.len();
+         ix.accounts[slot].pubkey = self.accounts[substitution.account as usize % self.accounts.len()];
+      }
+      let _ = self.env.process(ix, &[signer]);
+   }
+}
+
+fuzz_target!(|steps: Vec<Step>| {
+   let mut market = Market::new();
+   for (index, step) in steps.iter().enumerate() {
+      market.run(step);
+      if let Err(message) = check_accounting(&market.env, &market.banks, &market.users) {
+         panic!("step {index} {step:?}: {message}");
+      }
+   }
+});
@@ -0,0 +1,85 @@
+use std::{path::PathBuf, thread, time::Duration};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use lending_keeper::{bad_debt_instructions, refresh_instructions, RpcSource};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+   commitment_config::CommitmentConfig,
+   signature::{read_keypair_file, Keypair},
+   signer::Signer,
+   transaction::Transaction,
+};
+
+/*
+   Sends refresh_bank for every bank that wasn't touched for --min-age seconds, every --interval seconds,
+   so the totals, rates and cached prices of idle banks stay current, and socialize_loss for the debt of users
+   without collateral. Any keypair can pay for it.
+*/
+#[derive(Parser)]
+#[command(name = "crank", version, about = "Interest and price crank of the lending program")]
+struct Args {
+   /// RPC URL of the cluster
+   #[arg(long, short = 'u', default_value = "http://localhost:8899")]
+   url: String,
+
+   /// Keypair that pays for the transactions
+   #[arg(long, short = 'k')]
+   keypair: PathBuf,
+
+   /// Seconds between two rounds
+   #[arg(long, default_value_t = 60)]
+   interval: u64,
+
+   /// Banks accrued less than this many seconds ago are skipped
+   #[arg(long, default_value_t = 300)]
+   min_age: i64,
+
+   /// Refresh once, then exit
+   #[arg(long)]
+   once: bool,
+}
+
+fn run(source: &RpcSource, payer: &Keypair, min_age: i64) -> Result<()> {
+   let refreshes = refresh_instructions(source, min_age)?;
+   println!("{} bank(s) to refresh", refreshes.len());
+
+   // One transaction per bank, a bank with a stale oracle doesn't hold back the others
+   for (bank, instruction) in refreshes {
+      let blockhash = source.rpc.get_latest_blockhash()?;
+      let transaction = Transaction::new_signed_with_payer(&[instruction], Some(&payer.pubkey()), &[payer], blockhash);
+      match source.rpc.send_and_confirm_transaction(&transaction) {
+         Ok(signature) => println!("{bank}: refreshed {signature}"),
+         Err(err) => eprintln!("{bank}: failed: {err}"),
+      }
+   }
+
+   for (owner, instruction) in bad_debt_instructions(source)? {
+      let blockhash = source.rpc.get_latest_blockhash()?;
+      let transaction = Transaction::new_signed_with_payer(&[instruction], Some(&payer.pubkey()), &[payer], blockhash);
+      match source.rpc.send_and_confirm_transaction(&transaction) {
+         Ok(signature) => println!("{owner}: bad debt socialized {signature}"),
+         Err(err) => eprintln!("{owner}: socializing failed: {err}"),
+      }
+   }
+   Ok(())
+}
+
+fn main() -> Result<()> {
+   let args = Args::parse();
+   let payer = read_keypair_file(&args.keypair).map_err(|err| anyhow!("reading keypair {}: {err}", args.keypair.display()))?;
+   let source = RpcSource::new(RpcClient::new_with_commitment(args.url, CommitmentConfig::confirmed()));
+
+   loop {
+      if let Err(err) = run(&source, &payer, args.min_age) {
+         if args.once {
+            return Err(err);
+         }
+         eprintln!("round failed: {err:#}");
+      }
+      if args.once {
+         return Ok(());
+      }
+      thread::sleep(Duration::from_secs(args.interval));
+   }
+}
@@ -0,0 +1,100 @@
+use std::{path::PathBuf, thread, time::Duration};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use lending_keeper::{Keeper, RpcSource};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+   commitment_config::CommitmentConfig,
+   signature::{read_keypair_file, Keypair},
+   signer::Signer,
+   transaction::Transaction,
+};
+
+/*
+   Scans the market every --interval seconds and sends the liquidations it finds, the most profitable first.
+   Repayments come from the token accounts of the keypair, so it has to hold the borrowed mints it should repay.
+*/
+#[derive(Parser)]
+#[command(name = "liquidator", version, about = "Liquidation keeper of the lending program")]
+struct Args {
+   /// RPC URL of the cluster
+   #[arg(long, short = 'u', default_value = "http://localhost:8899")]
+   url: String,
+
+   /// Keypair of the liquidator, it signs and pays for the transactions
+   #[arg(long, short = 'k')]
+   keypair: PathBuf,
+
+   /// Seconds between two scans
+   #[arg(long, default_value_t = 10)]
+   interval: u64,
+
+   /// Minimum profit of a liquidation, in the quote currency with 8 decimals like the health values
+   #[arg(long, default_value_t = 0)]
+   min_profit: u128,
+
+   /// Scan and liquidate once, then exit
+   #[arg(long)]
+   once: bool,
+}
+
+fn run(keeper: &Keeper<RpcSource>, payer: &Keypair) -> Result<()> {
+   let rpc = &keeper.source.rpc;
+   // Starts the dutch auctions, a liquidation that doesn't pay yet may pay in a later scan
+   for (owner, instruction) in keeper.unflagged()? {
+      let blockhash = rpc.get_latest_blockhash()?;
+      let transaction = Transaction::new_signed_with_payer(&[instruction], Some(&payer.pubkey()), &[payer], blockhash);
+      match rpc.send_and_confirm_transaction(&transaction) {
+         Ok(signature) => println!("{owner}: flagged {signature}"),
+         Err(err) => eprintln!("{owner}: flagging failed: {err}"),
+      }
+   }
+
+   let plans = keeper.scan()?;
+   println!("{} liquidatable user(s)", plans.len());
+
+   for plan in plans {
+      let liquidation = &plan.liquidation;
+      println!(
+         "{}: health factor {}, repay {} of {} for {} of {} (profit {})",
+         liquidation.owner,
+         liquidation.health_factor,
+         liquidation.repay_amount,
+         liquidation.borrowed_bank,
+         liquidation.seized_amount,
+         liquidation.collateral_bank,
+         liquidation.profit,
+      );
+
+      let blockhash = rpc.get_latest_blockhash()?;
+      let transaction = Transaction::new_signed_with_payer(&plan.instructions, Some(&payer.pubkey()), &[payer], blockhash);
+      // Someone else may have been faster, a failed liquidation doesn't stop the others
+      match rpc.send_and_confirm_transaction(&transaction) {
+         Ok(signature) => println!("  liquidated: {signature}"),
+         Err(err) => eprintln!("  failed: {err}"),
+      }
+   }
+   Ok(())
+}
+
+fn main() -> Result<()> {
+   let args = Args::parse();
+   let payer = read_keypair_file(&args.keypair).map_err(|err| anyhow!("reading keypair {}: {err}", args.keypair.display()))?;
+
+   let rpc = RpcClient::new_with_commitment(args.url, CommitmentConfig::confirmed());
+   let keeper = Keeper::new(RpcSource::new(rpc), payer.pubkey()).min_profit(args.min_profit);
+
+   loop {
+      if let Err(err) = run(&keeper, &payer) {
+         if args.once {
+            return Err(err);
+         }
+         eprintln!("scan failed: {err:#}");
+      }
+      if args.once {
+         return Ok(());
+      }
+      thread::sleep(Duration::from_secs(args.interval));
+   }
+}
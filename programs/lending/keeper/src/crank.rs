@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use anchor_lang::{prelude::Pubkey, solana_program::instruction::Instruction};
+use anyhow::Result;
+use lending_client::{BankAccounts, RefreshBankBuilder, SocializeLossBuilder};
+
+use crate::source::AccountSource;
+
+/*
+   One refresh_bank per bank whose interest was last accrued at least `min_age` seconds ago, the oldest first.
+   Banks that are used often never need the crank, so a min_age of a few minutes keeps the fees low.
+*/
+pub fn refresh_instructions(source: &impl AccountSource, min_age: i64) -> Result<Vec<(Pubkey, Instruction)>> {
+   let now = source.now()?;
+   let mut banks: Vec<_> = source.banks()?.into_iter().filter(|(_, bank)| now - bank.last_updated >= min_age).collect();
+   banks.sort_by_key(|(_, bank)| bank.last_updated);
+
+   Ok(banks.into_iter().map(|(key, bank)| (key, RefreshBankBuilder::new(&BankAccounts::from_bank(&bank)).instruction())).collect())
+}
+
+/*
+   One socialize_loss per debt of a user that has no collateral left, there is nothing to liquidate anymore
+   and the debt only inflates the totals of its bank until it is written off.
+*/
+pub fn bad_debt_instructions(source: &impl AccountSource) -> Result<Vec<(Pubkey, Instruction)>> {
+   // The write off moves tokens from the insurance vault, it needs the token program of the mint
+   let banks = source.banks()?;
+   let mints = source.accounts(&banks.iter().map(|(_, bank)| bank.mint_address).collect::<Vec<_>>())?;
+   let accounts: HashMap<Pubkey, BankAccounts> = banks
+      .iter()
+      .zip(mints)
+      .filter_map(|((key, bank), mint)| Some((*key, BankAccounts::from_bank(bank).with_token_program(mint?.owner))))
+      .collect();
+
+   let mut instructions = Vec::new();
+   for (_, user) in source.users()? {
+      if user.positions.iter().any(|position| position.deposit_shares > 0) {
+         continue;
+      }
+      // The debt of a user in isolation is also written off its isolated bank
+      let isolated_bank = (user.isolated_bank != Pubkey::default()).then_some(user.isolated_bank);
+      for position in user.positions.iter().filter(|position| position.borrowed_shares > 0) {
+         if let Some(bank) = accounts.get(&position.bank) {
+            let ix = SocializeLossBuilder::new(bank, user.owner).isolated_bank(isolated_bank).instruction();
+            instructions.push((user.owner, ix));
+         }
+      }
+   }
+   Ok(instructions)
+}
@@ -0,0 +1,224 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+use anchor_lang::{
+   prelude::Pubkey,
+   solana_program::{instruction::Instruction, program_pack::Pack},
+};
+use anchor_spl::{associated_token, token::spl_token};
+use anyhow::Result;
+use lending_client::{
+   compute_health, decode_emode_category, emode_category_address, position_accounts, priced_bank, BankAccounts, EModeCategory,
+   Health, LiquidateBuilder, PricedBank, RefreshUserBuilder, User,
+};
+
+use crate::plan::{best_liquidation, Liquidation};
+use crate::source::{AccountSource, RawAccount};
+
+/*
+   The program has no flash loans of its own. A flash loan provider (another lending market, a flash swap...)
+   can lend the repaid tokens for the length of the transaction: `wrap` receives the instructions of the
+   liquidation and returns them between its borrow and its repayment, usually with a swap of the seized
+   collateral into the borrowed mint before the repayment.
+*/
+pub trait FlashLoan {
+   fn wrap(&self, liquidation: &Liquidation, liquidator_token_account: &Pubkey, instructions: Vec<Instruction>) -> Vec<Instruction>;
+}
+
+// A liquidation and the instructions of its transaction, signed by the liquidator
+#[derive(Clone, Debug)]
+pub struct Plan {
+   pub liquidation: Liquidation,
+   pub instructions: Vec<Instruction>,
+}
+
+pub struct Keeper<S> {
+   pub source: S,
+   pub liquidator: Pubkey,
+   min_profit: u128,
+   flash_loan: Option<Box<dyn FlashLoan>>,
+   token_accounts: HashMap<Pubkey, Pubkey>,
+}
+
+// What one scan knows about a bank
+struct MarketBank {
+   priced: PricedBank,
+   token_program: Pubkey,
+   liquidator_token_account: Pubkey,
+   liquidator_balance: Option<u64>, // None when the liquidator has no token account for the mint
+}
+
+fn token_amount(account: &RawAccount) -> Option<u64> {
+   // Token accounts of both token programs start with the spl_token layout
+   let data = account.data.get(..spl_token::state::Account::LEN)?;
+   spl_token::state::Account::unpack_from_slice(data).ok().map(|account| account.amount)
+}
+
+impl<S: AccountSource> Keeper<S> {
+   pub fn new(source: S, liquidator: Pubkey) -> Self {
+      Keeper { source, liquidator, min_profit: 0, flash_loan: None, token_accounts: HashMap::new() }
+   }
+
+   // Liquidations worth less than this (in amount x price, like the health values) are left to others
+   pub fn min_profit(mut self, min_profit: u128) -> Self {
+      self.min_profit = min_profit;
+      self
+   }
+
+   // Without a flash loan the repayments are limited by what the liquidator holds
+   pub fn flash_loan(mut self, flash_loan: impl FlashLoan + 'static) -> Self {
+      self.flash_loan = Some(Box::new(flash_loan));
+      self
+   }
+
+   // The liquidator token account of a mint, its associated token account otherwise
+   pub fn token_account(mut self, mint: Pubkey, token_account: Pubkey) -> Self {
+      self.token_accounts.insert(mint, token_account);
+      self
+   }
+
+   fn market(&self, now: i64) -> Result<Vec<MarketBank>> {
+      let banks = self.source.banks()?;
+      // Every oracle of every bank in one fetch, the oracles of a bank follow each other
+      let oracles = self.source.accounts(&banks.iter().flat_map(|(_, bank)| bank.oracles()).collect::<Vec<_>>())?;
+      let mut oracles = oracles.into_iter();
+      let mints = self.source.accounts(&banks.iter().map(|(_, bank)| bank.mint_address).collect::<Vec<_>>())?;
+
+      let mut market = Vec::new();
+      for ((key, bank), mint) in banks.into_iter().zip(mints) {
+         let oracles: Option<Vec<_>> = oracles.by_ref().take(bank.oracles().len()).collect();
+         let (Some(oracles), Some(mint)) = (oracles, mint) else { continue };
+         let oracle_data: Vec<&[u8]> = oracles.iter().map(|oracle| &oracle.data[..]).collect();
+         // A stale or invalid price fails the liquidation on-chain too, so users of this bank are skipped
+         let Ok(priced) = priced_bank(key, &bank, &oracle_data, now) else { continue };
+
+         let liquidator_token_account = self.token_accounts.get(&bank.mint_address).copied().unwrap_or_else(|| {
+            associated_token::get_associated_token_address_with_program_id(&self.liquidator, &bank.mint_address, &mint.owner)
+         });
+         market.push(MarketBank { priced, token_program: mint.owner, liquidator_token_account, liquidator_balance: None });
+      }
+
+      let token_accounts: Vec<Pubkey> = market.iter().map(|bank| bank.liquidator_token_account).collect();
+      for (bank, account) in market.iter_mut().zip(self.source.accounts(&token_accounts)?) {
+         bank.liquidator_balance = account.as_ref().and_then(token_amount);
+      }
+      Ok(market)
+   }
+
+   /*
+      Every liquidatable user with the most profitable liquidation for it, the most profitable first.
+      Each plan is computed with the whole balance of the liquidator, sending one changes what the next ones can do,
+      so a failed send is expected now and then and the next scan starts again from the new state.
+   */
+   pub fn scan(&self) -> Result<Vec<Plan>> {
+      let now = self.source.now()?;
+      let market = self.market(now)?;
+      let priced: Vec<PricedBank> = market.iter().map(|bank| bank.priced.clone()).collect();
+      let bank_of = |key: &Pubkey| market.iter().find(|bank| bank.priced.key == *key);
+      let bank_of_mint = |mint: &Pubkey| market.iter().find(|bank| bank.priced.bank.mint_address == *mint);
+
+      let budget = |mint: &Pubkey| match self.flash_loan {
+         Some(_) => None,
+         None => Some(bank_of_mint(mint).and_then(|bank| bank.liquidator_balance).unwrap_or(0)),
+      };
+      // One token program for both mints in the liquidate instruction
+      let allowed = |collateral: &PricedBank, borrowed: &PricedBank| {
+         bank_of(&collateral.key).map(|bank| bank.token_program) == bank_of(&borrowed.key).map(|bank| bank.token_program)
+      };
+
+      let mut plans = Vec::new();
+      for (user, emode, health) in self.liquidatable_users(&market)? {
+         let Some(liquidation) = best_liquidation(&user, &priced, emode.as_ref(), now, health.health_factor(), budget, allowed)? else { continue };
+         if liquidation.profit < self.min_profit {
+            continue;
+         }
+         let instructions = self.instructions(&user, &liquidation, &market)?;
+         plans.push(Plan { liquidation, instructions });
+      }
+      plans.sort_by_key(|plan| std::cmp::Reverse(plan.liquidation.profit));
+      Ok(plans)
+   }
+
+   /*
+      A refresh_user for every liquidatable user nobody flagged yet. Flagging starts the dutch auction of its collateral
+      in banks with that liquidation mode, so the bonus grows until a liquidation of it pays for itself.
+   */
+   pub fn unflagged(&self) -> Result<Vec<(Pubkey, Instruction)>> {
+      let market = self.market(self.source.now()?)?;
+      let oracles_of = |key: &Pubkey| market.iter().find(|bank| bank.priced.key == *key).map(|bank| bank.priced.bank.oracles());
+
+      let mut instructions = Vec::new();
+      for (user, _, _) in self.liquidatable_users(&market)? {
+         if user.unhealthy_since == 0 {
+            let positions = position_accounts(&user, &[], oracles_of)?;
+            instructions.push((user.owner, RefreshUserBuilder::new(user.owner).positions(positions).instruction()));
+         }
+      }
+      Ok(instructions)
+   }
+
+   // The users that can be liquidated with the prices of the market, with their e-mode category and health
+   fn liquidatable_users(&self, market: &[MarketBank]) -> Result<Vec<(User, Option<EModeCategory>, Health)>> {
+      let priced: Vec<PricedBank> = market.iter().map(|bank| bank.priced.clone()).collect();
+      let bank_of = |key: &Pubkey| market.iter().find(|bank| bank.priced.key == *key);
+
+      let mut categories = HashMap::new();
+      let mut users = Vec::new();
+      for (_, user) in self.source.users()? {
+         // The program needs the price of every position of the user
+         if user.positions.iter().any(|position| position.is_active() && bank_of(&position.bank).is_none()) {
+            continue;
+         }
+         // Like a missing bank, a category that can't be read fails on-chain too
+         let emode = match user.emode_category {
+            0 => None,
+            id => match self.emode_category(id, &mut categories)? {
+               Some(category) => Some(category),
+               None => continue,
+            },
+         };
+         let health = compute_health(&user, &priced, emode.as_ref())?;
+         if health.is_liquidatable() {
+            users.push((user, emode, health));
+         }
+      }
+      Ok(users)
+   }
+
+   // An e-mode category, read once per scan. None when its account can't be read
+   fn emode_category(&self, id: u8, categories: &mut HashMap<u8, Option<EModeCategory>>) -> Result<Option<EModeCategory>> {
+      if let Entry::Vacant(entry) = categories.entry(id) {
+         let account = self.source.accounts(&[emode_category_address(id)])?.remove(0);
+         entry.insert(account.and_then(|account| decode_emode_category(&account.data).ok()));
+      }
+      Ok(categories[&id].clone())
+   }
+
+   fn instructions(&self, user: &User, liquidation: &Liquidation, market: &[MarketBank]) -> Result<Vec<Instruction>> {
+      let bank_of = |key: &Pubkey| market.iter().find(|bank| bank.priced.key == *key).expect("banks of a liquidation are in the market");
+      let (collateral, borrowed) = (bank_of(&liquidation.collateral_bank), bank_of(&liquidation.borrowed_bank));
+      let accounts = |bank: &MarketBank| BankAccounts::from_bank(&bank.priced.bank).with_token_program(bank.token_program);
+      let oracles_of = |key: &Pubkey| market.iter().find(|bank| bank.priced.key == *key).map(|bank| bank.priced.bank.oracles());
+
+      let mut instructions = Vec::new();
+      // The first seizure of a mint needs the token account to receive it
+      if collateral.liquidator_balance.is_none() && !self.token_accounts.contains_key(&collateral.priced.bank.mint_address) {
+         instructions.push(associated_token::spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            &self.liquidator,
+            &self.liquidator,
+            &collateral.priced.bank.mint_address,
+            &collateral.token_program,
+         ));
+      }
+      instructions.push(
+         LiquidateBuilder::new(self.liquidator, user.owner, &accounts(collateral), &accounts(borrowed), liquidation.repay_amount)
+            .liquidator_token_accounts(collateral.liquidator_token_account, borrowed.liquidator_token_account)
+            .positions(position_accounts(user, &[collateral.priced.key, borrowed.priced.key], oracles_of)?)
+            .instruction(),
+      );
+
+      Ok(match &self.flash_loan {
+         Some(flash_loan) => flash_loan.wrap(liquidation, &borrowed.liquidator_token_account, instructions),
+         None => instructions,
+      })
+   }
+}
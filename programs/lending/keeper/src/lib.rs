@@ -0,0 +1,22 @@
+/*
+   The keepers of the program. The liquidator reads every bank and user, recomputes the health of each user
+   with the on-chain code, and for the ones that can be liquidated builds the most profitable liquidation.
+   The crank refreshes idle banks so their interest and price stay current, and writes off bad debt.
+   source   -> where the accounts come from, a live RPC node or an in-memory snapshot
+   plan     -> which collateral to seize and how much debt to repay for one user
+   keeper   -> the scan over all users, and the instructions of each liquidation
+   crank    -> the refresh_bank instructions of the banks that need one, and the socialize_loss of bad debts
+*/
+pub mod crank;
+pub mod keeper;
+pub mod plan;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod source;
+
+pub use crank::*;
+pub use keeper::*;
+pub use plan::*;
+#[cfg(feature = "rpc")]
+pub use rpc::*;
+pub use source::*;
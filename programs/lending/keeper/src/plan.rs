@@ -0,0 +1,218 @@
+use anchor_lang::prelude::Pubkey;
+use anyhow::Result;
+use lending_client::{
+   liquidation_bonus,
+   lending::{
+      constants::BPS,
+      math::{decimals_scale, mul_div, mul_div_u128, token_value, value_to_amount},
+   },
+   EModeCategory, PricedBank, User,
+};
+
+// One liquidate instruction: `repay_amount` of the borrowed bank for `seized_amount` of the collateral bank
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Liquidation {
+   pub owner: Pubkey, // wallet of the liquidated user
+   pub collateral_bank: Pubkey,
+   pub borrowed_bank: Pubkey,
+   pub repay_amount: u64,
+   pub seized_amount: u64,
+   pub profit: u128, // value of the seized collateral minus value of the repaid debt, with PRICE_DECIMALS decimals like the health values
+   pub health_factor: u64, // before the liquidation
+}
+
+// Collateral the program gives for `repay_amount` with `liquidation_bonus`, the same computation as process_liquidate
+pub fn seized_amount(
+   collateral: &PricedBank,
+   borrowed: &PricedBank,
+   liquidation_bonus: u64,
+   repay_amount: u64,
+   collateral_amount: u64,
+) -> Result<u64> {
+   if collateral.price == 0 {
+      return Ok(0);
+   }
+   let repaid_value = token_value(repay_amount, borrowed.price, borrowed.bank.decimals)?;
+   let bonus_factor = BPS + liquidation_bonus;
+   let seized_value = mul_div_u128(repaid_value, bonus_factor as u128, BPS as u128)?;
+   Ok(value_to_amount(seized_value, collateral.price, collateral.bank.decimals)?.min(collateral_amount))
+}
+
+/*
+   The best repay / seize pair of a liquidatable user. For every pair of a debt and a collateral:
+   - the repayment is at most liquidation_close_factor of the debt, like the program enforces,
+   - it is lowered so that the seized collateral is neither more than the user has nor more than the collateral
+     treasury holds, past that point the liquidator would pay without receiving the bonus,
+   - and at most `budget(mint)` tokens of the borrowed mint are repaid (None when a flash loan pays).
+   `allowed(collateral, borrowed)` filters pairs the liquidator can't take, the pair with the highest profit wins.
+   `emode` is the e-mode category of the user, its bonus replaces the one of the collateral banks in the category,
+   and the bonus of collateral banks in dutch auction mode is the one of a liquidation sent at `now`.
+*/
+pub fn best_liquidation(
+   user: &User,
+   banks: &[PricedBank],
+   emode: Option<&EModeCategory>,
+   now: i64,
+   health_factor: u64,
+   budget: impl Fn(&Pubkey) -> Option<u64>,
+   allowed: impl Fn(&PricedBank, &PricedBank) -> bool,
+) -> Result<Option<Liquidation>> {
+   let priced = |key: &Pubkey| banks.iter().find(|priced| priced.key == *key);
+   let mut best: Option<Liquidation> = None;
+
+   for debt in user.positions.iter().filter(|position| position.borrowed_shares > 0) {
+      let Some(borrowed) = priced(&debt.bank) else { continue };
+      let borrowed_amount = borrowed.bank.borrowed_shares_to_amount(debt.borrowed_shares)?;
+      let max_repay = mul_div(borrowed_amount, borrowed.bank.liquidation_close_factor, BPS)?;
+
+      for deposit in user.positions.iter().filter(|position| position.deposit_shares > 0 && position.bank != debt.bank) {
+         let Some(collateral) = priced(&deposit.bank) else { continue };
+         if !allowed(collateral, borrowed) || borrowed.price == 0 {
+            continue;
+         }
+         let collateral_amount = collateral.bank.deposit_shares_to_amount(deposit.deposit_shares)?.min(collateral.bank.available_liquidity());
+
+         // Repayment that seizes exactly collateral_amount
+         let bonus = liquidation_bonus(user, &collateral.bank, emode, now)?;
+         let bonus_factor = BPS + bonus;
+         let repay_for_all = mul_div_u128(
+            token_value(collateral_amount, collateral.price, collateral.bank.decimals)?,
+            BPS as u128 * decimals_scale(borrowed.bank.decimals)?,
+            borrowed.price as u128 * bonus_factor as u128,
+         )?;
+         let mut repay_amount = max_repay.min(repay_for_all.min(u64::MAX as u128) as u64);
+         if let Some(budget) = budget(&borrowed.bank.mint_address) {
+            repay_amount = repay_amount.min(budget);
+         }
+         if repay_amount == 0 {
+            continue;
+         }
+
+         let seized_amount = seized_amount(collateral, borrowed, bonus, repay_amount, collateral_amount)?;
+         let seized_value = token_value(seized_amount, collateral.price, collateral.bank.decimals)?;
+         let repaid_value = token_value(repay_amount, borrowed.price, borrowed.bank.decimals)?;
+         if seized_value <= repaid_value {
+            continue;
+         }
+
+         let profit = seized_value - repaid_value;
+         if best.as_ref().is_none_or(|best| profit > best.profit) {
+            best = Some(Liquidation {
+               owner: user.owner,
+               collateral_bank: collateral.key,
+               borrowed_bank: borrowed.key,
+               repay_amount,
+               seized_amount,
+               profit,
+               health_factor,
+            });
+         }
+      }
+   }
+   Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use lending_client::lending::health::test_utils::{test_bank, test_user};
+   use lending_client::{LiquidationMode, Position};
+
+   fn bank_with_bonus(key: Pubkey, deposits: u64, borrowed: u64, price: u64, liquidation_bonus: u64) -> PricedBank {
+      let mut priced = test_bank(key, deposits, borrowed, price);
+      priced.bank.liquidation_bonus = liquidation_bonus;
+      priced
+   }
+
+   #[test]
+   fn best_liquidation_takes_the_collateral_with_the_highest_profit() {
+      let (sol, eth, usdc) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+      // Few SOL with a small bonus, plenty of ETH with a bigger one
+      let banks = [bank_with_bonus(sol, 1_000, 0, 100, 500), bank_with_bonus(eth, 1_000, 0, 2_000, 1_000), bank_with_bonus(usdc, 100_000, 11_000, 1, 0)];
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 50, borrowed_shares: 0 },
+         Position { bank: eth, deposit_shares: 4, borrowed_shares: 0 },
+         Position { bank: usdc, deposit_shares: 0, borrowed_shares: 11_000 },
+      ]);
+
+      let liquidation = best_liquidation(&user, &banks, None, 0, 9_000, |_| None, |_, _| true).unwrap().unwrap();
+      assert_eq!((liquidation.collateral_bank, liquidation.borrowed_bank), (eth, usdc));
+      // Half of the debt, paid with 3 ETH (6_050 of value rounded down to whole tokens)
+      assert_eq!((liquidation.repay_amount, liquidation.seized_amount, liquidation.profit), (5_500, 3, 500));
+
+      // Without the ETH pair, the SOL collateral runs out before half of the debt is repaid
+      let liquidation = best_liquidation(&user, &banks, None, 0, 9_000, |_| None, |collateral, _| collateral.key == sol).unwrap().unwrap();
+      assert_eq!((liquidation.repay_amount, liquidation.seized_amount), (4_761, 49));
+   }
+
+   #[test]
+   fn best_liquidation_uses_the_bonus_of_the_emode_category() {
+      let (jitosol, sol) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [bank_with_bonus(jitosol, 1_000_000, 0, 110, 500), bank_with_bonus(sol, 1_000_000, 100_000, 100, 500)];
+      banks[0].bank.emode_category = 1;
+      let user = test_user(&[
+         Position { bank: jitosol, deposit_shares: 100_000, borrowed_shares: 0 },
+         Position { bank: sol, deposit_shares: 0, borrowed_shares: 100_000 },
+      ]);
+      let category = EModeCategory { id: 1, liquidation_bonus: 100, ..Default::default() };
+
+      let liquidation = best_liquidation(&user, &banks, Some(&category), 0, 9_500, |_| None, |_, _| true).unwrap().unwrap();
+      // Half of the debt (5_000_000 of value) with a 1% bonus instead of 5%
+      assert_eq!((liquidation.repay_amount, liquidation.seized_amount), (50_000, 45_909));
+      let liquidation = best_liquidation(&user, &banks, None, 0, 9_500, |_| None, |_, _| true).unwrap().unwrap();
+      assert_eq!((liquidation.repay_amount, liquidation.seized_amount), (50_000, 47_727));
+   }
+
+   #[test]
+   fn best_liquidation_stays_within_the_budget() {
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let banks = [bank_with_bonus(sol, 1_000, 0, 100, 500), bank_with_bonus(usdc, 100_000, 9_000, 1, 0)];
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 },
+         Position { bank: usdc, deposit_shares: 0, borrowed_shares: 9_000 },
+      ]);
+
+      let liquidation = best_liquidation(&user, &banks, None, 0, 8_888, |_| Some(2_000), |_, _| true).unwrap().unwrap();
+      assert_eq!((liquidation.repay_amount, liquidation.seized_amount), (2_000, 21));
+      assert!(best_liquidation(&user, &banks, None, 0, 8_888, |_| Some(0), |_, _| true).unwrap().is_none());
+   }
+
+   #[test]
+   fn best_liquidation_converts_between_the_decimals_of_the_mints() {
+      const DOLLAR: u64 = 100_000_000;
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [bank_with_bonus(sol, 1_000_000_000_000, 0, 150 * DOLLAR, 500), bank_with_bonus(usdc, 100_000_000_000, 13_000_000_000, DOLLAR, 0)];
+      banks[0].bank.decimals = 9;
+      banks[1].bank.decimals = 6;
+      // 100 SOL against 13_000 USDC
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 100_000_000_000, borrowed_shares: 0 },
+         Position { bank: usdc, deposit_shares: 0, borrowed_shares: 13_000_000_000 },
+      ]);
+
+      // 6_500 USDC repaid for 45.5 SOL, a $325 profit
+      let liquidation = best_liquidation(&user, &banks, None, 0, 9_230, |_| None, |_, _| true).unwrap().unwrap();
+      assert_eq!((liquidation.repay_amount, liquidation.seized_amount), (6_500_000_000, 45_500_000_000));
+      assert_eq!(liquidation.profit, 325 * DOLLAR as u128);
+   }
+
+   #[test]
+   fn best_liquidation_uses_the_bonus_the_dutch_auction_is_at() {
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [bank_with_bonus(sol, 1_000, 0, 100, 1_000), bank_with_bonus(usdc, 100_000, 9_000, 1, 0)];
+      banks[0].bank.liquidation_mode = LiquidationMode::DutchAuction;
+      banks[0].bank.liquidation_auction_duration = 100;
+      let mut user = test_user(&[
+         Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 },
+         Position { bank: usdc, deposit_shares: 0, borrowed_shares: 9_000 },
+      ]);
+
+      // Nobody flagged the user, the liquidation would start the auction without any bonus
+      assert!(best_liquidation(&user, &banks, None, 1_000, 8_888, |_| None, |_, _| true).unwrap().is_none());
+
+      // Half way through the auction the bonus is 5%
+      user.unhealthy_since = 950;
+      let liquidation = best_liquidation(&user, &banks, None, 1_000, 8_888, |_| None, |_, _| true).unwrap().unwrap();
+      assert_eq!((liquidation.repay_amount, liquidation.seized_amount), (4_500, 47));
+   }
+}
@@ -0,0 +1,64 @@
+use anchor_lang::{prelude::Pubkey, AccountDeserialize, Discriminator};
+use anyhow::{anyhow, Result};
+use lending_client::{lending, Bank, User};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+   rpc_client::RpcClient,
+   rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+   rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{clock::Clock, sysvar};
+
+use crate::source::{AccountSource, RawAccount};
+
+// getMultipleAccounts takes at most 100 keys
+const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+
+pub struct RpcSource {
+   pub rpc: RpcClient,
+}
+
+impl RpcSource {
+   pub fn new(rpc: RpcClient) -> Self {
+      RpcSource { rpc }
+   }
+
+   // Every account of type T of the program, found by its discriminator
+   fn program_accounts<T: AccountDeserialize + Discriminator>(&self) -> Result<Vec<(Pubkey, T)>> {
+      let config = RpcProgramAccountsConfig {
+         filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, &T::DISCRIMINATOR))]),
+         account_config: RpcAccountInfoConfig { encoding: Some(UiAccountEncoding::Base64), ..Default::default() },
+         ..Default::default()
+      };
+      let accounts = self.rpc.get_program_accounts_with_config(&lending::ID, config)?;
+      accounts
+         .into_iter()
+         .map(|(key, account)| Ok((key, T::try_deserialize(&mut &account.data[..])?)))
+         .collect()
+   }
+}
+
+impl AccountSource for RpcSource {
+   fn banks(&self) -> Result<Vec<(Pubkey, Bank)>> {
+      self.program_accounts()
+   }
+
+   fn users(&self) -> Result<Vec<(Pubkey, User)>> {
+      self.program_accounts()
+   }
+
+   fn accounts(&self, keys: &[Pubkey]) -> Result<Vec<Option<RawAccount>>> {
+      let mut accounts = Vec::with_capacity(keys.len());
+      for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+         let fetched = self.rpc.get_multiple_accounts(chunk)?;
+         accounts.extend(fetched.into_iter().map(|account| account.map(|account| RawAccount { owner: account.owner, data: account.data })));
+      }
+      Ok(accounts)
+   }
+
+   fn now(&self) -> Result<i64> {
+      let account = self.rpc.get_account(&sysvar::clock::ID)?;
+      let clock: Clock = solana_sdk::account::from_account(&account).ok_or_else(|| anyhow!("invalid clock sysvar"))?;
+      Ok(clock.unix_timestamp)
+   }
+}
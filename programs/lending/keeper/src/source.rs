@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use anchor_lang::{prelude::Pubkey, Discriminator};
+use anyhow::Result;
+use lending_client::{decode_bank, decode_user, lending, Bank, User};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RawAccount {
+   pub owner: Pubkey,
+   pub data: Vec<u8>,
+}
+
+/*
+   Everything the keeper reads. The binary reads a live node (RpcSource), tests give it a snapshot of
+   the accounts of their runtime, so the same scan runs in both.
+*/
+pub trait AccountSource {
+   fn banks(&self) -> Result<Vec<(Pubkey, Bank)>>;
+
+   fn users(&self) -> Result<Vec<(Pubkey, User)>>;
+
+   // Oracles, mints and token accounts of the liquidator, None for the ones that don't exist
+   fn accounts(&self, keys: &[Pubkey]) -> Result<Vec<Option<RawAccount>>>;
+
+   // unix_timestamp of the Clock sysvar, interest and oracle staleness are computed at this time
+   fn now(&self) -> Result<i64>;
+}
+
+// Accounts at one point in time, held in memory
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+   pub accounts: HashMap<Pubkey, RawAccount>,
+   pub now: i64,
+}
+
+impl Snapshot {
+   pub fn new(now: i64) -> Self {
+      Snapshot { accounts: HashMap::new(), now }
+   }
+
+   pub fn insert(&mut self, key: Pubkey, owner: Pubkey, data: Vec<u8>) {
+      self.accounts.insert(key, RawAccount { owner, data });
+   }
+
+   fn program_accounts<T: Discriminator>(&self) -> impl Iterator<Item = (&Pubkey, &RawAccount)> {
+      self.accounts
+         .iter()
+         .filter(|(_, account)| account.owner == lending::ID && account.data.starts_with(&T::DISCRIMINATOR))
+   }
+}
+
+impl AccountSource for Snapshot {
+   fn banks(&self) -> Result<Vec<(Pubkey, Bank)>> {
+      self.program_accounts::<Bank>().map(|(key, account)| Ok((*key, decode_bank(&account.data)?))).collect()
+   }
+
+   fn users(&self) -> Result<Vec<(Pubkey, User)>> {
+      self.program_accounts::<User>().map(|(key, account)| Ok((*key, decode_user(&account.data)?))).collect()
+   }
+
+   fn accounts(&self, keys: &[Pubkey]) -> Result<Vec<Option<RawAccount>>> {
+      Ok(keys.iter().map(|key| self.accounts.get(key).cloned()).collect())
+   }
+
+   fn now(&self) -> Result<i64> {
+      Ok(self.now)
+   }
+}
@@ -0,0 +1,40 @@
+// Every ratio stored on a Bank (max_ltv, liquidation_threshold, liquidation_bonus, liquidation_close_factor, borrow_weight_bps) is expressed in basis points
+pub const BPS: u64 = 10_000;
+
+// A health factor of exactly 1.0, using the same basis point scale as the ratios above
+pub const HEALTH_FACTOR_ONE: u64 = BPS;
+
+// Oracle prices are normalised to this many decimals before we value any position with them
+pub const PRICE_DECIMALS: i32 = 8;
+
+// A price of 1 with PRICE_DECIMALS decimals, what the debt ceiling of isolated banks counts each borrowed stable token at
+pub const STABLE_PRICE: u64 = 100_000_000;
+
+// Fixed point scale of the deposit / borrow indices (value of one share) reported in events
+pub const INDEX_ONE: u128 = 1_000_000_000_000_000_000;
+
+// How many different banks a single user account can have a position in
+pub const MAX_POSITIONS: usize = 8;
+
+// Defaults applied by init_bank, the authority can change them later with update_bank_config
+pub const DEFAULT_LIQUIDATION_BONUS: u64 = 500; // 5%
+pub const DEFAULT_LIQUIDATION_CLOSE_FACTOR: u64 = 5_000; // 50%
+pub const DEFAULT_ORACLE_MAX_AGE: u64 = 60; // seconds
+pub const DEFAULT_BORROW_WEIGHT: u64 = BPS; // 1x, the debt counts at its value
+
+// Highest yearly interest_rate (in percent) of a bank. Interest accrues with exp(rate * time), at 500% a year a bank
+// can stay untouched for more than a year before the accrual overflows and every instruction of the bank fails
+pub const MAX_INTEREST_RATE: u64 = 500;
+
+// Highest liquidation_bonus of a bank or an e-mode category
+pub const MAX_LIQUIDATION_BONUS: u64 = 2_500; // 25%
+
+// Longest smoothing window of the moving average of a bank price, a longer one would hide real moves for too long
+pub const MAX_EMA_WINDOW: u64 = 24 * 60 * 60; // seconds
+
+// Widest Pyth confidence interval, as a share of the price, a bank is priced with: beyond it the publishers
+// disagree too much for the price to mean anything and the source is left out like a stale one
+pub const MAX_PRICE_CONFIDENCE_BPS: u64 = 200; // 2%
+
+// Most of ema_window one update of the moving average counts for, however long ago the last one was (see Bank::ema_at)
+pub const EMA_MAX_STEP_BPS: u64 = 1_000; // 10%
@@ -1,14 +1,308 @@
 use anchor_lang::prelude::*;
+use anchor_lang::error::ERROR_CODE_OFFSET;
+use anchor_lang::solana_program::program_error::ProgramError;
 
+/*
+   Every rejection of the program has its own code. The codes are stable, new errors are appended to their range
+   and existing numbers are never reused. The numbers below are added to Anchor's offset, so on-chain they are 6000 + n:
+
+   6000 - 6099   math
+   6100 - 6199   invalid instruction arguments
+   6200 - 6299   accounts and authorization
+   6300 - 6399   oracles
+   6400 - 6499   health of the user
+   6500 - 6599   bank state and configuration
+   6600 - 6699   devnet faucet
+*/
 #[error_code]
+#[derive(PartialEq, Eq)]
 pub enum ErrCode {
-   
-   #[msg("InsufficientFunds.into()")]
-   InsufficientFunds,
 
-   #[msg("InsuficientSol.into()")]
-   InsuficientSol,
+   // ---------- math ----------
+   #[msg("Arithmetic overflow")]
+   MathOverflow = 0,
+
+   #[msg("Division by zero")]
+   DivisionByZero = 1,
+
+   // ---------- invalid instruction arguments ----------
+   #[msg("Amount must be greater than zero")]
+   InvalidAmount = 100,
+
+   #[msg("Amount is greater than what the user has deposited")]
+   InsufficientFunds = 101,
+
+   #[msg("Repay amount is greater than the borrowed amount")]
+   OverRepay = 102,
+
+   #[msg("Liquidation amount is greater than the close factor allows")]
+   OverLiquidation = 103,
+
+   #[msg("The amount received is lower than the minimum requested")]
+   SlippageExceeded = 104,
+
+   #[msg("The target LTV is above the max LTV of the collateral bank or on the wrong side of the current LTV")]
+   InvalidTargetLtv = 105,
+
+   #[msg("There is no request to unstake from the insurance fund")]
+   NoUnstakeRequested = 106,
+
+   // ---------- accounts and authorization ----------
+   #[msg("Signer is not allowed to perform this action")]
+   Unauthorized = 200,
+
+   #[msg("Mint does not match the mint of the bank")]
+   MintMismatch = 201,
+
+   #[msg("A remaining account does not match the bank of the user's position")]
+   InvalidPositionAccount = 202,
+
+   #[msg("The bank and oracles of every position of the user must be passed in the remaining accounts")]
+   MissingPositionAccounts = 203,
+
+   #[msg("The user already has a position in the maximum number of banks")]
+   PositionLimitReached = 204,
+
+   #[msg("Collateral and borrowed banks of a liquidation must be different")]
+   SameLiquidationBank = 205,
+
+   #[msg("Collateral and debt of a swap must be in different banks")]
+   SameSwapBank = 206,
+
+   #[msg("The swap adapter is not a program, or it took more than the collateral it was given")]
+   InvalidSwapAdapter = 207,
+
+   #[msg("The bank is not in the e-mode category of the user")]
+   EModeCategoryMismatch = 208,
+
+   #[msg("The e-mode category account is missing or is not the category of the user")]
+   InvalidEModeCategory = 209,
+
+   #[msg("The isolated bank of the user's collateral must be passed, writable, to track the debt it backs")]
+   MissingIsolatedBank = 210,
+
+   // ---------- oracles ----------
+   #[msg("The oracle account does not match the bank or could not be read")]
+   InvalidOracle = 300,
+
+   #[msg("The oracle price is older than the bank allows")]
+   StaleOracle = 301,
+
+   #[msg("The oracles of the bank disagree by more than its tolerance")]
+   OracleDivergence = 302,
+
+   #[msg("A secondary oracle of the bank must be passed with its primary oracle")]
+   MissingSecondaryOracle = 303,
+
+   #[msg("The confidence interval of the oracle price is too wide")]
+   OracleConfidenceTooWide = 304,
+
+   // ---------- health of the user ----------
+   #[msg("Borrowing this amount would take the debt above the borrow limit")]
+   BorrowLimitExceeded = 400,
+
+   #[msg("Withdrawing this amount would leave the debt above the borrow limit")]
+   WithdrawExceedsBorrowLimit = 401,
+
+   #[msg("The user is not undercollateralized and can't be liquidated")]
+   NotUndercollateralized = 402,
+
+   #[msg("The debt would stay above the borrow limit and the health factor would decrease")]
+   HealthDecreased = 403,
+
+   #[msg("The swap would leave the user below the liquidation threshold")]
+   LiquidatableAfterSwap = 404,
+
+   #[msg("Isolated collateral can't be combined with other collateral or with debt it doesn't back")]
+   IsolatedCollateralMixed = 405,
+
+   #[msg("The bank can't be borrowed from against isolated collateral")]
+   NotBorrowableInIsolation = 406,
+
+   #[msg("Isolated collateral and the debt it backs can't be swapped")]
+   IsolatedSwap = 407,
+
+   #[msg("Only the debt of a user without any collateral left can be socialized")]
+   NotBadDebt = 408,
+
+   #[msg("The health factor of the user is not below the soft liquidation threshold of the collateral bank")]
+   NotSoftLiquidatable = 409,
+
+   // ---------- bank state and configuration ----------
+   #[msg("Invalid bank configuration")]
+   InvalidBankConfig = 500,
+
+   #[msg("The bank is paused")]
+   BankPaused = 501,
+
+   #[msg("Deposit would take the bank above its deposit cap")]
+   DepositCapExceeded = 502,
+
+   #[msg("Borrow would take the bank above its borrow cap")]
+   BorrowCapExceeded = 503,
+
+   #[msg("Borrow would take the debt backed by the isolated collateral above its debt ceiling")]
+   DebtCeilingExceeded = 504,
+
+   #[msg("The cooldown of the request to unstake from the insurance fund is not over")]
+   UnstakeCooldown = 505,
+
+   #[msg("The insurance vault can't pay the unstake yet, the fees it is owed are still lent out")]
+   InsuranceFundIlliquid = 506,
+
+   #[msg("The bank doesn't hold that many tokens that are neither lent out nor owed to the insurance fund")]
+   InsufficientLiquidity = 507,
+
+   #[msg("A loss took the whole insurance fund, it takes stakes again once it earns fees")]
+   InsuranceFundWrittenOff = 508,
+
+   #[msg("Users in e-mode still borrow from the bank, it can't leave its e-mode category until they repay")]
+   EModeDebtOutstanding = 509,
+
+   // ---------- devnet faucet ----------
+   #[msg("Requested amount is greater than the faucet gives at once")]
+   FaucetAmountExceeded = 600,
+
+   #[msg("This wallet has to wait before using the faucet again")]
+   FaucetCooldown = 601,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrCategory {
+   Math,
+   InvalidArgument,
+   Account,
+   Oracle,
+   Health,
+   Bank,
+   Faucet,
+}
+
+impl ErrCode {
+   pub const ALL: [ErrCode; 47] = [
+      ErrCode::MathOverflow,
+      ErrCode::DivisionByZero,
+      ErrCode::InvalidAmount,
+      ErrCode::InsufficientFunds,
+      ErrCode::OverRepay,
+      ErrCode::OverLiquidation,
+      ErrCode::SlippageExceeded,
+      ErrCode::InvalidTargetLtv,
+      ErrCode::NoUnstakeRequested,
+      ErrCode::Unauthorized,
+      ErrCode::MintMismatch,
+      ErrCode::InvalidPositionAccount,
+      ErrCode::MissingPositionAccounts,
+      ErrCode::PositionLimitReached,
+      ErrCode::SameLiquidationBank,
+      ErrCode::SameSwapBank,
+      ErrCode::InvalidSwapAdapter,
+      ErrCode::EModeCategoryMismatch,
+      ErrCode::InvalidEModeCategory,
+      ErrCode::MissingIsolatedBank,
+      ErrCode::InvalidOracle,
+      ErrCode::StaleOracle,
+      ErrCode::OracleDivergence,
+      ErrCode::MissingSecondaryOracle,
+      ErrCode::OracleConfidenceTooWide,
+      ErrCode::BorrowLimitExceeded,
+      ErrCode::WithdrawExceedsBorrowLimit,
+      ErrCode::NotUndercollateralized,
+      ErrCode::HealthDecreased,
+      ErrCode::LiquidatableAfterSwap,
+      ErrCode::IsolatedCollateralMixed,
+      ErrCode::NotBorrowableInIsolation,
+      ErrCode::IsolatedSwap,
+      ErrCode::NotBadDebt,
+      ErrCode::NotSoftLiquidatable,
+      ErrCode::InvalidBankConfig,
+      ErrCode::BankPaused,
+      ErrCode::DepositCapExceeded,
+      ErrCode::BorrowCapExceeded,
+      ErrCode::DebtCeilingExceeded,
+      ErrCode::UnstakeCooldown,
+      ErrCode::InsuranceFundIlliquid,
+      ErrCode::InsufficientLiquidity,
+      ErrCode::InsuranceFundWrittenOff,
+      ErrCode::EModeDebtOutstanding,
+      ErrCode::FaucetAmountExceeded,
+      ErrCode::FaucetCooldown,
+   ];
+
+   // The custom error code the program returns for this error (6000 + n)
+   pub fn code(&self) -> u32 {
+      (*self).into()
+   }
+
+   pub fn category(&self) -> ErrCategory {
+      match (*self as u32) / 100 {
+         0 => ErrCategory::Math,
+         1 => ErrCategory::InvalidArgument,
+         2 => ErrCategory::Account,
+         3 => ErrCategory::Oracle,
+         4 => ErrCategory::Health,
+         5 => ErrCategory::Bank,
+         _ => ErrCategory::Faucet,
+      }
+   }
+
+   /*
+      For clients: turns the custom error code of a failed transaction (InstructionError::Custom(code))
+      back into the error of the program. Codes of other programs or of Anchor itself return None.
+   */
+   pub fn from_code(code: u32) -> Option<ErrCode> {
+      let number = code.checked_sub(ERROR_CODE_OFFSET)?;
+      ErrCode::ALL.iter().copied().find(|error| *error as u32 == number)
+   }
+
+   pub fn from_program_error(error: &ProgramError) -> Option<ErrCode> {
+      match error {
+         ProgramError::Custom(code) => ErrCode::from_code(*code),
+         _ => None,
+      }
+   }
+
+   pub fn from_anchor_error(error: &Error) -> Option<ErrCode> {
+      match error {
+         Error::AnchorError(error) => ErrCode::from_code(error.error_code_number),
+         Error::ProgramError(error) => ErrCode::from_program_error(&error.program_error),
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn every_code_round_trips() {
+      for error in ErrCode::ALL {
+         assert_eq!(ErrCode::from_code(error.code()), Some(error));
+         assert_eq!(ErrCode::from_program_error(&ProgramError::Custom(error.code())), Some(error));
+         assert_eq!(ErrCode::from_anchor_error(&error.into()), Some(error));
+      }
+   }
+
+   #[test]
+   fn codes_are_distinct_and_in_their_range() {
+      let mut codes: Vec<u32> = ErrCode::ALL.iter().map(|error| error.code()).collect();
+      codes.sort();
+      codes.dedup();
+      assert_eq!(codes.len(), ErrCode::ALL.len());
+
+      assert_eq!(ErrCode::MathOverflow.code(), 6000);
+      assert_eq!(ErrCode::InvalidAmount.category(), ErrCategory::InvalidArgument);
+      assert_eq!(ErrCode::MintMismatch.category(), ErrCategory::Account);
+      assert_eq!(ErrCode::StaleOracle.code(), 6301);
+      assert_eq!(ErrCode::BorrowLimitExceeded.category(), ErrCategory::Health);
+      assert_eq!(ErrCode::BankPaused.code(), 6501);
+   }
 
-   #[msg("InsufficientAmountToBorrow")]
-   InsufficientAmountToBorrow,
-}
\ No newline at end of file
+   #[test]
+   fn unknown_codes_are_not_decoded() {
+      assert_eq!(ErrCode::from_code(0), None);
+      assert_eq!(ErrCode::from_code(6099), None);
+      assert_eq!(ErrCode::from_code(ErrorCode::ConstraintHasOne.into()), None);
+      assert_eq!(ErrCode::from_program_error(&ProgramError::InvalidArgument), None);
+   }
+}
@@ -0,0 +1,355 @@
+use anchor_lang::prelude::*;
+use crate::state::{Bank, LiquidationMode};
+
+/*
+   Events are emitted with emit_cpi!, so they are stored in the inner instructions of the transaction
+   and can't be truncated like program logs.
+   Prices have PRICE_DECIMALS decimals, indices are scaled by INDEX_ONE and health factors by HEALTH_FACTOR_ONE.
+*/
+
+#[event]
+pub struct DepositEvent {
+   pub owner: Pubkey,
+   pub bank: Pubkey,
+   pub mint: Pubkey,
+   pub amount: u64,
+   pub shares: u64,
+   pub total_deposits: u64,
+   pub total_deposit_shares: u64,
+   pub deposit_index: u128,
+   pub price: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct WithdrawEvent {
+   pub owner: Pubkey,
+   pub bank: Pubkey,
+   pub mint: Pubkey,
+   pub amount: u64,
+   pub shares: u64,
+   pub total_deposits: u64,
+   pub total_deposit_shares: u64,
+   pub deposit_index: u128,
+   pub price: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct BorrowEvent {
+   pub owner: Pubkey,
+   pub bank: Pubkey,
+   pub mint: Pubkey,
+   pub amount: u64,
+   pub shares: u64,
+   pub total_borrowed: u64,
+   pub total_borrowed_shares: u64,
+   pub borrow_index: u128,
+   pub price: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct RepayEvent {
+   pub owner: Pubkey,
+   pub bank: Pubkey,
+   pub mint: Pubkey,
+   pub amount: u64,
+   pub shares: u64,
+   pub total_borrowed: u64,
+   pub total_borrowed_shares: u64,
+   pub borrow_index: u128,
+   pub price: u64, // 0 when the oracles couldn't be read, repay doesn't need them
+   pub health_factor: u64, // the one cached on the user, left as it was when the positions couldn't be priced
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct LiquidationEvent {
+   pub liquidator: Pubkey,
+   pub owner: Pubkey,
+   pub collateral_bank: Pubkey,
+   pub borrowed_bank: Pubkey,
+   pub repaid_amount: u64,
+   pub repaid_shares: u64,
+   pub seized_amount: u64,
+   pub seized_shares: u64,
+   pub liquidation_bonus: u64,
+   pub collateral_price: u64,
+   pub borrowed_price: u64,
+   pub health_factor_before: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+// The owner repaid its own debt with its collateral, the penalty went to the insurance fees of the collateral bank
+#[event]
+pub struct SelfLiquidationEvent {
+   pub owner: Pubkey,
+   pub collateral_bank: Pubkey,
+   pub borrowed_bank: Pubkey,
+   pub swap_program: Pubkey,
+   pub repaid_amount: u64,
+   pub repaid_shares: u64,
+   pub seized_amount: u64,
+   pub seized_shares: u64,
+   pub swapped_amount: u64, // what the adapter returned for the seized collateral minus the penalty
+   pub penalty_amount: u64,
+   pub self_liquidation_penalty: u64,
+   pub collateral_price: u64,
+   pub borrowed_price: u64,
+   pub health_factor_before: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+// Debt of a user without collateral written off, against the insurance fund then the deposits of the bank
+#[event]
+pub struct BadDebtSocialized {
+   pub bank: Pubkey,
+   pub owner: Pubkey,
+   pub amount: u64, // taken out of total_borrowed
+   pub shares: u64,
+   pub insurance_amount: u64, // part of the amount paid by the insurance fund, the rest is taken out of total_deposits
+   pub total_deposits: u64,
+   pub total_borrowed: u64,
+   pub deposit_index: u128, // after the write-down
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct RepayWithCollateralEvent {
+   pub owner: Pubkey,
+   pub collateral_bank: Pubkey,
+   pub borrowed_bank: Pubkey,
+   pub swap_program: Pubkey,
+   pub withdrawn_amount: u64,
+   pub withdrawn_shares: u64,
+   pub swapped_amount: u64, // received from the swap, what wasn't needed to repay stays with the user
+   pub repaid_amount: u64,
+   pub repaid_shares: u64,
+   pub collateral_price: u64,
+   pub borrowed_price: u64,
+   pub health_factor_before: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct LeverageEvent {
+   pub owner: Pubkey,
+   pub collateral_bank: Pubkey,
+   pub borrowed_bank: Pubkey,
+   pub swap_program: Pubkey,
+   pub borrowed_amount: u64,
+   pub borrowed_shares: u64,
+   pub deposited_amount: u64, // received from the swap
+   pub deposited_shares: u64,
+   pub collateral_price: u64,
+   pub borrowed_price: u64,
+   pub target_ltv: u64,
+   pub ltv: u64, // after the swap, at the oracle prices
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+// Positions are in tokens of their bank, before and after the swap
+#[event]
+pub struct CollateralSwapEvent {
+   pub owner: Pubkey,
+   pub from_bank: Pubkey,
+   pub to_bank: Pubkey,
+   pub swap_program: Pubkey,
+   pub withdrawn_amount: u64,
+   pub deposited_amount: u64,
+   pub from_deposit_before: u64,
+   pub from_deposit_after: u64,
+   pub to_deposit_before: u64,
+   pub to_deposit_after: u64,
+   pub from_price: u64,
+   pub to_price: u64,
+   pub health_factor_before: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct DebtSwapEvent {
+   pub owner: Pubkey,
+   pub from_bank: Pubkey,
+   pub to_bank: Pubkey,
+   pub swap_program: Pubkey,
+   pub borrowed_amount: u64,
+   pub repaid_amount: u64,
+   pub from_debt_before: u64,
+   pub from_debt_after: u64,
+   pub to_debt_before: u64,
+   pub to_debt_after: u64,
+   pub from_price: u64,
+   pub to_price: u64,
+   pub health_factor_before: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct InterestAccruedEvent {
+   pub bank: Pubkey,
+   pub interest: u64,
+   pub interest_rate: u64,
+   pub total_deposits: u64,
+   pub total_borrowed: u64,
+   pub insurance_fees: u64,
+   pub deposit_index: u128,
+   pub borrow_index: u128,
+   pub timestamp: i64,
+}
+
+impl InterestAccruedEvent {
+   pub fn new(bank_key: Pubkey, bank: &Bank, interest: u64) -> Self {
+      InterestAccruedEvent {
+         bank: bank_key,
+         interest,
+         interest_rate: bank.interest_rate,
+         total_deposits: bank.total_deposits,
+         total_borrowed: bank.total_borrowed,
+         insurance_fees: bank.insurance_fees,
+         deposit_index: bank.deposit_index(),
+         borrow_index: bank.borrow_index(),
+         timestamp: bank.last_updated,
+      }
+   }
+}
+
+#[event]
+pub struct BankRefreshed {
+   pub bank: Pubkey,
+   pub price: u64,
+   pub price_updated: i64,
+   pub ema_price: u64,
+   pub total_deposits: u64,
+   pub total_borrowed: u64,
+   pub deposit_index: u128,
+   pub borrow_index: u128,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct UserRefreshed {
+   pub owner: Pubkey,
+   pub health_factor: u64,
+   pub unhealthy_since: i64,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct BankConfigUpdated {
+   pub bank: Pubkey,
+   pub authority: Pubkey,
+   pub liquidation_threshold: u64,
+   pub liquidation_bonus: u64,
+   pub liquidation_close_factor: u64,
+   pub max_ltv: u64,
+   pub interest_rate: u64,
+   pub oracle_max_age: u64,
+   pub secondary_oracles: [Pubkey; 2],
+   pub oracle_tolerance_bps: u64,
+   pub ema_window: u64,
+   pub deposit_cap: u64,
+   pub borrow_cap: u64,
+   pub borrow_weight_bps: u64,
+   pub paused: bool,
+   pub emode_category: u8,
+   pub isolated: bool,
+   pub debt_ceiling: u64,
+   pub borrowable_in_isolation: bool,
+   pub liquidation_mode: LiquidationMode,
+   pub min_liquidation_bonus: u64,
+   pub liquidation_auction_duration: u64,
+   pub insurance_fee_bps: u64,
+   pub insurance_cooldown: u64,
+   pub soft_liquidation_health: u64,
+   pub self_liquidation_penalty: u64,
+   pub timestamp: i64,
+}
+
+impl BankConfigUpdated {
+   pub fn new(bank_key: Pubkey, bank: &Bank, timestamp: i64) -> Self {
+      BankConfigUpdated {
+         bank: bank_key,
+         authority: bank.authority,
+         liquidation_threshold: bank.liquidation_threshold,
+         liquidation_bonus: bank.liquidation_bonus,
+         liquidation_close_factor: bank.liquidation_close_factor,
+         max_ltv: bank.max_ltv,
+         interest_rate: bank.interest_rate,
+         oracle_max_age: bank.oracle_max_age,
+         secondary_oracles: bank.secondary_oracles,
+         oracle_tolerance_bps: bank.oracle_tolerance_bps,
+         ema_window: bank.ema_window,
+         deposit_cap: bank.deposit_cap,
+         borrow_cap: bank.borrow_cap,
+         borrow_weight_bps: bank.borrow_weight_bps,
+         paused: bank.paused,
+         emode_category: bank.emode_category,
+         isolated: bank.isolated,
+         debt_ceiling: bank.debt_ceiling,
+         borrowable_in_isolation: bank.borrowable_in_isolation,
+         liquidation_mode: bank.liquidation_mode,
+         min_liquidation_bonus: bank.min_liquidation_bonus,
+         liquidation_auction_duration: bank.liquidation_auction_duration,
+         insurance_fee_bps: bank.insurance_fee_bps,
+         insurance_cooldown: bank.insurance_cooldown,
+         soft_liquidation_health: bank.soft_liquidation_health,
+         self_liquidation_penalty: bank.self_liquidation_penalty,
+         timestamp,
+      }
+   }
+}
+
+#[event]
+pub struct EModeCategoryUpdated {
+   pub emode_category: Pubkey,
+   pub authority: Pubkey,
+   pub id: u8,
+   pub max_ltv: u64,
+   pub liquidation_threshold: u64,
+   pub liquidation_bonus: u64,
+   pub timestamp: i64,
+}
+
+#[event]
+pub struct UserEModeChanged {
+   pub owner: Pubkey,
+   pub emode_category_before: u8,
+   pub emode_category: u8,
+   pub health_factor_before: u64,
+   pub health_factor: u64,
+   pub timestamp: i64,
+}
+
+// Stakes and unstakes of the insurance fund of a bank, with the fund after them
+#[event]
+pub struct InsuranceStakeEvent {
+   pub bank: Pubkey,
+   pub staker: Pubkey,
+   pub action: InsuranceStakeAction,
+   pub amount: u64, // 0 for a request to unstake
+   pub shares: u64,
+   pub stake_shares: u64, // of the staker, after the event
+   pub unstake_shares: u64, // of the staker's pending request, after the event
+   pub insurance_funds: u64,
+   pub insurance_fees: u64,
+   pub insurance_shares: u64,
+   pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsuranceStakeAction {
+   Stake,
+   RequestUnstake,
+   Unstake,
+}
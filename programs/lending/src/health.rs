@@ -0,0 +1,372 @@
+use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::error::ErrCode;
+use crate::math::*;
+use crate::oracle::get_price;
+use crate::state::{Bank, EModeCategory, User};
+
+// A bank together with the oracle price it was valued at
+#[derive(Clone)]
+pub struct PricedBank {
+   pub key: Pubkey,
+   pub bank: Bank,
+   pub price: u64,
+   pub ema_price: u64, // the moving average of the bank at the time of `price`, see Bank::ema_at
+}
+
+impl PricedBank {
+   pub fn new(key: Pubkey, bank: Bank, price: u64, now: i64) -> Self {
+      let ema_price = bank.ema_at(price, now);
+      PricedBank { key, bank, price, ema_price }
+   }
+
+   // Health takes the conservative side of the spot price and its moving average, so a short spike moves neither side up
+   pub fn collateral_price(&self) -> u64 {
+      self.price.min(self.ema_price)
+   }
+
+   pub fn debt_price(&self) -> u64 {
+      self.price.max(self.ema_price)
+   }
+
+   // What `amount` of the bank is worth as collateral, rounded down
+   pub fn collateral_value(&self, amount: u64) -> Result<u128> {
+      token_value(amount, self.collateral_price(), self.bank.decimals)
+   }
+
+   // What `amount` of the bank is worth as debt, rounded up
+   pub fn debt_value(&self, amount: u64) -> Result<u128> {
+      token_value_ceil(amount, self.debt_price(), self.bank.decimals)
+   }
+}
+
+/*
+   All values are in the quote currency with PRICE_DECIMALS decimals, see token_value, so mints with
+   different decimals add up.
+   collateral_value            -> everything the user deposited
+   weighted_collateral         -> collateral_value * liquidation_threshold, below the debt the user can be liquidated
+   borrow_limit                -> collateral_value * max_ltv, the weighted debt can't grow above it
+   debt_value                  -> everything the user borrowed
+   weighted_debt               -> debt_value * borrow_weight_bps, what the collateral is compared with
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Health {
+   pub collateral_value: u128,
+   pub weighted_collateral: u128,
+   pub borrow_limit: u128,
+   pub debt_value: u128,
+   pub weighted_debt: u128,
+}
+
+impl Health {
+   // weighted_collateral / weighted_debt scaled by HEALTH_FACTOR_ONE, u64::MAX when there is no debt
+   pub fn health_factor(&self) -> u64 {
+      if self.weighted_debt == 0 {
+         return u64::MAX;
+      }
+      // Saturates, a health factor too big for a u64 is as healthy as no debt at all
+      mul_div_u128(self.weighted_collateral, HEALTH_FACTOR_ONE as u128, self.weighted_debt)
+         .map_or(u64::MAX, |health_factor| health_factor.min(u64::MAX as u128) as u64)
+   }
+
+   // debt_value / collateral_value in bps, u64::MAX for a debt without collateral (the market LTV, without the borrow weights)
+   pub fn ltv(&self) -> u64 {
+      if self.debt_value == 0 {
+         return 0;
+      }
+      mul_div_u128(self.debt_value, BPS as u128, self.collateral_value)
+         .map_or(u64::MAX, |ltv| ltv.min(u64::MAX as u128) as u64)
+   }
+
+   pub fn is_liquidatable(&self) -> bool {
+      self.weighted_debt > self.weighted_collateral
+   }
+
+   pub fn is_within_borrow_limit(&self) -> bool {
+      self.weighted_debt <= self.borrow_limit
+   }
+}
+
+/*
+   A user in e-mode passes the account of its category first in the remaining accounts, before the pairs of its positions.
+   Returns the category (None outside of e-mode) and the remaining accounts that follow it.
+*/
+pub fn load_emode_category<'a, 'info>(
+   user: &User,
+   remaining_accounts: &'a [AccountInfo<'info>],
+) -> Result<(Option<EModeCategory>, &'a [AccountInfo<'info>])> {
+   if user.emode_category == 0 {
+      return Ok((None, remaining_accounts));
+   }
+   let (category_info, remaining) = remaining_accounts.split_first().ok_or(ErrCode::InvalidEModeCategory)?;
+   require_keys_eq!(*category_info.owner, crate::ID, ErrCode::InvalidEModeCategory);
+   let category = EModeCategory::try_deserialize(&mut &category_info.try_borrow_data()?[..])?;
+   require!(category.id == user.emode_category, ErrCode::InvalidEModeCategory);
+   Ok((Some(category), remaining))
+}
+
+/*
+   The banks the instruction works on are already loaded (and accrued), so they are passed in `acted`.
+   The other banks are accrued up to `now` as well, without writing them back.
+   For every other position of the user the client has to pass, in the order of the positions,
+   the bank account followed by its oracle accounts (Bank::oracles) as remaining accounts.
+*/
+pub fn load_priced_banks(
+   user: &User,
+   acted: Vec<PricedBank>,
+   remaining_accounts: &[AccountInfo],
+   now: i64,
+) -> Result<Vec<PricedBank>> {
+   let mut priced_banks = acted;
+   let mut remaining = remaining_accounts.iter();
+
+   for position in user.positions.iter().filter(|position| position.is_active()) {
+      if priced_banks.iter().any(|priced| priced.key == position.bank) {
+         continue;
+      }
+
+      let bank_info = remaining.next().ok_or(ErrCode::MissingPositionAccounts)?;
+      require_keys_eq!(bank_info.key(), position.bank, ErrCode::InvalidPositionAccount);
+      require_keys_eq!(*bank_info.owner, crate::ID, ErrCode::InvalidPositionAccount);
+      let mut bank = Bank::try_deserialize(&mut &bank_info.try_borrow_data()?[..])?;
+      // Only the copy is accrued, the debt of the user includes the interest nobody refreshed the bank for yet
+      bank.accrue_interest(now)?;
+
+      let oracles = (0..bank.oracles().len())
+         .map(|_| remaining.next().ok_or_else(|| error!(ErrCode::MissingPositionAccounts)))
+         .collect::<Result<Vec<_>>>()?;
+      let price = get_price(&bank, &oracles, now)?;
+
+      priced_banks.push(PricedBank::new(position.bank, bank, price, now));
+   }
+
+   Ok(priced_banks)
+}
+
+// The isolated bank the user has collateral in, None when its collateral isn't isolated
+pub fn isolated_collateral(user: &User, priced_banks: &[PricedBank]) -> Result<Option<Pubkey>> {
+   for position in user.positions.iter().filter(|position| position.is_active() && position.deposit_shares > 0) {
+      let priced = priced_banks
+         .iter()
+         .find(|priced| priced.key == position.bank)
+         .ok_or(ErrCode::MissingPositionAccounts)?;
+      if priced.bank.isolated {
+         return Ok(Some(position.bank));
+      }
+   }
+   Ok(None)
+}
+
+// Isolated collateral has to be the only collateral of the user
+pub fn check_isolated_collateral(user: &User, priced_banks: &[PricedBank]) -> Result<()> {
+   let collaterals = user.positions.iter().filter(|position| position.is_active() && position.deposit_shares > 0).count();
+   if collaterals > 1 && isolated_collateral(user, priced_banks)?.is_some() {
+      return Err(ErrCode::IsolatedCollateralMixed.into());
+   }
+   Ok(())
+}
+
+// `emode` is the category of the user, see load_emode_category
+pub fn compute_health(user: &User, priced_banks: &[PricedBank], emode: Option<&EModeCategory>) -> Result<Health> {
+   let mut health = Health::default();
+
+   for position in user.positions.iter().filter(|position| position.is_active()) {
+      let priced = priced_banks
+         .iter()
+         .find(|priced| priced.key == position.bank)
+         .ok_or(ErrCode::MissingPositionAccounts)?;
+      let bank = &priced.bank;
+      let params = bank.risk_params(emode);
+
+      let deposited = bank.deposit_shares_to_amount(position.deposit_shares)?;
+      let borrowed = bank.borrowed_shares_to_amount(position.borrowed_shares)?;
+
+      let collateral_value = priced.collateral_value(deposited)?;
+      let weighted_collateral = mul_div_u128(collateral_value, params.liquidation_threshold as u128, BPS as u128)?;
+      let borrow_limit = mul_div_u128(collateral_value, params.max_ltv as u128, BPS as u128)?;
+      let debt_value = priced.debt_value(borrowed)?;
+      let weighted_debt = mul_div_u128(debt_value, bank.borrow_weight_bps as u128, BPS as u128)?;
+
+      health.collateral_value = checked_add_u128(health.collateral_value, collateral_value)?;
+      health.weighted_collateral = checked_add_u128(health.weighted_collateral, weighted_collateral)?;
+      health.borrow_limit = checked_add_u128(health.borrow_limit, borrow_limit)?;
+      health.debt_value = checked_add_u128(health.debt_value, debt_value)?;
+      health.weighted_debt = checked_add_u128(health.weighted_debt, weighted_debt)?;
+   }
+
+   Ok(health)
+}
+
+/*
+   Banks and users for the unit tests of the program, the client and the keeper, built without any account.
+   A bank has one share per token, the risk parameters of the TS tests (threshold 80%, max ltv 75%, 5% interest)
+   and is priced at the time 0 of its last update, so no interest accrues. Tests change its fields directly.
+*/
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils {
+   use super::*;
+   use crate::state::Position;
+
+   pub fn test_bank(key: Pubkey, deposits: u64, borrowed: u64, price: u64) -> PricedBank {
+      let bank = Bank {
+         mint_address: Pubkey::new_unique(),
+         total_deposits: deposits,
+         total_deposit_shares: deposits,
+         total_borrowed: borrowed,
+         total_borrowed_shares: borrowed,
+         liquidation_threshold: 8_000,
+         liquidation_close_factor: DEFAULT_LIQUIDATION_CLOSE_FACTOR,
+         max_ltv: 7_500,
+         interest_rate: 5,
+         borrow_weight_bps: DEFAULT_BORROW_WEIGHT,
+         ..Default::default()
+      };
+      PricedBank::new(key, bank, price, 0)
+   }
+
+   pub fn test_user(positions: &[Position]) -> User {
+      let mut user = User::default();
+      user.positions[..positions.len()].copy_from_slice(positions);
+      user
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use super::test_utils::{test_bank, test_user};
+   use crate::state::Position;
+
+   #[test]
+   fn health_weights_collateral_with_threshold_and_ltv() {
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let banks = [test_bank(sol, 1_000, 1_000, 150), test_bank(usdc, 100_000, 100_000, 1)];
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 },
+         Position { bank: usdc, deposit_shares: 0, borrowed_shares: 10_000 },
+      ]);
+
+      let health = compute_health(&user, &banks, None).unwrap();
+      assert_eq!(health.collateral_value, 15_000);
+      assert_eq!(health.weighted_collateral, 12_000);
+      assert_eq!(health.borrow_limit, 11_250);
+      assert_eq!(health.debt_value, 10_000);
+      assert_eq!(health.weighted_debt, 10_000);
+      assert_eq!(health.health_factor(), 12_000);
+      assert_eq!(health.ltv(), 6_666);
+      assert!(health.is_within_borrow_limit());
+      assert!(!health.is_liquidatable());
+   }
+
+   #[test]
+   fn emode_parameters_only_apply_to_the_banks_of_the_category() {
+      let (jitosol, sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [test_bank(jitosol, 1_000, 1_000, 150), test_bank(sol, 1_000, 1_000, 150), test_bank(usdc, 100_000, 100_000, 1)];
+      banks[0].bank.emode_category = 1;
+      banks[1].bank.emode_category = 1;
+      let user = test_user(&[
+         Position { bank: jitosol, deposit_shares: 100, borrowed_shares: 0 },
+         Position { bank: sol, deposit_shares: 0, borrowed_shares: 50 },
+         Position { bank: usdc, deposit_shares: 10_000, borrowed_shares: 0 },
+      ]);
+      let category = EModeCategory { id: 1, max_ltv: 9_000, liquidation_threshold: 9_500, ..Default::default() };
+
+      let health = compute_health(&user, &banks, Some(&category)).unwrap();
+      assert_eq!(health.collateral_value, 25_000);
+      // 15_000 of jitoSOL at 95% and 10_000 of USDC at 80%
+      assert_eq!(health.weighted_collateral, 14_250 + 8_000);
+      assert_eq!(health.borrow_limit, 13_500 + 7_500);
+
+      // Another category is the same as none
+      let other = EModeCategory { id: 2, ..category };
+      assert_eq!(compute_health(&user, &banks, Some(&other)).unwrap(), compute_health(&user, &banks, None).unwrap());
+   }
+
+   #[test]
+   fn borrow_weight_only_applies_to_the_debt_side() {
+      let (sol, usdc) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [test_bank(sol, 1_000, 1_000, 150), test_bank(usdc, 100_000, 100_000, 1)];
+      banks[0].bank.borrow_weight_bps = 15_000;
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 20, borrowed_shares: 10 },
+         Position { bank: usdc, deposit_shares: 10_000, borrowed_shares: 1_000 },
+      ]);
+
+      let health = compute_health(&user, &banks, None).unwrap();
+      // The SOL deposit is weighted like any other, its debt counts 1.5 times
+      assert_eq!(health.collateral_value, 13_000);
+      assert_eq!(health.debt_value, 1_500 + 1_000);
+      assert_eq!(health.weighted_debt, 2_250 + 1_000);
+      assert_eq!(health.health_factor(), 32_000);
+      assert_eq!(health.ltv(), 1_923);
+   }
+
+   #[test]
+   fn collateral_and_debt_take_the_conservative_side_of_the_moving_average() {
+      let (sol, eth) = (Pubkey::new_unique(), Pubkey::new_unique());
+      // SOL spikes above its average, ETH drops below it
+      let mut banks = [test_bank(sol, 1_000, 1_000, 200), test_bank(eth, 1_000, 1_000, 100)];
+      banks[0].ema_price = 150;
+      banks[1].ema_price = 150;
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 100, borrowed_shares: 0 },
+         Position { bank: eth, deposit_shares: 0, borrowed_shares: 10 },
+      ]);
+
+      let health = compute_health(&user, &banks, None).unwrap();
+      assert_eq!((health.collateral_value, health.debt_value), (15_000, 1_500));
+
+      // Without a window the average is the spot price
+      let spot = [PricedBank::new(sol, banks[0].bank.clone(), 200, 0), PricedBank::new(eth, banks[1].bank.clone(), 100, 0)];
+      let health = compute_health(&user, &spot, None).unwrap();
+      assert_eq!((health.collateral_value, health.debt_value), (20_000, 1_000));
+   }
+
+   #[test]
+   fn amounts_are_valued_with_the_decimals_of_their_mint() {
+      const DOLLAR: u64 = 100_000_000;
+      let (sol, usdc, eth, ticket) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+      let mut banks = [
+         test_bank(sol, u64::MAX, u64::MAX, 150 * DOLLAR),
+         test_bank(usdc, u64::MAX, u64::MAX, DOLLAR),
+         test_bank(eth, u64::MAX, u64::MAX, 3_000 * DOLLAR),
+         test_bank(ticket, u64::MAX, u64::MAX, 50 * DOLLAR),
+      ];
+      for (priced, decimals) in banks.iter_mut().zip([9, 6, 18, 0]) {
+         priced.bank.decimals = decimals;
+      }
+      // 10 SOL and 2 tickets against 1_000 USDC and 0.1 ETH
+      let user = test_user(&[
+         Position { bank: sol, deposit_shares: 10_000_000_000, borrowed_shares: 0 },
+         Position { bank: ticket, deposit_shares: 2, borrowed_shares: 0 },
+         Position { bank: usdc, deposit_shares: 0, borrowed_shares: 1_000_000_000 },
+         Position { bank: eth, deposit_shares: 0, borrowed_shares: 100_000_000_000_000_000 },
+      ]);
+
+      let health = compute_health(&user, &banks, None).unwrap();
+      assert_eq!(health.collateral_value, (1_500 + 100) * DOLLAR as u128);
+      assert_eq!(health.debt_value, (1_000 + 300) * DOLLAR as u128);
+      assert_eq!(health.health_factor(), 9_846);
+   }
+
+   #[test]
+   fn health_sums_overflow_instead_of_wrapping() {
+      // every position is worth u64::MAX * u64::MAX, two of them no longer fit in a u128
+      let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+      let banks = [test_bank(first, u64::MAX, u64::MAX, u64::MAX), test_bank(second, u64::MAX, u64::MAX, u64::MAX)];
+      let user = test_user(&[
+         Position { bank: first, deposit_shares: 0, borrowed_shares: u64::MAX },
+         Position { bank: second, deposit_shares: 0, borrowed_shares: u64::MAX },
+      ]);
+      assert_eq!(compute_health(&user, &banks, None).unwrap_err(), ErrCode::MathOverflow.into());
+   }
+
+   #[test]
+   fn health_factor_saturates() {
+      let health = Health { collateral_value: u128::MAX, weighted_collateral: u128::MAX, borrow_limit: 0, debt_value: 1, weighted_debt: 1 };
+      assert_eq!(health.health_factor(), u64::MAX);
+      assert_eq!(Health::default().health_factor(), u64::MAX);
+      assert_eq!(Health::default().ltv(), 0);
+      assert_eq!(Health { debt_value: 1, weighted_debt: 1, ..Health::default() }.ltv(), u64::MAX);
+   }
+}
@@ -1,5 +1,10 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
 use anchor_spl::{associated_token::AssociatedToken, token_interface::{Mint, TokenAccount, TokenInterface}};
+use crate::constants::*;
+use crate::error::ErrCode;
+use crate::events::{BankConfigUpdated, InterestAccruedEvent};
+use crate::oracle::OraclePrice;
 use crate::state::*;
 
 #[derive(Accounts)]
@@ -20,6 +25,7 @@ pub struct InitBank<'info> {
    )]
    pub bank: Account<'info, Bank>,
    
+   #[account(mint::token_program = token_program)]
    pub mint: InterfaceAccount<'info, Mint>,
 
    // We will need to have a token account to hold the tokens for the bank, and this will initialize the token account
@@ -27,6 +33,7 @@ pub struct InitBank<'info> {
       init,
       token::mint = mint, // We are going to set that this is for tokens and we are going to take the mint of the mint account that we are passing through 
       token::authority = bank_token_account, // We are going to set the authority to itself(that is going to be the bank_token_account)
+      token::token_program = token_program,
       payer = signer, 
       // We dont want to use an associated token account we just want to have token account with a pda, so we are able to know that this account is specific to the lending protocol bank
       seeds = [b"treasury", mint.key().as_ref()],
@@ -34,6 +41,31 @@ pub struct InitBank<'info> {
    )]
    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
 
+   // The vault of the insurance fund of the bank, a token account that is its own authority like the treasury
+   #[account(
+      init,
+      token::mint = mint,
+      token::authority = insurance_vault,
+      token::token_program = token_program,
+      payer = signer,
+      seeds = [b"insurance", mint.key().as_ref()],
+      bump
+   )]
+   pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: price account of the mint in one of the layouts of oracle.rs, checked here and validated again every time a price is read
+   #[account(constraint = OraclePrice::parse(&oracle.try_borrow_data()?).is_ok() @ ErrCode::InvalidOracle)]
+   pub oracle: UncheckedAccount<'info>,
+
+   // Only the upgrade authority of the program lists banks, the ProgramData account of the program says who it is
+   #[account(
+      seeds = [crate::ID.as_ref()],
+      bump,
+      seeds::program = bpf_loader_upgradeable::ID,
+      constraint = program_data.upgrade_authority_address == Some(signer.key()) @ ErrCode::Unauthorized,
+   )]
+   pub program_data: Account<'info, ProgramData>,
+
    // Because we are creating new token accounts 
    pub token_program: Interface<'info, TokenInterface>,
 
@@ -49,9 +81,7 @@ pub struct InitUser<'info> {
    #[account(mut)]
    pub signer: Signer<'info>,
 
-   #[account(mut)]
-   pub mint: InterfaceAccount<'info, Mint>, 
-
+   // A user account holds the positions of the signer in every bank, so it doesn't depend on any mint
    #[account(
       init,
       payer = signer,
@@ -61,39 +91,382 @@ pub struct InitUser<'info> {
    )]
    pub user_account: Account<'info, User>, 
 
-   // #[account(
-   //    // init,
-   //    token::mint = mint,
-   //    token::authority = signer,
-   //    // payer = signer,
-   //    // seeds = [b"user", signer.key().as_ref()],
-   //    // bump,
-   // )]
-   // pub user_token_account: InterfaceAccount<'info, TokenAccount>,
-
-   // Because we are creating new token accounts 
-   pub token_program: Interface<'info, TokenInterface>,
-
-   pub associated_token_account: Program<'info, AssociatedToken>,
    // Because we are initializing a new account we need to pass through the system program
    pub system_program: Program<'info, System>,
 }
 
+// Only the authority of the bank can change its risk parameters
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateBankConfig<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetBankPaused<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct BankConfig {
+   pub liquidation_threshold: u64,
+   pub liquidation_bonus: u64,
+   pub liquidation_close_factor: u64,
+   pub max_ltv: u64,
+   pub interest_rate: u64,
+   pub oracle_max_age: u64,
+   pub deposit_cap: u64,
+   pub borrow_cap: u64,
+   pub borrow_weight_bps: u64,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetBankIsolation<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct IsolationConfig {
+   pub isolated: bool,
+   pub debt_ceiling: u64,
+   pub borrowable_in_isolation: bool,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetBankLiquidationMode<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct LiquidationModeConfig {
+   pub mode: LiquidationMode,
+   pub min_liquidation_bonus: u64,
+   pub auction_duration: u64,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetBankInsurance<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct InsuranceConfig {
+   pub fee_bps: u64,
+   pub unstake_cooldown: u64,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetBankSelfLiquidation<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct SelfLiquidationConfig {
+   pub soft_liquidation_health: u64,
+   pub penalty: u64,
+}
+
+/*
+   The secondary oracles of a bank back up its primary oracle and are compared with it, see oracle::aggregate_price.
+   The accounts passed replace the current ones, passing none removes them.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetBankOracles<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+
+   /// CHECK: price account in one of the layouts of oracle.rs, checked when the bank is configured
+   pub secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: price account in one of the layouts of oracle.rs, checked when the bank is configured
+   pub secondary_oracle_2: Option<UncheckedAccount<'info>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct OracleConfig {
+   pub tolerance_bps: u64,
+}
+
+/*
+   The smoothing window of the moving average of the bank price (see Bank::ema_at). Health checks value deposits
+   at the lower of the price and the average and debts at the higher, a window of 0 goes back to the price alone.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetBankEma<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct EmaConfig {
+   pub window: u64,
+}
+
 // The initialization happened in the struct, so we save the information we need to the account state for the bank
 pub fn process_init_bank(ctx: Context<InitBank>, liquidation_threshold: u64, max_ltv: u64, interest_rate: u64) -> Result<()> {
+   require!(max_ltv <= liquidation_threshold, ErrCode::InvalidBankConfig);
+   require!(liquidation_threshold <= BPS, ErrCode::InvalidBankConfig);
+   require!(interest_rate <= MAX_INTEREST_RATE, ErrCode::InvalidBankConfig);
+   check_liquidation_bonus(liquidation_threshold, DEFAULT_LIQUIDATION_BONUS)?;
+
    let bank = &mut ctx.accounts.bank; // We take a mutable reference or a mutable borrow
    bank.mint_address = ctx.accounts.mint.key();
+   bank.decimals = ctx.accounts.mint.decimals;
    bank.authority = ctx.accounts.signer.key();
    bank.liquidation_threshold = liquidation_threshold; 
    bank.max_ltv = max_ltv;
    bank.interest_rate = interest_rate;
+   bank.liquidation_bonus = DEFAULT_LIQUIDATION_BONUS;
+   bank.liquidation_close_factor = DEFAULT_LIQUIDATION_CLOSE_FACTOR;
+   bank.oracle = ctx.accounts.oracle.key();
+   bank.oracle_max_age = DEFAULT_ORACLE_MAX_AGE;
+   bank.borrow_weight_bps = DEFAULT_BORROW_WEIGHT;
    bank.last_updated = Clock::get()?.unix_timestamp;
    Ok(())
 }
 
-pub fn process_init_user(ctx: Context<InitUser>, usdc_address: Pubkey) -> Result<()> {
+/*
+   Collateral worth the weighted debt has to cover the debt plus the bonus, liquidation_threshold * (1 + liquidation_bonus) <= 1,
+   otherwise every liquidation leaves the user less healthy than before and the last ones seize collateral that isn't there.
+*/
+pub(crate) fn check_liquidation_bonus(liquidation_threshold: u64, liquidation_bonus: u64) -> Result<()> {
+   require!(liquidation_bonus <= MAX_LIQUIDATION_BONUS, ErrCode::InvalidBankConfig);
+   let covered = liquidation_threshold as u128 * (BPS + liquidation_bonus) as u128;
+   require!(covered <= BPS as u128 * BPS as u128, ErrCode::InvalidBankConfig);
+   Ok(())
+}
+
+pub fn process_init_user(ctx: Context<InitUser>) -> Result<()> {
    let user_account = &mut ctx.accounts.user_account;
    user_account.owner = ctx.accounts.signer.key();
-   user_account.usdc_address = usdc_address;
+   user_account.last_updated = Clock::get()?.unix_timestamp;
+   Ok(())
+}
+
+pub fn process_update_bank_config(ctx: Context<UpdateBankConfig>, config: BankConfig) -> Result<()> {
+   // A user can't be allowed to borrow more than the point at which they get liquidated
+   require!(config.max_ltv <= config.liquidation_threshold, ErrCode::InvalidBankConfig);
+   require!(config.liquidation_threshold <= BPS, ErrCode::InvalidBankConfig);
+   require!(config.liquidation_close_factor <= BPS, ErrCode::InvalidBankConfig);
+   // The bank accrues with the old rate before taking the new one, a rate that overflows the accrual could never be undone
+   require!(config.interest_rate <= MAX_INTEREST_RATE, ErrCode::InvalidBankConfig);
+   check_liquidation_bonus(config.liquidation_threshold, config.liquidation_bonus)?;
+   // A weight below 1x would let a debt count for less than it is worth
+   require!(config.borrow_weight_bps >= BPS, ErrCode::InvalidBankConfig);
+   // A maximum age of 0 rejects every price, even one published in the same second
+   require!(config.oracle_max_age > 0, ErrCode::InvalidBankConfig);
+
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+
+   // Interest up to now is charged with the old rate
+   let interest = bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, bank, interest));
+   }
+
+   bank.liquidation_threshold = config.liquidation_threshold;
+   bank.liquidation_bonus = config.liquidation_bonus;
+   bank.liquidation_close_factor = config.liquidation_close_factor;
+   bank.max_ltv = config.max_ltv;
+   bank.interest_rate = config.interest_rate;
+   bank.oracle_max_age = config.oracle_max_age;
+   bank.deposit_cap = config.deposit_cap;
+   bank.borrow_cap = config.borrow_cap;
+   bank.borrow_weight_bps = config.borrow_weight_bps;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
+   Ok(())
+}
+
+pub fn process_set_bank_paused(ctx: Context<SetBankPaused>, paused: bool) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+   bank.paused = paused;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
+   Ok(())
+}
+
+/*
+   Deposits made before a bank became isolated (or stopped being) could sit next to other collateral,
+   so `isolated` only changes while nobody has deposited. The ceiling can be moved at any time,
+   lowering it below isolated_debt only stops new borrows.
+*/
+pub fn process_set_bank_isolation(ctx: Context<SetBankIsolation>, config: IsolationConfig) -> Result<()> {
+   // Isolated debt is borrowed from stable banks, never from another isolated one
+   require!(!(config.isolated && config.borrowable_in_isolation), ErrCode::InvalidBankConfig);
+
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+   require!(config.isolated == bank.isolated || bank.total_deposit_shares == 0, ErrCode::InvalidBankConfig);
+
+   bank.isolated = config.isolated;
+   bank.debt_ceiling = config.debt_ceiling;
+   bank.borrowable_in_isolation = config.borrowable_in_isolation;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
+   Ok(())
+}
+
+/*
+   Selects between the fixed liquidation bonus and a dutch auction of the collateral of the bank.
+   The maximum of the auction is the liquidation_bonus set with update_bank_config.
+*/
+pub fn process_set_bank_liquidation_mode(ctx: Context<SetBankLiquidationMode>, config: LiquidationModeConfig) -> Result<()> {
+   if config.mode == LiquidationMode::DutchAuction {
+      require!(config.auction_duration > 0, ErrCode::InvalidBankConfig);
+      require!(config.min_liquidation_bonus <= ctx.accounts.bank.liquidation_bonus, ErrCode::InvalidBankConfig);
+   }
+
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+   bank.liquidation_mode = config.mode;
+   bank.min_liquidation_bonus = config.min_liquidation_bonus;
+   bank.liquidation_auction_duration = config.auction_duration;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
    Ok(())
-}
\ No newline at end of file
+}
+
+/*
+   The fee applies to the interest accrued from now on, so the interest up to now is accrued with the old one.
+   A longer cooldown applies to the requests to unstake that are already pending as well.
+*/
+pub fn process_set_bank_insurance(ctx: Context<SetBankInsurance>, config: InsuranceConfig) -> Result<()> {
+   require!(config.fee_bps <= BPS, ErrCode::InvalidBankConfig);
+
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+
+   let interest = bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, bank, interest));
+   }
+
+   bank.insurance_fee_bps = config.fee_bps;
+   bank.insurance_cooldown = config.unstake_cooldown;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
+   Ok(())
+}
+
+/*
+   Lets the owners whose health factor is below soft_liquidation_health (HEALTH_FACTOR_ONE is the liquidation threshold)
+   repay their debt with the collateral of this bank for `penalty` instead of the liquidation bonus. 0 disables it.
+*/
+pub fn process_set_bank_self_liquidation(ctx: Context<SetBankSelfLiquidation>, config: SelfLiquidationConfig) -> Result<()> {
+   if config.soft_liquidation_health != 0 {
+      require!(config.soft_liquidation_health >= HEALTH_FACTOR_ONE, ErrCode::InvalidBankConfig);
+      require!(config.penalty <= ctx.accounts.bank.liquidation_bonus, ErrCode::InvalidBankConfig);
+   }
+
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+   bank.soft_liquidation_health = config.soft_liquidation_health;
+   bank.self_liquidation_penalty = config.penalty;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
+   Ok(())
+}
+
+pub fn process_set_bank_oracles(ctx: Context<SetBankOracles>, config: OracleConfig) -> Result<()> {
+   require!(config.tolerance_bps <= BPS, ErrCode::InvalidBankConfig);
+
+   let mut secondary_oracles = [Pubkey::default(); 2];
+   let oracles = [&ctx.accounts.secondary_oracle_1, &ctx.accounts.secondary_oracle_2];
+   for (index, oracle) in oracles.into_iter().flatten().enumerate() {
+      require!(OraclePrice::parse(&oracle.try_borrow_data()?).is_ok(), ErrCode::InvalidOracle);
+      // The same source twice would count twice in the median
+      require!(oracle.key() != ctx.accounts.bank.oracle && !secondary_oracles.contains(&oracle.key()), ErrCode::InvalidBankConfig);
+      secondary_oracles[index] = oracle.key();
+   }
+
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+   bank.secondary_oracles = secondary_oracles;
+   bank.oracle_tolerance_bps = config.tolerance_bps;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
+   Ok(())
+}
+
+pub fn process_set_bank_ema(ctx: Context<SetBankEma>, config: EmaConfig) -> Result<()> {
+   require!(config.window <= MAX_EMA_WINDOW, ErrCode::InvalidBankConfig);
+
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+   bank.ema_window = config.window;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
+   Ok(())
+}
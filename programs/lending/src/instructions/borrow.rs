@@ -1,13 +1,17 @@
 use anchor_lang::prelude::*;
 use anchor_spl::{
-   token::Transfer,
    associated_token::AssociatedToken,
-   token_interface::{Mint, TokenAccount, TokenInterface},
+   token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
 
 use crate::state::{Bank, User};
 use crate::error::ErrCode;
+use crate::events::{BorrowEvent, InterestAccruedEvent};
+use crate::health::{compute_health, isolated_collateral, load_emode_category, load_priced_banks, PricedBank};
+use crate::math::checked_add;
+use crate::oracle::{get_price, oracle_accounts};
 
+#[event_cpi]
 #[derive(Accounts)]
 pub struct Borrow<'info> {
    // signer
@@ -15,6 +19,7 @@ pub struct Borrow<'info> {
    pub signer: Signer<'info>,
 
    // mintUsdc (will be the asset to borrow, assuming mintSol is deposited)
+   #[account(mint::token_program = token_program)]
    pub mint: InterfaceAccount<'info, Mint>,
 
    // bank
@@ -22,6 +27,8 @@ pub struct Borrow<'info> {
       mut,
       seeds = [mint.key().as_ref()],
       bump,
+      constraint = bank.mint_address == mint.key() @ ErrCode::MintMismatch,
+      constraint = !bank.paused @ ErrCode::BankPaused,
    )]
    pub bank: Account<'info, Bank>,
 
@@ -30,16 +37,28 @@ pub struct Borrow<'info> {
       mut, // because I will transfer tokens from this account to user_token_account
       token::mint = mint,
       token::authority = bank_token_account,
+      token::token_program = token_program,
       seeds = [b"treasury", mint.key().as_ref()],
       bump,
    )]
    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
 
+   /// CHECK: must be the oracle stored in the bank, its data is validated when the price is read
+   #[account(address = bank.oracle @ ErrCode::InvalidOracle)]
+   pub oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
    // user
    #[account(
       mut, // becuase I will update the user state 
       seeds = [signer.key().as_ref()],
       bump,
+      constraint = user.owner == signer.key() @ ErrCode::Unauthorized,
    )]
    pub user: Account<'info, User>,
 
@@ -48,9 +67,17 @@ pub struct Borrow<'info> {
       mut,
       token::mint = mint,
       token::authority = signer,
+      token::token_program = token_program,
    )]
    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
 
+   // The isolated bank of the user's collateral when it has one, the new debt counts against its ceiling
+   #[account(
+      mut,
+      constraint = isolated_bank.key() != bank.key() @ ErrCode::MissingIsolatedBank,
+   )]
+   pub isolated_bank: Option<Account<'info, Bank>>,
+
    /*
       If the token you want to borrow is not already initialized it means you can't borrow it.
       Thus the token you want to borrow should already have been initialized.
@@ -64,44 +91,92 @@ pub struct Borrow<'info> {
 }
 
 pub fn process_borrow(ctx: Context<Borrow>, amount_to_borrow:u64) -> Result<()> {
-   // Check if there is collateral (that is if there is sol deposited)
+   require!(amount_to_borrow > 0, ErrCode::InvalidAmount);
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
    let user = &mut ctx.accounts.user;
    let bank = &mut ctx.accounts.bank;
-   
-   if user.deposited_sol <= 0 {
-      return Err(ErrCode::InsuficientSol.into());
+
+   let interest = bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, bank, interest));
    }
-      
-   // Determined the maximum amount for borrowing (borrow_maximum_amount)
-   // Get the value of sol in usdc and compute ltv amount
-   let sol_usdc_price = 1;
-   let sol_in_usdc = user.deposited_sol * sol_usdc_price;
-   let borrow_maximum_amount = sol_in_usdc * bank.max_ltv;
-
-   // Check for appropiate desired amount of borrowing (amount_to_borrow < borrow_maximum_amount)
-   if amount_to_borrow > borrow_maximum_amount {
-      return Err(ErrCode::InsufficientAmountToBorrow.into());
+
+   user.check_emode_borrow(bank)?;
+   bank.check_liquidity(amount_to_borrow)?;
+
+   // Record the new debt, the first borrower gets one share per token
+   let borrowed_shares = bank.borrow_amount_to_shares(amount_to_borrow)?;
+   bank.total_borrowed = checked_add(bank.total_borrowed, amount_to_borrow)?;
+   if bank.borrow_cap > 0 && bank.total_borrowed > bank.borrow_cap {
+      return Err(ErrCode::BorrowCapExceeded.into());
    }
+   bank.total_borrowed_shares = checked_add(bank.total_borrowed_shares, borrowed_shares)?;
+   bank.add_emode_debt(user, borrowed_shares)?;
+
+   let position = user.position_or_insert(&bank_key)?;
+   position.borrowed_shares = checked_add(position.borrowed_shares, borrowed_shares)?;
 
+   // Value every deposit and borrow of the user, the new debt has to stay under the borrow limit (collateral * max_ltv)
+   let oracles = oracle_accounts(
+      &ctx.accounts.oracle,
+      [&ctx.accounts.secondary_oracle_1, &ctx.accounts.secondary_oracle_2],
+   );
+   let price = get_price(bank, &oracles, now)?;
+   let acted = vec![PricedBank::new(bank_key, (**bank).clone(), price, now)];
+   let (emode, position_accounts) = load_emode_category(user, ctx.remaining_accounts)?;
+   let priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   if let Some(isolated_key) = isolated_collateral(user, &priced_banks)? {
+      let isolated_bank = ctx.accounts.isolated_bank
+         .as_mut()
+         .filter(|isolated_bank| isolated_bank.key() == isolated_key)
+         .ok_or(ErrCode::MissingIsolatedBank)?;
+      isolated_bank.add_isolated_debt(bank, amount_to_borrow)?;
+      user.isolated_bank = isolated_key;
+   }
+
+   if !health.is_within_borrow_limit() {
+      return Err(ErrCode::BorrowLimitExceeded.into());
+   }
+
+   user.record_health(&health, now);
+
+   // The treasury is its own authority, so it signs with its own seeds (not the ones of the bank)
    let mint_key = ctx.accounts.mint.key();
-   let bumbs = ctx.bumps.bank;
-   let seeds = &[mint_key.as_ref(), &[bumbs]];
+   let bumps = ctx.bumps.bank_token_account;
+   let seeds = &[b"treasury", mint_key.as_ref(), &[bumps]];
    let signer_seeds = &[&seeds[..]];
 
    // Transfer asset_to_borrow to user
    let cpi_program = ctx.accounts.token_program.to_account_info();
    let cpi_ctx = CpiContext::new_with_signer(
       cpi_program,
-      Transfer {
+      TransferChecked {
          from: ctx.accounts.bank_token_account.to_account_info(),
          to: ctx.accounts.user_token_account.to_account_info(),
-         authority: ctx.accounts.signer.to_account_info(),
+         authority: ctx.accounts.bank_token_account.to_account_info(),
+         mint: ctx.accounts.mint.to_account_info(),
       },
       signer_seeds,
    );
-   anchor_spl::token::transfer(cpi_ctx, amount_to_borrow)?;
-   
-   // Update bank state
-   // Update use state
+   token_interface::transfer_checked(cpi_ctx, amount_to_borrow, ctx.accounts.mint.decimals)?;
+
+   let bank = &ctx.accounts.bank;
+   emit_cpi!(BorrowEvent {
+      owner: ctx.accounts.user.owner,
+      bank: bank_key,
+      mint: bank.mint_address,
+      amount: amount_to_borrow,
+      shares: borrowed_shares,
+      total_borrowed: bank.total_borrowed,
+      total_borrowed_shares: bank.total_borrowed_shares,
+      borrow_index: bank.borrow_index(),
+      price,
+      health_factor: ctx.accounts.user.health_factor,
+      timestamp: now,
+   });
+
    Ok(())
-}
\ No newline at end of file
+}
@@ -3,13 +3,20 @@ use anchor_spl::{
    associated_token::AssociatedToken, 
    token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked}
 };
+use crate::error::ErrCode;
+use crate::events::{DepositEvent, InterestAccruedEvent};
+use crate::health::{check_isolated_collateral, compute_health, load_emode_category, load_priced_banks, PricedBank};
+use crate::math::checked_add;
+use crate::oracle::{get_price, oracle_accounts};
 use crate::state::*; 
 
+#[event_cpi]
 #[derive(Accounts)]
 pub struct Deposit<'info> {
    #[account(mut)]
    signer: Signer<'info>, // Do this account has the funds to make the transfer?
 
+   #[account(mint::token_program = token_program)]
    pub mint: InterfaceAccount<'info, Mint>,
    
    // Let's load the bank account
@@ -17,6 +24,8 @@ pub struct Deposit<'info> {
       mut,
       seeds = [mint.key().as_ref()], // we'll need the seeds as how they were defined
       bump,
+      constraint = bank.mint_address == mint.key() @ ErrCode::MintMismatch,
+      constraint = !bank.paused @ ErrCode::BankPaused,
    )]
    pub bank: Account<'info, Bank>,
 
@@ -26,16 +35,28 @@ pub struct Deposit<'info> {
       mut, // This will mutable because we are depositing into the account
       token::mint = mint, // (NOT associated_token::mint = mint)
       token::authority = bank_token_account, // (NEITHER associated_token::authority = bank NOR associated_token::authority = bank_token_account)
+      token::token_program = token_program,
       seeds = [b"treasury", mint.key().as_ref()], // Add seeds for PDA
       bump,
    )]
    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
 
+   /// CHECK: must be the oracle stored in the bank, its data is validated when the price is read
+   #[account(address = bank.oracle @ ErrCode::InvalidOracle)]
+   pub oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
    // The next account we will need is the user account which is storing all the information for the specific user who is using the lending protocol
    #[account(
       mut,
       seeds = [signer.key().as_ref()],
       bump,
+      constraint = user_account.owner == signer.key() @ ErrCode::Unauthorized,
    )]
    pub user_account: Account<'info, User>,
 
@@ -46,6 +67,7 @@ pub struct Deposit<'info> {
       mut, 
       token::mint = mint,
       token::authority = signer,
+      token::token_program = token_program,
       // seeds = [b"user", signer.key().as_ref()],
       // bump 
    )]
@@ -79,6 +101,7 @@ We nee to make the logic to make a deposit into the protocol
 */
 
 pub fn process_deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+   require!(amount > 0, ErrCode::InvalidAmount);
 
    // This is something similar to when your mother or father says, who authorized you to ( got out, ). So in this case someone has to authorized the transfer.
    let cpi_accounts = TransferChecked {
@@ -94,44 +117,72 @@ pub fn process_deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
 
    let decimals = ctx.accounts.mint.decimals;
 
-   let _ = token_interface::transfer_checked(cpi_ctx, amount, decimals);
+   token_interface::transfer_checked(cpi_ctx, amount, decimals)?;
 
    // update state of user token account and bank token account
 
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
    let bank  =&mut ctx.accounts.bank;
-   let user_shares:u64;
-
-   if bank.total_deposits == 0 {
-      bank.total_deposits = amount;
-      bank.total_deposit_shares = amount;
-      user_shares = bank.total_deposit_shares; 
-   } else {
-      bank.total_deposits += amount;
-
-      let deposit_ratio = amount.checked_div(bank.total_deposit_shares).unwrap();
-      user_shares = bank.total_deposit_shares.checked_mul(deposit_ratio).unwrap();
-      bank.total_deposit_shares += user_shares;
+
+   // Interest is accrued before the new deposit, so the depositor doesn't earn interest for the time before they arrived
+   let interest = bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, bank, interest));
    }
 
-   // let deposit_ratio = amount.checked_div(bank.total_deposit_shares).unwrap();
-   // let user_shares = bank.total_deposit_shares.checked_mul(deposit_ratio).unwrap();
+   /*
+      A SIMPLE RULE OF THREE
+      total_deposit_shares   -->      total_deposits
+                  x          -->      amount
+   */
+   let user_shares = bank.deposit_amount_to_shares(amount)?;
+   // Shares round down, an amount worth less than one share would be given to the other depositors
+   require!(user_shares > 0, ErrCode::InvalidAmount);
+   bank.total_deposits = checked_add(bank.total_deposits, amount)?;
+   if bank.deposit_cap > 0 && bank.total_deposits > bank.deposit_cap {
+      return Err(ErrCode::DepositCapExceeded.into());
+   }
+   bank.total_deposit_shares = checked_add(bank.total_deposit_shares, user_shares)?;
 
    let user = &mut ctx.accounts.user_account;
-   match ctx.accounts.mint.to_account_info().key() {
-      key if key == user.usdc_address => {
-         user.deposited_usdc += amount;
-         user.deposited_usdc_shares += user_shares;
-      },
-      _ => {
-         user.deposited_sol += amount;
-         user.deposited_sol_shares += user_shares;
-      },
+   // A debt the isolated bank didn't count can't end up backed by it
+   let has_debt = user.positions.iter().any(|position| position.is_active() && position.borrowed_shares > 0);
+   let entering_isolation = bank.isolated && user.position(&bank_key).map_or(0, |position| position.deposit_shares) == 0;
+   if entering_isolation && has_debt {
+      return Err(ErrCode::IsolatedCollateralMixed.into());
    }
 
-   // bank.total_deposits += amount;
-   // bank.total_deposit_shares += user_shares;
-
-   user.last_updated = Clock::get()?.unix_timestamp;
+   let position = user.position_or_insert(&bank_key)?;
+   position.deposit_shares = checked_add(position.deposit_shares, user_shares)?;
+
+   // Recompute the health of the user with the new deposit
+   let oracles = oracle_accounts(
+      &ctx.accounts.oracle,
+      [&ctx.accounts.secondary_oracle_1, &ctx.accounts.secondary_oracle_2],
+   );
+   let price = get_price(bank, &oracles, now)?;
+   let acted = vec![PricedBank::new(bank_key, (**bank).clone(), price, now)];
+   let (emode, position_accounts) = load_emode_category(user, ctx.remaining_accounts)?;
+   let priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   check_isolated_collateral(user, &priced_banks)?;
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   user.record_health(&health, now);
+
+   emit_cpi!(DepositEvent {
+      owner: user.owner,
+      bank: bank_key,
+      mint: bank.mint_address,
+      amount,
+      shares: user_shares,
+      total_deposits: bank.total_deposits,
+      total_deposit_shares: bank.total_deposit_shares,
+      deposit_index: bank.deposit_index(),
+      price,
+      health_factor: user.health_factor,
+      timestamp: now,
+   });
 
    Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,228 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::bpf_loader_upgradeable;
+use crate::constants::*;
+use crate::error::ErrCode;
+use crate::events::{BankConfigUpdated, EModeCategoryUpdated, UserEModeChanged};
+use crate::health::{compute_health, load_emode_category, load_priced_banks};
+use crate::instructions::admin::check_liquidation_bonus;
+use crate::state::*;
+
+/*
+   E-mode categories live next to the banks, one account per id. Only the upgrade authority of the program creates
+   them, like banks, and it stays the authority of the category: its parameters apply to every bank that joins, so
+   nobody else can take an id or raise them. Each bank authority decides whether its bank joins a category.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(id: u8)]
+pub struct InitEModeCategory<'info> {
+   #[account(mut)]
+   pub signer: Signer<'info>,
+
+   #[account(
+      init,
+      payer = signer,
+      space = 8 + EModeCategory::INIT_SPACE,
+      seeds = [b"emode".as_ref(), &[id]],
+      bump,
+   )]
+   pub emode_category: Account<'info, EModeCategory>,
+
+   #[account(
+      seeds = [crate::ID.as_ref()],
+      bump,
+      seeds::program = bpf_loader_upgradeable::ID,
+      constraint = program_data.upgrade_authority_address == Some(signer.key()) @ ErrCode::Unauthorized,
+   )]
+   pub program_data: Account<'info, ProgramData>,
+
+   pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateEModeCategory<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub emode_category: Account<'info, EModeCategory>,
+}
+
+/*
+   Without a category account the bank leaves its category. Users in e-mode can only be borrowing from banks of their
+   category, so a bank can't leave its category (or move to another one) while they still owe it, see Bank::add_emode_debt.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetBankEModeCategory<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub bank: Account<'info, Bank>,
+
+   pub emode_category: Option<Account<'info, EModeCategory>>,
+}
+
+/*
+   The user opts in to the category passed, or leaves e-mode without one. The remaining accounts are the ones of
+   every other instruction: the current category of the user if it has one, then the bank and oracles of each position.
+   The debt of the user moves in or out of e-mode with it, so the banks it borrows from have to be writable.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetUserEMode<'info> {
+   pub signer: Signer<'info>,
+
+   #[account(
+      mut,
+      seeds = [signer.key().as_ref()],
+      bump,
+      constraint = user_account.owner == signer.key() @ ErrCode::Unauthorized,
+   )]
+   pub user_account: Account<'info, User>,
+
+   pub emode_category: Option<Account<'info, EModeCategory>>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct EModeConfig {
+   pub max_ltv: u64,
+   pub liquidation_threshold: u64,
+   pub liquidation_bonus: u64,
+}
+
+// Same rules as the config of a bank
+fn check_emode_config(config: &EModeConfig) -> Result<()> {
+   require!(config.max_ltv <= config.liquidation_threshold, ErrCode::InvalidBankConfig);
+   require!(config.liquidation_threshold <= BPS, ErrCode::InvalidBankConfig);
+   check_liquidation_bonus(config.liquidation_threshold, config.liquidation_bonus)
+}
+
+fn category_updated(category_key: Pubkey, category: &EModeCategory, timestamp: i64) -> EModeCategoryUpdated {
+   EModeCategoryUpdated {
+      emode_category: category_key,
+      authority: category.authority,
+      id: category.id,
+      max_ltv: category.max_ltv,
+      liquidation_threshold: category.liquidation_threshold,
+      liquidation_bonus: category.liquidation_bonus,
+      timestamp,
+   }
+}
+
+pub fn process_init_emode_category(ctx: Context<InitEModeCategory>, id: u8, config: EModeConfig) -> Result<()> {
+   // 0 is the tag of banks and users without a category
+   require!(id != 0, ErrCode::InvalidEModeCategory);
+   check_emode_config(&config)?;
+
+   let category_key = ctx.accounts.emode_category.key();
+   let category = &mut ctx.accounts.emode_category;
+   category.authority = ctx.accounts.signer.key();
+   category.id = id;
+   category.max_ltv = config.max_ltv;
+   category.liquidation_threshold = config.liquidation_threshold;
+   category.liquidation_bonus = config.liquidation_bonus;
+
+   emit_cpi!(category_updated(category_key, category, Clock::get()?.unix_timestamp));
+
+   Ok(())
+}
+
+/*
+   The new parameters apply to every user of the category from its next instruction on,
+   lowering them can make users in e-mode liquidatable like lowering the ones of a bank does.
+*/
+pub fn process_update_emode_category(ctx: Context<UpdateEModeCategory>, config: EModeConfig) -> Result<()> {
+   check_emode_config(&config)?;
+
+   let category_key = ctx.accounts.emode_category.key();
+   let category = &mut ctx.accounts.emode_category;
+   category.max_ltv = config.max_ltv;
+   category.liquidation_threshold = config.liquidation_threshold;
+   category.liquidation_bonus = config.liquidation_bonus;
+
+   emit_cpi!(category_updated(category_key, category, Clock::get()?.unix_timestamp));
+
+   Ok(())
+}
+
+pub fn process_set_bank_emode_category(ctx: Context<SetBankEModeCategory>) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let category = ctx.accounts.emode_category.as_ref().map_or(0, |category| category.id);
+   let bank = &mut ctx.accounts.bank;
+   require!(category == bank.emode_category || bank.emode_borrowed_shares == 0, ErrCode::EModeDebtOutstanding);
+   bank.emode_category = category;
+
+   emit_cpi!(BankConfigUpdated::new(bank_key, bank, now));
+
+   Ok(())
+}
+
+pub fn process_set_user_emode(ctx: Context<SetUserEMode>) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let user = &mut ctx.accounts.user_account;
+
+   let (emode_before, position_accounts) = load_emode_category(user, ctx.remaining_accounts)?;
+   let priced_banks = load_priced_banks(user, Vec::new(), position_accounts, now)?;
+   let health_before = compute_health(user, &priced_banks, emode_before.as_ref())?;
+   let category_before = user.emode_category;
+
+   let emode = ctx.accounts.emode_category.as_deref().cloned();
+   update_emode_debt(user, position_accounts, Bank::remove_emode_debt)?;
+   user.emode_category = emode.as_ref().map_or(0, |category| category.id);
+
+   // Debts outside of the new category have to be repaid first
+   for position in user.positions.iter().filter(|position| position.is_active() && position.borrowed_shares > 0) {
+      let priced = priced_banks
+         .iter()
+         .find(|priced| priced.key == position.bank)
+         .ok_or(ErrCode::MissingPositionAccounts)?;
+      user.check_emode_borrow(&priced.bank)?;
+   }
+   update_emode_debt(user, position_accounts, Bank::add_emode_debt)?;
+
+   // Leaving a category lowers the borrow limit, it can't take a user over it
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+   if !health.is_within_borrow_limit() && health.health_factor() < health_before.health_factor() {
+      return Err(ErrCode::HealthDecreased.into());
+   }
+
+   user.record_health(&health, now);
+
+   emit_cpi!(UserEModeChanged {
+      owner: user.owner,
+      emode_category_before: category_before,
+      emode_category: user.emode_category,
+      health_factor_before: health_before.health_factor(),
+      health_factor: user.health_factor,
+      timestamp: now,
+   });
+
+   Ok(())
+}
+
+// Applies `change` to the emode_borrowed_shares of every bank the user borrows from, nothing to do outside of e-mode
+fn update_emode_debt(user: &User, position_accounts: &[AccountInfo], change: fn(&mut Bank, &User, u64) -> Result<()>) -> Result<()> {
+   if user.emode_category == 0 {
+      return Ok(());
+   }
+   for position in user.positions.iter().filter(|position| position.is_active() && position.borrowed_shares > 0) {
+      // Owner and type of the bank were checked by load_priced_banks
+      let bank_info = position_accounts
+         .iter()
+         .find(|info| info.key() == position.bank)
+         .ok_or(ErrCode::MissingPositionAccounts)?;
+      require!(bank_info.is_writable, ErrCode::InvalidPositionAccount);
+      let mut bank = Bank::try_deserialize(&mut &bank_info.try_borrow_data()?[..])?;
+      change(&mut bank, user, position.borrowed_shares)?;
+      bank.try_serialize(&mut &mut bank_info.try_borrow_mut_data()?[..])?;
+   }
+   Ok(())
+}
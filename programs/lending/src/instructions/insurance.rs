@@ -0,0 +1,255 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::error::ErrCode;
+use crate::events::{InsuranceStakeAction, InsuranceStakeEvent, InterestAccruedEvent};
+use crate::math::{checked_add, checked_sub};
+use crate::state::*;
+
+/*
+   Every bank has an insurance fund: its vault receives the stakes of backstop providers, and the fund is owed
+   insurance_fee_bps of the interest of the bank. Stakers own the fund through shares, so they earn the fees,
+   and they pay for bad debt before the depositors do (see socialize_loss). Unstaking takes two steps,
+   request_unstake and unstake once the cooldown of the bank is over, so a staker can't leave in front of a loss.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct StakeInsurance<'info> {
+   #[account(mut)]
+   pub staker: Signer<'info>,
+
+   #[account(mint::token_program = token_program)]
+   pub mint: InterfaceAccount<'info, Mint>,
+
+   #[account(
+      mut,
+      seeds = [mint.key().as_ref()],
+      bump,
+      constraint = bank.mint_address == mint.key() @ ErrCode::MintMismatch,
+   )]
+   pub bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = insurance_vault,
+      token::token_program = token_program,
+      seeds = [b"insurance", mint.key().as_ref()],
+      bump,
+   )]
+   pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      init_if_needed,
+      payer = staker,
+      space = 8 + InsuranceStake::INIT_SPACE,
+      seeds = [b"insurance_stake", bank.key().as_ref(), staker.key().as_ref()],
+      bump,
+   )]
+   pub stake: Account<'info, InsuranceStake>,
+
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = staker,
+      token::token_program = token_program,
+   )]
+   pub staker_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+   pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RequestUnstake<'info> {
+   pub staker: Signer<'info>,
+
+   pub bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      seeds = [b"insurance_stake", bank.key().as_ref(), staker.key().as_ref()],
+      bump,
+   )]
+   pub stake: Account<'info, InsuranceStake>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+   pub staker: Signer<'info>,
+
+   #[account(mint::token_program = token_program)]
+   pub mint: InterfaceAccount<'info, Mint>,
+
+   #[account(
+      mut,
+      seeds = [mint.key().as_ref()],
+      bump,
+      constraint = bank.mint_address == mint.key() @ ErrCode::MintMismatch,
+   )]
+   pub bank: Account<'info, Bank>,
+
+   // The fees owed to the fund are swept from here into the vault
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", mint.key().as_ref()],
+      bump,
+   )]
+   pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = insurance_vault,
+      token::token_program = token_program,
+      seeds = [b"insurance", mint.key().as_ref()],
+      bump,
+   )]
+   pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      seeds = [b"insurance_stake", bank.key().as_ref(), staker.key().as_ref()],
+      bump,
+   )]
+   pub stake: Account<'info, InsuranceStake>,
+
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = staker,
+      token::token_program = token_program,
+   )]
+   pub staker_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+}
+
+fn stake_event(bank_key: Pubkey, bank: &Bank, stake: &InsuranceStake, action: InsuranceStakeAction, amount: u64, shares: u64, now: i64) -> InsuranceStakeEvent {
+   InsuranceStakeEvent {
+      bank: bank_key,
+      staker: stake.staker,
+      action,
+      amount,
+      shares,
+      stake_shares: stake.shares,
+      unstake_shares: stake.unstake_shares,
+      insurance_funds: bank.insurance_funds,
+      insurance_fees: bank.insurance_fees,
+      insurance_shares: bank.insurance_shares,
+      timestamp: now,
+   }
+}
+
+pub fn process_stake_insurance(ctx: Context<StakeInsurance>, amount: u64) -> Result<()> {
+   require!(amount > 0, ErrCode::InvalidAmount);
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+
+   // The fees up to now belong to the stakers that were there before
+   let interest = bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, bank, interest));
+   }
+
+   bank.seed_insurance_shares()?;
+   let shares = bank.stake_amount_to_shares(amount)?;
+   require!(shares > 0, ErrCode::InvalidAmount);
+   bank.insurance_funds = checked_add(bank.insurance_funds, amount)?;
+   bank.insurance_shares = checked_add(bank.insurance_shares, shares)?;
+
+   let stake = &mut ctx.accounts.stake;
+   stake.staker = ctx.accounts.staker.key();
+   stake.bank = bank_key;
+   stake.shares = checked_add(stake.shares, shares)?;
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.staker_token_account.to_account_info(),
+      to: ctx.accounts.insurance_vault.to_account_info(),
+      authority: ctx.accounts.staker.to_account_info(),
+      mint: ctx.accounts.mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+   emit_cpi!(stake_event(bank_key, bank, stake, InsuranceStakeAction::Stake, amount, shares, now));
+
+   Ok(())
+}
+
+// A new request replaces the pending one and starts the cooldown again
+pub fn process_request_unstake(ctx: Context<RequestUnstake>, shares: u64) -> Result<()> {
+   require!(shares > 0, ErrCode::InvalidAmount);
+   let now = Clock::get()?.unix_timestamp;
+   let stake = &mut ctx.accounts.stake;
+   require!(shares <= stake.shares, ErrCode::InsufficientFunds);
+
+   stake.unstake_shares = shares;
+   stake.unstake_requested_at = now;
+
+   emit_cpi!(stake_event(ctx.accounts.bank.key(), &ctx.accounts.bank, stake, InsuranceStakeAction::RequestUnstake, 0, shares, now));
+
+   Ok(())
+}
+
+pub fn process_unstake(ctx: Context<Unstake>) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   ctx.accounts.stake.check_unstake(ctx.accounts.bank.insurance_cooldown, now)?;
+
+   let interest = ctx.accounts.bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, &ctx.accounts.bank, interest));
+   }
+
+   let bank = &mut ctx.accounts.bank;
+   let stake = &mut ctx.accounts.stake;
+   let mint_key = ctx.accounts.mint.key();
+
+   // The value of the shares includes the fees, the ones the treasury can give are moved into the vault first
+   let swept = bank.sweepable_insurance_fees(ctx.accounts.bank_token_account.amount);
+   if swept > 0 {
+      bank.insurance_fees = checked_sub(bank.insurance_fees, swept)?;
+      bank.insurance_funds = checked_add(bank.insurance_funds, swept)?;
+
+      let seeds = &[b"treasury", mint_key.as_ref(), &[ctx.bumps.bank_token_account]];
+      let signer = &[&seeds[..]];
+      let cpi_accounts = TransferChecked {
+         from: ctx.accounts.bank_token_account.to_account_info(),
+         to: ctx.accounts.insurance_vault.to_account_info(),
+         authority: ctx.accounts.bank_token_account.to_account_info(),
+         mint: ctx.accounts.mint.to_account_info(),
+      };
+      let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+      token_interface::transfer_checked(cpi_ctx, swept, ctx.accounts.mint.decimals)?;
+   }
+
+   let shares = stake.unstake_shares;
+   let amount = bank.stake_shares_to_amount(shares)?;
+   require!(amount <= bank.insurance_funds, ErrCode::InsuranceFundIlliquid);
+
+   bank.insurance_funds = checked_sub(bank.insurance_funds, amount)?;
+   bank.insurance_shares = checked_sub(bank.insurance_shares, shares)?;
+   stake.shares = checked_sub(stake.shares, shares)?;
+   stake.unstake_shares = 0;
+
+   let seeds = &[b"insurance", mint_key.as_ref(), &[ctx.bumps.insurance_vault]];
+   let signer = &[&seeds[..]];
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.insurance_vault.to_account_info(),
+      to: ctx.accounts.staker_token_account.to_account_info(),
+      authority: ctx.accounts.insurance_vault.to_account_info(),
+      mint: ctx.accounts.mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+   token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+   emit_cpi!(stake_event(bank_key, bank, stake, InsuranceStakeAction::Unstake, amount, shares, now));
+
+   Ok(())
+}
@@ -0,0 +1,321 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::BPS;
+use crate::error::ErrCode;
+use crate::events::{InterestAccruedEvent, LeverageEvent};
+use crate::health::{check_isolated_collateral, compute_health, isolated_collateral, load_emode_category, load_priced_banks, PricedBank};
+use crate::instructions::repay_with_collateral::{unwind, RepayWithCollateral};
+use crate::math::*;
+use crate::oracle::{get_price, oracle_accounts};
+use crate::state::*;
+use crate::swap::{split_remaining_accounts, swap};
+
+/*
+   Loops a position in one go, instead of borrowing, swapping and depositing again and again:
+   the borrowed bank lends the tokens first (a flash loan that only exists inside the instruction), the swap adapter turns them
+   into collateral, the collateral is deposited, and only then the debt is recorded and the borrow limit checked.
+   The amount borrowed takes the LTV of the user to `target_ltv` if the swap is at the oracle prices.
+   Remaining accounts: the bank and oracles of the other positions of the user, then the accounts of the swap adapter.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Leverage<'info> {
+   #[account(mut)]
+   pub signer: Signer<'info>,
+
+   #[account(mint::token_program = token_program)]
+   pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+   #[account(mint::token_program = token_program)]
+   pub borrowed_mint: InterfaceAccount<'info, Mint>,
+
+   // A deposit and a borrow, so both banks have to be running
+   #[account(
+      mut,
+      seeds = [collateral_mint.key().as_ref()],
+      bump,
+      constraint = collateral_bank.mint_address == collateral_mint.key() @ ErrCode::MintMismatch,
+      constraint = collateral_bank.key() != borrowed_bank.key() @ ErrCode::SameSwapBank,
+      constraint = !collateral_bank.paused @ ErrCode::BankPaused,
+   )]
+   pub collateral_bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      seeds = [borrowed_mint.key().as_ref()],
+      bump,
+      constraint = borrowed_bank.mint_address == borrowed_mint.key() @ ErrCode::MintMismatch,
+      constraint = !borrowed_bank.paused @ ErrCode::BankPaused,
+   )]
+   pub borrowed_bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      token::mint = collateral_mint,
+      token::authority = collateral_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", collateral_mint.key().as_ref()],
+      bump,
+   )]
+   pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      token::mint = borrowed_mint,
+      token::authority = borrowed_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", borrowed_mint.key().as_ref()],
+      bump,
+   )]
+   pub borrowed_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: must be the oracle stored in the collateral bank, its data is validated when the price is read
+   #[account(address = collateral_bank.oracle @ ErrCode::InvalidOracle)]
+   pub collateral_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the collateral bank if it has one, checked against the bank when the price is read
+   pub collateral_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the collateral bank if it has one, checked against the bank when the price is read
+   pub collateral_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: must be the oracle stored in the borrowed bank, its data is validated when the price is read
+   #[account(address = borrowed_bank.oracle @ ErrCode::InvalidOracle)]
+   pub borrowed_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the borrowed bank if it has one, checked against the bank when the price is read
+   pub borrowed_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the borrowed bank if it has one, checked against the bank when the price is read
+   pub borrowed_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   #[account(
+      mut,
+      seeds = [signer.key().as_ref()],
+      bump,
+      constraint = user_account.owner == signer.key() @ ErrCode::Unauthorized,
+   )]
+   pub user_account: Account<'info, User>,
+
+   // The swap pays into this account and the deposit leaves from it
+   #[account(
+      mut,
+      token::mint = collateral_mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   // The borrowed tokens go through this account on their way to the swap
+   #[account(
+      mut,
+      token::mint = borrowed_mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_borrowed_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: any program the user trusts with the borrowed tokens, what it returns is measured after the call
+   #[account(
+      executable,
+      constraint = swap_program.key() != crate::ID @ ErrCode::InvalidSwapAdapter,
+   )]
+   pub swap_program: UncheckedAccount<'info>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn process_leverage<'info>(
+   ctx: Context<'_, '_, '_, 'info, Leverage<'info>>,
+   target_ltv: u64,
+   min_amount_out: u64,
+) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let collateral_bank_key = ctx.accounts.collateral_bank.key();
+   let borrowed_bank_key = ctx.accounts.borrowed_bank.key();
+
+   let interest = ctx.accounts.collateral_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(collateral_bank_key, &ctx.accounts.collateral_bank, interest));
+   }
+   let interest = ctx.accounts.borrowed_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(borrowed_bank_key, &ctx.accounts.borrowed_bank, interest));
+   }
+
+   let collateral_oracles = oracle_accounts(
+      &ctx.accounts.collateral_oracle,
+      [&ctx.accounts.collateral_secondary_oracle_1, &ctx.accounts.collateral_secondary_oracle_2],
+   );
+   let collateral_price = get_price(&ctx.accounts.collateral_bank, &collateral_oracles, now)?;
+   let borrowed_oracles = oracle_accounts(
+      &ctx.accounts.borrowed_oracle,
+      [&ctx.accounts.borrowed_secondary_oracle_1, &ctx.accounts.borrowed_secondary_oracle_2],
+   );
+   let borrowed_price = get_price(&ctx.accounts.borrowed_bank, &borrowed_oracles, now)?;
+
+   let user = &ctx.accounts.user_account;
+   user.check_emode_borrow(&ctx.accounts.borrowed_bank)?;
+   let (position_accounts, adapter_accounts) =
+      split_remaining_accounts(user, &[collateral_bank_key, borrowed_bank_key], ctx.remaining_accounts)?;
+   let (emode, position_accounts) = load_emode_category(user, position_accounts)?;
+
+   // In e-mode a loop of correlated assets can go up to the max LTV of the category.
+   // The target is an LTV at market value, with a borrow weight above 1x the borrow limit is reached before max_ltv.
+   let max_ltv = ctx.accounts.collateral_bank.risk_params(emode.as_ref()).max_ltv;
+   require!(target_ltv > 0 && target_ltv <= max_ltv, ErrCode::InvalidTargetLtv);
+
+   let acted = vec![
+      PricedBank::new(collateral_bank_key, (*ctx.accounts.collateral_bank).clone(), collateral_price, now),
+      PricedBank::new(borrowed_bank_key, (*ctx.accounts.borrowed_bank).clone(), borrowed_price, now),
+   ];
+   let mut priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   let health_before = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   /*
+      The value x borrowed is also deposited, so
+      (debt + x) / (collateral + x) = target_ltv   ->   x = (target_ltv * collateral - debt) / (1 - target_ltv)
+   */
+   let target_debt = health_before.collateral_value.checked_mul(target_ltv as u128).ok_or(ErrCode::MathOverflow)?;
+   let debt = health_before.debt_value.checked_mul(BPS as u128).ok_or(ErrCode::MathOverflow)?;
+   if target_debt <= debt {
+      return Err(ErrCode::InvalidTargetLtv.into());
+   }
+   let divisor = (BPS - target_ltv) as u128 * borrowed_price.max(1) as u128;
+   let borrowed_amount = mul_div_u128(target_debt - debt, decimals_scale(ctx.accounts.borrowed_bank.decimals)?, divisor)?;
+   let borrowed_amount = to_u64(borrowed_amount)?;
+   require!(borrowed_amount > 0, ErrCode::InvalidAmount);
+   ctx.accounts.borrowed_bank.check_liquidity(borrowed_amount)?;
+
+   // The borrowed bank lends the tokens before the debt exists, it is checked once the collateral is in
+   let borrowed_mint_key = ctx.accounts.borrowed_mint.key();
+   let seeds = &[b"treasury", borrowed_mint_key.as_ref(), &[ctx.bumps.borrowed_bank_token_account]];
+   let signer = &[&seeds[..]];
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.borrowed_bank_token_account.to_account_info(),
+      to: ctx.accounts.user_borrowed_token_account.to_account_info(),
+      authority: ctx.accounts.borrowed_bank_token_account.to_account_info(),
+      mint: ctx.accounts.borrowed_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+   token_interface::transfer_checked(cpi_ctx, borrowed_amount, ctx.accounts.borrowed_mint.decimals)?;
+
+   let deposited_amount = swap(
+      &ctx.accounts.swap_program.to_account_info(),
+      &ctx.accounts.signer.to_account_info(),
+      &mut ctx.accounts.user_borrowed_token_account,
+      &mut ctx.accounts.user_collateral_token_account,
+      adapter_accounts,
+      borrowed_amount,
+      min_amount_out,
+   )?;
+   require!(deposited_amount > 0, ErrCode::InvalidAmount);
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.user_collateral_token_account.to_account_info(),
+      to: ctx.accounts.collateral_bank_token_account.to_account_info(),
+      authority: ctx.accounts.signer.to_account_info(),
+      mint: ctx.accounts.collateral_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::transfer_checked(cpi_ctx, deposited_amount, ctx.accounts.collateral_mint.decimals)?;
+
+   let collateral_bank = &mut ctx.accounts.collateral_bank;
+   let borrowed_bank = &mut ctx.accounts.borrowed_bank;
+   let user = &mut ctx.accounts.user_account;
+
+   // Same bookkeeping as a deposit and a borrow, caps included
+   let deposited_shares = collateral_bank.deposit_amount_to_shares(deposited_amount)?;
+   collateral_bank.total_deposits = checked_add(collateral_bank.total_deposits, deposited_amount)?;
+   if collateral_bank.deposit_cap > 0 && collateral_bank.total_deposits > collateral_bank.deposit_cap {
+      return Err(ErrCode::DepositCapExceeded.into());
+   }
+   collateral_bank.total_deposit_shares = checked_add(collateral_bank.total_deposit_shares, deposited_shares)?;
+
+   let borrowed_shares = borrowed_bank.borrow_amount_to_shares(borrowed_amount)?;
+   borrowed_bank.total_borrowed = checked_add(borrowed_bank.total_borrowed, borrowed_amount)?;
+   if borrowed_bank.borrow_cap > 0 && borrowed_bank.total_borrowed > borrowed_bank.borrow_cap {
+      return Err(ErrCode::BorrowCapExceeded.into());
+   }
+   borrowed_bank.total_borrowed_shares = checked_add(borrowed_bank.total_borrowed_shares, borrowed_shares)?;
+   borrowed_bank.add_emode_debt(user, borrowed_shares)?;
+
+   let position = user.position_or_insert(&collateral_bank_key)?;
+   position.deposit_shares = checked_add(position.deposit_shares, deposited_shares)?;
+   let position = user.position_or_insert(&borrowed_bank_key)?;
+   position.borrowed_shares = checked_add(position.borrowed_shares, borrowed_shares)?;
+
+   for priced in priced_banks.iter_mut() {
+      if priced.key == collateral_bank_key {
+         priced.bank = (**collateral_bank).clone();
+      } else if priced.key == borrowed_bank_key {
+         priced.bank = (**borrowed_bank).clone();
+      }
+   }
+
+   // Isolated collateral is looked up once the deposit is in, the loop may be what opens the isolated position.
+   // Looping it borrows against it like a borrow does, next to any other collateral it is rejected.
+   check_isolated_collateral(user, &priced_banks)?;
+   if isolated_collateral(user, &priced_banks)? == Some(collateral_bank_key) {
+      collateral_bank.add_isolated_debt(borrowed_bank, borrowed_amount)?;
+      user.isolated_bank = collateral_bank_key;
+   }
+
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   // A bad swap shows up here: less collateral for the same debt
+   if !health.is_within_borrow_limit() {
+      return Err(ErrCode::BorrowLimitExceeded.into());
+   }
+
+   user.record_health(&health, now);
+
+   emit_cpi!(LeverageEvent {
+      owner: user.owner,
+      collateral_bank: collateral_bank_key,
+      borrowed_bank: borrowed_bank_key,
+      swap_program: ctx.accounts.swap_program.key(),
+      borrowed_amount,
+      borrowed_shares,
+      deposited_amount,
+      deposited_shares,
+      collateral_price,
+      borrowed_price,
+      target_ltv,
+      ltv: health.ltv(),
+      health_factor: user.health_factor,
+      timestamp: now,
+   });
+
+   Ok(())
+}
+
+/*
+   The inverse of leverage, with the accounts of repay_with_collateral: the collateral swapped is the one that takes
+   the LTV of the user down to `target_ltv` if the swap is at the oracle prices. A target of 0 swaps collateral worth
+   the whole debt, to close the loop. Emits a RepayWithCollateralEvent like repay_with_collateral.
+*/
+pub fn process_deleverage<'info>(
+   ctx: Context<'_, '_, '_, 'info, RepayWithCollateral<'info>>,
+   target_ltv: u64,
+   min_amount_out: u64,
+) -> Result<()> {
+   require!(target_ltv < BPS, ErrCode::InvalidTargetLtv);
+
+   unwind(
+      ctx,
+      |health, collateral, deposited| {
+         // (debt - y) / (collateral - y) = target_ltv   ->   y = (debt - target_ltv * collateral) / (1 - target_ltv)
+         let target_debt = health.collateral_value.checked_mul(target_ltv as u128).ok_or(ErrCode::MathOverflow)?;
+         let debt = health.debt_value.checked_mul(BPS as u128).ok_or(ErrCode::MathOverflow)?;
+         if debt <= target_debt {
+            return Err(ErrCode::InvalidTargetLtv.into());
+         }
+         let divisor = (BPS - target_ltv) as u128 * collateral.price.max(1) as u128;
+         let collateral_amount = mul_div_ceil_u128(debt - target_debt, decimals_scale(collateral.bank.decimals)?, divisor)?;
+         Ok(to_u64(collateral_amount)?.min(deposited))
+      },
+      min_amount_out,
+   )
+}
@@ -0,0 +1,302 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::constants::BPS;
+use crate::error::ErrCode;
+use crate::events::{InterestAccruedEvent, LiquidationEvent};
+use crate::health::{compute_health, isolated_collateral, load_emode_category, load_priced_banks, PricedBank};
+use crate::math::*;
+use crate::oracle::{get_price, oracle_accounts};
+use crate::state::*;
+
+/*
+   A liquidator pays back part of the debt of an undercollateralized user in the borrowed bank,
+   and in exchange receives the same value plus the liquidation bonus from the user's collateral in the collateral bank.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Liquidate<'info> {
+   #[account(mut)]
+   pub liquidator: Signer<'info>,
+
+   #[account(mint::token_program = token_program)]
+   pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+   #[account(mint::token_program = token_program)]
+   pub borrowed_mint: InterfaceAccount<'info, Mint>,
+
+   #[account(
+      mut,
+      seeds = [collateral_mint.key().as_ref()],
+      bump,
+      constraint = collateral_bank.mint_address == collateral_mint.key() @ ErrCode::MintMismatch,
+      constraint = collateral_bank.key() != borrowed_bank.key() @ ErrCode::SameLiquidationBank,
+   )]
+   pub collateral_bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      seeds = [borrowed_mint.key().as_ref()],
+      bump,
+      constraint = borrowed_bank.mint_address == borrowed_mint.key() @ ErrCode::MintMismatch,
+   )]
+   pub borrowed_bank: Account<'info, Bank>,
+
+   // The seized collateral leaves this account
+   #[account(
+      mut,
+      token::mint = collateral_mint,
+      token::authority = collateral_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", collateral_mint.key().as_ref()],
+      bump,
+   )]
+   pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   // The repaid debt goes into this account
+   #[account(
+      mut,
+      token::mint = borrowed_mint,
+      token::authority = borrowed_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", borrowed_mint.key().as_ref()],
+      bump,
+   )]
+   pub borrowed_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: must be the oracle stored in the collateral bank, its data is validated when the price is read
+   #[account(address = collateral_bank.oracle @ ErrCode::InvalidOracle)]
+   pub collateral_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the collateral bank if it has one, checked against the bank when the price is read
+   pub collateral_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the collateral bank if it has one, checked against the bank when the price is read
+   pub collateral_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: must be the oracle stored in the borrowed bank, its data is validated when the price is read
+   #[account(address = borrowed_bank.oracle @ ErrCode::InvalidOracle)]
+   pub borrowed_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the borrowed bank if it has one, checked against the bank when the price is read
+   pub borrowed_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the borrowed bank if it has one, checked against the bank when the price is read
+   pub borrowed_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   // The user that is being liquidated, it has to be the user PDA of its owner
+   #[account(
+      mut,
+      seeds = [user_account.owner.as_ref()],
+      bump,
+   )]
+   pub user_account: Account<'info, User>,
+
+   #[account(
+      mut,
+      token::mint = collateral_mint,
+      token::authority = liquidator,
+      token::token_program = token_program,
+   )]
+   pub liquidator_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      token::mint = borrowed_mint,
+      token::authority = liquidator,
+      token::token_program = token_program,
+   )]
+   pub liquidator_borrowed_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn process_liquidate(ctx: Context<Liquidate>, amount: u64) -> Result<()> {
+   require!(amount > 0, ErrCode::InvalidAmount);
+   let now = Clock::get()?.unix_timestamp;
+   let collateral_bank_key = ctx.accounts.collateral_bank.key();
+   let borrowed_bank_key = ctx.accounts.borrowed_bank.key();
+
+   let interest = ctx.accounts.collateral_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(collateral_bank_key, &ctx.accounts.collateral_bank, interest));
+   }
+   let interest = ctx.accounts.borrowed_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(borrowed_bank_key, &ctx.accounts.borrowed_bank, interest));
+   }
+
+   let collateral_bank = &mut ctx.accounts.collateral_bank;
+   let borrowed_bank = &mut ctx.accounts.borrowed_bank;
+   let user = &mut ctx.accounts.user_account;
+
+   let collateral_oracles = oracle_accounts(
+      &ctx.accounts.collateral_oracle,
+      [&ctx.accounts.collateral_secondary_oracle_1, &ctx.accounts.collateral_secondary_oracle_2],
+   );
+   let collateral_price = get_price(collateral_bank, &collateral_oracles, now)?;
+   let borrowed_oracles = oracle_accounts(
+      &ctx.accounts.borrowed_oracle,
+      [&ctx.accounts.borrowed_secondary_oracle_1, &ctx.accounts.borrowed_secondary_oracle_2],
+   );
+   let borrowed_price = get_price(borrowed_bank, &borrowed_oracles, now)?;
+
+   // Only users whose debt is above collateral * liquidation_threshold can be liquidated
+   let acted = vec![
+      PricedBank::new(collateral_bank_key, (**collateral_bank).clone(), collateral_price, now),
+      PricedBank::new(borrowed_bank_key, (**borrowed_bank).clone(), borrowed_price, now),
+   ];
+   let (emode, position_accounts) = load_emode_category(user, ctx.remaining_accounts)?;
+   let priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   let health_before = compute_health(user, &priced_banks, emode.as_ref())?;
+   let isolated = isolated_collateral(user, &priced_banks)?;
+
+   if !health_before.is_liquidatable() {
+      return Err(ErrCode::NotUndercollateralized.into());
+   }
+   // A user nobody flagged before starts its liquidation auction now
+   user.record_health(&health_before, now);
+
+   // The bonus of the e-mode category of the user when the collateral is in it, or where the auction of the bank is at
+   let liquidation_bonus = collateral_bank.liquidation_bonus(emode.as_ref(), user.unhealthy_since, now)?;
+   let seizure = Seizure::new(user, &priced_banks, collateral_bank_key, borrowed_bank_key, amount, liquidation_bonus)?;
+   seizure.apply(user, collateral_bank, borrowed_bank, isolated)?;
+
+   let mut priced_banks = priced_banks;
+   for priced in priced_banks.iter_mut() {
+      if priced.key == collateral_bank_key {
+         priced.bank = (**collateral_bank).clone();
+      } else if priced.key == borrowed_bank_key {
+         priced.bank = (**borrowed_bank).clone();
+      }
+   }
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   user.record_health(&health, now);
+
+   // Liquidator pays the debt back to the borrowed bank
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.liquidator_borrowed_token_account.to_account_info(),
+      to: ctx.accounts.borrowed_bank_token_account.to_account_info(),
+      authority: ctx.accounts.liquidator.to_account_info(),
+      mint: ctx.accounts.borrowed_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.borrowed_mint.decimals)?;
+
+   // And the collateral bank pays the liquidator with the user's collateral
+   let collateral_mint_key = ctx.accounts.collateral_mint.key();
+   let seeds = &[b"treasury", collateral_mint_key.as_ref(), &[ctx.bumps.collateral_bank_token_account]];
+   let signer = &[&seeds[..]];
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.collateral_bank_token_account.to_account_info(),
+      to: ctx.accounts.liquidator_collateral_token_account.to_account_info(),
+      authority: ctx.accounts.collateral_bank_token_account.to_account_info(),
+      mint: ctx.accounts.collateral_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+   token_interface::transfer_checked(cpi_ctx, seizure.seized_amount, ctx.accounts.collateral_mint.decimals)?;
+
+   emit_cpi!(LiquidationEvent {
+      liquidator: ctx.accounts.liquidator.key(),
+      owner: ctx.accounts.user_account.owner,
+      collateral_bank: collateral_bank_key,
+      borrowed_bank: borrowed_bank_key,
+      repaid_amount: amount,
+      repaid_shares: seizure.repaid_shares,
+      seized_amount: seizure.seized_amount,
+      seized_shares: seizure.seized_shares,
+      liquidation_bonus,
+      collateral_price,
+      borrowed_price,
+      health_factor_before: health_before.health_factor(),
+      health_factor: ctx.accounts.user_account.health_factor,
+      timestamp: now,
+   });
+
+   Ok(())
+}
+
+/*
+   What a liquidation takes from the user: `amount` of its debt in the borrowed bank is repaid, and the same value plus `bonus`
+   is seized from its collateral in the collateral bank (all of it when it is worth less). Shared with self_liquidate,
+   the two banks are the accrued ones in `priced_banks`.
+*/
+pub(crate) struct Seizure {
+   pub collateral_bank: Pubkey,
+   pub borrowed_bank: Pubkey,
+   pub repaid_amount: u64,
+   pub repaid_shares: u64,
+   pub seized_amount: u64,
+   pub seized_shares: u64,
+   pub bonus_amount: u64, // the part of seized_amount above the value of the repaid debt
+}
+
+impl Seizure {
+   pub(crate) fn new(user: &User, priced_banks: &[PricedBank], collateral_bank: Pubkey, borrowed_bank: Pubkey, amount: u64, bonus: u64) -> Result<Seizure> {
+      let priced = |key: Pubkey| priced_banks.iter().find(|priced| priced.key == key).ok_or(ErrCode::MissingPositionAccounts);
+      let (collateral, borrowed) = (priced(collateral_bank)?, priced(borrowed_bank)?);
+
+      // At most liquidation_close_factor of the debt can be repaid in one go
+      let borrowed_shares = user.position(&borrowed_bank).map_or(0, |position| position.borrowed_shares);
+      let borrowed_value = borrowed.bank.borrowed_shares_to_amount(borrowed_shares)?;
+      let max_liquidation = mul_div(borrowed_value, borrowed.bank.liquidation_close_factor, BPS)?;
+
+      if amount > max_liquidation {
+         return Err(ErrCode::OverLiquidation.into());
+      }
+
+      // Value of the repaid debt plus the bonus, converted into collateral tokens
+      let deposit_shares = user.position(&collateral_bank).map_or(0, |position| position.deposit_shares);
+      let collateral_value = collateral.bank.deposit_shares_to_amount(deposit_shares)?;
+
+      let repaid_value = token_value(amount, borrowed.price, borrowed.bank.decimals)?;
+      let bonus_factor = checked_add(BPS, bonus)?;
+      let seized_value = mul_div_u128(repaid_value, bonus_factor as u128, BPS as u128)?;
+      let seized_amount = value_to_amount(seized_value, collateral.price, collateral.bank.decimals)?.min(collateral_value);
+      let repaid_in_collateral = value_to_amount(repaid_value, collateral.price, collateral.bank.decimals)?;
+
+      let repaid_shares = if amount == borrowed_value {
+         borrowed_shares
+      } else {
+         borrowed.bank.repay_amount_to_shares(amount)?
+      };
+      let seized_shares = if seized_amount == collateral_value {
+         deposit_shares
+      } else {
+         collateral.bank.withdraw_amount_to_shares(seized_amount)?.min(deposit_shares)
+      };
+
+      Ok(Seizure {
+         collateral_bank,
+         borrowed_bank,
+         repaid_amount: amount,
+         repaid_shares,
+         seized_amount,
+         seized_shares,
+         bonus_amount: seized_amount.saturating_sub(repaid_in_collateral),
+      })
+   }
+
+   // Moves the shares of the user and the totals of the banks, the tokens are moved by the instruction
+   pub(crate) fn apply(&self, user: &mut User, collateral_bank: &mut Bank, borrowed_bank: &mut Bank, isolated: Option<Pubkey>) -> Result<()> {
+      let position = user.position_mut(&self.borrowed_bank).ok_or(ErrCode::OverLiquidation)?;
+      position.borrowed_shares = checked_sub(position.borrowed_shares, self.repaid_shares)?;
+      borrowed_bank.total_borrowed = checked_sub(borrowed_bank.total_borrowed, self.repaid_amount)?;
+      borrowed_bank.total_borrowed_shares = checked_sub(borrowed_bank.total_borrowed_shares, self.repaid_shares)?;
+      borrowed_bank.remove_emode_debt(user, self.repaid_shares)?;
+
+      let position = user.position_mut(&self.collateral_bank).ok_or(ErrCode::NotUndercollateralized)?;
+      position.deposit_shares = checked_sub(position.deposit_shares, self.seized_shares)?;
+      collateral_bank.total_deposits = checked_sub(collateral_bank.total_deposits, self.seized_amount)?;
+      collateral_bank.total_deposit_shares = checked_sub(collateral_bank.total_deposit_shares, self.seized_shares)?;
+
+      // Isolated collateral is the only collateral of its users, so it is the one seized (any other seizes nothing)
+      if isolated == Some(self.collateral_bank) {
+         collateral_bank.remove_isolated_debt(borrowed_bank, self.repaid_amount)?;
+      }
+
+      user.close_empty_positions();
+      Ok(())
+   }
+}
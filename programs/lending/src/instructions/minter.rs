@@ -1,44 +1,139 @@
 use anchor_lang::prelude::*;
-use anchor_spl::{
-   associated_token::AssociatedToken, 
-   token::{mint_to, MintTo}, 
-   token_interface::{Mint, TokenAccount, TokenInterface} 
+use anchor_spl::token_interface::{
+   self, spl_token_2022::instruction::AuthorityType, Mint, MintTo, SetAuthority, TokenAccount, TokenInterface,
 };
+use crate::error::ErrCode;
+use crate::state::{Faucet, FaucetClaim};
 
+/*
+   Test networks only (built with the devnet-faucet feature).
+   The mint authority of a test mint is handed over to a Faucet PDA, and from then on anyone can
+   request up to max_amount tokens, once every cooldown seconds per wallet.
+*/
+#[derive(Accounts)]
+pub struct InitFaucet<'info> {
+   // The current mint authority, it gives the authority to the faucet
+   #[account(mut)]
+   pub signer: Signer<'info>,
+
+   #[account(
+      mut,
+      mint::authority = signer,
+      mint::token_program = token_program,
+   )]
+   pub mint: InterfaceAccount<'info, Mint>,
+
+   #[account(
+      init,
+      payer = signer,
+      space = 8 + Faucet::INIT_SPACE,
+      seeds = [b"faucet", mint.key().as_ref()],
+      bump,
+   )]
+   pub faucet: Account<'info, Faucet>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+   pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateFaucet<'info> {
+   pub authority: Signer<'info>,
+
+   #[account(
+      mut,
+      has_one = authority @ ErrCode::Unauthorized,
+   )]
+   pub faucet: Account<'info, Faucet>,
+}
 
 #[derive(Accounts)]
 pub struct MintTokens<'info> {
    #[account(mut)]
    pub signer: Signer<'info>,
 
+   #[account(
+      mut,
+      mint::authority = faucet,
+      mint::token_program = token_program,
+   )]
    pub mint: InterfaceAccount<'info, Mint>,
 
    #[account(
-      mut, 
+      seeds = [b"faucet", mint.key().as_ref()],
+      bump = faucet.bump,
+      has_one = mint @ ErrCode::MintMismatch,
+   )]
+   pub faucet: Account<'info, Faucet>,
+
+   // Remembers when this wallet last used the faucet of this mint
+   #[account(
+      init_if_needed,
+      payer = signer,
+      space = 8 + FaucetClaim::INIT_SPACE,
+      seeds = [b"faucet_claim", mint.key().as_ref(), signer.key().as_ref()],
+      bump,
+   )]
+   pub claim: Account<'info, FaucetClaim>,
+
+   #[account(
+      mut,
       token::mint = mint,
       token::authority = signer,
-      seeds = [b"user", signer.key().as_ref()],
-      bump 
+      token::token_program = token_program,
    )]
    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
 
+   pub token_program: Interface<'info, TokenInterface>,
    pub system_program: Program<'info, System>,
-   // pub token_program: Program<'info, Token>,
-   pub token_program: Interface<'info, TokenInterface>, 
-   pub associated_token_program: Program<'info, AssociatedToken>
+}
+
+pub fn process_init_faucet(ctx: Context<InitFaucet>, max_amount: u64, cooldown: u64) -> Result<()> {
+   let faucet = &mut ctx.accounts.faucet;
+   faucet.authority = ctx.accounts.signer.key();
+   faucet.mint = ctx.accounts.mint.key();
+   faucet.max_amount = max_amount;
+   faucet.cooldown = cooldown;
+   faucet.bump = ctx.bumps.faucet;
+
+   let cpi_accounts = SetAuthority {
+      current_authority: ctx.accounts.signer.to_account_info(),
+      account_or_mint: ctx.accounts.mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::set_authority(cpi_ctx, AuthorityType::MintTokens, Some(ctx.accounts.faucet.key()))?;
 
+   Ok(())
+}
+
+pub fn process_update_faucet(ctx: Context<UpdateFaucet>, max_amount: u64, cooldown: u64) -> Result<()> {
+   let faucet = &mut ctx.accounts.faucet;
+   faucet.max_amount = max_amount;
+   faucet.cooldown = cooldown;
+   Ok(())
 }
 
 pub fn process_mint_tokens(ctx: Context<MintTokens>, quantity:u64) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let faucet = &ctx.accounts.faucet;
+   let claim = &mut ctx.accounts.claim;
+
+   faucet.check_claim(claim, quantity, now)?;
+   claim.last_claimed = now;
+
+   let mint_key = ctx.accounts.mint.key();
+   let seeds = &[b"faucet", mint_key.as_ref(), &[faucet.bump]];
+   let signer = &[&seeds[..]];
 
-   mint_to(
-      CpiContext::new(
-         ctx.accounts.token_program.to_account_info(), 
+   token_interface::mint_to(
+      CpiContext::new_with_signer(
+         ctx.accounts.token_program.to_account_info(),
          MintTo{
             mint: ctx.accounts.mint.to_account_info(),
             to: ctx.accounts.user_token_account.to_account_info(),
-            authority: ctx.accounts.signer.to_account_info()
+            authority: ctx.accounts.faucet.to_account_info()
          },
+         signer,
       ),
       quantity
    )?;
@@ -5,7 +5,10 @@ pub mod admin;
 pub use deposit::*;
 pub mod deposit;
 
+// The faucet is only compiled for test networks
+#[cfg(feature = "devnet-faucet")]
 pub use minter::*;
+#[cfg(feature = "devnet-faucet")]
 pub mod minter;
 
 pub use withdraw::*;
@@ -14,6 +17,36 @@ pub mod withdraw;
 pub use borrow::*;
 pub mod borrow;
 
+pub use repay::*;
+pub mod repay;
+
+pub use liquidate::*;
+pub mod liquidate;
+
+pub use self_liquidate::*;
+pub mod self_liquidate;
+
+pub use repay_with_collateral::*;
+pub mod repay_with_collateral;
+
+pub use leverage::*;
+pub mod leverage;
+
+pub use swap_position::*;
+pub mod swap_position;
+
+pub use socialize_loss::*;
+pub mod socialize_loss;
+
+pub use insurance::*;
+pub mod insurance;
+
+pub use refresh::*;
+pub mod refresh;
+
+pub use emode::*;
+pub mod emode;
+
 
 
 
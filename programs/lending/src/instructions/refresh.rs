@@ -0,0 +1,96 @@
+use anchor_lang::prelude::*;
+use crate::error::ErrCode;
+use crate::events::{BankRefreshed, InterestAccruedEvent, UserRefreshed};
+use crate::health::{compute_health, load_emode_category, load_priced_banks};
+use crate::oracle::{get_price_and_time, oracle_accounts};
+use crate::state::*;
+
+/*
+   Interest only accrues when an instruction touches the bank, so the totals of an idle bank get old.
+   Anyone can refresh a bank: it accrues the interest up to now and caches the oracle price in the bank,
+   so the account alone shows current totals and a recent price. Paused banks can be refreshed too.
+   The price is also folded into the moving average of the bank, which health checks compare it with.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RefreshBank<'info> {
+   #[account(mut)]
+   pub bank: Account<'info, Bank>,
+
+   /// CHECK: must be the oracle stored in the bank, its data is validated when the price is read
+   #[account(address = bank.oracle @ ErrCode::InvalidOracle)]
+   pub oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_2: Option<UncheckedAccount<'info>>,
+}
+
+pub fn process_refresh_bank(ctx: Context<RefreshBank>) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+
+   let interest = bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, bank, interest));
+   }
+
+   // A stale price is rejected like everywhere else, the cache only ever holds prices the program would use
+   let oracles = oracle_accounts(&ctx.accounts.oracle, [&ctx.accounts.secondary_oracle_1, &ctx.accounts.secondary_oracle_2]);
+   let (price, price_updated) = get_price_and_time(bank, &oracles, now)?;
+   bank.last_price = price;
+   bank.last_price_updated = price_updated;
+   bank.update_ema(price, now);
+
+   emit_cpi!(BankRefreshed {
+      bank: bank_key,
+      price: bank.last_price,
+      price_updated: bank.last_price_updated,
+      ema_price: bank.ema_price,
+      total_deposits: bank.total_deposits,
+      total_borrowed: bank.total_borrowed,
+      deposit_index: bank.deposit_index(),
+      borrow_index: bank.borrow_index(),
+      timestamp: now,
+   });
+
+   Ok(())
+}
+
+/*
+   Prices move without any instruction touching the user, so anyone can refresh its health with the current prices.
+   This is how keepers flag a user that became liquidatable, which starts the dutch auction of banks in that mode.
+   The remaining accounts are the ones every instruction takes: the e-mode category, then the bank and oracles of each position.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RefreshUser<'info> {
+   #[account(
+      mut,
+      seeds = [user_account.owner.as_ref()],
+      bump,
+   )]
+   pub user_account: Account<'info, User>,
+}
+
+pub fn process_refresh_user(ctx: Context<RefreshUser>) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let user = &mut ctx.accounts.user_account;
+
+   let (emode, position_accounts) = load_emode_category(user, ctx.remaining_accounts)?;
+   let priced_banks = load_priced_banks(user, Vec::new(), position_accounts, now)?;
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+   user.record_health(&health, now);
+
+   emit_cpi!(UserRefreshed {
+      owner: user.owner,
+      health_factor: user.health_factor,
+      unhealthy_since: user.unhealthy_since,
+      timestamp: now,
+   });
+
+   Ok(())
+}
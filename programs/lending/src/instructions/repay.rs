@@ -0,0 +1,176 @@
+use anchor_lang::prelude::*;
+use anchor_spl::{
+   associated_token::AssociatedToken,
+   token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked}
+};
+use crate::error::ErrCode;
+use crate::events::{InterestAccruedEvent, RepayEvent};
+use crate::health::{compute_health, load_emode_category, load_priced_banks, Health, PricedBank};
+use crate::math::checked_sub;
+use crate::oracle::{get_price, oracle_accounts};
+use crate::state::*;
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Repay<'info> {
+   #[account(mut)]
+   pub signer: Signer<'info>,
+
+   // mint of the asset that was borrowed and is now being paid back
+   #[account(mint::token_program = token_program)]
+   pub mint: InterfaceAccount<'info, Mint>,
+
+   #[account(
+      mut,
+      seeds = [mint.key().as_ref()],
+      bump,
+      constraint = bank.mint_address == mint.key() @ ErrCode::MintMismatch,
+   )]
+   pub bank: Account<'info, Bank>,
+
+   // The repaid tokens go back to the bank token account
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", mint.key().as_ref()],
+      bump,
+   )]
+   pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: must be the oracle stored in the bank, its data is validated when the price is read
+   #[account(address = bank.oracle @ ErrCode::InvalidOracle)]
+   pub oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   #[account(
+      mut,
+      seeds = [signer.key().as_ref()],
+      bump,
+      constraint = user_account.owner == signer.key() @ ErrCode::Unauthorized,
+   )]
+   pub user_account: Account<'info, User>,
+
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   // User::isolated_bank when the user has one, the repaid debt no longer counts against its ceiling
+   #[account(
+      mut,
+      constraint = isolated_bank.key() != bank.key() @ ErrCode::MissingIsolatedBank,
+   )]
+   pub isolated_bank: Option<Account<'info, Bank>>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+   pub associated_token_program: Program<'info, AssociatedToken>,
+   pub system_program: Program<'info, System>,
+}
+
+pub fn process_repay(ctx: Context<Repay>, amount: u64) -> Result<()> {
+   require!(amount > 0, ErrCode::InvalidAmount);
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+   let bank = &mut ctx.accounts.bank;
+   let user = &mut ctx.accounts.user_account;
+
+   // The debt keeps growing with the interest until it is repaid
+   let interest = bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, bank, interest));
+   }
+
+   let borrowed_shares = user.position(&bank_key).map_or(0, |position| position.borrowed_shares);
+   let borrowed_value = bank.borrowed_shares_to_amount(borrowed_shares)?;
+
+   if amount > borrowed_value {
+      return Err(ErrCode::OverRepay.into());
+   }
+
+   /*
+      A SIMPLE RULE OF THREE
+      total_borrowed_shares   -->      total_borrowed
+                  x           -->      repay_amount
+      Repaying everything that is owed removes all the shares of the user, so no dust is left behind.
+   */
+   let shares_to_remove = if amount == borrowed_value {
+      borrowed_shares
+   } else {
+      bank.repay_amount_to_shares(amount)?
+   };
+
+   let position = user.position_mut(&bank_key).ok_or(ErrCode::OverRepay)?;
+   position.borrowed_shares = checked_sub(position.borrowed_shares, shares_to_remove)?;
+
+   bank.total_borrowed = checked_sub(bank.total_borrowed, amount)?;
+   bank.total_borrowed_shares = checked_sub(bank.total_borrowed_shares, shares_to_remove)?;
+   bank.remove_emode_debt(user, shares_to_remove)?;
+
+   if user.isolated_bank != Pubkey::default() {
+      let isolated_bank = ctx.accounts.isolated_bank
+         .as_mut()
+         .filter(|isolated_bank| isolated_bank.key() == user.isolated_bank)
+         .ok_or(ErrCode::MissingIsolatedBank)?;
+      isolated_bank.remove_isolated_debt(bank, amount)?;
+   }
+
+   user.close_empty_positions();
+
+   /*
+      Repaying only lowers the debt, so nothing has to be priced to allow it, and it keeps working while a bank is paused
+      or its oracles are stale. The prices are only read to refresh the health cached on the user (and put in the event):
+      when they can't be read, or the position accounts aren't all there, the repay goes through with the old health.
+   */
+   let oracles = oracle_accounts(
+      &ctx.accounts.oracle,
+      [&ctx.accounts.secondary_oracle_1, &ctx.accounts.secondary_oracle_2],
+   );
+   let price = get_price(bank, &oracles, now).ok();
+   if let Some(health) = price.and_then(|price| repaid_health(user, bank_key, bank, price, ctx.remaining_accounts, now).ok()) {
+      user.record_health(&health, now);
+   }
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.user_token_account.to_account_info(),
+      to: ctx.accounts.bank_token_account.to_account_info(),
+      authority: ctx.accounts.signer.to_account_info(),
+      mint: ctx.accounts.mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
+
+   let bank = &ctx.accounts.bank;
+   emit_cpi!(RepayEvent {
+      owner: ctx.accounts.user_account.owner,
+      bank: bank_key,
+      mint: bank.mint_address,
+      amount,
+      shares: shares_to_remove,
+      total_borrowed: bank.total_borrowed,
+      total_borrowed_shares: bank.total_borrowed_shares,
+      borrow_index: bank.borrow_index(),
+      price: price.unwrap_or(0),
+      health_factor: ctx.accounts.user_account.health_factor,
+      timestamp: now,
+   });
+
+   Ok(())
+}
+
+// The health of the user after the repay, when every position can be priced
+fn repaid_health(user: &User, bank_key: Pubkey, bank: &Bank, price: u64, remaining_accounts: &[AccountInfo], now: i64) -> Result<Health> {
+   let acted = vec![PricedBank::new(bank_key, bank.clone(), price, now)];
+   let (emode, position_accounts) = load_emode_category(user, remaining_accounts)?;
+   let priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   compute_health(user, &priced_banks, emode.as_ref())
+}
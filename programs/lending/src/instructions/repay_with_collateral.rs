@@ -0,0 +1,298 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::error::ErrCode;
+use crate::events::{InterestAccruedEvent, RepayWithCollateralEvent};
+use crate::health::{compute_health, isolated_collateral, load_emode_category, load_priced_banks, Health, PricedBank};
+use crate::math::*;
+use crate::oracle::{get_price, oracle_accounts};
+use crate::state::*;
+use crate::swap::{split_remaining_accounts, swap};
+
+/*
+   Unwinds a position in one go: `collateral_amount` of the user's collateral is withdrawn, swapped into the borrowed mint
+   by a swap adapter (see swap.rs), and what the swap returns pays back the debt in the borrowed bank.
+   The steps can't fail half way like a withdraw, a swap and a repay sent one by one.
+   Remaining accounts: the bank and oracles of the other positions of the user, then the accounts of the swap adapter.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RepayWithCollateral<'info> {
+   #[account(mut)]
+   pub signer: Signer<'info>,
+
+   #[account(mint::token_program = token_program)]
+   pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+   #[account(mint::token_program = token_program)]
+   pub borrowed_mint: InterfaceAccount<'info, Mint>,
+
+   // The collateral is withdrawn, so like withdraw it is rejected while the bank is paused
+   #[account(
+      mut,
+      seeds = [collateral_mint.key().as_ref()],
+      bump,
+      constraint = collateral_bank.mint_address == collateral_mint.key() @ ErrCode::MintMismatch,
+      constraint = collateral_bank.key() != borrowed_bank.key() @ ErrCode::SameSwapBank,
+      constraint = !collateral_bank.paused @ ErrCode::BankPaused,
+   )]
+   pub collateral_bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      seeds = [borrowed_mint.key().as_ref()],
+      bump,
+      constraint = borrowed_bank.mint_address == borrowed_mint.key() @ ErrCode::MintMismatch,
+   )]
+   pub borrowed_bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      token::mint = collateral_mint,
+      token::authority = collateral_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", collateral_mint.key().as_ref()],
+      bump,
+   )]
+   pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      token::mint = borrowed_mint,
+      token::authority = borrowed_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", borrowed_mint.key().as_ref()],
+      bump,
+   )]
+   pub borrowed_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: must be the oracle stored in the collateral bank, its data is validated when the price is read
+   #[account(address = collateral_bank.oracle @ ErrCode::InvalidOracle)]
+   pub collateral_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the collateral bank if it has one, checked against the bank when the price is read
+   pub collateral_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the collateral bank if it has one, checked against the bank when the price is read
+   pub collateral_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: must be the oracle stored in the borrowed bank, its data is validated when the price is read
+   #[account(address = borrowed_bank.oracle @ ErrCode::InvalidOracle)]
+   pub borrowed_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the borrowed bank if it has one, checked against the bank when the price is read
+   pub borrowed_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the borrowed bank if it has one, checked against the bank when the price is read
+   pub borrowed_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   #[account(
+      mut,
+      seeds = [signer.key().as_ref()],
+      bump,
+      constraint = user_account.owner == signer.key() @ ErrCode::Unauthorized,
+   )]
+   pub user_account: Account<'info, User>,
+
+   // The withdrawn collateral goes through this account on its way to the swap
+   #[account(
+      mut,
+      token::mint = collateral_mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   // The swap pays into this account and the repayment leaves from it
+   #[account(
+      mut,
+      token::mint = borrowed_mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_borrowed_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: any program the user trusts with the withdrawn collateral, what it returns is measured after the call
+   #[account(
+      executable,
+      constraint = swap_program.key() != crate::ID @ ErrCode::InvalidSwapAdapter,
+   )]
+   pub swap_program: UncheckedAccount<'info>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn process_repay_with_collateral<'info>(
+   ctx: Context<'_, '_, '_, 'info, RepayWithCollateral<'info>>,
+   collateral_amount: u64,
+   min_amount_out: u64,
+) -> Result<()> {
+   require!(collateral_amount > 0, ErrCode::InvalidAmount);
+   unwind(ctx, |_, _, _| Ok(collateral_amount), min_amount_out)
+}
+
+/*
+   repay_with_collateral and deleverage. `collateral_amount` picks how much collateral is swapped, once the banks are accrued:
+   it gets the health of the user before, the priced collateral bank and how much of it the user has deposited.
+*/
+pub(crate) fn unwind<'info>(
+   ctx: Context<'_, '_, '_, 'info, RepayWithCollateral<'info>>,
+   collateral_amount: impl FnOnce(&Health, &PricedBank, u64) -> Result<u64>,
+   min_amount_out: u64,
+) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let collateral_bank_key = ctx.accounts.collateral_bank.key();
+   let borrowed_bank_key = ctx.accounts.borrowed_bank.key();
+
+   let interest = ctx.accounts.collateral_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(collateral_bank_key, &ctx.accounts.collateral_bank, interest));
+   }
+   let interest = ctx.accounts.borrowed_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(borrowed_bank_key, &ctx.accounts.borrowed_bank, interest));
+   }
+
+   let collateral_oracles = oracle_accounts(
+      &ctx.accounts.collateral_oracle,
+      [&ctx.accounts.collateral_secondary_oracle_1, &ctx.accounts.collateral_secondary_oracle_2],
+   );
+   let collateral_price = get_price(&ctx.accounts.collateral_bank, &collateral_oracles, now)?;
+   let borrowed_oracles = oracle_accounts(
+      &ctx.accounts.borrowed_oracle,
+      [&ctx.accounts.borrowed_secondary_oracle_1, &ctx.accounts.borrowed_secondary_oracle_2],
+   );
+   let borrowed_price = get_price(&ctx.accounts.borrowed_bank, &borrowed_oracles, now)?;
+
+   let user = &ctx.accounts.user_account;
+   let (position_accounts, adapter_accounts) =
+      split_remaining_accounts(user, &[collateral_bank_key, borrowed_bank_key], ctx.remaining_accounts)?;
+   let (emode, position_accounts) = load_emode_category(user, position_accounts)?;
+
+   let acted = vec![
+      PricedBank::new(collateral_bank_key, (*ctx.accounts.collateral_bank).clone(), collateral_price, now),
+      PricedBank::new(borrowed_bank_key, (*ctx.accounts.borrowed_bank).clone(), borrowed_price, now),
+   ];
+   let mut priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   let health_before = compute_health(user, &priced_banks, emode.as_ref())?;
+   let isolated = isolated_collateral(user, &priced_banks)?;
+
+   let deposit_shares = user.position(&collateral_bank_key).map_or(0, |position| position.deposit_shares);
+   let deposited_value = ctx.accounts.collateral_bank.deposit_shares_to_amount(deposit_shares)?;
+   let collateral = priced_banks.iter().find(|priced| priced.key == collateral_bank_key).ok_or(ErrCode::MissingPositionAccounts)?;
+   let collateral_amount = collateral_amount(&health_before, collateral, deposited_value)?;
+   if collateral_amount > deposited_value {
+      return Err(ErrCode::InsufficientFunds.into());
+   }
+   ctx.accounts.collateral_bank.check_liquidity(collateral_amount)?;
+
+   let borrowed_shares = user.position(&borrowed_bank_key).map_or(0, |position| position.borrowed_shares);
+   let borrowed_value = ctx.accounts.borrowed_bank.borrowed_shares_to_amount(borrowed_shares)?;
+   if borrowed_value == 0 {
+      return Err(ErrCode::OverRepay.into());
+   }
+
+   // The collateral bank sends the collateral to the user
+   let collateral_mint_key = ctx.accounts.collateral_mint.key();
+   let seeds = &[b"treasury", collateral_mint_key.as_ref(), &[ctx.bumps.collateral_bank_token_account]];
+   let signer = &[&seeds[..]];
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.collateral_bank_token_account.to_account_info(),
+      to: ctx.accounts.user_collateral_token_account.to_account_info(),
+      authority: ctx.accounts.collateral_bank_token_account.to_account_info(),
+      mint: ctx.accounts.collateral_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+   token_interface::transfer_checked(cpi_ctx, collateral_amount, ctx.accounts.collateral_mint.decimals)?;
+
+   // And the adapter swaps it into the borrowed mint
+   let swapped_amount = swap(
+      &ctx.accounts.swap_program.to_account_info(),
+      &ctx.accounts.signer.to_account_info(),
+      &mut ctx.accounts.user_collateral_token_account,
+      &mut ctx.accounts.user_borrowed_token_account,
+      adapter_accounts,
+      collateral_amount,
+      min_amount_out,
+   )?;
+
+   // What the debt doesn't need stays in the user's token account
+   let repaid_amount = swapped_amount.min(borrowed_value);
+
+   let collateral_bank = &mut ctx.accounts.collateral_bank;
+   let borrowed_bank = &mut ctx.accounts.borrowed_bank;
+   let user = &mut ctx.accounts.user_account;
+
+   let withdrawn_shares = if collateral_amount == deposited_value {
+      deposit_shares
+   } else {
+      collateral_bank.withdraw_amount_to_shares(collateral_amount)?.min(deposit_shares)
+   };
+   let repaid_shares = if repaid_amount == borrowed_value {
+      borrowed_shares
+   } else {
+      borrowed_bank.repay_amount_to_shares(repaid_amount)?
+   };
+
+   let position = user.position_mut(&collateral_bank_key).ok_or(ErrCode::InsufficientFunds)?;
+   position.deposit_shares = checked_sub(position.deposit_shares, withdrawn_shares)?;
+   collateral_bank.total_deposits = checked_sub(collateral_bank.total_deposits, collateral_amount)?;
+   collateral_bank.total_deposit_shares = checked_sub(collateral_bank.total_deposit_shares, withdrawn_shares)?;
+
+   let position = user.position_mut(&borrowed_bank_key).ok_or(ErrCode::OverRepay)?;
+   position.borrowed_shares = checked_sub(position.borrowed_shares, repaid_shares)?;
+   borrowed_bank.total_borrowed = checked_sub(borrowed_bank.total_borrowed, repaid_amount)?;
+   borrowed_bank.total_borrowed_shares = checked_sub(borrowed_bank.total_borrowed_shares, repaid_shares)?;
+   borrowed_bank.remove_emode_debt(user, repaid_shares)?;
+
+   // The collateral of a user in isolation can only be its isolated bank
+   if isolated == Some(collateral_bank_key) {
+      collateral_bank.remove_isolated_debt(borrowed_bank, repaid_amount)?;
+   }
+
+   user.close_empty_positions();
+
+   for priced in priced_banks.iter_mut() {
+      if priced.key == collateral_bank_key {
+         priced.bank = (**collateral_bank).clone();
+      } else if priced.key == borrowed_bank_key {
+         priced.bank = (**borrowed_bank).clone();
+      }
+   }
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   // A user above the borrow limit can still unwind, as long as the position gets healthier
+   if !health.is_within_borrow_limit() && health.health_factor() < health_before.health_factor() {
+      return Err(ErrCode::HealthDecreased.into());
+   }
+
+   user.record_health(&health, now);
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.user_borrowed_token_account.to_account_info(),
+      to: ctx.accounts.borrowed_bank_token_account.to_account_info(),
+      authority: ctx.accounts.signer.to_account_info(),
+      mint: ctx.accounts.borrowed_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::transfer_checked(cpi_ctx, repaid_amount, ctx.accounts.borrowed_mint.decimals)?;
+
+   emit_cpi!(RepayWithCollateralEvent {
+      owner: ctx.accounts.user_account.owner,
+      collateral_bank: collateral_bank_key,
+      borrowed_bank: borrowed_bank_key,
+      swap_program: ctx.accounts.swap_program.key(),
+      withdrawn_amount: collateral_amount,
+      withdrawn_shares,
+      swapped_amount,
+      repaid_amount,
+      repaid_shares,
+      collateral_price,
+      borrowed_price,
+      health_factor_before: health_before.health_factor(),
+      health_factor: ctx.accounts.user_account.health_factor,
+      timestamp: now,
+   });
+
+   Ok(())
+}
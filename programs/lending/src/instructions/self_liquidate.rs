@@ -0,0 +1,257 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::error::ErrCode;
+use crate::events::{InterestAccruedEvent, SelfLiquidationEvent};
+use crate::health::{compute_health, isolated_collateral, load_emode_category, load_priced_banks, PricedBank};
+use crate::instructions::liquidate::Seizure;
+use crate::math::checked_add;
+use crate::oracle::{get_price, oracle_accounts};
+use crate::state::*;
+use crate::swap::{split_remaining_accounts, swap};
+
+/*
+   A user close to liquidation can liquidate itself instead of waiting for a liquidator: `amount` of its debt is repaid
+   with the same value of its collateral, which a swap adapter (see swap.rs) turns into the borrowed mint on the way.
+   Like a liquidation the collateral is taken even above the borrow limit, but the bonus is replaced by the smaller
+   self_liquidation_penalty of the collateral bank, which stays in the treasury for its insurance fund.
+   Allowed once the health factor is below the soft_liquidation_health of the collateral bank, and within the close factor.
+   The swap pays the repayment; when it returns less than `amount` the rest is taken from the user's borrowed token account,
+   min_amount_out bounds how much. What it returns above `amount` stays in that account.
+   Remaining accounts: the bank and oracles of the other positions of the user, then the accounts of the swap adapter.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SelfLiquidate<'info> {
+   #[account(mut)]
+   pub signer: Signer<'info>,
+
+   #[account(mint::token_program = token_program)]
+   pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+   #[account(mint::token_program = token_program)]
+   pub borrowed_mint: InterfaceAccount<'info, Mint>,
+
+   #[account(
+      mut,
+      seeds = [collateral_mint.key().as_ref()],
+      bump,
+      constraint = collateral_bank.mint_address == collateral_mint.key() @ ErrCode::MintMismatch,
+      constraint = collateral_bank.key() != borrowed_bank.key() @ ErrCode::SameLiquidationBank,
+   )]
+   pub collateral_bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      seeds = [borrowed_mint.key().as_ref()],
+      bump,
+      constraint = borrowed_bank.mint_address == borrowed_mint.key() @ ErrCode::MintMismatch,
+   )]
+   pub borrowed_bank: Account<'info, Bank>,
+
+   // The collateral leaves this account, minus the penalty which stays as insurance fees
+   #[account(
+      mut,
+      token::mint = collateral_mint,
+      token::authority = collateral_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", collateral_mint.key().as_ref()],
+      bump,
+   )]
+   pub collateral_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      token::mint = borrowed_mint,
+      token::authority = borrowed_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", borrowed_mint.key().as_ref()],
+      bump,
+   )]
+   pub borrowed_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: must be the oracle stored in the collateral bank, its data is validated when the price is read
+   #[account(address = collateral_bank.oracle @ ErrCode::InvalidOracle)]
+   pub collateral_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the collateral bank if it has one, checked against the bank when the price is read
+   pub collateral_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the collateral bank if it has one, checked against the bank when the price is read
+   pub collateral_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: must be the oracle stored in the borrowed bank, its data is validated when the price is read
+   #[account(address = borrowed_bank.oracle @ ErrCode::InvalidOracle)]
+   pub borrowed_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the borrowed bank if it has one, checked against the bank when the price is read
+   pub borrowed_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the borrowed bank if it has one, checked against the bank when the price is read
+   pub borrowed_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   #[account(
+      mut,
+      seeds = [signer.key().as_ref()],
+      bump,
+      constraint = user_account.owner == signer.key() @ ErrCode::Unauthorized,
+   )]
+   pub user_account: Account<'info, User>,
+
+   // The seized collateral goes through this account on its way to the swap
+   #[account(
+      mut,
+      token::mint = collateral_mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_collateral_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   // The swap pays into this account and the repayment leaves from it
+   #[account(
+      mut,
+      token::mint = borrowed_mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_borrowed_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: any program the user trusts with the seized collateral, what it returns is measured after the call
+   #[account(
+      executable,
+      constraint = swap_program.key() != crate::ID @ ErrCode::InvalidSwapAdapter,
+   )]
+   pub swap_program: UncheckedAccount<'info>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn process_self_liquidate<'info>(
+   ctx: Context<'_, '_, '_, 'info, SelfLiquidate<'info>>,
+   amount: u64,
+   min_amount_out: u64,
+) -> Result<()> {
+   require!(amount > 0, ErrCode::InvalidAmount);
+   let now = Clock::get()?.unix_timestamp;
+   let collateral_bank_key = ctx.accounts.collateral_bank.key();
+   let borrowed_bank_key = ctx.accounts.borrowed_bank.key();
+
+   let interest = ctx.accounts.collateral_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(collateral_bank_key, &ctx.accounts.collateral_bank, interest));
+   }
+   let interest = ctx.accounts.borrowed_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(borrowed_bank_key, &ctx.accounts.borrowed_bank, interest));
+   }
+
+   let collateral_bank = &mut ctx.accounts.collateral_bank;
+   let borrowed_bank = &mut ctx.accounts.borrowed_bank;
+   let user = &mut ctx.accounts.user_account;
+
+   let collateral_oracles = oracle_accounts(
+      &ctx.accounts.collateral_oracle,
+      [&ctx.accounts.collateral_secondary_oracle_1, &ctx.accounts.collateral_secondary_oracle_2],
+   );
+   let collateral_price = get_price(collateral_bank, &collateral_oracles, now)?;
+   let borrowed_oracles = oracle_accounts(
+      &ctx.accounts.borrowed_oracle,
+      [&ctx.accounts.borrowed_secondary_oracle_1, &ctx.accounts.borrowed_secondary_oracle_2],
+   );
+   let borrowed_price = get_price(borrowed_bank, &borrowed_oracles, now)?;
+
+   let acted = vec![
+      PricedBank::new(collateral_bank_key, (**collateral_bank).clone(), collateral_price, now),
+      PricedBank::new(borrowed_bank_key, (**borrowed_bank).clone(), borrowed_price, now),
+   ];
+   let (position_accounts, adapter_accounts) =
+      split_remaining_accounts(user, &[collateral_bank_key, borrowed_bank_key], ctx.remaining_accounts)?;
+   let (emode, position_accounts) = load_emode_category(user, position_accounts)?;
+   let mut priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   let health_before = compute_health(user, &priced_banks, emode.as_ref())?;
+   let isolated = isolated_collateral(user, &priced_banks)?;
+
+   let soft_liquidation_health = collateral_bank.soft_liquidation_health;
+   if soft_liquidation_health == 0 || health_before.health_factor() >= soft_liquidation_health {
+      return Err(ErrCode::NotSoftLiquidatable.into());
+   }
+
+   let penalty = collateral_bank.self_liquidation_penalty;
+   let seizure = Seizure::new(user, &priced_banks, collateral_bank_key, borrowed_bank_key, amount, penalty)?;
+   seizure.apply(user, collateral_bank, borrowed_bank, isolated)?;
+
+   // The penalty is seized like the rest but stays in the treasury, owed to the insurance fund
+   collateral_bank.insurance_fees = checked_add(collateral_bank.insurance_fees, seizure.bonus_amount)?;
+   let returned_amount = seizure.seized_amount - seizure.bonus_amount;
+
+   for priced in priced_banks.iter_mut() {
+      if priced.key == collateral_bank_key {
+         priced.bank = (**collateral_bank).clone();
+      } else if priced.key == borrowed_bank_key {
+         priced.bank = (**borrowed_bank).clone();
+      }
+   }
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   // With a penalty above the margin of the collateral the user would end up closer to liquidation
+   if health.health_factor() < health_before.health_factor() {
+      return Err(ErrCode::HealthDecreased.into());
+   }
+
+   user.record_health(&health, now);
+
+   // The collateral bank sends the seized collateral to the user, without the penalty
+   let collateral_mint_key = ctx.accounts.collateral_mint.key();
+   let seeds = &[b"treasury", collateral_mint_key.as_ref(), &[ctx.bumps.collateral_bank_token_account]];
+   let signer = &[&seeds[..]];
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.collateral_bank_token_account.to_account_info(),
+      to: ctx.accounts.user_collateral_token_account.to_account_info(),
+      authority: ctx.accounts.collateral_bank_token_account.to_account_info(),
+      mint: ctx.accounts.collateral_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+   token_interface::transfer_checked(cpi_ctx, returned_amount, ctx.accounts.collateral_mint.decimals)?;
+
+   // The adapter swaps it into the borrowed mint
+   let swapped_amount = swap(
+      &ctx.accounts.swap_program.to_account_info(),
+      &ctx.accounts.signer.to_account_info(),
+      &mut ctx.accounts.user_collateral_token_account,
+      &mut ctx.accounts.user_borrowed_token_account,
+      adapter_accounts,
+      returned_amount,
+      min_amount_out,
+   )?;
+
+   // And the debt is paid back to the borrowed bank
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.user_borrowed_token_account.to_account_info(),
+      to: ctx.accounts.borrowed_bank_token_account.to_account_info(),
+      authority: ctx.accounts.signer.to_account_info(),
+      mint: ctx.accounts.borrowed_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.borrowed_mint.decimals)?;
+
+   emit_cpi!(SelfLiquidationEvent {
+      owner: ctx.accounts.user_account.owner,
+      collateral_bank: collateral_bank_key,
+      borrowed_bank: borrowed_bank_key,
+      swap_program: ctx.accounts.swap_program.key(),
+      repaid_amount: amount,
+      repaid_shares: seizure.repaid_shares,
+      seized_amount: seizure.seized_amount,
+      seized_shares: seizure.seized_shares,
+      swapped_amount,
+      penalty_amount: seizure.bonus_amount,
+      self_liquidation_penalty: penalty,
+      collateral_price,
+      borrowed_price,
+      health_factor_before: health_before.health_factor(),
+      health_factor: ctx.accounts.user_account.health_factor,
+      timestamp: now,
+   });
+
+   Ok(())
+}
@@ -0,0 +1,141 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::error::ErrCode;
+use crate::events::{BadDebtSocialized, InterestAccruedEvent};
+use crate::health::Health;
+use crate::math::{checked_add, checked_sub};
+use crate::state::*;
+
+/*
+   A user whose collateral was all seized can still owe debt that nobody will ever repay, and it would keep
+   total_borrowed (and the interest the depositors think they earn) inflated forever. socialize_loss removes the debt
+   of such a user from one bank. The insurance fund of the bank pays first: the fees it is owed, then the tokens of its
+   vault, which are moved into the treasury. Whatever is left writes total_deposits down, so every depositor of the bank
+   takes its share of the loss through a lower share price. Anyone can call it, the only condition is a user without collateral.
+   A debt that was backed by isolated collateral is also written off the isolated_debt of that bank (User::isolated_bank),
+   the collateral is gone and the debt shouldn't hold its ceiling forever.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SocializeLoss<'info> {
+   #[account(mint::token_program = token_program)]
+   pub mint: InterfaceAccount<'info, Mint>,
+
+   #[account(
+      mut,
+      seeds = [mint.key().as_ref()],
+      bump,
+      constraint = bank.mint_address == mint.key() @ ErrCode::MintMismatch,
+   )]
+   pub bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", mint.key().as_ref()],
+      bump,
+   )]
+   pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      token::mint = mint,
+      token::authority = insurance_vault,
+      token::token_program = token_program,
+      seeds = [b"insurance", mint.key().as_ref()],
+      bump,
+   )]
+   pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      seeds = [user_account.owner.as_ref()],
+      bump,
+   )]
+   pub user_account: Account<'info, User>,
+
+   // The isolated bank of the user when it has one, see User::isolated_bank
+   #[account(
+      mut,
+      constraint = isolated_bank.key() != bank.key() @ ErrCode::MissingIsolatedBank,
+   )]
+   pub isolated_bank: Option<Account<'info, Bank>>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+}
+
+pub fn process_socialize_loss(ctx: Context<SocializeLoss>) -> Result<()> {
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
+
+   // The debt is written off with its interest up to now
+   let interest = ctx.accounts.bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, &ctx.accounts.bank, interest));
+   }
+
+   let bank = &mut ctx.accounts.bank;
+   let user = &mut ctx.accounts.user_account;
+
+   // No prices are needed: collateral worth anything at all still has to be liquidated first
+   require!(user.positions.iter().all(|position| position.deposit_shares == 0), ErrCode::NotBadDebt);
+   let shares = user.position(&bank_key).map_or(0, |position| position.borrowed_shares);
+   require!(shares > 0, ErrCode::NotBadDebt);
+
+   // The last shares of the bank can round up above total_borrowed
+   let amount = bank.borrowed_shares_to_amount(shares)?.min(bank.total_borrowed);
+   bank.total_borrowed = checked_sub(bank.total_borrowed, amount)?;
+   bank.total_borrowed_shares = checked_sub(bank.total_borrowed_shares, shares)?;
+   bank.remove_emode_debt(user, shares)?;
+   // The fees are already in the treasury, they just stop being owed to the fund
+   let (from_fees, from_funds) = bank.cover_with_insurance(amount);
+   let insurance_amount = checked_add(from_fees, from_funds)?;
+   bank.total_deposits = checked_sub(bank.total_deposits, amount - insurance_amount)?;
+
+   if user.isolated_bank != Pubkey::default() {
+      let isolated_bank = ctx.accounts.isolated_bank
+         .as_mut()
+         .filter(|isolated_bank| isolated_bank.key() == user.isolated_bank)
+         .ok_or(ErrCode::MissingIsolatedBank)?;
+      isolated_bank.remove_isolated_debt(bank, amount)?;
+   }
+
+   let position = user.position_mut(&bank_key).ok_or(ErrCode::NotBadDebt)?;
+   position.borrowed_shares = 0;
+   user.close_empty_positions();
+
+   // Without any debt left the user is healthy again, otherwise its other debts are bad debt as well
+   if user.positions.iter().all(|position| position.borrowed_shares == 0) {
+      user.record_health(&Health::default(), now);
+   }
+
+   if from_funds > 0 {
+      let mint_key = ctx.accounts.mint.key();
+      let seeds = &[b"insurance", mint_key.as_ref(), &[ctx.bumps.insurance_vault]];
+      let signer = &[&seeds[..]];
+      let cpi_accounts = TransferChecked {
+         from: ctx.accounts.insurance_vault.to_account_info(),
+         to: ctx.accounts.bank_token_account.to_account_info(),
+         authority: ctx.accounts.insurance_vault.to_account_info(),
+         mint: ctx.accounts.mint.to_account_info(),
+      };
+      let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+      token_interface::transfer_checked(cpi_ctx, from_funds, ctx.accounts.mint.decimals)?;
+   }
+
+   emit_cpi!(BadDebtSocialized {
+      bank: bank_key,
+      owner: user.owner,
+      amount,
+      shares,
+      insurance_amount,
+      total_deposits: bank.total_deposits,
+      total_borrowed: bank.total_borrowed,
+      deposit_index: bank.deposit_index(),
+      timestamp: now,
+   });
+
+   Ok(())
+}
@@ -0,0 +1,430 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
+use crate::error::ErrCode;
+use crate::events::{CollateralSwapEvent, DebtSwapEvent, InterestAccruedEvent};
+use crate::health::{compute_health, isolated_collateral, load_emode_category, load_priced_banks, Health, PricedBank};
+use crate::math::*;
+use crate::oracle::{get_price, oracle_accounts};
+use crate::state::*;
+use crate::swap::{split_remaining_accounts, swap};
+
+/*
+   Moves a position from one bank to another without closing it first, through a swap adapter (see swap.rs):
+   swap_collateral  -> withdraws collateral from `from_bank`, swaps it into the mint of `to_bank` and deposits it there
+   swap_debt        -> borrows from `to_bank` (a flash loan, the new debt is only recorded at the end), swaps it into
+                       the mint of `from_bank` and repays the debt there, what the debt doesn't need stays with the user
+   In between the user can be anywhere, only the end counts: it has to stay within the borrow limit, or, for a user
+   already above it, above the liquidation threshold and healthier than before. The adapter is picked by the user, so
+   a swap that returns nothing must not be a way to borrow or withdraw past the limit.
+   Remaining accounts: the bank and oracles of the other positions of the user, then the accounts of the swap adapter.
+*/
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SwapPosition<'info> {
+   #[account(mut)]
+   pub signer: Signer<'info>,
+
+   #[account(mint::token_program = token_program)]
+   pub from_mint: InterfaceAccount<'info, Mint>,
+
+   #[account(mint::token_program = token_program)]
+   pub to_mint: InterfaceAccount<'info, Mint>,
+
+   // Repaying works while paused, withdrawing doesn't: swap_collateral checks it
+   #[account(
+      mut,
+      seeds = [from_mint.key().as_ref()],
+      bump,
+      constraint = from_bank.mint_address == from_mint.key() @ ErrCode::MintMismatch,
+      constraint = from_bank.key() != to_bank.key() @ ErrCode::SameSwapBank,
+   )]
+   pub from_bank: Account<'info, Bank>,
+
+   // Receives a deposit or a borrow, both are rejected while paused
+   #[account(
+      mut,
+      seeds = [to_mint.key().as_ref()],
+      bump,
+      constraint = to_bank.mint_address == to_mint.key() @ ErrCode::MintMismatch,
+      constraint = !to_bank.paused @ ErrCode::BankPaused,
+   )]
+   pub to_bank: Account<'info, Bank>,
+
+   #[account(
+      mut,
+      token::mint = from_mint,
+      token::authority = from_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", from_mint.key().as_ref()],
+      bump,
+   )]
+   pub from_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      token::mint = to_mint,
+      token::authority = to_bank_token_account,
+      token::token_program = token_program,
+      seeds = [b"treasury", to_mint.key().as_ref()],
+      bump,
+   )]
+   pub to_bank_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: must be the oracle stored in the from bank, its data is validated when the price is read
+   #[account(address = from_bank.oracle @ ErrCode::InvalidOracle)]
+   pub from_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the from bank if it has one, checked against the bank when the price is read
+   pub from_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the from bank if it has one, checked against the bank when the price is read
+   pub from_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: must be the oracle stored in the to bank, its data is validated when the price is read
+   #[account(address = to_bank.oracle @ ErrCode::InvalidOracle)]
+   pub to_oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the to bank if it has one, checked against the bank when the price is read
+   pub to_secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the to bank if it has one, checked against the bank when the price is read
+   pub to_secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
+   #[account(
+      mut,
+      seeds = [signer.key().as_ref()],
+      bump,
+      constraint = user_account.owner == signer.key() @ ErrCode::Unauthorized,
+   )]
+   pub user_account: Account<'info, User>,
+
+   #[account(
+      mut,
+      token::mint = from_mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_from_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   #[account(
+      mut,
+      token::mint = to_mint,
+      token::authority = signer,
+      token::token_program = token_program,
+   )]
+   pub user_to_token_account: InterfaceAccount<'info, TokenAccount>,
+
+   /// CHECK: any program the user trusts with the swapped tokens, what it returns is measured after the call
+   #[account(
+      executable,
+      constraint = swap_program.key() != crate::ID @ ErrCode::InvalidSwapAdapter,
+   )]
+   pub swap_program: UncheckedAccount<'info>,
+
+   pub token_program: Interface<'info, TokenInterface>,
+}
+
+// Both banks accrued and priced, with the health of the user before the swap
+struct Priced {
+   now: i64,
+   from_price: u64,
+   to_price: u64,
+   priced_banks: Vec<PricedBank>,
+   emode: Option<EModeCategory>,
+   health: Health,
+}
+
+fn accrue_and_price<'info>(ctx: &mut Context<'_, '_, '_, 'info, SwapPosition<'info>>) -> Result<Priced> {
+   let now = Clock::get()?.unix_timestamp;
+   let from_bank_key = ctx.accounts.from_bank.key();
+   let to_bank_key = ctx.accounts.to_bank.key();
+
+   let interest = ctx.accounts.from_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(from_bank_key, &ctx.accounts.from_bank, interest));
+   }
+   let interest = ctx.accounts.to_bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(to_bank_key, &ctx.accounts.to_bank, interest));
+   }
+
+   let from_oracles = oracle_accounts(
+      &ctx.accounts.from_oracle,
+      [&ctx.accounts.from_secondary_oracle_1, &ctx.accounts.from_secondary_oracle_2],
+   );
+   let from_price = get_price(&ctx.accounts.from_bank, &from_oracles, now)?;
+   let to_oracles = oracle_accounts(
+      &ctx.accounts.to_oracle,
+      [&ctx.accounts.to_secondary_oracle_1, &ctx.accounts.to_secondary_oracle_2],
+   );
+   let to_price = get_price(&ctx.accounts.to_bank, &to_oracles, now)?;
+
+   let user = &ctx.accounts.user_account;
+   let (position_accounts, _) = split_remaining_accounts(user, &[from_bank_key, to_bank_key], ctx.remaining_accounts)?;
+   let (emode, position_accounts) = load_emode_category(user, position_accounts)?;
+   let acted = vec![
+      PricedBank::new(from_bank_key, (*ctx.accounts.from_bank).clone(), from_price, now),
+      PricedBank::new(to_bank_key, (*ctx.accounts.to_bank).clone(), to_price, now),
+   ];
+   let priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   Ok(Priced { now, from_price, to_price, priced_banks, emode, health })
+}
+
+// The health once both banks have their new totals, the same rule as repay_with_collateral: within the borrow limit,
+// or healthier than before without being liquidatable
+fn health_after(
+   health_before: &Health,
+   priced_banks: &mut [PricedBank],
+   emode: Option<&EModeCategory>,
+   from_bank: (&Pubkey, &Bank),
+   to_bank: (&Pubkey, &Bank),
+   user: &User,
+) -> Result<Health> {
+   for priced in priced_banks.iter_mut() {
+      if priced.key == *from_bank.0 {
+         priced.bank = from_bank.1.clone();
+      } else if priced.key == *to_bank.0 {
+         priced.bank = to_bank.1.clone();
+      }
+   }
+   let health = compute_health(user, priced_banks, emode)?;
+   if health.is_liquidatable() {
+      return Err(ErrCode::LiquidatableAfterSwap.into());
+   }
+   if !health.is_within_borrow_limit() && health.health_factor() <= health_before.health_factor() {
+      return Err(ErrCode::HealthDecreased.into());
+   }
+   Ok(health)
+}
+
+pub fn process_swap_collateral<'info>(
+   mut ctx: Context<'_, '_, '_, 'info, SwapPosition<'info>>,
+   amount: u64,
+   min_amount_out: u64,
+) -> Result<()> {
+   require!(amount > 0, ErrCode::InvalidAmount);
+   require!(!ctx.accounts.from_bank.paused, ErrCode::BankPaused);
+   // Swapping part of an isolated deposit would leave it next to other collateral
+   require!(!ctx.accounts.from_bank.isolated && !ctx.accounts.to_bank.isolated, ErrCode::IsolatedSwap);
+   let Priced { now, from_price, to_price, mut priced_banks, emode, health: health_before } = accrue_and_price(&mut ctx)?;
+   let from_bank_key = ctx.accounts.from_bank.key();
+   let to_bank_key = ctx.accounts.to_bank.key();
+
+   let user = &ctx.accounts.user_account;
+   let (_, adapter_accounts) = split_remaining_accounts(user, &[from_bank_key, to_bank_key], ctx.remaining_accounts)?;
+
+   let deposit_shares = user.position(&from_bank_key).map_or(0, |position| position.deposit_shares);
+   let from_deposit_before = ctx.accounts.from_bank.deposit_shares_to_amount(deposit_shares)?;
+   if amount > from_deposit_before {
+      return Err(ErrCode::InsufficientFunds.into());
+   }
+   ctx.accounts.from_bank.check_liquidity(amount)?;
+   let to_shares = user.position(&to_bank_key).map_or(0, |position| position.deposit_shares);
+   let to_deposit_before = ctx.accounts.to_bank.deposit_shares_to_amount(to_shares)?;
+
+   // The from bank sends the collateral to the user
+   let from_mint_key = ctx.accounts.from_mint.key();
+   let seeds = &[b"treasury", from_mint_key.as_ref(), &[ctx.bumps.from_bank_token_account]];
+   let signer = &[&seeds[..]];
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.from_bank_token_account.to_account_info(),
+      to: ctx.accounts.user_from_token_account.to_account_info(),
+      authority: ctx.accounts.from_bank_token_account.to_account_info(),
+      mint: ctx.accounts.from_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+   token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.from_mint.decimals)?;
+
+   let deposited_amount = swap(
+      &ctx.accounts.swap_program.to_account_info(),
+      &ctx.accounts.signer.to_account_info(),
+      &mut ctx.accounts.user_from_token_account,
+      &mut ctx.accounts.user_to_token_account,
+      adapter_accounts,
+      amount,
+      min_amount_out,
+   )?;
+   require!(deposited_amount > 0, ErrCode::InvalidAmount);
+
+   // And everything the swap returned is deposited into the to bank
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.user_to_token_account.to_account_info(),
+      to: ctx.accounts.to_bank_token_account.to_account_info(),
+      authority: ctx.accounts.signer.to_account_info(),
+      mint: ctx.accounts.to_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::transfer_checked(cpi_ctx, deposited_amount, ctx.accounts.to_mint.decimals)?;
+
+   let from_bank = &mut ctx.accounts.from_bank;
+   let to_bank = &mut ctx.accounts.to_bank;
+   let user = &mut ctx.accounts.user_account;
+
+   let withdrawn_shares = if amount == from_deposit_before {
+      deposit_shares
+   } else {
+      from_bank.withdraw_amount_to_shares(amount)?.min(deposit_shares)
+   };
+   let position = user.position_mut(&from_bank_key).ok_or(ErrCode::InsufficientFunds)?;
+   position.deposit_shares = checked_sub(position.deposit_shares, withdrawn_shares)?;
+   from_bank.total_deposits = checked_sub(from_bank.total_deposits, amount)?;
+   from_bank.total_deposit_shares = checked_sub(from_bank.total_deposit_shares, withdrawn_shares)?;
+
+   let deposited_shares = to_bank.deposit_amount_to_shares(deposited_amount)?;
+   to_bank.total_deposits = checked_add(to_bank.total_deposits, deposited_amount)?;
+   if to_bank.deposit_cap > 0 && to_bank.total_deposits > to_bank.deposit_cap {
+      return Err(ErrCode::DepositCapExceeded.into());
+   }
+   to_bank.total_deposit_shares = checked_add(to_bank.total_deposit_shares, deposited_shares)?;
+   let position = user.position_or_insert(&to_bank_key)?;
+   position.deposit_shares = checked_add(position.deposit_shares, deposited_shares)?;
+
+   user.close_empty_positions();
+
+   let health = health_after(&health_before, &mut priced_banks, emode.as_ref(), (&from_bank_key, from_bank), (&to_bank_key, to_bank), user)?;
+   user.record_health(&health, now);
+
+   let from_deposit_after = from_bank.deposit_shares_to_amount(user.position(&from_bank_key).map_or(0, |position| position.deposit_shares))?;
+   let to_deposit_after = to_bank.deposit_shares_to_amount(user.position(&to_bank_key).map_or(0, |position| position.deposit_shares))?;
+
+   emit_cpi!(CollateralSwapEvent {
+      owner: user.owner,
+      from_bank: from_bank_key,
+      to_bank: to_bank_key,
+      swap_program: ctx.accounts.swap_program.key(),
+      withdrawn_amount: amount,
+      deposited_amount,
+      from_deposit_before,
+      from_deposit_after,
+      to_deposit_before,
+      to_deposit_after,
+      from_price,
+      to_price,
+      health_factor_before: health_before.health_factor(),
+      health_factor: user.health_factor,
+      timestamp: now,
+   });
+
+   Ok(())
+}
+
+pub fn process_swap_debt<'info>(
+   mut ctx: Context<'_, '_, '_, 'info, SwapPosition<'info>>,
+   borrow_amount: u64,
+   min_amount_out: u64,
+) -> Result<()> {
+   require!(borrow_amount > 0, ErrCode::InvalidAmount);
+   ctx.accounts.user_account.check_emode_borrow(&ctx.accounts.to_bank)?;
+   let Priced { now, from_price, to_price, mut priced_banks, emode, health: health_before } = accrue_and_price(&mut ctx)?;
+   let from_bank_key = ctx.accounts.from_bank.key();
+   let to_bank_key = ctx.accounts.to_bank.key();
+
+   let user = &ctx.accounts.user_account;
+   let (_, adapter_accounts) = split_remaining_accounts(user, &[from_bank_key, to_bank_key], ctx.remaining_accounts)?;
+
+   // The isolated bank isn't one of the accounts, its debt ceiling couldn't follow the swap
+   if isolated_collateral(user, &priced_banks)?.is_some() {
+      return Err(ErrCode::IsolatedSwap.into());
+   }
+
+   let borrowed_shares = user.position(&from_bank_key).map_or(0, |position| position.borrowed_shares);
+   let from_debt_before = ctx.accounts.from_bank.borrowed_shares_to_amount(borrowed_shares)?;
+   if from_debt_before == 0 {
+      return Err(ErrCode::OverRepay.into());
+   }
+   let to_shares = user.position(&to_bank_key).map_or(0, |position| position.borrowed_shares);
+   let to_debt_before = ctx.accounts.to_bank.borrowed_shares_to_amount(to_shares)?;
+
+   // The to bank lends the new debt before it is recorded
+   ctx.accounts.to_bank.check_liquidity(borrow_amount)?;
+   let to_mint_key = ctx.accounts.to_mint.key();
+   let seeds = &[b"treasury", to_mint_key.as_ref(), &[ctx.bumps.to_bank_token_account]];
+   let signer = &[&seeds[..]];
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.to_bank_token_account.to_account_info(),
+      to: ctx.accounts.user_to_token_account.to_account_info(),
+      authority: ctx.accounts.to_bank_token_account.to_account_info(),
+      mint: ctx.accounts.to_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new_with_signer(ctx.accounts.token_program.to_account_info(), cpi_accounts, signer);
+   token_interface::transfer_checked(cpi_ctx, borrow_amount, ctx.accounts.to_mint.decimals)?;
+
+   let swapped_amount = swap(
+      &ctx.accounts.swap_program.to_account_info(),
+      &ctx.accounts.signer.to_account_info(),
+      &mut ctx.accounts.user_to_token_account,
+      &mut ctx.accounts.user_from_token_account,
+      adapter_accounts,
+      borrow_amount,
+      min_amount_out,
+   )?;
+
+   // What the old debt doesn't need stays in the user's token account
+   let repaid_amount = swapped_amount.min(from_debt_before);
+
+   let cpi_accounts = TransferChecked {
+      from: ctx.accounts.user_from_token_account.to_account_info(),
+      to: ctx.accounts.from_bank_token_account.to_account_info(),
+      authority: ctx.accounts.signer.to_account_info(),
+      mint: ctx.accounts.from_mint.to_account_info(),
+   };
+   let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+   token_interface::transfer_checked(cpi_ctx, repaid_amount, ctx.accounts.from_mint.decimals)?;
+
+   let from_bank = &mut ctx.accounts.from_bank;
+   let to_bank = &mut ctx.accounts.to_bank;
+   let user = &mut ctx.accounts.user_account;
+
+   let repaid_shares = if repaid_amount == from_debt_before {
+      borrowed_shares
+   } else {
+      from_bank.repay_amount_to_shares(repaid_amount)?
+   };
+   let position = user.position_mut(&from_bank_key).ok_or(ErrCode::OverRepay)?;
+   position.borrowed_shares = checked_sub(position.borrowed_shares, repaid_shares)?;
+   from_bank.total_borrowed = checked_sub(from_bank.total_borrowed, repaid_amount)?;
+   from_bank.total_borrowed_shares = checked_sub(from_bank.total_borrowed_shares, repaid_shares)?;
+   from_bank.remove_emode_debt(user, repaid_shares)?;
+
+   let new_shares = to_bank.borrow_amount_to_shares(borrow_amount)?;
+   to_bank.total_borrowed = checked_add(to_bank.total_borrowed, borrow_amount)?;
+   if to_bank.borrow_cap > 0 && to_bank.total_borrowed > to_bank.borrow_cap {
+      return Err(ErrCode::BorrowCapExceeded.into());
+   }
+   to_bank.total_borrowed_shares = checked_add(to_bank.total_borrowed_shares, new_shares)?;
+   to_bank.add_emode_debt(user, new_shares)?;
+   let position = user.position_or_insert(&to_bank_key)?;
+   position.borrowed_shares = checked_add(position.borrowed_shares, new_shares)?;
+
+   user.close_empty_positions();
+
+   let health = health_after(&health_before, &mut priced_banks, emode.as_ref(), (&from_bank_key, from_bank), (&to_bank_key, to_bank), user)?;
+   user.record_health(&health, now);
+
+   let from_debt_after = from_bank.borrowed_shares_to_amount(user.position(&from_bank_key).map_or(0, |position| position.borrowed_shares))?;
+   let to_debt_after = to_bank.borrowed_shares_to_amount(user.position(&to_bank_key).map_or(0, |position| position.borrowed_shares))?;
+
+   emit_cpi!(DebtSwapEvent {
+      owner: user.owner,
+      from_bank: from_bank_key,
+      to_bank: to_bank_key,
+      swap_program: ctx.accounts.swap_program.key(),
+      borrowed_amount: borrow_amount,
+      repaid_amount,
+      from_debt_before,
+      from_debt_after,
+      to_debt_before,
+      to_debt_after,
+      from_price,
+      to_price,
+      health_factor_before: health_before.health_factor(),
+      health_factor: user.health_factor,
+      timestamp: now,
+   });
+
+   Ok(())
+}
@@ -1,5 +1,3 @@
-use std::f64::consts::E;
-
 use anchor_lang::prelude::*;
 // use anchor_spl::{associated_token::AssociatedToken, token::TransferChecked, token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked}};
 // use anchor_spl::associated_token::AssociatedToken;
@@ -7,8 +5,7 @@ use anchor_lang::prelude::*;
 // use anchor_spl::token_interface::{ Mint, TokenAccount, TokenInterface };
 use anchor_spl::{
    associated_token::AssociatedToken,
-   token::Transfer,
-   token_interface::{ Mint, TokenAccount, TokenInterface }
+   token_interface::{ self, Mint, TokenAccount, TokenInterface, TransferChecked }
 };
 
 
@@ -16,14 +13,20 @@ use anchor_spl::{
 // use anchor_spl::token::{Mint, Token, TokenAccount, Transfer};
 use crate::state::{Bank, User};
 use crate::error::ErrCode;
+use crate::events::{InterestAccruedEvent, WithdrawEvent};
+use crate::health::{compute_health, load_emode_category, load_priced_banks, PricedBank};
+use crate::math::checked_sub;
+use crate::oracle::{get_price, oracle_accounts};
 
 
+#[event_cpi]
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
    
    #[account(mut)]
    pub signer: Signer<'info>,
 
+   #[account(mint::token_program = token_program)]
    pub mint: InterfaceAccount<'info, Mint>,
 
    // Load the bank account
@@ -31,6 +34,8 @@ pub struct Withdraw<'info> {
       mut,
       seeds=[mint.key().as_ref()],
       bump,
+      constraint = bank.mint_address == mint.key() @ ErrCode::MintMismatch,
+      constraint = !bank.paused @ ErrCode::BankPaused,
    )]
    pub bank: Account<'info, Bank>,
 
@@ -38,15 +43,27 @@ pub struct Withdraw<'info> {
       mut, // This will mutable because we are depositing into the account
       token::mint = mint, // (NOT associated_token::mint = mint)
       token::authority = bank_token_account, // (NEITHER associated_token::authority = bank NOR associated_token::authority = bank_token_account)
+      token::token_program = token_program,
       seeds = [b"treasury", mint.key().as_ref()], // Add seeds for PDA
       bump, 
    )]
    pub bank_token_account: InterfaceAccount<'info, TokenAccount>,
 
+   /// CHECK: must be the oracle stored in the bank, its data is validated when the price is read
+   #[account(address = bank.oracle @ ErrCode::InvalidOracle)]
+   pub oracle: UncheckedAccount<'info>,
+
+   /// CHECK: the first secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_1: Option<UncheckedAccount<'info>>,
+
+   /// CHECK: the second secondary oracle of the bank if it has one, checked against the bank when the price is read
+   pub secondary_oracle_2: Option<UncheckedAccount<'info>>,
+
    #[account(
       mut,
       seeds = [signer.key().as_ref()],
-      bump
+      bump,
+      constraint = user_account.owner == signer.key() @ ErrCode::Unauthorized,
    )]
    pub user_account: Account<'info, User>, 
 
@@ -56,7 +73,7 @@ pub struct Withdraw<'info> {
       // payer = signer,
       token::mint = mint,
       token::authority = signer,
-      // token::token_program = token_program,
+      token::token_program = token_program,
    )]
    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
 
@@ -66,32 +83,26 @@ pub struct Withdraw<'info> {
 }
 
 pub fn process_withdraw(ctx: Context<Withdraw>, amount:u64) -> Result<()> {
+   require!(amount > 0, ErrCode::InvalidAmount);
+   let now = Clock::get()?.unix_timestamp;
+   let bank_key = ctx.accounts.bank.key();
    let bank = &mut ctx.accounts.bank;
    let user = &mut ctx.accounts.user_account;
 
-   let deposited_value: u64;
-   if ctx.accounts.mint.to_account_info().key() == user.usdc_address {
-      deposited_value = user.deposited_usdc;
-   } else {
-      deposited_value = user.deposited_sol;
+   // Bring the bank up to date so the withdrawal includes the interest earned so far
+   let interest = bank.accrue_interest(now)?;
+   if interest > 0 {
+      emit_cpi!(InterestAccruedEvent::new(bank_key, bank, interest));
    }
 
-   /*
-      To compute the interest on an investment, the classical formula is:
-      principal * (1 + r*days/365)^(365/days) = principal * (1+r/t)^t -> principal*exp(rt)  // t=1 means one year
-   */
-
-   // Get the elements for computing the interest
-   let last_updated = bank.last_updated;
-   let elapsed_time = Clock::get()?.unix_timestamp - last_updated;
-   // let elapsed_time = 60*60*24*365;
-   let interest_rate = bank.interest_rate;
-
-   bank.total_deposits = ( (bank.total_deposits as f64) * E.powf((elapsed_time as f64) * (interest_rate as f64)/ (60.0 * 60.0 * 24.0 * 365.0 * 100.0)) ) as u64;
+   let deposit_shares = user.position(&bank_key).map_or(0, |position| position.deposit_shares);
+   let deposited_value = bank.deposit_shares_to_amount(deposit_shares)?;
 
    if amount > deposited_value {
       return Err(ErrCode::InsufficientFunds.into()); 
    }
+   // What is lent out, or owed to the insurance fund, can't be withdrawn even by its depositors
+   bank.check_liquidity(amount)?;
 
    let mint_key = ctx.accounts.mint.key();
 
@@ -102,15 +113,16 @@ pub fn process_withdraw(ctx: Context<Withdraw>, amount:u64) -> Result<()> {
 
    let cpi_ctx = CpiContext::new_with_signer(
       ctx.accounts.token_program.to_account_info().clone(),
-      Transfer{
+      TransferChecked{
          from: ctx.accounts.bank_token_account.to_account_info(),
          to: ctx.accounts.user_token_account.to_account_info(),
          authority: ctx.accounts.bank_token_account.to_account_info(),
+         mint: ctx.accounts.mint.to_account_info(),
       },
       signer,   
    );
       
-   anchor_spl::token::transfer(cpi_ctx, amount)?;
+   token_interface::transfer_checked(cpi_ctx, amount, ctx.accounts.mint.decimals)?;
 
    // After withdrawing or transfer success, we need to update the state
 
@@ -120,23 +132,51 @@ pub fn process_withdraw(ctx: Context<Withdraw>, amount:u64) -> Result<()> {
             x        -->      withdrawal_amount
    */
 
-   let shares_to_remove = bank.total_deposit_shares * amount / bank.total_deposits;
-   
-
-   if ctx.accounts.mint.to_account_info().key() == user.usdc_address {
-      user.deposited_usdc -= amount;
-      user.deposited_usdc_shares -= shares_to_remove as u64;
+   // Withdrawing everything burns all the shares, otherwise the shares are rounded up (and never more than the user has)
+   let shares_to_remove = if amount == deposited_value {
+      deposit_shares
    } else {
-      user.deposited_sol -= amount;
-      user.deposited_sol_shares -= shares_to_remove as u64;
+      bank.withdraw_amount_to_shares(amount)?.min(deposit_shares)
+   };
+
+   let position = user.position_mut(&bank_key).ok_or(ErrCode::InsufficientFunds)?;
+   position.deposit_shares = checked_sub(position.deposit_shares, shares_to_remove)?;
+
+   bank.total_deposits = checked_sub(bank.total_deposits, amount)?;
+   bank.total_deposit_shares = checked_sub(bank.total_deposit_shares, shares_to_remove)?;
+
+   user.close_empty_positions();
+
+   // The collateral that is left must still cover everything the user has borrowed
+   let oracles = oracle_accounts(
+      &ctx.accounts.oracle,
+      [&ctx.accounts.secondary_oracle_1, &ctx.accounts.secondary_oracle_2],
+   );
+   let price = get_price(bank, &oracles, now)?;
+   let acted = vec![PricedBank::new(bank_key, (**bank).clone(), price, now)];
+   let (emode, position_accounts) = load_emode_category(user, ctx.remaining_accounts)?;
+   let priced_banks = load_priced_banks(user, acted, position_accounts, now)?;
+   let health = compute_health(user, &priced_banks, emode.as_ref())?;
+
+   if !health.is_within_borrow_limit() {
+      return Err(ErrCode::WithdrawExceedsBorrowLimit.into());
    }
 
-   bank.total_deposits -= amount;
-   bank.total_deposit_shares -= shares_to_remove as u64;
+   user.record_health(&health, now);
+
+   emit_cpi!(WithdrawEvent {
+      owner: user.owner,
+      bank: bank_key,
+      mint: bank.mint_address,
+      amount,
+      shares: shares_to_remove,
+      total_deposits: bank.total_deposits,
+      total_deposit_shares: bank.total_deposit_shares,
+      deposit_index: bank.deposit_index(),
+      price,
+      health_factor: user.health_factor,
+      timestamp: now,
+   });
 
    Ok(())
 }
-
-pub fn calculate_interest(principal: f64, interest_rate: f64, time: f64) -> f64{
-   principal * (interest_rate*time).exp()
-}
\ No newline at end of file
@@ -4,6 +4,12 @@ use anchor_lang::prelude::*;
 // use state::Bank;     // First import State (Why we don't need to import state, maybe because they are the same level)
 pub mod state;
 pub mod error;       // Then register the mod state
+pub mod constants;
+pub mod events;
+pub mod oracle;
+pub mod health;
+pub mod math;
+pub mod swap;
 
 use instructions::*;    // First import instructions
 pub mod instructions;   // Then register the mod instructions
@@ -13,27 +19,158 @@ pub mod instructions;   // Then register the mod instructions
 // declare_id!("Bz6FrjvxEwRegNYrSeeSZ3omynTP8d1xjSNr5wBNW7fK");
 declare_id!("Ho5vdUND3M8RG7ztqJFVZDy834DcAqskuwxwcp2rjYac");
 
-#[program]
-mod lending {
-    use super::*;
-    pub fn init_bank(ctx: Context<InitBank>, liquidation_threshold: u64, max_ltv: u64, interest_rate: u64) -> Result<()> {
-        process_init_bank(ctx, liquidation_threshold, max_ltv, interest_rate)
-    }
+/*
+   Anchor 0.30 doesn't support #[cfg] on single instructions, so the program module is written once here
+   and the devnet faucet instructions are only added to it when the devnet-faucet feature is enabled.
+*/
+macro_rules! lending_program {
+    ($($faucet:item)*) => {
+        #[program]
+        mod lending {
+            use super::*;
+            pub fn init_bank(ctx: Context<InitBank>, liquidation_threshold: u64, max_ltv: u64, interest_rate: u64) -> Result<()> {
+                process_init_bank(ctx, liquidation_threshold, max_ltv, interest_rate)
+            }
 
-    pub fn init_user(ctx: Context<InitUser>, usdc_address: Pubkey) -> Result<()> {
-        process_init_user(ctx, usdc_address)
-    }
+            pub fn update_bank_config(ctx: Context<UpdateBankConfig>, config: BankConfig) -> Result<()> {
+                process_update_bank_config(ctx, config)
+            }
 
-    pub fn minter(ctx: Context<MintTokens>, quantity:u64) -> Result<()>{
-        process_mint_tokens(ctx, quantity)
-    }
+            pub fn set_bank_paused(ctx: Context<SetBankPaused>, paused: bool) -> Result<()> {
+                process_set_bank_paused(ctx, paused)
+            }
+
+            pub fn init_emode_category(ctx: Context<InitEModeCategory>, id: u8, config: EModeConfig) -> Result<()> {
+                process_init_emode_category(ctx, id, config)
+            }
+
+            pub fn update_emode_category(ctx: Context<UpdateEModeCategory>, config: EModeConfig) -> Result<()> {
+                process_update_emode_category(ctx, config)
+            }
+
+            pub fn set_bank_emode_category(ctx: Context<SetBankEModeCategory>) -> Result<()> {
+                process_set_bank_emode_category(ctx)
+            }
+
+            pub fn set_bank_isolation(ctx: Context<SetBankIsolation>, config: IsolationConfig) -> Result<()> {
+                process_set_bank_isolation(ctx, config)
+            }
+
+            pub fn set_bank_liquidation_mode(ctx: Context<SetBankLiquidationMode>, config: LiquidationModeConfig) -> Result<()> {
+                process_set_bank_liquidation_mode(ctx, config)
+            }
+
+            pub fn set_bank_insurance(ctx: Context<SetBankInsurance>, config: InsuranceConfig) -> Result<()> {
+                process_set_bank_insurance(ctx, config)
+            }
+
+            pub fn set_bank_self_liquidation(ctx: Context<SetBankSelfLiquidation>, config: SelfLiquidationConfig) -> Result<()> {
+                process_set_bank_self_liquidation(ctx, config)
+            }
+
+            pub fn set_bank_oracles(ctx: Context<SetBankOracles>, config: OracleConfig) -> Result<()> {
+                process_set_bank_oracles(ctx, config)
+            }
+
+            pub fn set_bank_ema(ctx: Context<SetBankEma>, config: EmaConfig) -> Result<()> {
+                process_set_bank_ema(ctx, config)
+            }
+
+            pub fn init_user(ctx: Context<InitUser>) -> Result<()> {
+                process_init_user(ctx)
+            }
+
+            pub fn set_user_emode(ctx: Context<SetUserEMode>) -> Result<()> {
+                process_set_user_emode(ctx)
+            }
+
+            pub fn deposit(ctx:Context<Deposit>, amount:u64) -> Result<()> {
+                process_deposit(ctx, amount)
+            }
+
+            pub fn withdraw(ctx: Context<Withdraw>, amount:u64) -> Result<()> {
+                process_withdraw(ctx, amount)
+            }
+
+            pub fn borrow(ctx: Context<Borrow>, amount:u64) -> Result<()> {
+                process_borrow(ctx, amount)
+            }
+
+            pub fn repay(ctx: Context<Repay>, amount:u64) -> Result<()> {
+                process_repay(ctx, amount)
+            }
 
-    pub fn deposit(ctx:Context<Deposit>, amount:u64) -> Result<()> {
-        process_deposit(ctx, amount)
+            pub fn liquidate(ctx: Context<Liquidate>, amount:u64) -> Result<()> {
+                process_liquidate(ctx, amount)
+            }
+
+            pub fn self_liquidate<'info>(ctx: Context<'_, '_, '_, 'info, SelfLiquidate<'info>>, amount: u64, min_amount_out: u64) -> Result<()> {
+                process_self_liquidate(ctx, amount, min_amount_out)
+            }
+
+            pub fn repay_with_collateral<'info>(ctx: Context<'_, '_, '_, 'info, RepayWithCollateral<'info>>, collateral_amount: u64, min_amount_out: u64) -> Result<()> {
+                process_repay_with_collateral(ctx, collateral_amount, min_amount_out)
+            }
+
+            pub fn leverage<'info>(ctx: Context<'_, '_, '_, 'info, Leverage<'info>>, target_ltv: u64, min_amount_out: u64) -> Result<()> {
+                process_leverage(ctx, target_ltv, min_amount_out)
+            }
+
+            pub fn deleverage<'info>(ctx: Context<'_, '_, '_, 'info, RepayWithCollateral<'info>>, target_ltv: u64, min_amount_out: u64) -> Result<()> {
+                process_deleverage(ctx, target_ltv, min_amount_out)
+            }
+
+            pub fn swap_collateral<'info>(ctx: Context<'_, '_, '_, 'info, SwapPosition<'info>>, amount: u64, min_amount_out: u64) -> Result<()> {
+                process_swap_collateral(ctx, amount, min_amount_out)
+            }
+
+            pub fn swap_debt<'info>(ctx: Context<'_, '_, '_, 'info, SwapPosition<'info>>, borrow_amount: u64, min_amount_out: u64) -> Result<()> {
+                process_swap_debt(ctx, borrow_amount, min_amount_out)
+            }
+
+            pub fn socialize_loss(ctx: Context<SocializeLoss>) -> Result<()> {
+                process_socialize_loss(ctx)
+            }
+
+            pub fn stake_insurance(ctx: Context<StakeInsurance>, amount: u64) -> Result<()> {
+                process_stake_insurance(ctx, amount)
+            }
+
+            pub fn request_unstake(ctx: Context<RequestUnstake>, shares: u64) -> Result<()> {
+                process_request_unstake(ctx, shares)
+            }
+
+            pub fn unstake(ctx: Context<Unstake>) -> Result<()> {
+                process_unstake(ctx)
+            }
+
+            pub fn refresh_bank(ctx: Context<RefreshBank>) -> Result<()> {
+                process_refresh_bank(ctx)
+            }
+
+            pub fn refresh_user(ctx: Context<RefreshUser>) -> Result<()> {
+                process_refresh_user(ctx)
+            }
+
+            $($faucet)*
+        }
+    };
+}
+
+#[cfg(not(feature = "devnet-faucet"))]
+lending_program!();
+
+#[cfg(feature = "devnet-faucet")]
+lending_program! {
+    pub fn init_faucet(ctx: Context<InitFaucet>, max_amount: u64, cooldown: u64) -> Result<()> {
+        process_init_faucet(ctx, max_amount, cooldown)
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>, amount:u64) -> Result<()> {
-        process_withdraw(ctx, amount)
+    pub fn update_faucet(ctx: Context<UpdateFaucet>, max_amount: u64, cooldown: u64) -> Result<()> {
+        process_update_faucet(ctx, max_amount, cooldown)
     }
 
+    pub fn minter(ctx: Context<MintTokens>, quantity:u64) -> Result<()>{
+        process_mint_tokens(ctx, quantity)
+    }
 }
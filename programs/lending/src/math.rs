@@ -0,0 +1,161 @@
+use anchor_lang::prelude::*;
+use crate::error::ErrCode;
+
+// Fixed point scale used for the interest growth factor
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+
+pub const SECONDS_PER_YEAR: u128 = 60 * 60 * 24 * 365;
+
+/*
+   Every amount, share and value in the program goes through these helpers, so an overflow or a division by zero
+   becomes ErrCode::MathOverflow / ErrCode::DivisionByZero instead of a panic (or a silent wrap).
+   The products are computed in u128, so a * b never overflows when both sides fit in a u64.
+*/
+
+// floor(a * b / c)
+pub fn mul_div(a: u64, b: u64, c: u64) -> Result<u64> {
+   require!(c != 0, ErrCode::DivisionByZero);
+   to_u64(a as u128 * b as u128 / c as u128)
+}
+
+// ceil(a * b / c)
+pub fn mul_div_ceil(a: u64, b: u64, c: u64) -> Result<u64> {
+   require!(c != 0, ErrCode::DivisionByZero);
+   to_u64((a as u128 * b as u128).div_ceil(c as u128))
+}
+
+// floor(a * b / c) for values that are already u128
+pub fn mul_div_u128(a: u128, b: u128, c: u128) -> Result<u128> {
+   require!(c != 0, ErrCode::DivisionByZero);
+   let product = a.checked_mul(b).ok_or(ErrCode::MathOverflow)?;
+   Ok(product / c)
+}
+
+// ceil(a * b / c) for values that are already u128
+pub fn mul_div_ceil_u128(a: u128, b: u128, c: u128) -> Result<u128> {
+   require!(c != 0, ErrCode::DivisionByZero);
+   let product = a.checked_mul(b).ok_or(ErrCode::MathOverflow)?;
+   Ok(product.div_ceil(c))
+}
+
+/*
+   Values are in the quote currency of the oracles with PRICE_DECIMALS decimals, whatever the mint:
+   an amount of a mint with `decimals` decimals is amount / 10^decimals tokens, each worth `price`.
+   1 SOL (9 decimals) at $150 and 150 USDC (6 decimals) at $1 are both worth 150 * 10^8.
+*/
+
+// floor(amount * price / 10^decimals), what collateral is worth
+pub fn token_value(amount: u64, price: u64, decimals: u8) -> Result<u128> {
+   mul_div_u128(amount as u128, price as u128, decimals_scale(decimals)?)
+}
+
+// ceil(amount * price / 10^decimals), what a debt is worth
+pub fn token_value_ceil(amount: u64, price: u64, decimals: u8) -> Result<u128> {
+   mul_div_ceil_u128(amount as u128, price as u128, decimals_scale(decimals)?)
+}
+
+// floor(value * 10^decimals / price), the amount of the mint worth `value`
+pub fn value_to_amount(value: u128, price: u64, decimals: u8) -> Result<u64> {
+   to_u64(mul_div_u128(value, decimals_scale(decimals)?, price as u128)?)
+}
+
+// 10^decimals, the number of base units in one token
+pub fn decimals_scale(decimals: u8) -> Result<u128> {
+   10u128.checked_pow(decimals as u32).ok_or_else(|| error!(ErrCode::MathOverflow))
+}
+
+pub fn to_u64(value: u128) -> Result<u64> {
+   u64::try_from(value).map_err(|_| error!(ErrCode::MathOverflow))
+}
+
+pub fn checked_add(a: u64, b: u64) -> Result<u64> {
+   a.checked_add(b).ok_or_else(|| error!(ErrCode::MathOverflow))
+}
+
+pub fn checked_sub(a: u64, b: u64) -> Result<u64> {
+   a.checked_sub(b).ok_or_else(|| error!(ErrCode::MathOverflow))
+}
+
+pub fn checked_add_u128(a: u128, b: u128) -> Result<u128> {
+   a.checked_add(b).ok_or_else(|| error!(ErrCode::MathOverflow))
+}
+
+/*
+   To compute the interest on an investment, the classical formula is:
+   principal * (1 + r*days/365)^(365/days) = principal * (1+r/t)^t -> principal*exp(rt)  // t=1 means one year
+
+   exp(x) is approximated with the first terms of its Taylor series 1 + x + x^2/2 + x^3/6, which is precise
+   for the small x of the time between two updates of a bank. The result is scaled by WAD.
+*/
+pub fn compounded_interest_factor(interest_rate: u64, elapsed_time: u64) -> Result<u128> {
+   // interest_rate is a yearly percentage
+   let x = mul_div_u128(interest_rate as u128 * WAD, elapsed_time as u128, 100 * SECONDS_PER_YEAR)?;
+   let x_squared = mul_div_u128(x, x, WAD)?;
+   let x_cubed = mul_div_u128(x_squared, x, WAD)?;
+
+   let factor = checked_add_u128(WAD, x)?;
+   let factor = checked_add_u128(factor, x_squared / 2)?;
+   checked_add_u128(factor, x_cubed / 6)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn mul_div_keeps_the_intermediate_product_in_u128() {
+      assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX).unwrap(), u64::MAX);
+      assert_eq!(mul_div_ceil(u64::MAX, 2, 4).unwrap(), u64::MAX / 2 + 1);
+   }
+
+   #[test]
+   fn mul_div_overflows_when_the_result_does_not_fit() {
+      assert_eq!(mul_div(u64::MAX, 2, 1).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(mul_div_ceil(u64::MAX, u64::MAX, u64::MAX - 1).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(mul_div_u128(u128::MAX, 2, 1).unwrap_err(), ErrCode::MathOverflow.into());
+   }
+
+   #[test]
+   fn division_by_zero_is_an_error() {
+      assert_eq!(mul_div(1, 1, 0).unwrap_err(), ErrCode::DivisionByZero.into());
+      assert_eq!(mul_div_ceil(1, 1, 0).unwrap_err(), ErrCode::DivisionByZero.into());
+      assert_eq!(mul_div_u128(1, 1, 0).unwrap_err(), ErrCode::DivisionByZero.into());
+   }
+
+   #[test]
+   fn token_value_puts_every_mint_on_the_same_scale() {
+      const DOLLAR: u64 = 100_000_000;
+      // $150 of a 0, 6, 9 and 18 decimals mint
+      assert_eq!(token_value(1, 150 * DOLLAR, 0).unwrap(), 150 * DOLLAR as u128);
+      assert_eq!(token_value(150_000_000, DOLLAR, 6).unwrap(), 150 * DOLLAR as u128);
+      assert_eq!(token_value(1_000_000_000, 150 * DOLLAR, 9).unwrap(), 150 * DOLLAR as u128);
+      assert_eq!(token_value(50_000_000_000_000_000, 3_000 * DOLLAR, 18).unwrap(), 150 * DOLLAR as u128);
+      assert_eq!(value_to_amount(150 * DOLLAR as u128, 3_000 * DOLLAR, 18).unwrap(), 50_000_000_000_000_000);
+
+      // Dust below the precision of a value is worth nothing as collateral and something as debt
+      assert_eq!(token_value(1, 3_000 * DOLLAR, 18).unwrap(), 0);
+      assert_eq!(token_value_ceil(1, 3_000 * DOLLAR, 18).unwrap(), 1);
+      assert_eq!(token_value(1, 1, 39).unwrap_err(), ErrCode::MathOverflow.into());
+   }
+
+   #[test]
+   fn add_and_sub_overflow() {
+      assert_eq!(checked_add(u64::MAX, 1).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(checked_sub(0, 1).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(checked_add_u128(u128::MAX, 1).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(to_u64(u64::MAX as u128 + 1).unwrap_err(), ErrCode::MathOverflow.into());
+   }
+
+   #[test]
+   fn interest_factor_matches_exp() {
+      // 5% during a full year -> exp(0.05) = 1.051271...
+      let factor = compounded_interest_factor(5, SECONDS_PER_YEAR as u64).unwrap();
+      assert_eq!(factor / 1_000_000_000_000, 1_051_270);
+      assert_eq!(compounded_interest_factor(5, 0).unwrap(), WAD);
+   }
+
+   #[test]
+   fn interest_factor_overflows_for_absurd_rates() {
+      assert_eq!(compounded_interest_factor(u64::MAX, u64::MAX).unwrap_err(), ErrCode::MathOverflow.into());
+   }
+}
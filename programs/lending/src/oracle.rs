@@ -0,0 +1,433 @@
+use anchor_lang::prelude::*;
+use crate::constants::{BPS, MAX_PRICE_CONFIDENCE_BPS, PRICE_DECIMALS};
+use crate::error::ErrCode;
+use crate::state::Bank;
+
+/*
+   Banks are priced with a Pyth (push oracle) price account, and optionally up to two secondary oracles (see aggregate_price). We only read the few fields we need,
+   at the same offsets the Pyth program uses, so a mock account with that layout works in local tests.
+*/
+pub const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+pub const PYTH_VERSION: u32 = 2;
+pub const PYTH_PRICE_ACCOUNT_TYPE: u32 = 3;
+pub const PYTH_STATUS_TRADING: u32 = 1;
+
+pub const PYTH_MAGIC_OFFSET: usize = 0;
+pub const PYTH_VERSION_OFFSET: usize = 4;
+pub const PYTH_ACCOUNT_TYPE_OFFSET: usize = 8;
+pub const PYTH_EXPO_OFFSET: usize = 20;
+pub const PYTH_TIMESTAMP_OFFSET: usize = 96;
+pub const PYTH_PRICE_OFFSET: usize = 208;
+pub const PYTH_CONF_OFFSET: usize = 216;
+pub const PYTH_STATUS_OFFSET: usize = 224;
+pub const PYTH_PRICE_ACCOUNT_LEN: usize = 240;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PythPrice {
+   pub price: i64,
+   pub conf: u64,
+   pub expo: i32,
+   pub publish_time: i64,
+   pub status: u32,
+}
+
+impl PythPrice {
+   pub fn parse(data: &[u8]) -> Result<Self> {
+      require!(data.len() >= PYTH_PRICE_ACCOUNT_LEN, ErrCode::InvalidOracle);
+      require!(read_u32(data, PYTH_MAGIC_OFFSET) == PYTH_MAGIC, ErrCode::InvalidOracle);
+      require!(read_u32(data, PYTH_VERSION_OFFSET) == PYTH_VERSION, ErrCode::InvalidOracle);
+      require!(read_u32(data, PYTH_ACCOUNT_TYPE_OFFSET) == PYTH_PRICE_ACCOUNT_TYPE, ErrCode::InvalidOracle);
+
+      Ok(PythPrice {
+         price: read_u64(data, PYTH_PRICE_OFFSET) as i64,
+         conf: read_u64(data, PYTH_CONF_OFFSET),
+         expo: read_u32(data, PYTH_EXPO_OFFSET) as i32,
+         publish_time: read_u64(data, PYTH_TIMESTAMP_OFFSET) as i64,
+         status: read_u32(data, PYTH_STATUS_OFFSET),
+      })
+   }
+
+   // The price with PRICE_DECIMALS decimals, if it is a trading price published less than max_age seconds ago
+   // with a confidence interval within MAX_PRICE_CONFIDENCE_BPS of it
+   pub fn checked_price(&self, max_age: u64, now: i64) -> Result<u64> {
+      require!(self.status == PYTH_STATUS_TRADING, ErrCode::InvalidOracle);
+      require!(self.price > 0, ErrCode::InvalidOracle);
+      check_age(self.publish_time, max_age, now)?;
+      let confidence = self.conf as u128 * BPS as u128;
+      require!(confidence <= self.price as u128 * MAX_PRICE_CONFIDENCE_BPS as u128, ErrCode::OracleConfidenceTooWide);
+
+      normalize_price(self.price as u64, self.expo)
+   }
+
+   // Writes the fields we read into a buffer of PYTH_PRICE_ACCOUNT_LEN bytes, used to create mock oracles
+   pub fn write(&self, data: &mut [u8]) {
+      data[PYTH_MAGIC_OFFSET..PYTH_MAGIC_OFFSET + 4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+      data[PYTH_VERSION_OFFSET..PYTH_VERSION_OFFSET + 4].copy_from_slice(&PYTH_VERSION.to_le_bytes());
+      data[PYTH_ACCOUNT_TYPE_OFFSET..PYTH_ACCOUNT_TYPE_OFFSET + 4].copy_from_slice(&PYTH_PRICE_ACCOUNT_TYPE.to_le_bytes());
+      data[PYTH_EXPO_OFFSET..PYTH_EXPO_OFFSET + 4].copy_from_slice(&self.expo.to_le_bytes());
+      data[PYTH_TIMESTAMP_OFFSET..PYTH_TIMESTAMP_OFFSET + 8].copy_from_slice(&self.publish_time.to_le_bytes());
+      data[PYTH_PRICE_OFFSET..PYTH_PRICE_OFFSET + 8].copy_from_slice(&self.price.to_le_bytes());
+      data[PYTH_CONF_OFFSET..PYTH_CONF_OFFSET + 8].copy_from_slice(&self.conf.to_le_bytes());
+      data[PYTH_STATUS_OFFSET..PYTH_STATUS_OFFSET + 4].copy_from_slice(&self.status.to_le_bytes());
+   }
+}
+
+/*
+   Switchboard-style feeds: an aggregator account holding the result of its latest round as a decimal (mantissa and scale)
+   and the time the round was opened. This is not the byte layout of the Switchboard program, only the same fields
+   after the discriminator of its AggregatorAccountData, enough for a mock secondary oracle in local tests.
+*/
+pub const SWITCHBOARD_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+pub const SWITCHBOARD_MANTISSA_OFFSET: usize = 8;
+pub const SWITCHBOARD_SCALE_OFFSET: usize = 24;
+pub const SWITCHBOARD_TIMESTAMP_OFFSET: usize = 28;
+pub const SWITCHBOARD_ACCOUNT_LEN: usize = 36;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwitchboardPrice {
+   pub mantissa: i128,
+   pub scale: u32,
+   pub timestamp: i64,
+}
+
+impl SwitchboardPrice {
+   pub fn parse(data: &[u8]) -> Result<Self> {
+      require!(data.len() >= SWITCHBOARD_ACCOUNT_LEN, ErrCode::InvalidOracle);
+      require!(data[..8] == SWITCHBOARD_DISCRIMINATOR, ErrCode::InvalidOracle);
+
+      let mut mantissa = [0u8; 16];
+      mantissa.copy_from_slice(&data[SWITCHBOARD_MANTISSA_OFFSET..SWITCHBOARD_MANTISSA_OFFSET + 16]);
+      Ok(SwitchboardPrice {
+         mantissa: i128::from_le_bytes(mantissa),
+         scale: read_u32(data, SWITCHBOARD_SCALE_OFFSET),
+         timestamp: read_u64(data, SWITCHBOARD_TIMESTAMP_OFFSET) as i64,
+      })
+   }
+
+   // mantissa / 10^scale with PRICE_DECIMALS decimals, if the round is less than max_age seconds old
+   pub fn checked_price(&self, max_age: u64, now: i64) -> Result<u64> {
+      require!(self.mantissa > 0 && self.mantissa <= u64::MAX as i128, ErrCode::InvalidOracle);
+      let expo = i32::try_from(self.scale).map_err(|_| ErrCode::InvalidOracle)?;
+      check_age(self.timestamp, max_age, now)?;
+
+      normalize_price(self.mantissa as u64, -expo)
+   }
+
+   // Writes the fields we read into a buffer of SWITCHBOARD_ACCOUNT_LEN bytes, used to create mock oracles
+   pub fn write(&self, data: &mut [u8]) {
+      data[..8].copy_from_slice(&SWITCHBOARD_DISCRIMINATOR);
+      data[SWITCHBOARD_MANTISSA_OFFSET..SWITCHBOARD_MANTISSA_OFFSET + 16].copy_from_slice(&self.mantissa.to_le_bytes());
+      data[SWITCHBOARD_SCALE_OFFSET..SWITCHBOARD_SCALE_OFFSET + 4].copy_from_slice(&self.scale.to_le_bytes());
+      data[SWITCHBOARD_TIMESTAMP_OFFSET..SWITCHBOARD_TIMESTAMP_OFFSET + 8].copy_from_slice(&self.timestamp.to_le_bytes());
+   }
+}
+
+/*
+   AMM TWAP feeds: the time weighted average price of a pool, kept by the AMM in an account of its own,
+   with the length of the window it averages over. Like the others only the fields we read, for mocks.
+*/
+pub const TWAP_MAGIC: u32 = 0x50415754; // "TWAP" in little endian
+pub const TWAP_PRICE_OFFSET: usize = 4;
+pub const TWAP_EXPO_OFFSET: usize = 12;
+pub const TWAP_WINDOW_OFFSET: usize = 16;
+pub const TWAP_UPDATED_AT_OFFSET: usize = 24;
+pub const TWAP_ACCOUNT_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TwapPrice {
+   pub price: u64,
+   pub expo: i32,
+   pub window: u64,
+   pub updated_at: i64,
+}
+
+impl TwapPrice {
+   pub fn parse(data: &[u8]) -> Result<Self> {
+      require!(data.len() >= TWAP_ACCOUNT_LEN, ErrCode::InvalidOracle);
+      require!(read_u32(data, 0) == TWAP_MAGIC, ErrCode::InvalidOracle);
+
+      Ok(TwapPrice {
+         price: read_u64(data, TWAP_PRICE_OFFSET),
+         expo: read_u32(data, TWAP_EXPO_OFFSET) as i32,
+         window: read_u64(data, TWAP_WINDOW_OFFSET),
+         updated_at: read_u64(data, TWAP_UPDATED_AT_OFFSET) as i64,
+      })
+   }
+
+   // The average with PRICE_DECIMALS decimals, if the AMM updated it less than max_age seconds ago
+   pub fn checked_price(&self, max_age: u64, now: i64) -> Result<u64> {
+      require!(self.price > 0 && self.window > 0, ErrCode::InvalidOracle);
+      check_age(self.updated_at, max_age, now)?;
+
+      normalize_price(self.price, self.expo)
+   }
+
+   // Writes the fields we read into a buffer of TWAP_ACCOUNT_LEN bytes, used to create mock oracles
+   pub fn write(&self, data: &mut [u8]) {
+      data[..4].copy_from_slice(&TWAP_MAGIC.to_le_bytes());
+      data[TWAP_PRICE_OFFSET..TWAP_PRICE_OFFSET + 8].copy_from_slice(&self.price.to_le_bytes());
+      data[TWAP_EXPO_OFFSET..TWAP_EXPO_OFFSET + 4].copy_from_slice(&self.expo.to_le_bytes());
+      data[TWAP_WINDOW_OFFSET..TWAP_WINDOW_OFFSET + 8].copy_from_slice(&self.window.to_le_bytes());
+      data[TWAP_UPDATED_AT_OFFSET..TWAP_UPDATED_AT_OFFSET + 8].copy_from_slice(&self.updated_at.to_le_bytes());
+   }
+}
+
+// An oracle account in any of the layouts above, told apart by their first bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OraclePrice {
+   Pyth(PythPrice),
+   Switchboard(SwitchboardPrice),
+   Twap(TwapPrice),
+}
+
+impl OraclePrice {
+   pub fn parse(data: &[u8]) -> Result<Self> {
+      require!(data.len() >= 8, ErrCode::InvalidOracle);
+      if data[..8] == SWITCHBOARD_DISCRIMINATOR {
+         return Ok(OraclePrice::Switchboard(SwitchboardPrice::parse(data)?));
+      }
+      match read_u32(data, 0) {
+         PYTH_MAGIC => Ok(OraclePrice::Pyth(PythPrice::parse(data)?)),
+         TWAP_MAGIC => Ok(OraclePrice::Twap(TwapPrice::parse(data)?)),
+         _ => Err(ErrCode::InvalidOracle.into()),
+      }
+   }
+
+   pub fn checked_price(&self, max_age: u64, now: i64) -> Result<u64> {
+      match self {
+         OraclePrice::Pyth(price) => price.checked_price(max_age, now),
+         OraclePrice::Switchboard(price) => price.checked_price(max_age, now),
+         OraclePrice::Twap(price) => price.checked_price(max_age, now),
+      }
+   }
+
+   pub fn publish_time(&self) -> i64 {
+      match self {
+         OraclePrice::Pyth(price) => price.publish_time,
+         OraclePrice::Switchboard(price) => price.timestamp,
+         OraclePrice::Twap(price) => price.updated_at,
+      }
+   }
+}
+
+/*
+   The price of a bank from the data of its oracles, in the order of Bank::oracles (the primary one first).
+   It is the median of the sources with a fresh price, the average of the two middle ones for an even count.
+   A stale or unreadable source is left out, so the secondary oracles take over when the primary one stops updating,
+   and only when none of them is fresh the error of the primary oracle is returned.
+   With an oracle_tolerance_bps, a fresh source further than that from the median means the sources disagree
+   and the price is rejected rather than trusting any of them.
+   Returns the price with PRICE_DECIMALS decimals and the publish time of the oldest source in it.
+*/
+pub fn aggregate_price(bank: &Bank, oracle_data: &[&[u8]], now: i64) -> Result<(u64, i64)> {
+   let mut fresh = Vec::with_capacity(oracle_data.len());
+   let mut primary_error = None;
+   for (index, data) in oracle_data.iter().enumerate() {
+      let source = OraclePrice::parse(data)
+         .and_then(|oracle| Ok((oracle.checked_price(bank.oracle_max_age, now)?, oracle.publish_time())));
+      match source {
+         Ok(source) => fresh.push(source),
+         Err(error) if index == 0 => primary_error = Some(error),
+         Err(_) => {}
+      }
+   }
+   if fresh.is_empty() {
+      return Err(primary_error.unwrap_or_else(|| ErrCode::InvalidOracle.into()));
+   }
+
+   fresh.sort_unstable_by_key(|(price, _)| *price);
+   let middle = fresh.len() / 2;
+   let median = if fresh.len() % 2 == 1 {
+      fresh[middle].0
+   } else {
+      ((fresh[middle - 1].0 as u128 + fresh[middle].0 as u128) / 2) as u64
+   };
+
+   if bank.oracle_tolerance_bps > 0 {
+      for (price, _) in fresh.iter() {
+         let distance = price.abs_diff(median) as u128 * BPS as u128;
+         require!(distance <= bank.oracle_tolerance_bps as u128 * median as u128, ErrCode::OracleDivergence);
+      }
+   }
+
+   let publish_time = fresh.iter().map(|(_, publish_time)| *publish_time).min().unwrap_or_default();
+   Ok((median, publish_time))
+}
+
+/*
+   Reads the oracles of a bank and returns its price with PRICE_DECIMALS decimals and its publish time, see aggregate_price.
+   `oracles` are the accounts of Bank::oracles in the same order.
+*/
+pub fn get_price_and_time(bank: &Bank, oracles: &[&AccountInfo], now: i64) -> Result<(u64, i64)> {
+   let expected = bank.oracles();
+   require!(oracles.len() >= expected.len(), ErrCode::MissingSecondaryOracle);
+   require!(oracles.len() == expected.len(), ErrCode::InvalidOracle);
+   for (oracle, key) in oracles.iter().zip(expected.iter()) {
+      require_keys_eq!(oracle.key(), *key, ErrCode::InvalidOracle);
+   }
+
+   let data = oracles.iter().map(|oracle| oracle.try_borrow_data()).collect::<std::result::Result<Vec<_>, _>>()?;
+   let data: Vec<&[u8]> = data.iter().map(|data| &data[..]).collect();
+   aggregate_price(bank, &data, now)
+}
+
+// Reads the oracles of a bank and returns its price with PRICE_DECIMALS decimals
+pub fn get_price(bank: &Bank, oracles: &[&AccountInfo], now: i64) -> Result<u64> {
+   Ok(get_price_and_time(bank, oracles, now)?.0)
+}
+
+// The oracle of a bank followed by the secondary oracles an instruction was given, for get_price
+pub fn oracle_accounts<'a, 'info>(
+   oracle: &'a AccountInfo<'info>,
+   secondary_oracles: [&'a Option<UncheckedAccount<'info>>; 2],
+) -> Vec<&'a AccountInfo<'info>> {
+   std::iter::once(oracle)
+      .chain(secondary_oracles.into_iter().flatten().map(|oracle| oracle.as_ref()))
+      .collect()
+}
+
+/*
+   price * 10^expo expressed with PRICE_DECIMALS decimals. A price below what PRICE_DECIMALS can express is an invalid
+   oracle rather than 0: a bank priced at 0 would lend without limit, its debt worth nothing.
+*/
+pub fn normalize_price(price: u64, expo: i32) -> Result<u64> {
+   let shift = PRICE_DECIMALS.checked_add(expo).ok_or(ErrCode::MathOverflow)?;
+   let normalized = if shift >= 0 {
+      let scale = 10u64.checked_pow(shift.unsigned_abs()).ok_or(ErrCode::MathOverflow)?;
+      price.checked_mul(scale).ok_or(ErrCode::MathOverflow)?
+   } else {
+      // Past 10^19 any u64 price divides down to 0
+      10u64.checked_pow(shift.unsigned_abs()).map_or(0, |scale| price / scale)
+   };
+   require!(normalized > 0, ErrCode::InvalidOracle);
+   Ok(normalized)
+}
+
+fn check_age(publish_time: i64, max_age: u64, now: i64) -> Result<()> {
+   let age = now.checked_sub(publish_time).ok_or(ErrCode::MathOverflow)?;
+   require!(age <= max_age as i64, ErrCode::StaleOracle);
+   Ok(())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+   let mut bytes = [0u8; 4];
+   bytes.copy_from_slice(&data[offset..offset + 4]);
+   u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+   let mut bytes = [0u8; 8];
+   bytes.copy_from_slice(&data[offset..offset + 8]);
+   u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   const NOW: i64 = 1_000_000;
+
+   fn pyth(price: u64, publish_time: i64) -> Vec<u8> {
+      let mut data = vec![0; PYTH_PRICE_ACCOUNT_LEN];
+      PythPrice { price: price as i64 * 100_000_000, conf: 0, expo: -8, publish_time, status: PYTH_STATUS_TRADING }.write(&mut data);
+      data
+   }
+
+   fn switchboard(price: u64, timestamp: i64) -> Vec<u8> {
+      let mut data = vec![0; SWITCHBOARD_ACCOUNT_LEN];
+      SwitchboardPrice { mantissa: price as i128 * 1_000, scale: 3, timestamp }.write(&mut data);
+      data
+   }
+
+   fn twap(price: u64, updated_at: i64) -> Vec<u8> {
+      let mut data = vec![0; TWAP_ACCOUNT_LEN];
+      TwapPrice { price: price * 100, expo: -2, window: 1_800, updated_at }.write(&mut data);
+      data
+   }
+
+   fn bank(oracle_tolerance_bps: u64) -> Bank {
+      Bank { oracle_max_age: 60, oracle_tolerance_bps, ..Default::default() }
+   }
+
+   fn aggregate(bank: &Bank, sources: &[Vec<u8>]) -> Result<(u64, i64)> {
+      aggregate_price(bank, &sources.iter().map(|data| &data[..]).collect::<Vec<_>>(), NOW)
+   }
+
+   #[test]
+   fn every_layout_is_read_with_price_decimals() {
+      assert_eq!(OraclePrice::parse(&pyth(150, NOW)).unwrap().checked_price(60, NOW).unwrap(), 15_000_000_000);
+      assert_eq!(OraclePrice::parse(&switchboard(150, NOW)).unwrap().checked_price(60, NOW).unwrap(), 15_000_000_000);
+      assert_eq!(OraclePrice::parse(&twap(150, NOW)).unwrap().checked_price(60, NOW).unwrap(), 15_000_000_000);
+      assert_eq!(OraclePrice::parse(&[0; 64]).unwrap_err(), ErrCode::InvalidOracle.into());
+   }
+
+   #[test]
+   fn price_is_the_median_of_the_fresh_sources() {
+      let (price, publish_time) = aggregate(&bank(0), &[pyth(150, NOW), switchboard(148, NOW - 10), twap(160, NOW - 5)]).unwrap();
+      assert_eq!((price, publish_time), (15_000_000_000, NOW - 10));
+
+      // Two sources average the middle ones
+      assert_eq!(aggregate(&bank(0), &[pyth(150, NOW), twap(151, NOW)]).unwrap().0, 15_050_000_000);
+   }
+
+   #[test]
+   fn secondary_oracles_take_over_a_stale_primary() {
+      let (price, _) = aggregate(&bank(0), &[pyth(150, NOW - 61), switchboard(148, NOW), twap(152, NOW)]).unwrap();
+      assert_eq!(price, 15_000_000_000);
+
+      // Without any fresh source the error is the one of the primary
+      let error = aggregate(&bank(0), &[pyth(150, NOW - 61), switchboard(148, NOW - 61)]).unwrap_err();
+      assert_eq!(error, ErrCode::StaleOracle.into());
+   }
+
+   #[test]
+   fn sources_further_than_the_tolerance_from_the_median_are_rejected() {
+      // The median is 150, 160 is 6.7% away from it
+      let sources = [pyth(150, NOW), switchboard(148, NOW), twap(160, NOW)];
+      assert_eq!(aggregate(&bank(500), &sources).unwrap_err(), ErrCode::OracleDivergence.into());
+      assert_eq!(aggregate(&bank(700), &sources).unwrap().0, 15_000_000_000);
+
+      // A stale source is left out of the check as well
+      let sources = [pyth(150, NOW), switchboard(148, NOW), twap(160, NOW - 61)];
+      assert_eq!(aggregate(&bank(500), &sources).unwrap().0, 14_900_000_000);
+   }
+
+   #[test]
+   fn normalize_price_moves_the_exponent_to_price_decimals() {
+      assert_eq!(normalize_price(15_000_000_000, -8).unwrap(), 15_000_000_000);
+      assert_eq!(normalize_price(150_000, -3).unwrap(), 15_000_000_000);
+      assert_eq!(normalize_price(1_500_000_000_000, -10).unwrap(), 15_000_000_000);
+   }
+
+   #[test]
+   fn prices_too_small_for_price_decimals_are_invalid() {
+      assert_eq!(normalize_price(1, -8).unwrap(), 1);
+      assert_eq!(normalize_price(9, -9).unwrap_err(), ErrCode::InvalidOracle.into());
+      assert_eq!(normalize_price(u64::MAX, -30).unwrap_err(), ErrCode::InvalidOracle.into());
+      assert_eq!(normalize_price(1, i32::MIN).unwrap_err(), ErrCode::InvalidOracle.into());
+
+      let tiny = PythPrice { price: 5, conf: 0, expo: -12, publish_time: NOW, status: PYTH_STATUS_TRADING };
+      assert_eq!(tiny.checked_price(60, NOW).unwrap_err(), ErrCode::InvalidOracle.into());
+   }
+
+   #[test]
+   fn wide_confidence_intervals_are_rejected() {
+      let price = PythPrice { price: 15_000_000_000, conf: 300_000_000, expo: -8, publish_time: NOW, status: PYTH_STATUS_TRADING };
+      assert_eq!(price.checked_price(60, NOW).unwrap(), 15_000_000_000);
+      let wide = PythPrice { conf: 300_000_001, ..price };
+      assert_eq!(wide.checked_price(60, NOW).unwrap_err(), ErrCode::OracleConfidenceTooWide.into());
+
+      // Like a stale one the source is left out, a secondary oracle takes over
+      let mut data = vec![0; PYTH_PRICE_ACCOUNT_LEN];
+      wide.write(&mut data);
+      let sources = [data, switchboard(148, NOW)];
+      assert_eq!(aggregate(&bank(0), &sources).unwrap().0, 14_800_000_000);
+   }
+
+   #[test]
+   fn normalize_price_overflows_instead_of_panicking() {
+      assert_eq!(normalize_price(u64::MAX, -7).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(normalize_price(1, 20).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(normalize_price(1, i32::MAX).unwrap_err(), ErrCode::MathOverflow.into());
+   }
+}
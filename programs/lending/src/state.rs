@@ -1,10 +1,15 @@
 use anchor_lang::prelude::*;
+use crate::constants::*;
+use crate::error::ErrCode;
+use crate::health::Health;
+use crate::math::*;
 
 #[account]
-#[derive(InitSpace)]
+#[derive(InitSpace, Default)]
 pub struct Bank {
    pub authority: Pubkey, // Every bank should have an authority, who will have special permissions to change the config of the bank
    pub mint_address: Pubkey, // represents the address of the underlying asset
+   pub decimals: u8, // decimals of the mint, cached by init_bank so amounts of different mints can be valued together
    pub total_deposits: u64,
    pub total_deposit_shares: u64,
    pub total_borrowed: u64,
@@ -15,21 +20,705 @@ pub struct Bank {
    pub max_ltv: u64, // max percentage of collateral that can be borrow
    pub last_updated: i64,
    pub interest_rate: u64,
+   pub oracle: Pubkey, // price account used to value deposits and borrows of this bank
+   pub oracle_max_age: u64, // seconds after which a price is considered stale
+   pub secondary_oracles: [Pubkey; 2], // fallbacks and cross-checks of the primary oracle, Pubkey::default() when unused
+   pub oracle_tolerance_bps: u64, // how far a fresh oracle can be from the median price, 0 disables the check
+   pub deposit_cap: u64, // maximum total_deposits, 0 means no cap
+   pub borrow_cap: u64, // maximum total_borrowed, 0 means no cap
+   pub paused: bool, // while paused deposits, withdrawals and borrows are rejected, repay and liquidate keep working
+   pub last_price: u64, // oracle price cached by refresh_bank, with PRICE_DECIMALS decimals, 0 before the first refresh
+   pub last_price_updated: i64, // publish time of last_price
+   pub ema_price: u64, // time weighted moving average of the oracle price, updated by refresh_bank, 0 before the first refresh
+   pub ema_updated: i64, // time ema_price was last updated
+   pub ema_window: u64, // seconds a price has to hold to fully move ema_price, 0 values positions at the spot price only
+   pub emode_category: u8, // e-mode category of the asset, 0 when it is in none
+   pub emode_borrowed_shares: u64, // borrowed shares of users in e-mode (so in emode_category), the bank can't leave its category while there are any
+   pub isolated: bool, // isolated collateral is the only collateral of its users, and only backs borrows from banks borrowable_in_isolation
+   pub debt_ceiling: u64, // maximum isolated_debt of an isolated bank, 0 means no ceiling
+   pub isolated_debt: u64, // debt backed by this isolated collateral, with PRICE_DECIMALS decimals and every token at 1, without interest
+   pub borrowable_in_isolation: bool, // (stable) banks users with isolated collateral can borrow from
+   pub borrow_weight_bps: u64, // the debt of this bank counts this much on the debt side of health, BPS is 1x, more for volatile assets
+   pub liquidation_mode: LiquidationMode, // how the bonus for seizing the collateral of this bank is chosen
+   pub min_liquidation_bonus: u64, // bonus of a dutch auction when it starts, it grows to liquidation_bonus
+   pub liquidation_auction_duration: u64, // seconds it takes a dutch auction to go from min_liquidation_bonus to liquidation_bonus
+   pub insurance_fee_bps: u64, // part of the interest paid by the borrowers that goes to the insurance fund instead of the depositors
+   pub insurance_fees: u64, // fees owed to the insurance fund, they stay in the treasury until they are swept into the vault
+   pub insurance_funds: u64, // tokens of the insurance fund in its vault: the stakes and the swept fees
+   pub insurance_shares: u64, // stake shares of the insurance fund, see InsuranceStake
+   pub insurance_cooldown: u64, // seconds a staker waits between request_unstake and unstake
+   pub soft_liquidation_health: u64, // health factor below which owners can self_liquidate this collateral, 0 disables it
+   pub self_liquidation_penalty: u64, // seized on top of the repaid value by self_liquidate, it goes to the insurance fund
 }
 
-#[account] 
-#[derive(InitSpace)]   // Because an account takes up space on-chain we use InitSpace to calculate the space needed.
+/*
+   Fixed: every liquidation pays liquidation_bonus.
+   DutchAuction: the bonus starts at min_liquidation_bonus when the user is first flagged unhealthy and grows linearly
+   to liquidation_bonus over liquidation_auction_duration, so liquidators take the collateral at the smallest bonus that pays for it.
+*/
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub enum LiquidationMode {
+   #[default]
+   Fixed,
+   DutchAuction,
+}
+
+impl Bank {
+   /*
+      A SIMPLE RULE OF THREE
+      total_shares   -->      total_deposits
+            shares   -->      x
+   */
+   pub fn deposit_shares_to_amount(&self, shares: u64) -> Result<u64> {
+      if self.total_deposit_shares == 0 {
+         return Ok(0);
+      }
+      mul_div(shares, self.total_deposits, self.total_deposit_shares)
+   }
+
+   // Debt is rounded up so the protocol never under-counts what a user owes
+   pub fn borrowed_shares_to_amount(&self, shares: u64) -> Result<u64> {
+      if self.total_borrowed_shares == 0 {
+         return Ok(0);
+      }
+      mul_div_ceil(shares, self.total_borrowed, self.total_borrowed_shares)
+   }
+
+   // Shares minted for a deposit, the first depositor gets one share per token
+   pub fn deposit_amount_to_shares(&self, amount: u64) -> Result<u64> {
+      if self.total_deposit_shares == 0 {
+         return Ok(amount);
+      }
+      mul_div(amount, self.total_deposit_shares, self.total_deposits)
+   }
+
+   // Shares burned for a withdrawal, rounded up so nobody can take out more than their shares are worth
+   pub fn withdraw_amount_to_shares(&self, amount: u64) -> Result<u64> {
+      mul_div_ceil(amount, self.total_deposit_shares, self.total_deposits)
+   }
+
+   // Shares of debt created by a borrow, rounded up in favour of the depositors
+   pub fn borrow_amount_to_shares(&self, amount: u64) -> Result<u64> {
+      if self.total_borrowed_shares == 0 {
+         return Ok(amount);
+      }
+      mul_div_ceil(amount, self.total_borrowed_shares, self.total_borrowed)
+   }
+
+   // Shares of debt removed by a repayment, rounded down in favour of the depositors
+   pub fn repay_amount_to_shares(&self, amount: u64) -> Result<u64> {
+      mul_div(amount, self.total_borrowed_shares, self.total_borrowed)
+   }
+
+   // Value of one deposit share, scaled by INDEX_ONE (a u64 times INDEX_ONE always fits in a u128)
+   pub fn deposit_index(&self) -> u128 {
+      if self.total_deposit_shares == 0 {
+         return INDEX_ONE;
+      }
+      self.total_deposits as u128 * INDEX_ONE / self.total_deposit_shares as u128
+   }
+
+   // Value of one borrow share, scaled by INDEX_ONE
+   pub fn borrow_index(&self) -> u128 {
+      if self.total_borrowed_shares == 0 {
+         return INDEX_ONE;
+      }
+      self.total_borrowed as u128 * INDEX_ONE / self.total_borrowed_shares as u128
+   }
+
+   /*
+      Borrowers pay interest on the outstanding debt and that same interest is earned by the depositors,
+      so both totals grow by the same amount and the value of every share is updated at once.
+      The insurance fee is kept out of the deposits, it is owed to the insurance fund instead.
+      Returns the amount of interest that was accrued (fee included).
+   */
+   pub fn accrue_interest(&mut self, now: i64) -> Result<u64> {
+      let elapsed_time = now.checked_sub(self.last_updated).ok_or(ErrCode::MathOverflow)?;
+      if elapsed_time <= 0 {
+         return Ok(0);
+      }
+
+      let mut interest = 0;
+      if self.total_borrowed > 0 {
+         let factor = compounded_interest_factor(self.interest_rate, elapsed_time as u64)?;
+         let new_total_borrowed = to_u64(mul_div_u128(self.total_borrowed as u128, factor, WAD)?)?;
+         interest = checked_sub(new_total_borrowed, self.total_borrowed)?;
+         let fee = mul_div(interest, self.insurance_fee_bps, BPS)?;
+
+         self.total_borrowed = new_total_borrowed;
+         self.total_deposits = checked_add(self.total_deposits, interest - fee)?;
+         self.insurance_fees = checked_add(self.insurance_fees, fee)?;
+      }
+
+      self.last_updated = now;
+      Ok(interest)
+   }
+
+   /*
+      The moving average brought up to `now` with the current price. A price counts for the time since the last update
+      as a share of ema_window, a spike of a few seconds barely moves it. Nothing says the price held between two updates
+      though, so one update counts for at most EMA_MAX_STEP_BPS of the window: after a bank sat untouched for longer
+      than a window a spike still only moves the average that far, a price has to be seen again and again to become it.
+      Without a window, or before the first update, the average is the price itself.
+   */
+   pub fn ema_at(&self, price: u64, now: i64) -> u64 {
+      if self.ema_window == 0 || self.ema_price == 0 {
+         return price;
+      }
+      // At least a second, or a window under 10 seconds would never move
+      let max_step = (self.ema_window * EMA_MAX_STEP_BPS / BPS).max(1);
+      let elapsed = now.saturating_sub(self.ema_updated).clamp(0, max_step as i64) as u128;
+      let window = self.ema_window as u128;
+      // A weighted average of two u64 is a u64
+      ((self.ema_price as u128 * (window - elapsed) + price as u128 * elapsed) / window) as u64
+   }
+
+   pub fn update_ema(&mut self, price: u64, now: i64) {
+      self.ema_price = self.ema_at(price, now);
+      self.ema_updated = now;
+   }
+
+   // The primary oracle of the bank followed by its secondary oracles, in the order instructions take them
+   pub fn oracles(&self) -> Vec<Pubkey> {
+      std::iter::once(self.oracle)
+         .chain(self.secondary_oracles.iter().copied().filter(|oracle| *oracle != Pubkey::default()))
+         .collect()
+   }
+
+   // A user in e-mode values the deposits of the banks of its category with the parameters of the category
+   pub fn risk_params(&self, emode: Option<&EModeCategory>) -> RiskParams {
+      match emode {
+         Some(category) if self.emode_category == category.id => RiskParams {
+            max_ltv: category.max_ltv,
+            liquidation_threshold: category.liquidation_threshold,
+            liquidation_bonus: category.liquidation_bonus,
+         },
+         _ => RiskParams {
+            max_ltv: self.max_ltv,
+            liquidation_threshold: self.liquidation_threshold,
+            liquidation_bonus: self.liquidation_bonus,
+         },
+      }
+   }
+
+   /*
+      Bonus paid for seizing this collateral from a user that has been unhealthy since `unhealthy_since`.
+      The bonus of the risk params (the one of the e-mode category when it applies) is the maximum of the auction.
+   */
+   pub fn liquidation_bonus(&self, emode: Option<&EModeCategory>, unhealthy_since: i64, now: i64) -> Result<u64> {
+      let max_bonus = self.risk_params(emode).liquidation_bonus;
+      if self.liquidation_mode == LiquidationMode::Fixed {
+         return Ok(max_bonus);
+      }
+
+      let elapsed = now.checked_sub(unhealthy_since).ok_or(ErrCode::MathOverflow)?.max(0) as u64;
+      if elapsed >= self.liquidation_auction_duration {
+         return Ok(max_bonus);
+      }
+      // The bonus can be lowered below the minimum after the auction was configured
+      let min_bonus = self.min_liquidation_bonus.min(max_bonus);
+      checked_add(min_bonus, mul_div(max_bonus - min_bonus, elapsed, self.liquidation_auction_duration)?)
+   }
+
+   /*
+      Called on the isolated bank for a borrow backed by it. Only stable banks are borrowable in isolation, so the ceiling
+      counts every borrowed token at 1 in the quote currency: the borrowed amounts are brought to PRICE_DECIMALS decimals
+      from the decimals of their mint, and no price is needed (repaying doesn't have to read an oracle to release the ceiling).
+   */
+   pub fn add_isolated_debt(&mut self, borrowed_bank: &Bank, amount: u64) -> Result<()> {
+      require!(borrowed_bank.borrowable_in_isolation, ErrCode::NotBorrowableInIsolation);
+      let debt = to_u64(token_value_ceil(amount, STABLE_PRICE, borrowed_bank.decimals)?)?;
+      let isolated_debt = checked_add(self.isolated_debt, debt)?;
+      if self.debt_ceiling > 0 && isolated_debt > self.debt_ceiling {
+         return Err(ErrCode::DebtCeilingExceeded.into());
+      }
+      self.isolated_debt = isolated_debt;
+      Ok(())
+   }
+
+   // The interest isn't in isolated_debt, so repayments that include it can go below 0
+   pub fn remove_isolated_debt(&mut self, borrowed_bank: &Bank, amount: u64) -> Result<()> {
+      let debt = to_u64(token_value(amount, STABLE_PRICE, borrowed_bank.decimals)?)?;
+      self.isolated_debt = self.isolated_debt.saturating_sub(debt);
+      Ok(())
+   }
+
+   /*
+      E-MODE DEBT
+      A user in e-mode only borrows from banks of its category and its collateral in the category is valued with the
+      parameters of the category. Taking a bank out of the category would leave those users with a debt outside of it,
+      still backed by the e-mode parameters, so the debt of e-mode users is counted and set_bank_emode_category waits for it.
+      Every change to the borrowed shares of a user goes through these two, after the user is checked with check_emode_borrow.
+   */
+   pub fn add_emode_debt(&mut self, user: &User, shares: u64) -> Result<()> {
+      if user.emode_category != 0 {
+         self.emode_borrowed_shares = checked_add(self.emode_borrowed_shares, shares)?;
+      }
+      Ok(())
+   }
+
+   // Saturates like remove_isolated_debt, a repayment never fails on the count
+   pub fn remove_emode_debt(&mut self, user: &User, shares: u64) -> Result<()> {
+      if user.emode_category != 0 {
+         self.emode_borrowed_shares = self.emode_borrowed_shares.saturating_sub(shares);
+      }
+      Ok(())
+   }
+
+   /*
+      INSURANCE FUND
+      The fund is worth its tokens in the vault plus the fees it is still owed, and the stakers own it through shares,
+      so the fees raise the value of every share like the interest raises the value of deposit shares.
+   */
+   pub fn insurance_value(&self) -> Result<u64> {
+      checked_add(self.insurance_funds, self.insurance_fees)
+   }
+
+   /*
+      Before the first stake the fund can already be worth something: the fees booked since the fee was set, or what
+      the last stakers left behind. It is seeded with shares nobody owns for that value, one per token like the stakes,
+      otherwise the first staker would get it for free.
+   */
+   pub fn seed_insurance_shares(&mut self) -> Result<()> {
+      if self.insurance_shares == 0 {
+         self.insurance_shares = self.insurance_value()?;
+      }
+      Ok(())
+   }
+
+   /*
+      Shares minted for a stake, rounded down in favour of the fund. Once a loss took the whole fund its shares are
+      worth nothing, and no number of new shares is fair to both sides: stakes wait until the fund earns fees again.
+   */
+   pub fn stake_amount_to_shares(&self, amount: u64) -> Result<u64> {
+      if self.insurance_shares == 0 {
+         return Ok(amount);
+      }
+      let value = self.insurance_value()?;
+      require!(value > 0, ErrCode::InsuranceFundWrittenOff);
+      mul_div(amount, self.insurance_shares, value)
+   }
+
+   pub fn stake_shares_to_amount(&self, shares: u64) -> Result<u64> {
+      if self.insurance_shares == 0 {
+         return Ok(0);
+      }
+      mul_div(shares, self.insurance_value()?, self.insurance_shares)
+   }
+
+   /*
+      Every token in the treasury is owed either to the depositors (total_deposits - total_borrowed of them are not lent out)
+      or to the insurance fund, so the fees can be swept into the vault as far as the treasury holds more than the first part.
+   */
+   pub fn sweepable_insurance_fees(&self, treasury_balance: u64) -> u64 {
+      self.insurance_fees.min(treasury_balance.saturating_sub(self.available_liquidity()))
+   }
+
+   // The part of the treasury a withdraw or a borrow can take: deposits that aren't lent out, never the unswept fees
+   pub fn available_liquidity(&self) -> u64 {
+      self.total_deposits.saturating_sub(self.total_borrowed)
+   }
+
+   pub fn check_liquidity(&self, amount: u64) -> Result<()> {
+      require!(amount <= self.available_liquidity(), ErrCode::InsufficientLiquidity);
+      Ok(())
+   }
+
+   /*
+      Bad debt is paid by the insurance fund before the depositors: first with its fees, which are still in the treasury,
+      then with the tokens of its vault, which the instruction moves into the treasury.
+      Returns (from the fees, from the vault), the rest of the loss is for the depositors.
+   */
+   pub fn cover_with_insurance(&mut self, loss: u64) -> (u64, u64) {
+      let from_fees = loss.min(self.insurance_fees);
+      let from_funds = (loss - from_fees).min(self.insurance_funds);
+      self.insurance_fees -= from_fees;
+      self.insurance_funds -= from_funds;
+      (from_fees, from_funds)
+   }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RiskParams {
+   pub max_ltv: u64,
+   pub liquidation_threshold: u64,
+   pub liquidation_bonus: u64,
+}
+
+/*
+   Efficiency mode: correlated assets (SOL and its liquid staking tokens, USDC and USDT...) are tagged with the same category,
+   which has its own, higher, risk parameters. A user that opts in to a category gets them on the deposits of its banks,
+   but while it is in e-mode it can only borrow from banks of the category.
+*/
+#[account]
+#[derive(InitSpace, Default)]
+pub struct EModeCategory {
+   pub authority: Pubkey, // can change the parameters of the category
+   pub id: u8, // the tag stored on banks and users, never 0
+   pub max_ltv: u64,
+   pub liquidation_threshold: u64,
+   pub liquidation_bonus: u64,
+}
+
+// The stake of a backstop provider in the insurance fund of a bank, at [b"insurance_stake", bank, staker]
+#[account]
+#[derive(InitSpace, Default)]
+pub struct InsuranceStake {
+   pub staker: Pubkey,
+   pub bank: Pubkey,
+   pub shares: u64, // shares waiting for their cooldown included, they cover losses until they are unstaked
+   pub unstake_shares: u64, // shares of the pending request to unstake, 0 when there is none
+   pub unstake_requested_at: i64,
+}
+
+impl InsuranceStake {
+   pub fn check_unstake(&self, cooldown: u64, now: i64) -> Result<()> {
+      require!(self.unstake_shares > 0, ErrCode::NoUnstakeRequested);
+      let elapsed = now.checked_sub(self.unstake_requested_at).ok_or(ErrCode::MathOverflow)?;
+      require!(elapsed >= cooldown as i64, ErrCode::UnstakeCooldown);
+      Ok(())
+   }
+}
+
+// The shares a user holds in one bank. An empty slot has bank == Pubkey::default()
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct Position {
+   pub bank: Pubkey,
+   pub deposit_shares: u64,
+   pub borrowed_shares: u64,
+}
+
+impl Position {
+   pub fn is_active(&self) -> bool {
+      self.bank != Pubkey::default()
+   }
+}
+
+#[account]
+#[derive(InitSpace, Default)]   // Because an account takes up space on-chain we use InitSpace to calculate the space needed.
 pub struct User { // This will be the structure to be able to initialized multiple user accounts for any user that comes to this application
    pub owner: Pubkey,
-   pub deposited_sol: u64, 
-   pub deposited_sol_shares: u64,
-   pub borrowed_sol: u64,
-   pub borrowed_sol_shares: u64, 
-   pub deposited_usdc: u64,
-   pub deposited_usdc_shares: u64,
-   pub borrowed_usdc: u64,
-   pub borrowed_usdc_shares: u64,
-   pub usdc_address: Pubkey,
+   pub positions: [Position; MAX_POSITIONS], // one entry for every bank the user has deposited into or borrowed from
    pub health_factor: u64,
    pub last_updated: i64,
-}
\ No newline at end of file
+   pub emode_category: u8, // e-mode category the user opted in to, 0 when it is in none
+   pub unhealthy_since: i64, // when the user was first seen liquidatable, 0 while it is healthy
+   pub isolated_bank: Pubkey, // isolated bank whose ceiling counts the debt of the user, default once it has no debt left
+}
+
+impl User {
+   pub fn position(&self, bank: &Pubkey) -> Option<&Position> {
+      self.positions.iter().find(|position| position.bank == *bank)
+   }
+
+   pub fn position_mut(&mut self, bank: &Pubkey) -> Option<&mut Position> {
+      self.positions.iter_mut().find(|position| position.bank == *bank)
+   }
+
+   // Returns the position for the bank, taking a free slot the first time the user touches that bank
+   pub fn position_or_insert(&mut self, bank: &Pubkey) -> Result<&mut Position> {
+      let index = match self.positions.iter().position(|position| position.bank == *bank) {
+         Some(index) => index,
+         None => {
+            let index = self.positions.iter().position(|position| !position.is_active())
+               .ok_or(ErrCode::PositionLimitReached)?;
+            self.positions[index].bank = *bank;
+            index
+         }
+      };
+      Ok(&mut self.positions[index])
+   }
+
+   // In e-mode only the banks of the category can be borrowed from
+   pub fn check_emode_borrow(&self, bank: &Bank) -> Result<()> {
+      require!(self.emode_category == 0 || bank.emode_category == self.emode_category, ErrCode::EModeCategoryMismatch);
+      Ok(())
+   }
+
+   /*
+      Every instruction that computes the health of the user stores it here. The first one that finds the user liquidatable
+      starts its liquidation auction, and the auction ends as soon as the user is healthy again.
+   */
+   pub fn record_health(&mut self, health: &Health, now: i64) {
+      self.health_factor = health.health_factor();
+      self.last_updated = now;
+      if !health.is_liquidatable() {
+         self.unhealthy_since = 0;
+      } else if self.unhealthy_since == 0 {
+         self.unhealthy_since = now;
+      }
+   }
+
+   // Free the slots of positions that no longer hold any shares
+   pub fn close_empty_positions(&mut self) {
+      for position in self.positions.iter_mut() {
+         if position.is_active() && position.deposit_shares == 0 && position.borrowed_shares == 0 {
+            *position = Position::default();
+         }
+      }
+      if self.positions.iter().all(|position| position.borrowed_shares == 0) {
+         self.isolated_bank = Pubkey::default();
+      }
+   }
+}
+
+// Holds the mint authority of a test mint, see instructions/minter.rs
+#[cfg(feature = "devnet-faucet")]
+#[account]
+#[derive(InitSpace, Default)]
+pub struct Faucet {
+   pub authority: Pubkey, // can change the limits of the faucet
+   pub mint: Pubkey,
+   pub max_amount: u64, // maximum amount of tokens in one request
+   pub cooldown: u64, // seconds a wallet has to wait between two requests
+   pub bump: u8,
+}
+
+#[cfg(feature = "devnet-faucet")]
+#[account]
+#[derive(InitSpace, Default)]
+pub struct FaucetClaim {
+   pub last_claimed: i64, // 0 until the wallet uses the faucet for the first time
+}
+
+#[cfg(feature = "devnet-faucet")]
+impl Faucet {
+   pub fn check_claim(&self, claim: &FaucetClaim, quantity: u64, now: i64) -> Result<()> {
+      require!(quantity > 0, ErrCode::InvalidAmount);
+      require!(quantity <= self.max_amount, ErrCode::FaucetAmountExceeded);
+      if claim.last_claimed != 0 {
+         let elapsed = now.checked_sub(claim.last_claimed).ok_or(ErrCode::MathOverflow)?;
+         require!(elapsed >= self.cooldown as i64, ErrCode::FaucetCooldown);
+      }
+      Ok(())
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   fn new_bank(total_deposits: u64, total_deposit_shares: u64, total_borrowed: u64, total_borrowed_shares: u64) -> Bank {
+      Bank {
+         total_deposits,
+         total_deposit_shares,
+         total_borrowed,
+         total_borrowed_shares,
+         interest_rate: 5,
+         ..Default::default()
+      }
+   }
+
+   #[test]
+   fn deposit_shares_follow_the_share_price() {
+      // 2 tokens per share after interest
+      let bank = new_bank(2_000, 1_000, 0, 0);
+      assert_eq!(bank.deposit_amount_to_shares(500).unwrap(), 250);
+      assert_eq!(bank.withdraw_amount_to_shares(3).unwrap(), 2);
+      assert_eq!(bank.deposit_shares_to_amount(250).unwrap(), 500);
+   }
+
+   #[test]
+   fn moving_average_weights_a_price_by_how_long_it_held() {
+      let mut bank = Bank { ema_window: 1_000, ..Default::default() };
+      bank.update_ema(100, 0);
+      assert_eq!((bank.ema_price, bank.ema_updated), (100, 0));
+
+      // 10% of the window at 200
+      bank.update_ema(200, 100);
+      assert_eq!(bank.ema_price, 110);
+      assert_eq!(bank.ema_at(50, 100), 110);
+
+      // Seen once after five windows, 50 counts for 10% of the window like it did after 100 seconds
+      assert_eq!(bank.ema_at(50, 5_000), 104);
+      bank.update_ema(50, 5_000);
+      bank.update_ema(50, 5_100);
+      assert_eq!(bank.ema_price, 98);
+
+      // A window too short for 10% of it to be a second still moves a second at a time
+      let short = Bank { ema_window: 5, ema_price: 100, ema_updated: 0, ..Default::default() };
+      assert_eq!(short.ema_at(200, 60), 120);
+
+      bank.ema_window = 0;
+      assert_eq!(bank.ema_at(50, 100), 50);
+   }
+
+   #[test]
+   fn share_conversions_overflow_instead_of_panicking() {
+      // one token is worth u64::MAX shares, so two tokens are more shares than fit in a u64
+      let bank = new_bank(1, u64::MAX, 1, u64::MAX);
+      assert_eq!(bank.deposit_amount_to_shares(2).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(bank.withdraw_amount_to_shares(2).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(bank.borrow_amount_to_shares(2).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(bank.repay_amount_to_shares(2).unwrap_err(), ErrCode::MathOverflow.into());
+
+      // one share is worth u64::MAX tokens
+      let bank = new_bank(u64::MAX, 1, u64::MAX, 1);
+      assert_eq!(bank.deposit_shares_to_amount(2).unwrap_err(), ErrCode::MathOverflow.into());
+      assert_eq!(bank.borrowed_shares_to_amount(2).unwrap_err(), ErrCode::MathOverflow.into());
+   }
+
+   #[test]
+   fn share_conversions_with_shares_but_no_tokens_are_a_division_by_zero() {
+      let bank = new_bank(0, 1_000, 0, 1_000);
+      assert_eq!(bank.deposit_amount_to_shares(1).unwrap_err(), ErrCode::DivisionByZero.into());
+      assert_eq!(bank.withdraw_amount_to_shares(1).unwrap_err(), ErrCode::DivisionByZero.into());
+      assert_eq!(bank.borrow_amount_to_shares(1).unwrap_err(), ErrCode::DivisionByZero.into());
+      assert_eq!(bank.repay_amount_to_shares(1).unwrap_err(), ErrCode::DivisionByZero.into());
+   }
+
+   #[test]
+   fn accrue_interest_moves_borrowed_and_deposits_together() {
+      let mut bank = new_bank(2_000_000, 2_000_000, 1_000_000, 1_000_000);
+      let interest = bank.accrue_interest(365 * 24 * 60 * 60).unwrap();
+      assert_eq!(interest, 51_270);
+      assert_eq!(bank.total_borrowed, 1_051_270);
+      assert_eq!(bank.total_deposits, 2_051_270);
+      assert_eq!(bank.last_updated, 365 * 24 * 60 * 60);
+   }
+
+   #[test]
+   fn accrue_interest_overflows_instead_of_wrapping() {
+      // the debt itself no longer fits in a u64
+      let mut bank = new_bank(u64::MAX, u64::MAX, u64::MAX, u64::MAX);
+      assert_eq!(bank.accrue_interest(365 * 24 * 60 * 60).unwrap_err(), ErrCode::MathOverflow.into());
+
+      // the debt fits but the deposits plus the interest don't
+      let mut bank = new_bank(u64::MAX, u64::MAX, u64::MAX / 2, u64::MAX / 2);
+      assert_eq!(bank.accrue_interest(365 * 24 * 60 * 60).unwrap_err(), ErrCode::MathOverflow.into());
+
+      let mut bank = new_bank(0, 0, 0, 0);
+      bank.last_updated = i64::MIN;
+      assert_eq!(bank.accrue_interest(i64::MAX).unwrap_err(), ErrCode::MathOverflow.into());
+   }
+
+   #[test]
+   fn isolated_debt_stays_under_the_ceiling() {
+      // A ceiling of 1_000 in the quote currency, borrowed from a stable with 6 decimals and one with 9
+      let mut isolated = Bank { isolated: true, debt_ceiling: 1_000 * STABLE_PRICE, ..Default::default() };
+      let usdc = Bank { borrowable_in_isolation: true, decimals: 6, ..Default::default() };
+      let dai = Bank { borrowable_in_isolation: true, decimals: 9, ..Default::default() };
+      assert_eq!(isolated.add_isolated_debt(&Bank::default(), 1).unwrap_err(), ErrCode::NotBorrowableInIsolation.into());
+
+      isolated.add_isolated_debt(&usdc, 600_000_000).unwrap();
+      isolated.add_isolated_debt(&dai, 400_000_000_000).unwrap();
+      assert_eq!(isolated.isolated_debt, 1_000 * STABLE_PRICE);
+      // The smallest unit of DAI still counts
+      assert_eq!(isolated.add_isolated_debt(&dai, 1).unwrap_err(), ErrCode::DebtCeilingExceeded.into());
+
+      // Repaying with the interest doesn't underflow
+      isolated.remove_isolated_debt(&dai, 400_000_000_000).unwrap();
+      assert_eq!(isolated.isolated_debt, 600 * STABLE_PRICE);
+      isolated.remove_isolated_debt(&usdc, 610_000_000).unwrap();
+      assert_eq!(isolated.isolated_debt, 0);
+   }
+
+   #[test]
+   fn insurance_fee_is_kept_out_of_the_deposits() {
+      let mut bank = Bank { insurance_fee_bps: 1_000, ..new_bank(2_000_000, 2_000_000, 1_000_000, 1_000_000) };
+      let interest = bank.accrue_interest(365 * 24 * 60 * 60).unwrap();
+      assert_eq!(interest, 51_270);
+      assert_eq!(bank.insurance_fees, 5_127);
+      assert_eq!(bank.total_deposits, 2_000_000 + 51_270 - 5_127);
+      assert_eq!(bank.total_borrowed, 1_051_270);
+   }
+
+   #[test]
+   fn insurance_shares_follow_the_fees_and_the_losses() {
+      let mut bank = Bank::default();
+      assert_eq!(bank.stake_amount_to_shares(1_000).unwrap(), 1_000);
+      bank.insurance_funds = 1_000;
+      bank.insurance_shares = 1_000;
+
+      // The fees are worth as much to the stakers as the tokens in the vault
+      bank.insurance_fees = 1_000;
+      assert_eq!(bank.stake_amount_to_shares(1_000).unwrap(), 500);
+      assert_eq!(bank.stake_shares_to_amount(500).unwrap(), 1_000);
+
+      // Losses are taken from the fees first
+      assert_eq!(bank.cover_with_insurance(600), (600, 0));
+      assert_eq!(bank.cover_with_insurance(2_000), (400, 1_000));
+      assert_eq!(bank.insurance_value().unwrap(), 0);
+      assert_eq!(bank.stake_amount_to_shares(1_000).unwrap_err(), ErrCode::InsuranceFundWrittenOff.into());
+   }
+
+   #[test]
+   fn fees_booked_before_the_first_stake_seed_the_fund() {
+      let mut bank = Bank { insurance_fees: 500, ..Default::default() };
+      bank.seed_insurance_shares().unwrap();
+      assert_eq!(bank.insurance_shares, 500);
+      // The first staker gets what it put in, not the fees
+      let shares = bank.stake_amount_to_shares(1_000).unwrap();
+      bank.insurance_funds += 1_000;
+      bank.insurance_shares += shares;
+      assert_eq!(bank.stake_shares_to_amount(shares).unwrap(), 1_000);
+
+      // Seeding only happens to a fund without shares
+      bank.seed_insurance_shares().unwrap();
+      assert_eq!(bank.insurance_shares, 1_500);
+   }
+
+   #[test]
+   fn only_fees_beyond_the_lendable_tokens_can_be_swept() {
+      let bank = Bank { total_deposits: 1_000, total_borrowed: 400, insurance_fees: 100, ..Default::default() };
+      assert_eq!(bank.sweepable_insurance_fees(700), 100);
+      assert_eq!(bank.sweepable_insurance_fees(650), 50);
+      assert_eq!(bank.sweepable_insurance_fees(500), 0);
+      // The treasury holds 700, only the 600 not lent out can leave
+      assert!(bank.check_liquidity(600).is_ok());
+      assert_eq!(bank.check_liquidity(601).unwrap_err(), ErrCode::InsufficientLiquidity.into());
+   }
+
+   #[test]
+   fn unstake_waits_for_the_cooldown() {
+      let mut stake = InsuranceStake { shares: 100, ..Default::default() };
+      assert_eq!(stake.check_unstake(60, 1_000).unwrap_err(), ErrCode::NoUnstakeRequested.into());
+      stake.unstake_shares = 100;
+      stake.unstake_requested_at = 1_000;
+      assert_eq!(stake.check_unstake(60, 1_059).unwrap_err(), ErrCode::UnstakeCooldown.into());
+      assert!(stake.check_unstake(60, 1_060).is_ok());
+   }
+
+   #[test]
+   fn dutch_auction_bonus_grows_from_the_minimum_to_the_bonus() {
+      let fixed = Bank { liquidation_bonus: 500, min_liquidation_bonus: 100, liquidation_auction_duration: 400, ..Default::default() };
+      assert_eq!(fixed.liquidation_bonus(None, 1_000, 1_000).unwrap(), 500);
+
+      let auction = Bank { liquidation_mode: LiquidationMode::DutchAuction, ..fixed };
+      assert_eq!(auction.liquidation_bonus(None, 1_000, 1_000).unwrap(), 100);
+      assert_eq!(auction.liquidation_bonus(None, 1_000, 1_100).unwrap(), 200);
+      assert_eq!(auction.liquidation_bonus(None, 1_000, 1_400).unwrap(), 500);
+      assert_eq!(auction.liquidation_bonus(None, 1_000, 9_000).unwrap(), 500);
+
+      // The e-mode bonus is the maximum, and caps the minimum
+      let category = EModeCategory { id: 1, liquidation_bonus: 50, ..Default::default() };
+      let auction = Bank { emode_category: 1, ..auction };
+      assert_eq!(auction.liquidation_bonus(Some(&category), 1_000, 1_000).unwrap(), 50);
+   }
+
+   #[test]
+   fn the_auction_starts_when_the_user_is_first_liquidatable() {
+      let unhealthy = Health { weighted_collateral: 90, weighted_debt: 100, ..Default::default() };
+      let mut user = User::default();
+      user.record_health(&unhealthy, 10);
+      user.record_health(&unhealthy, 20);
+      assert_eq!(user.unhealthy_since, 10);
+
+      user.record_health(&Health::default(), 30);
+      assert_eq!((user.unhealthy_since, user.health_factor, user.last_updated), (0, u64::MAX, 30));
+   }
+
+   #[cfg(feature = "devnet-faucet")]
+   #[test]
+   fn faucet_limits_the_amount_and_the_frequency_per_wallet() {
+      let faucet = Faucet { max_amount: 1_000, cooldown: 3_600, ..Default::default() };
+      let first = FaucetClaim::default();
+      assert!(faucet.check_claim(&first, 1_000, 10).is_ok());
+      assert_eq!(faucet.check_claim(&first, 1_001, 10).unwrap_err(), ErrCode::FaucetAmountExceeded.into());
+      assert_eq!(faucet.check_claim(&first, 0, 10).unwrap_err(), ErrCode::InvalidAmount.into());
+
+      let claimed = FaucetClaim { last_claimed: 10 };
+      assert_eq!(faucet.check_claim(&claimed, 1, 3_609).unwrap_err(), ErrCode::FaucetCooldown.into());
+      assert!(faucet.check_claim(&claimed, 1, 3_610).is_ok());
+   }
+}
@@ -0,0 +1,122 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{instruction::Instruction, program::invoke};
+use anchor_spl::token_interface::TokenAccount;
+use crate::error::ErrCode;
+use crate::state::{Bank, User};
+
+/*
+   The program doesn't know any AMM. Instructions that swap call a swap adapter program, chosen by the user,
+   with a single instruction that any adapter (an aggregator, a wrapper around one pool...) implements:
+   data     -> SWAP_DISCRIMINATOR, then amount_in: u64 and min_amount_out: u64, little endian,
+               which is the data of an Anchor instruction `swap(amount_in: u64, min_amount_out: u64)`
+   accounts -> the authority of the source token account (signer), the source token account,
+               the destination token account, then every account the adapter needs (pools, vaults, token programs...)
+   The adapter isn't trusted: what it took and what it gave are measured on the token accounts after the call.
+*/
+pub const SWAP_DISCRIMINATOR: [u8; 8] = [248, 198, 158, 145, 225, 117, 135, 200]; // sha256("global:swap")[..8]
+
+pub fn swap_instruction(
+   adapter: Pubkey,
+   authority: Pubkey,
+   source: Pubkey,
+   destination: Pubkey,
+   adapter_accounts: &[AccountInfo],
+   amount_in: u64,
+   min_amount_out: u64,
+) -> Instruction {
+   let mut accounts = vec![
+      AccountMeta::new_readonly(authority, true),
+      AccountMeta::new(source, false),
+      AccountMeta::new(destination, false),
+   ];
+   accounts.extend(adapter_accounts.iter().map(|info| AccountMeta {
+      pubkey: info.key(),
+      is_signer: info.is_signer,
+      is_writable: info.is_writable,
+   }));
+
+   let mut data = Vec::with_capacity(24);
+   data.extend_from_slice(&SWAP_DISCRIMINATOR);
+   data.extend_from_slice(&amount_in.to_le_bytes());
+   data.extend_from_slice(&min_amount_out.to_le_bytes());
+
+   Instruction { program_id: adapter, accounts, data }
+}
+
+/*
+   Calls the adapter with the signature of `authority` and returns what the destination received.
+   The adapter may leave some of amount_in in the source but can't take more, and it has to give at least min_amount_out.
+   The runtime doesn't let it call back into this program, and it can't move the treasuries,
+   so the worst it can do is a bad price, which the health check of the instruction catches.
+*/
+pub fn swap<'info>(
+   adapter: &AccountInfo<'info>,
+   authority: &AccountInfo<'info>,
+   source: &mut InterfaceAccount<'info, TokenAccount>,
+   destination: &mut InterfaceAccount<'info, TokenAccount>,
+   adapter_accounts: &[AccountInfo<'info>],
+   amount_in: u64,
+   min_amount_out: u64,
+) -> Result<u64> {
+   // Transfers earlier in the instruction aren't in the deserialized accounts yet
+   source.reload()?;
+   destination.reload()?;
+   let source_balance = source.amount;
+   let destination_balance = destination.amount;
+
+   let instruction = swap_instruction(
+      adapter.key(),
+      authority.key(),
+      source.key(),
+      destination.key(),
+      adapter_accounts,
+      amount_in,
+      min_amount_out,
+   );
+   let mut accounts = vec![authority.clone(), source.to_account_info(), destination.to_account_info(), adapter.clone()];
+   accounts.extend_from_slice(adapter_accounts);
+   invoke(&instruction, &accounts)?;
+
+   source.reload()?;
+   destination.reload()?;
+   if source.amount < source_balance.saturating_sub(amount_in) {
+      return Err(ErrCode::InvalidSwapAdapter.into());
+   }
+   let amount_out = destination.amount.checked_sub(destination_balance).ok_or(ErrCode::InvalidSwapAdapter)?;
+   if amount_out < min_amount_out {
+      return Err(ErrCode::SlippageExceeded.into());
+   }
+   Ok(amount_out)
+}
+
+/*
+   The accounts of the positions of the user come first in the remaining accounts (its e-mode category if it has one,
+   then the bank and oracles of the other positions), the accounts of the adapter after them.
+   The number of oracles of a position is read from its bank, which load_priced_banks checks afterwards.
+*/
+pub fn split_remaining_accounts<'a, 'info>(
+   user: &User,
+   acted: &[Pubkey],
+   remaining_accounts: &'a [AccountInfo<'info>],
+) -> Result<(&'a [AccountInfo<'info>], &'a [AccountInfo<'info>])> {
+   let mut split = (user.emode_category != 0) as usize;
+   let other_positions = user.positions.iter().filter(|position| position.is_active() && !acted.contains(&position.bank));
+   for _ in other_positions {
+      let bank_info = remaining_accounts.get(split).ok_or(ErrCode::MissingPositionAccounts)?;
+      require_keys_eq!(*bank_info.owner, crate::ID, ErrCode::InvalidPositionAccount);
+      let bank = Bank::try_deserialize(&mut &bank_info.try_borrow_data()?[..])?;
+      split += 1 + bank.oracles().len();
+   }
+   Ok(remaining_accounts.split_at(split.min(remaining_accounts.len())))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use anchor_lang::solana_program::hash::hash;
+
+   #[test]
+   fn discriminator_is_the_one_of_an_anchor_swap_instruction() {
+      assert_eq!(SWAP_DISCRIMINATOR[..], hash(b"global:swap").to_bytes()[..8]);
+   }
+}
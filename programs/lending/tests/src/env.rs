@@ -0,0 +1,782 @@
+use std::collections::HashMap;
+
+use anchor_lang::{
+   prelude::*,
+   solana_program::{instruction::Instruction, program_option::COption, program_pack::Pack, system_program},
+   Discriminator,
+};
+use anchor_spl::token::spl_token;
+use lending::{
+   health::{compute_health, Health, PricedBank},
+   instructions::{BankConfig, EModeConfig, EmaConfig, InsuranceConfig, IsolationConfig, LiquidationModeConfig, OracleConfig, SelfLiquidationConfig},
+   oracle::{
+      aggregate_price, OraclePrice, PythPrice, SwitchboardPrice, TwapPrice, PYTH_PRICE_ACCOUNT_LEN, PYTH_STATUS_TRADING, SWITCHBOARD_ACCOUNT_LEN,
+      TWAP_ACCOUNT_LEN,
+   },
+   state::{Bank, EModeCategory, InsuranceStake, LiquidationMode, User},
+};
+use lending_client::{
+   BankAccounts, BorrowBuilder, DeleverageBuilder, DepositBuilder, InitBankBuilder, InitEModeCategoryBuilder, InitUserBuilder, LeverageBuilder,
+   LiquidateBuilder, RefreshBankBuilder, RefreshUserBuilder, RepayBuilder, RequestUnstakeBuilder, SocializeLossBuilder, RepayWithCollateralBuilder,
+   SelfLiquidateBuilder, SetBankEModeCategoryBuilder, SetBankEmaBuilder, SetBankInsuranceBuilder, SetBankIsolationBuilder, SetBankLiquidationModeBuilder, SetBankOraclesBuilder, SetBankPausedBuilder,
+   SetBankSelfLiquidationBuilder, SetUserEModeBuilder, StakeInsuranceBuilder, SwapCollateralBuilder, SwapDebtBuilder, UnstakeBuilder, UpdateBankConfigBuilder, UpdateEModeCategoryBuilder, WithdrawBuilder,
+};
+
+use crate::mock_swap::{SwapPool, MOCK_SWAP_ID};
+use crate::svm::{Account, Svm, TransactionError, TransactionMeta};
+
+pub use lending_client::pda::*;
+
+pub type TxResult = std::result::Result<TransactionMeta, TransactionError>;
+
+pub const PRICE_EXPO: i32 = -8;
+
+/*
+   Everything a test needs to talk to the program: an admin that creates banks, builders for banks
+   (a mint, its treasury and a mock oracle) and users, and one helper per instruction.
+*/
+pub struct TestEnv {
+   pub svm: Svm,
+   pub admin: Pubkey,
+   oracles: Vec<Pubkey>,
+   token_accounts: HashMap<(Pubkey, Pubkey), Pubkey>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TestBank {
+   pub mint: Pubkey,
+   pub bank: Pubkey,
+   pub treasury: Pubkey,
+   pub insurance_vault: Pubkey,
+   pub oracle: Pubkey,
+   pub decimals: u8,
+}
+
+impl TestBank {
+   // The accounts of the bank as it was created, TestEnv::bank_accounts adds the secondary oracles it has now
+   pub fn accounts(&self) -> BankAccounts {
+      BankAccounts::new(self.mint, self.oracle)
+   }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TestUser {
+   pub wallet: Pubkey,
+   pub account: Pubkey,
+}
+
+impl Default for TestEnv {
+   fn default() -> Self {
+      Self::new()
+   }
+}
+
+impl TestEnv {
+   pub fn new() -> Self {
+      let mut svm = Svm::new();
+      let admin = Pubkey::new_unique();
+      svm.airdrop(&admin, 1_000_000_000_000);
+      // The admin deployed the program, which makes it the only one that can list banks
+      svm.set_upgrade_authority(&lending::ID, Some(admin));
+      TestEnv { svm, admin, oracles: Vec::new(), token_accounts: HashMap::new() }
+   }
+
+   pub fn now(&self) -> i64 {
+      self.svm.now()
+   }
+
+   // Moves the clock and republishes every oracle at the new time, so only interest changes between two calls
+   pub fn warp(&mut self, seconds: i64) {
+      self.svm.warp(seconds);
+      for oracle in self.oracles.clone() {
+         self.publish_oracle(oracle, self.now());
+      }
+   }
+
+   // ---------- mints and token accounts ----------
+
+   pub fn create_mint(&mut self, decimals: u8) -> Pubkey {
+      let mint = Pubkey::new_unique();
+      let state = spl_token::state::Mint {
+         mint_authority: COption::Some(self.admin),
+         supply: 0,
+         decimals,
+         is_initialized: true,
+         freeze_authority: COption::None,
+      };
+      self.set_packed(mint, state, spl_token::ID);
+      mint
+   }
+
+   pub fn create_token_account(&mut self, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+      let token_account = Pubkey::new_unique();
+      let state = spl_token::state::Account {
+         mint: *mint,
+         owner: *owner,
+         amount: 0,
+         delegate: COption::None,
+         state: spl_token::state::AccountState::Initialized,
+         is_native: COption::None,
+         delegated_amount: 0,
+         close_authority: COption::None,
+      };
+      self.set_packed(token_account, state, spl_token::ID);
+      token_account
+   }
+
+   // The token account of the wallet for a mint, created the first time it is needed
+   pub fn token_account(&mut self, wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+      if let Some(token_account) = self.token_accounts.get(&(*wallet, *mint)) {
+         return *token_account;
+      }
+      let token_account = self.create_token_account(mint, wallet);
+      self.token_accounts.insert((*wallet, *mint), token_account);
+      token_account
+   }
+
+   // Mints new tokens straight into the token account (and the supply of the mint)
+   pub fn mint_to(&mut self, token_account: &Pubkey, amount: u64) {
+      let mut state: spl_token::state::Account = self.unpack(token_account);
+      let mut mint: spl_token::state::Mint = self.unpack(&state.mint);
+      state.amount += amount;
+      mint.supply += amount;
+      self.set_packed(*token_account, state, spl_token::ID);
+      self.set_packed(state.mint, mint, spl_token::ID);
+   }
+
+   pub fn fund(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> Pubkey {
+      let token_account = self.token_account(&user.wallet, &bank.mint);
+      self.mint_to(&token_account, amount);
+      token_account
+   }
+
+   pub fn supply(&self, mint: &Pubkey) -> u64 {
+      self.unpack::<spl_token::state::Mint>(mint).supply
+   }
+
+   pub fn mint_authority(&self, mint: &Pubkey) -> Option<Pubkey> {
+      self.unpack::<spl_token::state::Mint>(mint).mint_authority.into()
+   }
+
+   pub fn balance(&self, token_account: &Pubkey) -> u64 {
+      self.unpack::<spl_token::state::Account>(token_account).amount
+   }
+
+   pub fn wallet_balance(&mut self, user: &TestUser, bank: &TestBank) -> u64 {
+      let token_account = self.token_account(&user.wallet, &bank.mint);
+      self.balance(&token_account)
+   }
+
+   fn set_packed<T: Pack>(&mut self, key: Pubkey, state: T, owner: Pubkey) {
+      let mut data = vec![0; T::LEN];
+      state.pack_into_slice(&mut data);
+      self.svm.set_account(key, Account { lamports: Rent::default().minimum_balance(T::LEN), data, owner, executable: false });
+   }
+
+   fn unpack<T: Pack>(&self, key: &Pubkey) -> T {
+      T::unpack_from_slice(&self.svm.account(key).expect("missing account").data).unwrap()
+   }
+
+   // ---------- oracles ----------
+
+   // A Pyth style price account with the price in whole units (8 decimals)
+   pub fn create_oracle(&mut self, price: u64) -> Pubkey {
+      let oracle = Pubkey::new_unique();
+      self.oracles.push(oracle);
+      self.set_oracle_price(oracle, price);
+      oracle
+   }
+
+   // A mock of a Switchboard-style aggregator, see oracle::SwitchboardPrice
+   pub fn create_switchboard_oracle(&mut self, price: u64) -> Pubkey {
+      let oracle = Pubkey::new_unique();
+      self.oracles.push(oracle);
+      self.write_oracle(oracle, OraclePrice::Switchboard(SwitchboardPrice { mantissa: 0, scale: 8, timestamp: 0 }));
+      self.set_oracle_price(oracle, price);
+      oracle
+   }
+
+   // A mock of the TWAP account of an AMM pool averaging over 30 minutes, see oracle::TwapPrice
+   pub fn create_twap_oracle(&mut self, price: u64) -> Pubkey {
+      let oracle = Pubkey::new_unique();
+      self.oracles.push(oracle);
+      self.write_oracle(oracle, OraclePrice::Twap(TwapPrice { price: 0, expo: PRICE_EXPO, window: 30 * 60, updated_at: 0 }));
+      self.set_oracle_price(oracle, price);
+      oracle
+   }
+
+   // Publishes a price in whole units now, in the layout the oracle already has (Pyth for a new account)
+   pub fn set_oracle_price(&mut self, oracle: Pubkey, price: u64) {
+      let mantissa = price * 100_000_000;
+      let now = self.now();
+      let source = match self.svm.account(&oracle).map(|account| OraclePrice::parse(&account.data)) {
+         Some(Ok(OraclePrice::Switchboard(_))) => OraclePrice::Switchboard(SwitchboardPrice { mantissa: mantissa as i128, scale: 8, timestamp: now }),
+         Some(Ok(OraclePrice::Twap(twap))) => OraclePrice::Twap(TwapPrice { price: mantissa, expo: PRICE_EXPO, updated_at: now, ..twap }),
+         _ => OraclePrice::Pyth(PythPrice { price: mantissa as i64, conf: 0, expo: PRICE_EXPO, publish_time: now, status: PYTH_STATUS_TRADING }),
+      };
+      self.write_oracle(oracle, source);
+   }
+
+   // Keeps the price of the oracle and changes when it was published, in the past to make it stale
+   pub fn publish_oracle(&mut self, oracle: Pubkey, publish_time: i64) {
+      let source = match self.oracle_source(&oracle) {
+         OraclePrice::Pyth(price) => OraclePrice::Pyth(PythPrice { publish_time, ..price }),
+         OraclePrice::Switchboard(price) => OraclePrice::Switchboard(SwitchboardPrice { timestamp: publish_time, ..price }),
+         OraclePrice::Twap(price) => OraclePrice::Twap(TwapPrice { updated_at: publish_time, ..price }),
+      };
+      self.write_oracle(oracle, source);
+   }
+
+   pub fn set_oracle(&mut self, oracle: Pubkey, price: PythPrice) {
+      self.write_oracle(oracle, OraclePrice::Pyth(price));
+   }
+
+   pub fn write_oracle(&mut self, oracle: Pubkey, source: OraclePrice) {
+      let data = match source {
+         OraclePrice::Pyth(price) => {
+            let mut data = vec![0; PYTH_PRICE_ACCOUNT_LEN];
+            price.write(&mut data);
+            data
+         }
+         OraclePrice::Switchboard(price) => {
+            let mut data = vec![0; SWITCHBOARD_ACCOUNT_LEN];
+            price.write(&mut data);
+            data
+         }
+         OraclePrice::Twap(price) => {
+            let mut data = vec![0; TWAP_ACCOUNT_LEN];
+            price.write(&mut data);
+            data
+         }
+      };
+      self.svm.set_account(oracle, Account { lamports: 1_000_000_000, data, owner: system_program::ID, executable: false });
+   }
+
+   pub fn oracle_price(&self, oracle: &Pubkey) -> PythPrice {
+      PythPrice::parse(&self.svm.account(oracle).expect("missing oracle").data).unwrap()
+   }
+
+   pub fn oracle_source(&self, oracle: &Pubkey) -> OraclePrice {
+      OraclePrice::parse(&self.svm.account(oracle).expect("missing oracle").data).unwrap()
+   }
+
+   // ---------- program accounts ----------
+
+   pub fn bank_state(&self, bank: &TestBank) -> Bank {
+      self.read_account(&bank.bank)
+   }
+
+   pub fn user_state(&self, user: &TestUser) -> User {
+      self.read_account(&user.account)
+   }
+
+   pub fn read_account<T: AccountDeserialize>(&self, key: &Pubkey) -> T {
+      T::try_deserialize(&mut &self.svm.account(key).expect("missing account").data[..]).unwrap()
+   }
+
+   // A bank accrued to the current time with the price of its oracle, like the program loads it
+   pub fn priced_bank(&self, key: &Pubkey) -> PricedBank {
+      let mut bank: Bank = self.read_account(key);
+      bank.accrue_interest(self.now()).unwrap();
+      let oracles: Vec<&Account> = bank.oracles().iter().map(|oracle| self.svm.account(oracle).expect("missing oracle")).collect();
+      let oracle_data: Vec<&[u8]> = oracles.iter().map(|oracle| &oracle.data[..]).collect();
+      let (price, _) = aggregate_price(&bank, &oracle_data, self.now()).unwrap();
+      PricedBank::new(*key, bank, price, self.now())
+   }
+
+   // The e-mode category the user is in
+   pub fn emode_category(&self, user: &TestUser) -> Option<EModeCategory> {
+      match self.user_state(user).emode_category {
+         0 => None,
+         id => Some(self.read_account(&emode_category_address(id))),
+      }
+   }
+
+   pub fn health(&self, user: &TestUser) -> Health {
+      let user_state = self.user_state(user);
+      let priced_banks: Vec<PricedBank> = user_state
+         .positions
+         .iter()
+         .filter(|position| position.is_active())
+         .map(|position| self.priced_bank(&position.bank))
+         .collect();
+      compute_health(&user_state, &priced_banks, self.emode_category(user).as_ref()).unwrap()
+   }
+
+   // The bank and oracles of every other position of the user, in position order
+   pub fn position_accounts(&self, user: &TestUser, acted: &[&TestBank]) -> Vec<AccountMeta> {
+      let Some(account) = self.svm.account(&user.account) else {
+         return Vec::new();
+      };
+      let user_state = User::try_deserialize(&mut &account.data[..]).unwrap();
+      let acted: Vec<Pubkey> = acted.iter().map(|bank| bank.bank).collect();
+      lending_client::position_accounts(&user_state, &acted, |bank| Some(self.read_account::<Bank>(bank).oracles())).unwrap()
+   }
+
+   // The accounts of the bank with the secondary oracles it has now
+   pub fn bank_accounts(&self, bank: &TestBank) -> BankAccounts {
+      BankAccounts::from_bank(&self.bank_state(bank))
+   }
+
+   // User::isolated_bank, or before the first borrow the isolated bank the user has collateral in, passed to borrow and repay
+   pub fn isolated_bank(&self, user: &TestUser) -> Option<Pubkey> {
+      let account = self.svm.account(&user.account)?;
+      let user_state = User::try_deserialize(&mut &account.data[..]).unwrap();
+      if user_state.isolated_bank != Pubkey::default() {
+         return Some(user_state.isolated_bank);
+      }
+      user_state
+         .positions
+         .iter()
+         .filter(|position| position.is_active() && position.deposit_shares > 0)
+         .map(|position| position.bank)
+         .find(|bank| self.read_account::<Bank>(bank).isolated)
+   }
+
+   // ---------- builders ----------
+
+   pub fn bank(&mut self) -> BankBuilder<'_> {
+      BankBuilder::new(self)
+   }
+
+   pub fn user(&mut self) -> TestUser {
+      let wallet = Pubkey::new_unique();
+      self.svm.airdrop(&wallet, 10_000_000_000);
+      let user = TestUser { wallet, account: user_address(&wallet) };
+      self.init_user(&user).expect("init_user failed");
+      user
+   }
+
+   // ---------- instructions ----------
+
+   pub fn process(&mut self, instruction: Instruction, signers: &[Pubkey]) -> TxResult {
+      self.svm.process_transaction(&[instruction], signers)
+   }
+
+   pub fn init_bank(&mut self, mint: &Pubkey, oracle: &Pubkey, liquidation_threshold: u64, max_ltv: u64, interest_rate: u64) -> TxResult {
+      let bank = BankAccounts::new(*mint, *oracle);
+      let ix = InitBankBuilder::new(self.admin, &bank, liquidation_threshold, max_ltv, interest_rate).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn update_bank_config(&mut self, bank: &TestBank, authority: &Pubkey, config: BankConfig) -> TxResult {
+      let ix = UpdateBankConfigBuilder::new(*authority, bank.bank, config).instruction();
+      self.process(ix, &[*authority])
+   }
+
+   pub fn set_bank_paused(&mut self, bank: &TestBank, paused: bool) -> TxResult {
+      let ix = SetBankPausedBuilder::new(self.admin, bank.bank, paused).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   // Signed by a fresh wallet, refresh_bank needs no authority
+   pub fn refresh_bank(&mut self, bank: &TestBank) -> TxResult {
+      let ix = RefreshBankBuilder::new(&self.bank_accounts(bank)).instruction();
+      self.process(ix, &[Pubkey::new_unique()])
+   }
+
+   // Signed by a fresh wallet, anyone can refresh any user
+   pub fn refresh_user(&mut self, user: &TestUser) -> TxResult {
+      let ix = RefreshUserBuilder::new(user.wallet).positions(self.position_accounts(user, &[])).instruction();
+      self.process(ix, &[Pubkey::new_unique()])
+   }
+
+   // Signed by a fresh wallet, anyone can socialize bad debt
+   pub fn socialize_loss(&mut self, bank: &TestBank, user: &TestUser) -> TxResult {
+      let ix = self.socialize_loss_ix(bank, user);
+      self.process(ix, &[Pubkey::new_unique()])
+   }
+
+   pub fn socialize_loss_ix(&self, bank: &TestBank, user: &TestUser) -> Instruction {
+      let isolated_bank = self.user_state(user).isolated_bank;
+      SocializeLossBuilder::new(&self.bank_accounts(bank), user.wallet)
+         .isolated_bank((isolated_bank != Pubkey::default()).then_some(isolated_bank))
+         .instruction()
+   }
+
+   pub fn init_emode_category(&mut self, id: u8, max_ltv: u64, liquidation_threshold: u64, liquidation_bonus: u64) -> TxResult {
+      let config = EModeConfig { max_ltv, liquidation_threshold, liquidation_bonus };
+      let ix = InitEModeCategoryBuilder::new(self.admin, id, config).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn update_emode_category(&mut self, id: u8, authority: &Pubkey, config: EModeConfig) -> TxResult {
+      let ix = UpdateEModeCategoryBuilder::new(*authority, id, config).instruction();
+      self.process(ix, &[*authority])
+   }
+
+   pub fn set_bank_emode_category(&mut self, bank: &TestBank, category: u8) -> TxResult {
+      let ix = SetBankEModeCategoryBuilder::new(self.admin, bank.bank, category).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn set_bank_isolation(&mut self, bank: &TestBank, isolated: bool, debt_ceiling: u64, borrowable_in_isolation: bool) -> TxResult {
+      let config = IsolationConfig { isolated, debt_ceiling, borrowable_in_isolation };
+      let ix = SetBankIsolationBuilder::new(self.admin, bank.bank, config).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn set_liquidation_mode(&mut self, bank: &TestBank, mode: LiquidationMode, min_liquidation_bonus: u64, auction_duration: u64) -> TxResult {
+      let config = LiquidationModeConfig { mode, min_liquidation_bonus, auction_duration };
+      let ix = SetBankLiquidationModeBuilder::new(self.admin, bank.bank, config).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn set_bank_insurance(&mut self, bank: &TestBank, fee_bps: u64, unstake_cooldown: u64) -> TxResult {
+      let config = InsuranceConfig { fee_bps, unstake_cooldown };
+      let ix = SetBankInsuranceBuilder::new(self.admin, bank.bank, config).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn set_self_liquidation(&mut self, bank: &TestBank, soft_liquidation_health: u64, penalty: u64) -> TxResult {
+      let config = SelfLiquidationConfig { soft_liquidation_health, penalty };
+      let ix = SetBankSelfLiquidationBuilder::new(self.admin, bank.bank, config).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn set_bank_oracles(&mut self, bank: &TestBank, secondary_oracles: &[Pubkey], tolerance_bps: u64) -> TxResult {
+      let secondary_oracles = [secondary_oracles.first().copied(), secondary_oracles.get(1).copied()];
+      let ix = SetBankOraclesBuilder::new(self.admin, bank.bank, secondary_oracles, OracleConfig { tolerance_bps }).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn set_bank_ema(&mut self, bank: &TestBank, window: u64) -> TxResult {
+      let ix = SetBankEmaBuilder::new(self.admin, bank.bank, EmaConfig { window }).instruction();
+      self.process(ix, &[self.admin])
+   }
+
+   pub fn stake_insurance(&mut self, staker: &TestUser, bank: &TestBank, amount: u64) -> TxResult {
+      let staker_token_account = self.token_account(&staker.wallet, &bank.mint);
+      let ix = StakeInsuranceBuilder::new(staker.wallet, &self.bank_accounts(bank), amount).staker_token_account(staker_token_account).instruction();
+      self.process(ix, &[staker.wallet])
+   }
+
+   pub fn request_unstake(&mut self, staker: &TestUser, bank: &TestBank, shares: u64) -> TxResult {
+      let ix = RequestUnstakeBuilder::new(staker.wallet, bank.bank, shares).instruction();
+      self.process(ix, &[staker.wallet])
+   }
+
+   pub fn unstake(&mut self, staker: &TestUser, bank: &TestBank) -> TxResult {
+      let staker_token_account = self.token_account(&staker.wallet, &bank.mint);
+      let ix = UnstakeBuilder::new(staker.wallet, &self.bank_accounts(bank)).staker_token_account(staker_token_account).instruction();
+      self.process(ix, &[staker.wallet])
+   }
+
+   pub fn insurance_stake(&self, staker: &TestUser, bank: &TestBank) -> InsuranceStake {
+      self.read_account(&insurance_stake_address(&bank.bank, &staker.wallet))
+   }
+
+   pub fn init_user(&mut self, user: &TestUser) -> TxResult {
+      let ix = InitUserBuilder::new(user.wallet).instruction();
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn set_user_emode(&mut self, user: &TestUser, category: u8) -> TxResult {
+      let user_state = self.user_state(user);
+      let positions = lending_client::emode_position_accounts(&user_state, |bank| Some(self.read_account::<Bank>(bank).oracles())).unwrap();
+      let ix = SetUserEModeBuilder::new(user.wallet, category).positions(positions).instruction();
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn deposit(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> TxResult {
+      let ix = self.deposit_ix(user, bank, amount);
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn deposit_ix(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> Instruction {
+      let user_token_account = self.token_account(&user.wallet, &bank.mint);
+      DepositBuilder::new(user.wallet, &self.bank_accounts(bank), amount)
+         .user_token_account(user_token_account)
+         .positions(self.position_accounts(user, &[bank]))
+         .instruction()
+   }
+
+   pub fn withdraw(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> TxResult {
+      let ix = self.withdraw_ix(user, bank, amount);
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn withdraw_ix(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> Instruction {
+      let user_token_account = self.token_account(&user.wallet, &bank.mint);
+      WithdrawBuilder::new(user.wallet, &self.bank_accounts(bank), amount)
+         .user_token_account(user_token_account)
+         .positions(self.position_accounts(user, &[bank]))
+         .instruction()
+   }
+
+   pub fn borrow(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> TxResult {
+      let ix = self.borrow_ix(user, bank, amount);
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn borrow_ix(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> Instruction {
+      let user_token_account = self.token_account(&user.wallet, &bank.mint);
+      BorrowBuilder::new(user.wallet, &self.bank_accounts(bank), amount)
+         .user_token_account(user_token_account)
+         .positions(self.position_accounts(user, &[bank]))
+         .isolated_bank(self.isolated_bank(user))
+         .instruction()
+   }
+
+   pub fn repay(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> TxResult {
+      let ix = self.repay_ix(user, bank, amount);
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn repay_ix(&mut self, user: &TestUser, bank: &TestBank, amount: u64) -> Instruction {
+      let user_token_account = self.token_account(&user.wallet, &bank.mint);
+      RepayBuilder::new(user.wallet, &self.bank_accounts(bank), amount)
+         .user_token_account(user_token_account)
+         .positions(self.position_accounts(user, &[bank]))
+         .isolated_bank(self.isolated_bank(user))
+         .instruction()
+   }
+
+   pub fn liquidate(&mut self, liquidator: &TestUser, user: &TestUser, collateral: &TestBank, borrowed: &TestBank, amount: u64) -> TxResult {
+      let ix = self.liquidate_ix(liquidator, user, collateral, borrowed, amount);
+      self.process(ix, &[liquidator.wallet])
+   }
+
+   pub fn liquidate_ix(&mut self, liquidator: &TestUser, user: &TestUser, collateral: &TestBank, borrowed: &TestBank, amount: u64) -> Instruction {
+      let collateral_token_account = self.token_account(&liquidator.wallet, &collateral.mint);
+      let borrowed_token_account = self.token_account(&liquidator.wallet, &borrowed.mint);
+      LiquidateBuilder::new(liquidator.wallet, user.wallet, &self.bank_accounts(collateral), &self.bank_accounts(borrowed), amount)
+         .liquidator_token_accounts(collateral_token_account, borrowed_token_account)
+         .positions(self.position_accounts(user, &[collateral, borrowed]))
+         .instruction()
+   }
+
+   // Swapped through the mock swap
+   pub fn self_liquidate(
+      &mut self,
+      user: &TestUser,
+      collateral: &TestBank,
+      borrowed: &TestBank,
+      pool: &SwapPool,
+      amount: u64,
+      min_amount_out: u64,
+   ) -> TxResult {
+      let collateral_token_account = self.token_account(&user.wallet, &collateral.mint);
+      let borrowed_token_account = self.token_account(&user.wallet, &borrowed.mint);
+      let ix = SelfLiquidateBuilder::new(user.wallet, &self.bank_accounts(collateral), &self.bank_accounts(borrowed), amount, min_amount_out, MOCK_SWAP_ID)
+         .user_token_accounts(collateral_token_account, borrowed_token_account)
+         .positions(self.position_accounts(user, &[collateral, borrowed]))
+         .swap_accounts(pool.accounts())
+         .instruction();
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn repay_with_collateral(
+      &mut self,
+      user: &TestUser,
+      collateral: &TestBank,
+      borrowed: &TestBank,
+      pool: &SwapPool,
+      collateral_amount: u64,
+      min_amount_out: u64,
+   ) -> TxResult {
+      let ix = self.repay_with_collateral_ix(user, collateral, borrowed, pool, collateral_amount, min_amount_out);
+      self.process(ix, &[user.wallet])
+   }
+
+   // Swapped through the mock swap
+   pub fn repay_with_collateral_ix(
+      &mut self,
+      user: &TestUser,
+      collateral: &TestBank,
+      borrowed: &TestBank,
+      pool: &SwapPool,
+      collateral_amount: u64,
+      min_amount_out: u64,
+   ) -> Instruction {
+      let collateral_token_account = self.token_account(&user.wallet, &collateral.mint);
+      let borrowed_token_account = self.token_account(&user.wallet, &borrowed.mint);
+      RepayWithCollateralBuilder::new(user.wallet, &self.bank_accounts(collateral), &self.bank_accounts(borrowed), collateral_amount, min_amount_out, MOCK_SWAP_ID)
+         .user_token_accounts(collateral_token_account, borrowed_token_account)
+         .positions(self.position_accounts(user, &[collateral, borrowed]))
+         .swap_accounts(pool.accounts())
+         .instruction()
+   }
+
+   pub fn leverage(&mut self, user: &TestUser, collateral: &TestBank, borrowed: &TestBank, pool: &SwapPool, target_ltv: u64, min_amount_out: u64) -> TxResult {
+      let collateral_token_account = self.token_account(&user.wallet, &collateral.mint);
+      let borrowed_token_account = self.token_account(&user.wallet, &borrowed.mint);
+      let ix = LeverageBuilder::new(user.wallet, &self.bank_accounts(collateral), &self.bank_accounts(borrowed), target_ltv, min_amount_out, MOCK_SWAP_ID)
+         .user_token_accounts(collateral_token_account, borrowed_token_account)
+         .positions(self.position_accounts(user, &[collateral, borrowed]))
+         .swap_accounts(pool.accounts())
+         .instruction();
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn deleverage(&mut self, user: &TestUser, collateral: &TestBank, borrowed: &TestBank, pool: &SwapPool, target_ltv: u64, min_amount_out: u64) -> TxResult {
+      let collateral_token_account = self.token_account(&user.wallet, &collateral.mint);
+      let borrowed_token_account = self.token_account(&user.wallet, &borrowed.mint);
+      let ix = DeleverageBuilder::new(user.wallet, &self.bank_accounts(collateral), &self.bank_accounts(borrowed), target_ltv, min_amount_out, MOCK_SWAP_ID)
+         .user_token_accounts(collateral_token_account, borrowed_token_account)
+         .positions(self.position_accounts(user, &[collateral, borrowed]))
+         .swap_accounts(pool.accounts())
+         .instruction();
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn swap_collateral(&mut self, user: &TestUser, from: &TestBank, to: &TestBank, pool: &SwapPool, amount: u64, min_amount_out: u64) -> TxResult {
+      let from_token_account = self.token_account(&user.wallet, &from.mint);
+      let to_token_account = self.token_account(&user.wallet, &to.mint);
+      let ix = SwapCollateralBuilder::new(user.wallet, &self.bank_accounts(from), &self.bank_accounts(to), amount, min_amount_out, MOCK_SWAP_ID)
+         .user_token_accounts(from_token_account, to_token_account)
+         .positions(self.position_accounts(user, &[from, to]))
+         .swap_accounts(pool.accounts())
+         .instruction();
+      self.process(ix, &[user.wallet])
+   }
+
+   pub fn swap_debt(&mut self, user: &TestUser, from: &TestBank, to: &TestBank, pool: &SwapPool, borrow_amount: u64, min_amount_out: u64) -> TxResult {
+      let from_token_account = self.token_account(&user.wallet, &from.mint);
+      let to_token_account = self.token_account(&user.wallet, &to.mint);
+      let ix = SwapDebtBuilder::new(user.wallet, &self.bank_accounts(from), &self.bank_accounts(to), borrow_amount, min_amount_out, MOCK_SWAP_ID)
+         .user_token_accounts(from_token_account, to_token_account)
+         .positions(self.position_accounts(user, &[from, to]))
+         .swap_accounts(pool.accounts())
+         .instruction();
+      self.process(ix, &[user.wallet])
+   }
+}
+
+// A SOL bank at $150 and a USDC bank at $1, with a lender that already supplied USDC to borrow
+pub struct Market {
+   pub sol: TestBank,
+   pub usdc: TestBank,
+   pub lender: TestUser,
+}
+
+impl TestEnv {
+   pub fn market(&mut self, usdc_liquidity: u64) -> Market {
+      let sol = self.bank().price(150).build();
+      let usdc = self.bank().price(1).build();
+      let lender = self.user();
+      self.fund(&lender, &usdc, usdc_liquidity);
+      self.deposit(&lender, &usdc, usdc_liquidity).expect("lender deposit failed");
+      Market { sol, usdc, lender }
+   }
+
+   // A user that deposited `collateral` SOL into the market
+   pub fn borrower(&mut self, market: &Market, collateral: u64) -> TestUser {
+      let user = self.user();
+      self.fund(&user, &market.sol, collateral);
+      self.deposit(&user, &market.sol, collateral).expect("collateral deposit failed");
+      user
+   }
+}
+
+/*
+   Creates a mint, its mock oracle and the bank. Risk parameters default to the ones of the TS tests
+   (threshold 80%, max ltv 75%, 5% interest), anything else is set with update_bank_config after init.
+*/
+pub struct BankBuilder<'a> {
+   env: &'a mut TestEnv,
+   decimals: u8,
+   price: u64,
+   interest_rate: u64,
+   config: BankConfig,
+}
+
+impl<'a> BankBuilder<'a> {
+   fn new(env: &'a mut TestEnv) -> Self {
+      BankBuilder {
+         env,
+         decimals: 6,
+         price: 1,
+         interest_rate: 5,
+         config: BankConfig {
+            liquidation_threshold: 8_000,
+            liquidation_bonus: lending::constants::DEFAULT_LIQUIDATION_BONUS,
+            liquidation_close_factor: lending::constants::DEFAULT_LIQUIDATION_CLOSE_FACTOR,
+            max_ltv: 7_500,
+            interest_rate: 5,
+            oracle_max_age: lending::constants::DEFAULT_ORACLE_MAX_AGE,
+            deposit_cap: 0,
+            borrow_cap: 0,
+            borrow_weight_bps: lending::constants::DEFAULT_BORROW_WEIGHT,
+         },
+      }
+   }
+
+   pub fn decimals(mut self, decimals: u8) -> Self {
+      self.decimals = decimals;
+      self
+   }
+
+   pub fn price(mut self, price: u64) -> Self {
+      self.price = price;
+      self
+   }
+
+   pub fn interest_rate(mut self, interest_rate: u64) -> Self {
+      self.interest_rate = interest_rate;
+      self.config.interest_rate = interest_rate;
+      self
+   }
+
+   pub fn ltv(mut self, max_ltv: u64, liquidation_threshold: u64) -> Self {
+      self.config.max_ltv = max_ltv;
+      self.config.liquidation_threshold = liquidation_threshold;
+      self
+   }
+
+   pub fn liquidation(mut self, liquidation_bonus: u64, liquidation_close_factor: u64) -> Self {
+      self.config.liquidation_bonus = liquidation_bonus;
+      self.config.liquidation_close_factor = liquidation_close_factor;
+      self
+   }
+
+   pub fn caps(mut self, deposit_cap: u64, borrow_cap: u64) -> Self {
+      self.config.deposit_cap = deposit_cap;
+      self.config.borrow_cap = borrow_cap;
+      self
+   }
+
+   pub fn borrow_weight(mut self, borrow_weight_bps: u64) -> Self {
+      self.config.borrow_weight_bps = borrow_weight_bps;
+      self
+   }
+
+   pub fn build(self) -> TestBank {
+      let env = self.env;
+      let mint = env.create_mint(self.decimals);
+      let oracle = env.create_oracle(self.price);
+      env.init_bank(&mint, &oracle, self.config.liquidation_threshold, self.config.max_ltv, self.interest_rate)
+         .expect("init_bank failed");
+
+      let bank = TestBank {
+         mint,
+         bank: bank_address(&mint),
+         treasury: treasury_address(&mint),
+         insurance_vault: insurance_vault_address(&mint),
+         oracle,
+         decimals: self.decimals,
+      };
+      let admin = env.admin;
+      env.update_bank_config(&bank, &admin, self.config).expect("update_bank_config failed");
+      bank
+   }
+}
+
+/*
+   Events are emitted with emit_cpi!, so they are the data of the inner instructions the program
+   sends to itself: the event instruction tag, the discriminator of the event and the event itself.
+*/
+pub fn events<T: AnchorDeserialize + Discriminator>(meta: &TransactionMeta) -> Vec<T> {
+   meta.inner_instructions
+      .iter()
+      .filter(|ix| ix.program_id == lending::ID)
+      .filter_map(|ix| ix.data.strip_prefix(&anchor_lang::event::EVENT_IX_TAG_LE[..]))
+      .filter_map(|data| data.strip_prefix(&T::DISCRIMINATOR[..]))
+      .map(|mut data| T::deserialize(&mut data).unwrap())
+      .collect()
+}
+
+// The error of the program a failed transaction ended with
+pub fn error_code(result: TxResult) -> Option<lending::error::ErrCode> {
+   lending::error::ErrCode::from_program_error(&result.expect_err("transaction should have failed").error)
+}
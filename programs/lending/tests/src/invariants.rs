@@ -0,0 +1,67 @@
+use crate::env::{TestBank, TestEnv, TestUser};
+
+/*
+   Accounting invariants that hold after any sequence of instructions, checked by the randomized
+   tests and by the fuzzer. `users` has to hold every user with a position in the banks.
+*/
+pub fn check_accounting(env: &TestEnv, banks: &[TestBank], users: &[TestUser]) -> Result<(), String> {
+   let user_states: Vec<_> = users.iter().map(|user| env.user_state(user)).collect();
+
+   for (index, user) in user_states.iter().enumerate() {
+      let banks: Vec<_> = user.positions.iter().filter(|position| position.is_active()).map(|position| position.bank).collect();
+      if (1..banks.len()).any(|i| banks[..i].contains(&banks[i])) {
+         return Err(format!("user {index} has two positions in the same bank: {banks:?}"));
+      }
+   }
+
+   for (index, test_bank) in banks.iter().enumerate() {
+      let bank = env.bank_state(test_bank);
+      // The fees owed to the insurance fund stay in the treasury until they are swept into the vault
+      let treasury = env.balance(&test_bank.treasury);
+      if (treasury as u128 + bank.total_borrowed as u128) < bank.total_deposits as u128 + bank.insurance_fees as u128 {
+         return Err(format!(
+            "bank {index}: treasury holds {treasury} but total_deposits + insurance_fees - total_borrowed is {} + {} - {}",
+            bank.total_deposits, bank.insurance_fees, bank.total_borrowed
+         ));
+      }
+      let vault = env.balance(&test_bank.insurance_vault);
+      if vault < bank.insurance_funds {
+         return Err(format!("bank {index}: insurance vault holds {vault} but insurance_funds is {}", bank.insurance_funds));
+      }
+
+      let emode_borrowed_shares: u128 = user_states
+         .iter()
+         .filter(|user| user.emode_category != 0)
+         .filter_map(|user| user.position(&test_bank.bank))
+         .map(|position| position.borrowed_shares as u128)
+         .sum();
+      if emode_borrowed_shares != bank.emode_borrowed_shares as u128 {
+         return Err(format!("bank {index}: users in e-mode hold {emode_borrowed_shares} borrowed shares, the bank counts {}", bank.emode_borrowed_shares));
+      }
+
+      let positions: Vec<_> = user_states.iter().filter_map(|user| user.position(&test_bank.bank)).collect();
+      let deposit_shares: u128 = positions.iter().map(|position| position.deposit_shares as u128).sum();
+      let borrowed_shares: u128 = positions.iter().map(|position| position.borrowed_shares as u128).sum();
+      if deposit_shares != bank.total_deposit_shares as u128 {
+         return Err(format!("bank {index}: users hold {deposit_shares} deposit shares, the bank has {}", bank.total_deposit_shares));
+      }
+      if borrowed_shares != bank.total_borrowed_shares as u128 {
+         return Err(format!("bank {index}: users hold {borrowed_shares} borrowed shares, the bank has {}", bank.total_borrowed_shares));
+      }
+
+      // Rounding always favours the bank: users can't claim more than it holds or owe less than it lent
+      let mut claims = 0u128;
+      let mut debts = 0u128;
+      for position in &positions {
+         claims += bank.deposit_shares_to_amount(position.deposit_shares).map_err(|err| format!("bank {index}: {err}"))? as u128;
+         debts += bank.borrowed_shares_to_amount(position.borrowed_shares).map_err(|err| format!("bank {index}: {err}"))? as u128;
+      }
+      if claims > bank.total_deposits as u128 {
+         return Err(format!("bank {index}: users can claim {claims} but total_deposits is {}", bank.total_deposits));
+      }
+      if debts < bank.total_borrowed as u128 {
+         return Err(format!("bank {index}: users owe {debts} but total_borrowed is {}", bank.total_borrowed));
+      }
+   }
+   Ok(())
+}
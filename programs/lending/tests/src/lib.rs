@@ -0,0 +1,14 @@
+/*
+   Integration tests of the lending program that run with `cargo test`, without a validator or Node.
+   svm is the in-process runtime, env has the builders and instruction helpers used by the tests in tests/,
+   invariants the accounting checks shared with the fuzzer and mock_swap a swap adapter to swap through.
+*/
+pub mod env;
+pub mod invariants;
+pub mod mock_swap;
+pub mod svm;
+
+pub use env::*;
+pub use invariants::*;
+pub use mock_swap::*;
+pub use svm::*;
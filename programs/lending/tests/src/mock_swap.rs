@@ -0,0 +1,121 @@
+use anchor_lang::{
+   prelude::*,
+   solana_program::{
+      entrypoint::ProgramResult,
+      instruction::Instruction,
+      program::{invoke, invoke_signed},
+      program_error::ProgramError,
+   },
+   InstructionData,
+};
+use anchor_spl::token::spl_token;
+use lending::swap::SWAP_DISCRIMINATOR;
+
+use crate::env::{TestBank, TestEnv};
+use crate::svm::Account;
+
+/*
+   A swap adapter for the tests (see lending::swap for the interface). Every pool swaps one mint into another
+   at a fixed rate, amount_out = amount_in * rate_numerator / rate_denominator, from a vault it owns.
+   The rate is set by the test, so a swap can be made as good or as bad as a test needs, and min_amount_out
+   is ignored so the checks of the lending program are the only ones.
+   Accounts after the three of the interface: the pool, its input vault, its output vault and the token program.
+   Any account after those is passed on to a refresh_bank of the lending program before the swap, to play an
+   adapter that calls back into the program in the middle of its instruction.
+*/
+pub const MOCK_SWAP_ID: Pubkey = pubkey!("MockSwap11111111111111111111111111111111111");
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct MockPool {
+   pub input_mint: Pubkey,
+   pub output_mint: Pubkey,
+   pub rate_numerator: u64,
+   pub rate_denominator: u64,
+   pub extra_in: u64, // taken from the source on top of amount_in, to play an adapter that takes too much
+   pub bump: u8,
+}
+
+pub fn mock_pool_address(input_mint: &Pubkey, output_mint: &Pubkey) -> (Pubkey, u8) {
+   Pubkey::find_program_address(&[b"pool", input_mint.as_ref(), output_mint.as_ref()], &MOCK_SWAP_ID)
+}
+
+pub fn process_mock_swap(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+   let data = data.strip_prefix(&SWAP_DISCRIMINATOR[..]).ok_or(ProgramError::InvalidInstructionData)?;
+   let (amount_in, _min_amount_out) = <(u64, u64)>::try_from_slice(data).map_err(|_| ProgramError::InvalidInstructionData)?;
+   let [authority, source, destination, pool, input_vault, output_vault, token_program, callback @ ..] = accounts else {
+      return Err(ProgramError::NotEnoughAccountKeys);
+   };
+   if !callback.is_empty() {
+      let metas = callback.iter().map(|info| AccountMeta { pubkey: *info.key, is_signer: info.is_signer, is_writable: info.is_writable });
+      let refresh = Instruction { program_id: lending::ID, accounts: metas.collect(), data: lending::instruction::RefreshBank {}.data() };
+      invoke(&refresh, callback)?;
+   }
+   if pool.owner != program_id {
+      return Err(ProgramError::IncorrectProgramId);
+   }
+   let state = MockPool::deserialize(&mut &pool.try_borrow_data()?[..])?;
+
+   let amount_out = (amount_in as u128 * state.rate_numerator as u128 / state.rate_denominator as u128) as u64;
+
+   let pay = spl_token::instruction::transfer(token_program.key, source.key, input_vault.key, authority.key, &[], amount_in + state.extra_in)?;
+   invoke(&pay, &[source.clone(), input_vault.clone(), authority.clone(), token_program.clone()])?;
+
+   let seeds: &[&[u8]] = &[b"pool", state.input_mint.as_ref(), state.output_mint.as_ref(), &[state.bump]];
+   let receive = spl_token::instruction::transfer(token_program.key, output_vault.key, destination.key, pool.key, &[], amount_out)?;
+   invoke_signed(&receive, &[output_vault.clone(), destination.clone(), pool.clone(), token_program.clone()], &[seeds])
+}
+
+// A pool of the mock swap, with the accounts a swap through it needs
+#[derive(Clone, Copy, Debug)]
+pub struct SwapPool {
+   pub pool: Pubkey,
+   pub input_vault: Pubkey,
+   pub output_vault: Pubkey,
+}
+
+impl SwapPool {
+   pub fn accounts(&self) -> Vec<AccountMeta> {
+      vec![
+         AccountMeta::new_readonly(self.pool, false),
+         AccountMeta::new(self.input_vault, false),
+         AccountMeta::new(self.output_vault, false),
+         AccountMeta::new_readonly(spl_token::ID, false),
+      ]
+   }
+}
+
+impl TestEnv {
+   // A pool that swaps the mint of `from` into the mint of `to`, with `liquidity` of the output mint to pay out
+   pub fn swap_pool(&mut self, from: &TestBank, to: &TestBank, rate_numerator: u64, rate_denominator: u64, liquidity: u64) -> SwapPool {
+      self.svm.add_program(MOCK_SWAP_ID, process_mock_swap);
+      let (pool, bump) = mock_pool_address(&from.mint, &to.mint);
+      let input_vault = self.create_token_account(&from.mint, &pool);
+      let output_vault = self.create_token_account(&to.mint, &pool);
+      self.mint_to(&output_vault, liquidity);
+
+      let swap_pool = SwapPool { pool, input_vault, output_vault };
+      self.set_mock_pool(&swap_pool, MockPool {
+         input_mint: from.mint,
+         output_mint: to.mint,
+         rate_numerator,
+         rate_denominator,
+         extra_in: 0,
+         bump,
+      });
+      swap_pool
+   }
+
+   pub fn mock_pool(&self, pool: &SwapPool) -> MockPool {
+      MockPool::deserialize(&mut &self.svm.account(&pool.pool).expect("missing pool").data[..]).unwrap()
+   }
+
+   pub fn set_mock_pool(&mut self, pool: &SwapPool, state: MockPool) {
+      let data = state.try_to_vec().unwrap();
+      self.svm.set_account(pool.pool, Account { lamports: 1_000_000_000, data, owner: MOCK_SWAP_ID, executable: false });
+   }
+
+   pub fn set_swap_rate(&mut self, pool: &SwapPool, rate_numerator: u64, rate_denominator: u64) {
+      let state = self.mock_pool(pool);
+      self.set_mock_pool(pool, MockPool { rate_numerator, rate_denominator, ..state });
+   }
+}
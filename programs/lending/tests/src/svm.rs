@@ -0,0 +1,628 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Once, OnceLock, RwLock};
+
+use anchor_lang::solana_program::{
+   account_info::AccountInfo,
+   bpf_loader_upgradeable,
+   clock::Clock,
+   entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE},
+   instruction::Instruction,
+   message::Message,
+   program_error::ProgramError,
+   program_stubs::{self, SyscallStubs},
+   pubkey::Pubkey,
+   rent::Rent,
+   system_program,
+};
+use anchor_spl::{associated_token, token::spl_token};
+
+/*
+   A small in-process runtime for the lending program.
+   The program is compiled for the host and called through its entry function, and the syscalls it makes
+   (logs, sysvars, cross program invocations) are answered by the stubs below. The system program and the
+   token program run natively, the same way they run inside the validator, so every CPI of the program
+   (creating accounts, token transfers, emit_cpi!) goes through the same checks as on-chain.
+
+   The rules of the runtime that are enforced, each with the message the validator logs:
+   - signer and writable privileges can't be escalated by a CPI, read-only accounts can't change
+   - only the owner of an account changes its data or its owner or takes lamports from it, executable accounts don't change,
+     checked when a program returns and before each of its CPIs, so what a callee did isn't blamed on its caller
+   - the lamports of the accounts of a program add up to the same sum when it returns
+   - a program can call itself (emit_cpi!) but can't be called back by a program it called, and the call depth is limited
+   - a transaction fits in a packet, and the accounts it changed are rent exempt or closed when it ends
+   What isn't enforced: compute units (the programs run natively, so neither the compute budget nor the stack and heap
+   limits of the SBF VM apply), signatures (only who signed), the size limit of accounts, account locks
+   between transactions and the sysvars other than Clock and Rent.
+*/
+
+pub type Processor = fn(&Pubkey, &[AccountInfo], &[u8]) -> ProgramResult;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Account {
+   pub lamports: u64,
+   pub data: Vec<u8>,
+   pub owner: Pubkey,
+   pub executable: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransactionMeta {
+   pub logs: Vec<String>,
+   pub inner_instructions: Vec<Instruction>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransactionError {
+   // None when the transaction as a whole is refused (its size, the rent of its accounts)
+   pub instruction: Option<usize>,
+   pub error: ProgramError,
+   pub logs: Vec<String>,
+}
+
+thread_local! {
+   static CLOCK: RefCell<Clock> = RefCell::new(Clock::default());
+   static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+   static INNER_INSTRUCTIONS: RefCell<Vec<Instruction>> = const { RefCell::new(Vec::new()) };
+   static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+   // Programs currently executing, the last one is the caller of a CPI and the owner of its PDA signers
+   static CALL_STACK: RefCell<Vec<Pubkey>> = const { RefCell::new(Vec::new()) };
+   // For each program of CALL_STACK, its accounts as they were when it started or got back control from a CPI
+   static SNAPSHOTS: RefCell<Vec<Vec<AccountState>>> = const { RefCell::new(Vec::new()) };
+}
+
+// MAX_INVOKE_STACK_HEIGHT of the validator, the transaction level instruction counts as one
+const MAX_CALL_DEPTH: usize = 5;
+
+// PACKET_DATA_SIZE of the validator, the most a serialized transaction (signatures and message) can take
+pub const MAX_TRANSACTION_SIZE: usize = 1_232;
+
+fn programs() -> &'static RwLock<HashMap<Pubkey, Processor>> {
+   static PROGRAMS: OnceLock<RwLock<HashMap<Pubkey, Processor>>> = OnceLock::new();
+   PROGRAMS.get_or_init(|| {
+      let mut programs: HashMap<Pubkey, Processor> = HashMap::new();
+      programs.insert(system_program::ID, process_system_instruction);
+      programs.insert(spl_token::ID, spl_token::processor::Processor::process);
+      programs.insert(lending::ID, process_lending_instruction);
+      RwLock::new(programs)
+   })
+}
+
+// Registers another program (for example a mock of an external program) for every Svm of the process
+pub fn add_program(program_id: Pubkey, processor: Processor) {
+   programs().write().unwrap().insert(program_id, processor);
+}
+
+fn log(message: String) {
+   LOGS.with(|logs| logs.borrow_mut().push(message));
+}
+
+fn process_lending_instruction(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+   // Anchor's entry wants the slice and the account infos to share one lifetime, both live until we return
+   let accounts: &[AccountInfo] = unsafe { std::mem::transmute(accounts) };
+   lending::entry(program_id, accounts, data)
+}
+
+pub fn execute_program(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+   let processor = *programs().read().unwrap().get(program_id).ok_or(ProgramError::IncorrectProgramId)?;
+
+   let depth = CALL_STACK.with(|stack| {
+      let mut stack = stack.borrow_mut();
+      stack.push(*program_id);
+      stack.len()
+   });
+   log(format!("Program {program_id} invoke [{depth}]"));
+   let result = snapshot(accounts).and_then(|before| {
+      SNAPSHOTS.with(|snapshots| snapshots.borrow_mut().push(before));
+      let result = processor(program_id, accounts, data).and_then(|()| {
+         SNAPSHOTS.with(|snapshots| {
+            let snapshots = snapshots.borrow();
+            let before = snapshots.last().unwrap();
+            verify_ownership(program_id, before, accounts)?;
+            verify_balance(before, accounts)
+         })
+      });
+      SNAPSHOTS.with(|snapshots| snapshots.borrow_mut().pop());
+      result
+   });
+   CALL_STACK.with(|stack| stack.borrow_mut().pop());
+   match &result {
+      Ok(()) => log(format!("Program {program_id} success")),
+      Err(error) => log(format!("Program {program_id} failed: {error}")),
+   }
+   result
+}
+
+// An account as a program saw it, to find out what the program changed
+#[derive(Clone)]
+struct AccountState {
+   key: Pubkey,
+   lamports: u64,
+   owner: Pubkey,
+   data: Vec<u8>,
+   executable: bool,
+}
+
+fn snapshot(accounts: &[AccountInfo]) -> Result<Vec<AccountState>, ProgramError> {
+   let mut states: Vec<AccountState> = Vec::with_capacity(accounts.len());
+   for info in accounts {
+      if states.iter().any(|state| state.key == *info.key) {
+         continue;
+      }
+      states.push(AccountState {
+         key: *info.key,
+         lamports: info.lamports(),
+         owner: *info.owner,
+         data: info.try_borrow_data()?.to_vec(),
+         executable: info.executable,
+      });
+   }
+   Ok(states)
+}
+
+fn runtime_error(message: &str) -> ProgramError {
+   log(message.to_string());
+   ProgramError::InvalidArgument
+}
+
+// What `program_id` did to the accounts of `before` since then, against the ownership rules of the runtime
+fn verify_ownership(program_id: &Pubkey, before: &[AccountState], accounts: &[AccountInfo]) -> ProgramResult {
+   for state in before {
+      let Some(info) = accounts.iter().find(|info| *info.key == state.key) else { continue };
+      let owned = state.owner == *program_id;
+      let data_changed = info.try_borrow_data()?[..] != state.data[..];
+      if state.executable && (data_changed || info.lamports() != state.lamports) {
+         return Err(runtime_error("instruction changed the balance or the data of an executable account"));
+      }
+      if data_changed && !owned {
+         return Err(runtime_error("instruction modified data of an account it does not own"));
+      }
+      if info.lamports() < state.lamports && !owned {
+         return Err(runtime_error("instruction spent from the balance of an account it does not own"));
+      }
+      if *info.owner != state.owner && !owned {
+         return Err(runtime_error("instruction illegally modified the program id of an account"));
+      }
+   }
+   Ok(())
+}
+
+fn verify_balance(before: &[AccountState], accounts: &[AccountInfo]) -> ProgramResult {
+   let sum_before: u128 = before.iter().map(|state| state.lamports as u128).sum();
+   let sum_after: u128 = before
+      .iter()
+      .filter_map(|state| accounts.iter().find(|info| *info.key == state.key))
+      .map(|info| info.lamports() as u128)
+      .sum();
+   if sum_before != sum_after {
+      return Err(runtime_error("sum of account balances before and after instruction do not match"));
+   }
+   Ok(())
+}
+
+struct Stubs;
+
+impl SyscallStubs for Stubs {
+   fn sol_log(&self, message: &str) {
+      log(format!("Program log: {message}"));
+   }
+
+   fn sol_log_data(&self, fields: &[&[u8]]) {
+      log(format!("Program data: {fields:?}"));
+   }
+
+   fn sol_invoke_signed(
+      &self,
+      instruction: &Instruction,
+      account_infos: &[AccountInfo],
+      signers_seeds: &[&[&[u8]]],
+   ) -> ProgramResult {
+      let (caller, depth, reentrant) = CALL_STACK.with(|stack| {
+         let stack = stack.borrow();
+         let caller = *stack.last().expect("CPI outside of a program");
+         (caller, stack.len(), caller != instruction.program_id && stack.contains(&instruction.program_id))
+      });
+      if reentrant {
+         return Err(runtime_error("Cross-program invocation reentrancy not allowed for this instruction"));
+      }
+      if depth >= MAX_CALL_DEPTH {
+         return Err(runtime_error("Cross-program invocation call depth too deep"));
+      }
+
+      let pda_signers = signers_seeds
+         .iter()
+         .map(|seeds| Pubkey::create_program_address(seeds, &caller))
+         .collect::<Result<Vec<_>, _>>()
+         .map_err(|_| ProgramError::InvalidSeeds)?;
+
+      // The callee gets the same accounts (sharing lamports and data), with the privileges of the instruction
+      let mut callee_infos = Vec::with_capacity(instruction.accounts.len());
+      for meta in &instruction.accounts {
+         let info = account_infos
+            .iter()
+            .find(|info| *info.key == meta.pubkey)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+         if meta.is_signer && !info.is_signer && !pda_signers.contains(&meta.pubkey) {
+            log(format!("{}'s signer privilege escalated", meta.pubkey));
+            return Err(ProgramError::MissingRequiredSignature);
+         }
+         if meta.is_writable && !info.is_writable {
+            log(format!("{}'s writable privilege escalated", meta.pubkey));
+            return Err(ProgramError::InvalidArgument);
+         }
+         let mut info = info.clone();
+         info.is_signer = meta.is_signer;
+         info.is_writable = meta.is_writable;
+         callee_infos.push(info);
+      }
+
+      // What the caller did so far is checked now, after the call its snapshot takes the changes of the callee
+      SNAPSHOTS.with(|snapshots| verify_ownership(&caller, snapshots.borrow().last().unwrap(), &callee_infos))?;
+      INNER_INSTRUCTIONS.with(|inner| inner.borrow_mut().push(instruction.clone()));
+      execute_program(&instruction.program_id, &callee_infos, &instruction.data)?;
+      let after = snapshot(&callee_infos)?;
+      SNAPSHOTS.with(|snapshots| {
+         let mut snapshots = snapshots.borrow_mut();
+         for state in snapshots.last_mut().unwrap() {
+            if let Some(changed) = after.iter().find(|changed| changed.key == state.key) {
+               *state = changed.clone();
+            }
+         }
+      });
+      Ok(())
+   }
+
+   fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+      let clock = CLOCK.with(|clock| clock.borrow().clone());
+      unsafe { *(var_addr as *mut Clock) = clock };
+      0
+   }
+
+   fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+      unsafe { *(var_addr as *mut Rent) = Rent::default() };
+      0
+   }
+
+   fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+      RETURN_DATA.with(|data| data.borrow().clone())
+   }
+
+   fn sol_set_return_data(&self, data: &[u8]) {
+      let program_id = CALL_STACK.with(|stack| stack.borrow().last().copied().unwrap_or_default());
+      RETURN_DATA.with(|return_data| *return_data.borrow_mut() = Some((program_id, data.to_vec())));
+   }
+}
+
+/*
+   An account while an instruction runs. The layout mimics the serialized input of the runtime:
+   the original data length sits right before the key and the current length right before the data,
+   and the data can grow by MAX_PERMITTED_DATA_INCREASE, so AccountInfo::realloc works as on-chain.
+*/
+#[repr(C)]
+struct SerializedKey {
+   original_data_len: u32,
+   key: Pubkey,
+}
+
+struct Slot {
+   key: Box<SerializedKey>,
+   lamports: Box<u64>,
+   owner: Box<Pubkey>,
+   buffer: Vec<u8>,
+   executable: bool,
+}
+
+impl Slot {
+   fn new(key: Pubkey, account: Account) -> Self {
+      let len = account.data.len();
+      let mut buffer = vec![0u8; 8 + len + MAX_PERMITTED_DATA_INCREASE];
+      buffer[..8].copy_from_slice(&(len as u64).to_le_bytes());
+      buffer[8..8 + len].copy_from_slice(&account.data);
+      Slot {
+         key: Box::new(SerializedKey { original_data_len: len as u32, key }),
+         lamports: Box::new(account.lamports),
+         owner: Box::new(account.owner),
+         buffer,
+         executable: account.executable,
+      }
+   }
+
+   fn account_info(&mut self, is_signer: bool, is_writable: bool) -> AccountInfo<'_> {
+      let len = self.key.original_data_len as usize;
+      let data = unsafe { std::slice::from_raw_parts_mut(self.buffer.as_mut_ptr().add(8), len) };
+      AccountInfo::new(
+         &self.key.key,
+         is_signer,
+         is_writable,
+         &mut self.lamports,
+         data,
+         &self.owner,
+         self.executable,
+         0,
+      )
+   }
+
+   fn into_account(self, data_len: usize) -> Account {
+      Account {
+         lamports: *self.lamports,
+         data: self.buffer[8..8 + data_len].to_vec(),
+         owner: *self.owner,
+         executable: self.executable,
+      }
+   }
+}
+
+pub struct Svm {
+   accounts: HashMap<Pubkey, Account>,
+   clock: Clock,
+}
+
+impl Default for Svm {
+   fn default() -> Self {
+      Self::new()
+   }
+}
+
+impl Svm {
+   pub fn new() -> Self {
+      static STUBS: Once = Once::new();
+      STUBS.call_once(|| {
+         program_stubs::set_syscall_stubs(Box::new(Stubs));
+      });
+
+      let mut svm = Svm {
+         accounts: HashMap::new(),
+         clock: Clock { slot: 1, unix_timestamp: 1_700_000_000, ..Clock::default() },
+      };
+      for program_id in [system_program::ID, spl_token::ID, associated_token::ID, lending::ID] {
+         svm.set_account(program_id, Account { lamports: 1, executable: true, ..Account::default() });
+      }
+      svm
+   }
+
+   pub fn account(&self, key: &Pubkey) -> Option<&Account> {
+      self.accounts.get(key)
+   }
+
+   pub fn accounts(&self) -> impl Iterator<Item = (&Pubkey, &Account)> {
+      self.accounts.iter()
+   }
+
+   pub fn set_account(&mut self, key: Pubkey, account: Account) {
+      self.accounts.insert(key, account);
+   }
+
+   pub fn airdrop(&mut self, key: &Pubkey, lamports: u64) {
+      let account = self.accounts.entry(*key).or_insert_with(|| Account { owner: system_program::ID, ..Account::default() });
+      account.lamports += lamports;
+   }
+
+   /*
+      Programs run natively instead of through the upgradeable loader, so their ProgramData account is written here:
+      the bincode encoding of UpgradeableLoaderState::ProgramData { slot, upgrade_authority_address }.
+   */
+   pub fn set_upgrade_authority(&mut self, program_id: &Pubkey, authority: Option<Pubkey>) {
+      let mut data = 3u32.to_le_bytes().to_vec();
+      data.extend_from_slice(&0u64.to_le_bytes());
+      match authority {
+         Some(authority) => {
+            data.push(1);
+            data.extend_from_slice(authority.as_ref());
+         }
+         None => data.push(0),
+      }
+      let (program_data, _) = Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::ID);
+      self.set_account(program_data, Account { lamports: 1, data, owner: bpf_loader_upgradeable::ID, executable: false });
+   }
+
+   pub fn add_program(&mut self, program_id: Pubkey, processor: Processor) {
+      add_program(program_id, processor);
+      self.set_account(program_id, Account { lamports: 1, executable: true, ..Account::default() });
+   }
+
+   pub fn clock(&self) -> &Clock {
+      &self.clock
+   }
+
+   pub fn now(&self) -> i64 {
+      self.clock.unix_timestamp
+   }
+
+   // Moves the clock forward, one slot every 400ms like the validator
+   pub fn warp(&mut self, seconds: i64) {
+      self.clock.unix_timestamp += seconds;
+      self.clock.slot += (seconds.max(0) as u64 * 5).div_ceil(2);
+   }
+
+   /*
+      Runs the instructions one after the other. Like a transaction, either all of them succeed
+      and their changes are stored, or none of the changes are kept.
+      Signers are the wallets that signed the transaction, the first one pays the fees like on-chain.
+      Signatures themselves are not checked, but each of them counts in the size of the transaction.
+   */
+   pub fn process_transaction(&mut self, instructions: &[Instruction], signers: &[Pubkey]) -> Result<TransactionMeta, TransactionError> {
+      CLOCK.with(|clock| *clock.borrow_mut() = self.clock.clone());
+      LOGS.with(|logs| logs.borrow_mut().clear());
+      INNER_INSTRUCTIONS.with(|inner| inner.borrow_mut().clear());
+      let refused = |error| TransactionError { instruction: None, error, logs: LOGS.with(|logs| logs.take()) };
+
+      let size = transaction_size(instructions, signers);
+      if size > MAX_TRANSACTION_SIZE {
+         log(format!("encoded transaction too large: {size} bytes (max: {MAX_TRANSACTION_SIZE})"));
+         return Err(refused(ProgramError::InvalidArgument));
+      }
+
+      let mut accounts = self.accounts.clone();
+      for (index, instruction) in instructions.iter().enumerate() {
+         RETURN_DATA.with(|data| *data.borrow_mut() = None);
+         if let Err(error) = Self::execute(&mut accounts, instruction, signers) {
+            return Err(TransactionError { instruction: Some(index), error, logs: LOGS.with(|logs| logs.take()) });
+         }
+      }
+      self.verify_rent(&accounts).map_err(refused)?;
+      self.accounts = accounts;
+
+      Ok(TransactionMeta {
+         logs: LOGS.with(|logs| logs.take()),
+         inner_instructions: INNER_INSTRUCTIONS.with(|inner| inner.take()),
+      })
+   }
+
+   /*
+      The rent rules of the validator on the accounts a transaction changed: an account ends with no lamports
+      or rent exempt. One that already wasn't exempt may stay so, as long as its size doesn't change
+      and its balance doesn't grow.
+   */
+   fn verify_rent(&self, accounts: &HashMap<Pubkey, Account>) -> ProgramResult {
+      let rent = Rent::default();
+      for (key, account) in accounts {
+         let previous = self.accounts.get(key);
+         if previous == Some(account) || account.lamports == 0 || rent.is_exempt(account.lamports, account.data.len()) {
+            continue;
+         }
+         let was_paying_rent = previous.is_some_and(|previous| {
+            previous.lamports > 0 && !rent.is_exempt(previous.lamports, previous.data.len()) && previous.data.len() == account.data.len()
+         });
+         if !was_paying_rent || account.lamports > previous.unwrap().lamports {
+            log(format!("Transaction results in an account ({key}) with insufficient funds for rent"));
+            return Err(ProgramError::AccountNotRentExempt);
+         }
+      }
+      Ok(())
+   }
+
+   fn execute(accounts: &mut HashMap<Pubkey, Account>, instruction: &Instruction, signers: &[Pubkey]) -> ProgramResult {
+      let mut keys: Vec<Pubkey> = Vec::new();
+      for meta in &instruction.accounts {
+         if meta.is_signer && !signers.contains(&meta.pubkey) {
+            log(format!("{} has to sign the transaction", meta.pubkey));
+            return Err(ProgramError::MissingRequiredSignature);
+         }
+         if !keys.contains(&meta.pubkey) {
+            keys.push(meta.pubkey);
+         }
+      }
+      let writable: Vec<bool> = keys
+         .iter()
+         .map(|key| instruction.accounts.iter().any(|meta| meta.pubkey == *key && meta.is_writable))
+         .collect();
+
+      let mut slots: Vec<Slot> = keys
+         .iter()
+         .map(|key| Slot::new(*key, accounts.get(key).cloned().unwrap_or_else(|| Account { owner: system_program::ID, ..Account::default() })))
+         .collect();
+
+      let (result, data_lens) = {
+         let infos: Vec<AccountInfo> = slots
+            .iter_mut()
+            .zip(&keys)
+            .zip(&writable)
+            .map(|((slot, key), is_writable)| slot.account_info(signers.contains(key), *is_writable))
+            .collect();
+         // Accounts that appear twice in the instruction share the same AccountInfo
+         let instruction_infos: Vec<AccountInfo> = instruction
+            .accounts
+            .iter()
+            .map(|meta| infos[keys.iter().position(|key| *key == meta.pubkey).unwrap()].clone())
+            .collect();
+
+         let result = execute_program(&instruction.program_id, &instruction_infos, &instruction.data);
+         let data_lens: Vec<usize> = infos.iter().map(|info| info.data_len()).collect();
+         (result, data_lens)
+      };
+      result?;
+
+      for (((slot, key), data_len), is_writable) in slots.into_iter().zip(keys).zip(data_lens).zip(writable) {
+         let account = slot.into_account(data_len);
+         let previous = accounts.get(&key).cloned().unwrap_or_else(|| Account { owner: system_program::ID, ..Account::default() });
+         if account != previous {
+            if !is_writable {
+               log(format!("{key} is read-only but was modified"));
+               return Err(ProgramError::InvalidArgument);
+            }
+            accounts.insert(key, account);
+         }
+      }
+      Ok(())
+   }
+}
+
+/*
+   The size of the legacy transaction the validator would get: its signatures, prefixed by their count, then its message.
+   Counts below 128 take one byte.
+*/
+fn transaction_size(instructions: &[Instruction], signers: &[Pubkey]) -> usize {
+   let message = Message::new(instructions, signers.first());
+   let signatures = message.header.num_required_signatures as usize;
+   1 + signatures * 64 + message.serialize().len()
+}
+
+/*
+   The part of the system program used by Anchor: creating, funding, allocating and assigning accounts.
+   Instructions are bincode encoded, a u32 tag followed by the fields.
+*/
+fn process_system_instruction(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+   let read_u64 = |offset: usize| -> Result<u64, ProgramError> {
+      let bytes = data.get(offset..offset + 8).ok_or(ProgramError::InvalidInstructionData)?;
+      Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+   };
+   let read_pubkey = |offset: usize| -> Result<Pubkey, ProgramError> {
+      let bytes = data.get(offset..offset + 32).ok_or(ProgramError::InvalidInstructionData)?;
+      Ok(Pubkey::try_from(bytes).unwrap())
+   };
+   let tag = u32::from_le_bytes(data.get(..4).ok_or(ProgramError::InvalidInstructionData)?.try_into().unwrap());
+   let account = |index: usize| accounts.get(index).ok_or(ProgramError::NotEnoughAccountKeys);
+
+   match tag {
+      // CreateAccount { lamports, space, owner }
+      0 => {
+         let (from, to) = (account(0)?, account(1)?);
+         if to.lamports() > 0 || !to.data_is_empty() || *to.owner != system_program::ID {
+            log(format!("Create Account: account {} already in use", to.key));
+            return Err(ProgramError::Custom(0));
+         }
+         transfer(from, to, read_u64(4)?)?;
+         allocate(to, read_u64(12)?)?;
+         assign(to, &read_pubkey(20)?)
+      }
+      // Assign { owner }
+      1 => assign(account(0)?, &read_pubkey(4)?),
+      // Transfer { lamports }
+      2 => transfer(account(0)?, account(1)?, read_u64(4)?),
+      // Allocate { space }
+      8 => allocate(account(0)?, read_u64(4)?),
+      _ => Err(ProgramError::InvalidInstructionData),
+   }
+}
+
+fn transfer(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> ProgramResult {
+   if !from.is_signer {
+      return Err(ProgramError::MissingRequiredSignature);
+   }
+   let from_lamports = from.lamports().checked_sub(lamports).ok_or(ProgramError::InsufficientFunds)?;
+   let to_lamports = to.lamports().checked_add(lamports).ok_or(ProgramError::ArithmeticOverflow)?;
+   **from.try_borrow_mut_lamports()? = from_lamports;
+   **to.try_borrow_mut_lamports()? = to_lamports;
+   Ok(())
+}
+
+fn allocate(account: &AccountInfo, space: u64) -> ProgramResult {
+   if !account.is_signer {
+      return Err(ProgramError::MissingRequiredSignature);
+   }
+   if !account.data_is_empty() || *account.owner != system_program::ID {
+      return Err(ProgramError::Custom(0));
+   }
+   account.realloc(space as usize, true)
+}
+
+fn assign(account: &AccountInfo, owner: &Pubkey) -> ProgramResult {
+   if *account.owner == *owner {
+      return Ok(());
+   }
+   if !account.is_signer {
+      return Err(ProgramError::MissingRequiredSignature);
+   }
+   account.assign(owner);
+   Ok(())
+}
@@ -0,0 +1,83 @@
+use lending::constants::{DEFAULT_BORROW_WEIGHT, DEFAULT_LIQUIDATION_CLOSE_FACTOR, DEFAULT_ORACLE_MAX_AGE, MAX_INTEREST_RATE, MAX_LIQUIDATION_BONUS};
+use lending::error::ErrCode;
+use lending_client::{bank_address, Bank, BankAccounts, BankConfig, InitBankBuilder};
+use lending_tests::*;
+
+fn config(liquidation_threshold: u64, liquidation_bonus: u64, interest_rate: u64) -> BankConfig {
+   BankConfig {
+      liquidation_threshold,
+      liquidation_bonus,
+      liquidation_close_factor: DEFAULT_LIQUIDATION_CLOSE_FACTOR,
+      max_ltv: liquidation_threshold,
+      interest_rate,
+      oracle_max_age: DEFAULT_ORACLE_MAX_AGE,
+      deposit_cap: 0,
+      borrow_cap: 0,
+      borrow_weight_bps: DEFAULT_BORROW_WEIGHT,
+   }
+}
+
+#[test]
+fn only_the_upgrade_authority_lists_banks() {
+   let mut env = TestEnv::new();
+   let mint = env.create_mint(6);
+   let oracle = env.create_oracle(1);
+   let stranger = env.user().wallet;
+
+   let ix = InitBankBuilder::new(stranger, &BankAccounts::new(mint, oracle), 8_000, 7_500, 5).instruction();
+   assert_eq!(error_code(env.process(ix, &[stranger])), Some(ErrCode::Unauthorized));
+
+   // Neither can anyone once the program is immutable
+   env.svm.set_upgrade_authority(&lending::ID, None);
+   assert_eq!(error_code(env.init_bank(&mint, &oracle, 8_000, 7_500, 5)), Some(ErrCode::Unauthorized));
+
+   let admin = env.admin;
+   env.svm.set_upgrade_authority(&lending::ID, Some(admin));
+   env.init_bank(&mint, &oracle, 8_000, 7_500, 5).unwrap();
+   assert_eq!(env.read_account::<Bank>(&bank_address(&mint)).authority, admin);
+}
+
+#[test]
+fn interest_rate_is_bounded() {
+   let mut env = TestEnv::new();
+   let mint = env.create_mint(6);
+   let oracle = env.create_oracle(1);
+   assert_eq!(error_code(env.init_bank(&mint, &oracle, 8_000, 7_500, MAX_INTEREST_RATE + 1)), Some(ErrCode::InvalidBankConfig));
+
+   let market = env.market(1_000);
+   let admin = env.admin;
+   let result = env.update_bank_config(&market.usdc, &admin, config(8_000, 500, MAX_INTEREST_RATE + 1));
+   assert_eq!(error_code(result), Some(ErrCode::InvalidBankConfig));
+
+   // The highest rate still accrues after a year without any instruction
+   env.update_bank_config(&market.usdc, &admin, config(8_000, 500, MAX_INTEREST_RATE)).unwrap();
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 100).unwrap();
+   env.warp(365 * 24 * 60 * 60);
+   env.refresh_bank(&market.usdc).unwrap();
+   env.update_bank_config(&market.usdc, &admin, config(8_000, 500, 5)).unwrap();
+}
+
+#[test]
+fn oracle_max_age_is_positive() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+   let admin = env.admin;
+   let result = env.update_bank_config(&market.usdc, &admin, BankConfig { oracle_max_age: 0, ..config(8_000, 500, 5) });
+   assert_eq!(error_code(result), Some(ErrCode::InvalidBankConfig));
+
+   env.update_bank_config(&market.usdc, &admin, BankConfig { oracle_max_age: 1, ..config(8_000, 500, 5) }).unwrap();
+   assert_eq!(env.bank_state(&market.usdc).oracle_max_age, 1);
+}
+
+#[test]
+fn liquidation_bonus_is_bounded_by_the_threshold() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+   let admin = env.admin;
+
+   assert_eq!(error_code(env.update_bank_config(&market.sol, &admin, config(5_000, MAX_LIQUIDATION_BONUS + 1, 5))), Some(ErrCode::InvalidBankConfig));
+   // 9_000 * 112% is more than the collateral
+   assert_eq!(error_code(env.update_bank_config(&market.sol, &admin, config(9_000, 1_200, 5))), Some(ErrCode::InvalidBankConfig));
+   env.update_bank_config(&market.sol, &admin, config(9_000, 1_100, 5)).unwrap();
+}
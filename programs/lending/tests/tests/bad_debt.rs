@@ -0,0 +1,51 @@
+use lending::error::ErrCode;
+use lending::events::BadDebtSocialized;
+use lending_tests::*;
+
+// A user with 100 SOL and 11_000 USDC of debt after SOL falls to $50, and a liquidator with USDC
+fn crashed() -> (TestEnv, Market, TestUser, TestUser) {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 11_000).unwrap();
+   env.set_oracle_price(market.sol.oracle, 50);
+
+   let liquidator = env.user();
+   env.fund(&liquidator, &market.usdc, 20_000);
+   (env, market, user, liquidator)
+}
+
+#[test]
+fn debt_without_collateral_is_written_off_against_the_depositors() {
+   let (mut env, market, user, liquidator) = crashed();
+   // $4_800 + 5% is more than the $5_000 of collateral, the liquidator takes all of it and 6_200 USDC of debt remain
+   env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 4_800).unwrap();
+   assert_eq!(env.wallet_balance(&liquidator, &market.sol), 100);
+
+   let meta = env.socialize_loss(&market.usdc, &user).unwrap();
+   let event = &events::<BadDebtSocialized>(&meta)[0];
+   assert_eq!((event.owner, event.amount, event.shares), (user.wallet, 6_200, 6_200));
+   assert_eq!((event.total_deposits, event.total_borrowed), (43_800, 0));
+
+   // The user is gone from the bank and healthy again
+   let user_state = env.user_state(&user);
+   assert!(user_state.positions.iter().all(|position| !position.is_active()));
+   assert_eq!((user_state.health_factor, user_state.unhealthy_since), (u64::MAX, 0));
+
+   // The lender's shares are worth what the treasury holds
+   assert_eq!(env.balance(&market.usdc.treasury), 43_800);
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user, liquidator]).unwrap();
+   env.withdraw(&market.lender, &market.usdc, 43_800).unwrap();
+}
+
+#[test]
+fn only_users_without_collateral_have_bad_debt() {
+   let (mut env, market, user, liquidator) = crashed();
+
+   assert_eq!(error_code(env.socialize_loss(&market.usdc, &user)), Some(ErrCode::NotBadDebt));
+   env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 4_800).unwrap();
+   // Nothing is owed to the SOL bank
+   assert_eq!(error_code(env.socialize_loss(&market.sol, &user)), Some(ErrCode::NotBadDebt));
+   env.socialize_loss(&market.usdc, &user).unwrap();
+   assert_eq!(error_code(env.socialize_loss(&market.usdc, &user)), Some(ErrCode::NotBadDebt));
+}
@@ -0,0 +1,113 @@
+use lending::error::ErrCode;
+use lending::events::BorrowEvent;
+use lending_tests::*;
+
+#[test]
+fn borrow_is_paid_by_the_treasury() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+
+   let meta = env.borrow(&user, &market.usdc, 10_000).unwrap();
+
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 10_000);
+   assert_eq!(env.balance(&market.usdc.treasury), 40_000);
+
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!((bank.total_borrowed, bank.total_borrowed_shares), (10_000, 10_000));
+   let user_state = env.user_state(&user);
+   assert_eq!(user_state.position(&market.usdc.bank).unwrap().borrowed_shares, 10_000);
+   // $15_000 * 80% / $10_000
+   assert_eq!(user_state.health_factor, 12_000);
+
+   let event = &events::<BorrowEvent>(&meta)[0];
+   assert_eq!((event.amount, event.shares, event.health_factor), (10_000, 10_000, 12_000));
+}
+
+#[test]
+fn borrow_is_limited_by_the_max_ltv() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 11_251)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &market.usdc, 11_250).unwrap();
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 1)), Some(ErrCode::BorrowLimitExceeded));
+}
+
+#[test]
+fn a_volatile_borrow_uses_more_of_the_borrow_limit_than_a_stable_one() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   // At $1 like USDC, but its debt counts twice
+   let volatile = env.bank().price(1).borrow_weight(20_000).build();
+   env.fund(&market.lender, &volatile, 50_000);
+   env.deposit(&market.lender, &volatile, 50_000).unwrap();
+
+   // 100 SOL at $150 with a 75% max LTV: 11_250 of USDC, or half of it of the volatile asset
+   let stable_user = env.borrower(&market, 100);
+   env.borrow(&stable_user, &market.usdc, 11_250).unwrap();
+   let volatile_user = env.borrower(&market, 100);
+   assert_eq!(error_code(env.borrow(&volatile_user, &volatile, 5_626)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&volatile_user, &volatile, 5_625).unwrap();
+
+   // Half the debt value, and as close to liquidation: 12_000 / 11_250
+   assert_eq!((env.health(&stable_user).ltv(), env.health(&volatile_user).ltv()), (7_500, 3_750));
+   assert_eq!(env.user_state(&stable_user).health_factor, 10_666);
+   assert_eq!(env.user_state(&volatile_user).health_factor, 10_666);
+}
+
+#[test]
+fn borrow_needs_the_accounts_of_every_other_position() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+
+   // Without the SOL bank and oracle the collateral can't be valued
+   let mut ix = env.borrow_ix(&user, &market.usdc, 1_000);
+   ix.accounts.truncate(ix.accounts.len() - 2);
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::MissingPositionAccounts));
+
+   // Nor with the oracle of another bank
+   let mut ix = env.borrow_ix(&user, &market.usdc, 1_000);
+   let last = ix.accounts.len() - 1;
+   ix.accounts[last].pubkey = market.usdc.oracle;
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::InvalidOracle));
+}
+
+#[test]
+fn borrow_with_a_stale_oracle_is_rejected() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+
+   // The clock moves but the oracles are not published again
+   env.svm.warp(lending::constants::DEFAULT_ORACLE_MAX_AGE as i64 + 1);
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 1_000)), Some(ErrCode::StaleOracle));
+}
+
+#[test]
+fn borrow_cap_limits_the_total_borrowed() {
+   let mut env = TestEnv::new();
+   let sol = env.bank().price(150).build();
+   let usdc = env.bank().price(1).caps(0, 5_000).build();
+   let lender = env.user();
+   env.fund(&lender, &usdc, 50_000);
+   env.deposit(&lender, &usdc, 50_000).unwrap();
+   let user = env.user();
+   env.fund(&user, &sol, 100);
+   env.deposit(&user, &sol, 100).unwrap();
+
+   assert_eq!(error_code(env.borrow(&user, &usdc, 5_001)), Some(ErrCode::BorrowCapExceeded));
+   env.borrow(&user, &usdc, 5_000).unwrap();
+}
+
+#[test]
+fn borrow_is_rejected_while_the_bank_is_paused() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+
+   env.set_bank_paused(&market.usdc, true).unwrap();
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 1_000)), Some(ErrCode::BankPaused));
+}
@@ -0,0 +1,69 @@
+use lending::error::ErrCode;
+use lending_client::{decode_bank, decode_user, PricedBank};
+use lending_tests::*;
+
+// The banks of the market as lending_client sees them from the raw accounts
+fn priced_banks(env: &TestEnv, banks: &[&TestBank]) -> Vec<PricedBank> {
+   banks
+      .iter()
+      .map(|bank| {
+         let state = decode_bank(&env.svm.account(&bank.bank).unwrap().data).unwrap();
+         let oracle = env.svm.account(&bank.oracle).unwrap();
+         lending_client::priced_bank(bank.bank, &state, &[&oracle.data], env.now()).unwrap()
+      })
+      .collect()
+}
+
+fn user_state(env: &TestEnv, user: &TestUser) -> lending::state::User {
+   decode_user(&env.svm.account(&user.account).unwrap().data).unwrap()
+}
+
+#[test]
+fn client_max_borrow_is_what_the_program_allows() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 5_000).unwrap();
+   // Interest makes the share price uneven, so the rounding has to match too
+   env.warp(90 * 24 * 60 * 60);
+
+   let banks = priced_banks(&env, &[&market.sol, &market.usdc]);
+   let max = lending_client::max_borrow(&user_state(&env, &user), &banks, None, &market.usdc.bank).unwrap();
+   assert!(max > 0 && max < 11_250 - 5_000);
+
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, max + 1)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &market.usdc, max).unwrap();
+
+   let banks = priced_banks(&env, &[&market.sol, &market.usdc]);
+   let user_state = user_state(&env, &user);
+   assert_eq!(lending_client::health_factor(&user_state, &banks, None).unwrap(), user_state.health_factor);
+}
+
+#[test]
+fn client_max_withdraw_is_what_the_program_allows() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 5_000).unwrap();
+   env.warp(90 * 24 * 60 * 60);
+
+   let banks = priced_banks(&env, &[&market.sol, &market.usdc]);
+   let max = lending_client::max_withdraw(&user_state(&env, &user), &banks, None, &market.sol.bank).unwrap();
+   assert!(max > 0 && max < 100);
+
+   assert_eq!(error_code(env.withdraw(&user, &market.sol, max + 1)), Some(ErrCode::WithdrawExceedsBorrowLimit));
+   env.withdraw(&user, &market.sol, max).unwrap();
+}
+
+#[test]
+fn client_builders_derive_the_accounts_of_the_program() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+
+   let bank = decode_bank(&env.svm.account(&market.usdc.bank).unwrap().data).unwrap();
+   assert_eq!(lending_client::BankAccounts::from_bank(&bank), market.usdc.accounts());
+   assert_eq!(market.usdc.bank, bank_address(&market.usdc.mint));
+   assert_eq!(market.lender.account, user_address(&market.lender.wallet));
+   // A user account is not a bank
+   assert!(decode_bank(&env.svm.account(&market.lender.account).unwrap().data).is_err());
+}
@@ -0,0 +1,84 @@
+use lending::error::ErrCode;
+use lending::events::LiquidationEvent;
+use lending_tests::*;
+
+const SOL: u64 = 1_000_000_000;
+const USDC: u64 = 1_000_000;
+const ETH: u64 = 1_000_000_000_000_000_000;
+
+// A bank of a mint with `decimals` decimals at `price`, with `liquidity` base units a lender deposited
+fn bank(env: &mut TestEnv, decimals: u8, price: u64, liquidity: u64) -> TestBank {
+   let bank = env.bank().decimals(decimals).price(price).build();
+   if liquidity > 0 {
+      let lender = env.user();
+      env.fund(&lender, &bank, liquidity);
+      env.deposit(&lender, &bank, liquidity).unwrap();
+   }
+   bank
+}
+
+fn depositor(env: &mut TestEnv, bank: &TestBank, amount: u64) -> TestUser {
+   let user = env.user();
+   env.fund(&user, bank, amount);
+   env.deposit(&user, bank, amount).unwrap();
+   user
+}
+
+#[test]
+fn banks_cache_the_decimals_of_their_mint() {
+   let mut env = TestEnv::new();
+   for decimals in [0, 6, 9, 18] {
+      let bank = bank(&mut env, decimals, 1, 0);
+      assert_eq!(env.bank_state(&bank).decimals, decimals);
+   }
+}
+
+#[test]
+fn borrow_limit_compares_values_not_base_units() {
+   let mut env = TestEnv::new();
+   let sol = bank(&mut env, 9, 150, 0);
+   let usdc = bank(&mut env, 6, 1, 10_000 * USDC);
+   let user = depositor(&mut env, &sol, 10 * SOL);
+
+   // 10 SOL * $150 * 75%
+   assert_eq!(error_code(env.borrow(&user, &usdc, 1_125 * USDC + 1)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &usdc, 1_125 * USDC).unwrap();
+   // 10 SOL * $150 * 80% / $1_125
+   assert_eq!(env.user_state(&user).health_factor, 10_666);
+}
+
+#[test]
+fn eighteen_decimals_collateral_borrows_a_mint_without_decimals() {
+   let mut env = TestEnv::new();
+   let eth = bank(&mut env, 18, 3_000, 0);
+   let ticket = bank(&mut env, 0, 50, 100);
+   let user = depositor(&mut env, &eth, ETH);
+
+   // 1 ETH * $3_000 * 75% is 45 tickets at $50
+   assert_eq!(error_code(env.borrow(&user, &ticket, 46)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &ticket, 45).unwrap();
+
+   // At the limit not even the smallest unit of ETH can leave, the rest of the collateral is valued rounding down
+   assert_eq!(error_code(env.withdraw(&user, &eth, 1)), Some(ErrCode::WithdrawExceedsBorrowLimit));
+}
+
+#[test]
+fn liquidation_seizes_collateral_in_its_own_decimals() {
+   let mut env = TestEnv::new();
+   let sol = bank(&mut env, 9, 150, 0);
+   let usdc = bank(&mut env, 6, 1, 10_000 * USDC);
+   let user = depositor(&mut env, &sol, 10 * SOL);
+   env.borrow(&user, &usdc, 1_125 * USDC).unwrap();
+
+   // 10 SOL * $100 * 80% = $800 < $1_125
+   env.set_oracle_price(sol.oracle, 100);
+   let liquidator = env.user();
+   env.fund(&liquidator, &usdc, 1_000 * USDC);
+   let meta = env.liquidate(&liquidator, &user, &sol, &usdc, 500 * USDC).unwrap();
+
+   // $500 + 5% bonus at $100 per SOL
+   let event = &events::<LiquidationEvent>(&meta)[0];
+   assert_eq!((event.repaid_amount, event.seized_amount), (500 * USDC, 5_250_000_000));
+   assert_eq!(env.wallet_balance(&liquidator, &sol), 5_250_000_000);
+   assert_eq!(env.wallet_balance(&liquidator, &usdc), 500 * USDC);
+}
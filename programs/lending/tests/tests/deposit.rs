@@ -0,0 +1,106 @@
+use lending::error::ErrCode;
+use lending::events::DepositEvent;
+use lending_tests::*;
+
+#[test]
+fn deposit_moves_the_tokens_and_mints_shares() {
+   let mut env = TestEnv::new();
+   let usdc = env.bank().build();
+   let user = env.user();
+   env.fund(&user, &usdc, 1_000);
+
+   let meta = env.deposit(&user, &usdc, 600).unwrap();
+
+   assert_eq!(env.wallet_balance(&user, &usdc), 400);
+   assert_eq!(env.balance(&usdc.treasury), 600);
+
+   let bank = env.bank_state(&usdc);
+   assert_eq!(bank.total_deposits, 600);
+   assert_eq!(bank.total_deposit_shares, 600);
+   let position = *env.user_state(&user).position(&usdc.bank).unwrap();
+   assert_eq!(position.deposit_shares, 600);
+
+   let events = events::<DepositEvent>(&meta);
+   assert_eq!(events.len(), 1);
+   assert_eq!(events[0].amount, 600);
+   assert_eq!(events[0].shares, 600);
+   assert_eq!(events[0].owner, user.wallet);
+}
+
+#[test]
+fn deposits_in_two_banks_take_two_positions() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.fund(&user, &market.usdc, 1_000);
+   env.deposit(&user, &market.usdc, 1_000).unwrap();
+
+   let user_state = env.user_state(&user);
+   assert_eq!(user_state.position(&market.sol.bank).unwrap().deposit_shares, 100);
+   assert_eq!(user_state.position(&market.usdc.bank).unwrap().deposit_shares, 1_000);
+   assert_eq!(user_state.health_factor, u64::MAX);
+}
+
+#[test]
+fn deposit_rejects_zero() {
+   let mut env = TestEnv::new();
+   let usdc = env.bank().build();
+   let user = env.user();
+   env.fund(&user, &usdc, 1_000);
+
+   assert_eq!(error_code(env.deposit(&user, &usdc, 0)), Some(ErrCode::InvalidAmount));
+}
+
+#[test]
+fn deposit_rejects_an_amount_worth_less_than_a_share() {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+
+   // After a year of interest a share is worth 2_051_270 / 2_000_000 tokens
+   env.warp(365 * 24 * 60 * 60);
+   env.fund(&user, &market.usdc, 2);
+   assert_eq!(error_code(env.deposit(&user, &market.usdc, 1)), Some(ErrCode::InvalidAmount));
+   env.deposit(&user, &market.usdc, 2).unwrap();
+   assert_eq!(env.user_state(&user).position(&market.usdc.bank).unwrap().deposit_shares, 1);
+}
+
+#[test]
+fn deposit_is_rejected_while_the_bank_is_paused() {
+   let mut env = TestEnv::new();
+   let usdc = env.bank().build();
+   let user = env.user();
+   env.fund(&user, &usdc, 1_000);
+
+   env.set_bank_paused(&usdc, true).unwrap();
+   assert_eq!(error_code(env.deposit(&user, &usdc, 100)), Some(ErrCode::BankPaused));
+
+   env.set_bank_paused(&usdc, false).unwrap();
+   env.deposit(&user, &usdc, 100).unwrap();
+}
+
+#[test]
+fn deposit_cap_limits_the_total_deposits() {
+   let mut env = TestEnv::new();
+   let usdc = env.bank().caps(1_000, 0).build();
+   let user = env.user();
+   env.fund(&user, &usdc, 2_000);
+
+   env.deposit(&user, &usdc, 600).unwrap();
+   assert_eq!(error_code(env.deposit(&user, &usdc, 401)), Some(ErrCode::DepositCapExceeded));
+   env.deposit(&user, &usdc, 400).unwrap();
+   assert_eq!(env.bank_state(&usdc).total_deposits, 1_000);
+}
+
+#[test]
+fn deposit_without_the_tokens_fails_in_the_token_program() {
+   let mut env = TestEnv::new();
+   let usdc = env.bank().build();
+   let user = env.user();
+   env.fund(&user, &usdc, 100);
+
+   let result = env.deposit(&user, &usdc, 101);
+   assert!(result.is_err());
+   assert_eq!(env.bank_state(&usdc).total_deposits, 0);
+}
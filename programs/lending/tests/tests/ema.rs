@@ -0,0 +1,120 @@
+use lending::constants::MAX_EMA_WINDOW;
+use lending::error::ErrCode;
+use lending::events::{BankConfigUpdated, BankRefreshed};
+use lending_tests::*;
+
+const WINDOW: u64 = 60 * 60;
+
+#[test]
+fn ema_window_is_configured_by_the_authority() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+
+   assert_eq!(error_code(env.set_bank_ema(&market.sol, MAX_EMA_WINDOW + 1)), Some(ErrCode::InvalidBankConfig));
+   let meta = env.set_bank_ema(&market.sol, WINDOW).unwrap();
+   assert_eq!(events::<BankConfigUpdated>(&meta)[0].ema_window, WINDOW);
+   assert_eq!(env.bank_state(&market.sol).ema_window, WINDOW);
+}
+
+#[test]
+fn refresh_folds_the_price_into_the_moving_average() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+   env.set_bank_ema(&market.sol, 1_000).unwrap();
+
+   // The first refresh starts the average at the price
+   env.refresh_bank(&market.sol).unwrap();
+   let bank = env.bank_state(&market.sol);
+   assert_eq!((bank.ema_price, bank.ema_updated), (150 * 100_000_000, env.now()));
+
+   // $250 for 10% of the window
+   env.warp(100);
+   env.set_oracle_price(market.sol.oracle, 250);
+   let meta = env.refresh_bank(&market.sol).unwrap();
+   let event = &events::<BankRefreshed>(&meta)[0];
+   assert_eq!((event.price, event.ema_price), (250 * 100_000_000, 160 * 100_000_000));
+   assert_eq!(env.bank_state(&market.sol).ema_price, 160 * 100_000_000);
+}
+
+#[test]
+fn a_price_spike_does_not_raise_the_borrow_limit() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   env.set_bank_ema(&market.sol, WINDOW).unwrap();
+   env.refresh_bank(&market.sol).unwrap();
+   let user = env.borrower(&market, 100);
+
+   // Collateral is valued at the $150 average, not the $300 spike
+   env.set_oracle_price(market.sol.oracle, 300);
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 11_251)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &market.usdc, 11_250).unwrap();
+
+   // Without a window the spot price counts
+   env.set_bank_ema(&market.sol, 0).unwrap();
+   env.borrow(&user, &market.usdc, 11_250).unwrap();
+}
+
+#[test]
+fn debt_is_valued_at_the_higher_of_the_price_and_its_average() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+   env.borrower(&market, 1_000);
+   env.set_bank_ema(&market.sol, WINDOW).unwrap();
+   env.refresh_bank(&market.sol).unwrap();
+
+   let user = env.user();
+   env.fund(&user, &market.usdc, 100_000);
+   env.deposit(&user, &market.usdc, 100_000).unwrap();
+   env.borrow(&user, &market.sol, 50).unwrap();
+
+   // $80_000 of weighted collateral for 50 SOL still valued at $150
+   env.set_oracle_price(market.sol.oracle, 100);
+   env.refresh_user(&user).unwrap();
+   assert_eq!(env.user_state(&user).health_factor, 106_666);
+
+   // Nothing recorded that $100 held for the window, it only takes the average 10% of the way: $145
+   env.warp(WINDOW as i64);
+   env.refresh_user(&user).unwrap();
+   assert_eq!(env.user_state(&user).health_factor, 110_344);
+
+   // Each refresh_bank that sees it again moves the average further
+   for _ in 0..50 {
+      env.warp(WINDOW as i64 / 10);
+      env.refresh_bank(&market.sol).unwrap();
+   }
+   env.refresh_user(&user).unwrap();
+   assert!(env.user_state(&user).health_factor > 159_000);
+}
+
+#[test]
+fn a_bank_left_alone_for_longer_than_the_window_still_dampens_a_spike() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   env.set_bank_ema(&market.sol, WINDOW).unwrap();
+   env.refresh_bank(&market.sol).unwrap();
+   let user = env.borrower(&market, 100);
+
+   // Ten windows without a refresh, then a spike to $300 that refresh_bank records right before the borrow
+   env.warp(10 * WINDOW as i64);
+   env.set_oracle_price(market.sol.oracle, 300);
+   let meta = env.refresh_bank(&market.sol).unwrap();
+   assert_eq!(events::<BankRefreshed>(&meta)[0].ema_price, 165 * 100_000_000);
+
+   // 100 SOL at the $165 average * 75%
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 12_376)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &market.usdc, 12_375).unwrap();
+}
+
+#[test]
+fn a_window_of_a_few_seconds_still_moves() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+   env.set_bank_ema(&market.sol, 5).unwrap();
+   env.refresh_bank(&market.sol).unwrap();
+
+   // A second of the 5 counts at each refresh: 150 + 20% of 100
+   env.warp(60);
+   env.set_oracle_price(market.sol.oracle, 250);
+   env.refresh_bank(&market.sol).unwrap();
+   assert_eq!(env.bank_state(&market.sol).ema_price, 170 * 100_000_000);
+}
@@ -0,0 +1,208 @@
+use lending::constants::MAX_LIQUIDATION_BONUS;
+use lending::error::ErrCode;
+use lending::events::UserEModeChanged;
+use lending::instructions::EModeConfig;
+use lending_client::{InitEModeCategoryBuilder, SetUserEModeBuilder};
+use lending_tests::*;
+
+// SOL and jitoSOL (both at $150) in category 1, with 90% max LTV, 95% liquidation threshold and a 1% bonus
+struct Setup {
+   market: Market,
+   jitosol: TestBank,
+   user: TestUser,
+}
+
+fn setup(env: &mut TestEnv) -> Setup {
+   let market = env.market(50_000);
+   let jitosol = env.bank().price(150).build();
+   env.fund(&market.lender, &market.sol, 1_000);
+   env.deposit(&market.lender, &market.sol, 1_000).unwrap();
+
+   env.init_emode_category(1, 9_000, 9_500, 100).unwrap();
+   env.set_bank_emode_category(&market.sol, 1).unwrap();
+   env.set_bank_emode_category(&jitosol, 1).unwrap();
+
+   let user = env.user();
+   env.fund(&user, &jitosol, 100);
+   env.deposit(&user, &jitosol, 100).unwrap();
+   Setup { market, jitosol, user }
+}
+
+#[test]
+fn emode_raises_the_borrow_limit_of_correlated_assets() {
+   let mut env = TestEnv::new();
+   let Setup { market, user, .. } = setup(&mut env);
+
+   // 80 SOL are 12_000 of debt for 15_000 of collateral, above the 75% of the bank
+   assert_eq!(error_code(env.borrow(&user, &market.sol, 80)), Some(ErrCode::BorrowLimitExceeded));
+
+   let meta = env.set_user_emode(&user, 1).unwrap();
+   let event = &events::<UserEModeChanged>(&meta)[0];
+   assert_eq!((event.emode_category_before, event.emode_category), (0, 1));
+   assert_eq!(env.user_state(&user).emode_category, 1);
+
+   env.borrow(&user, &market.sol, 80).unwrap();
+   assert_eq!(error_code(env.borrow(&user, &market.sol, 11)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &market.sol, 10).unwrap();
+   assert_eq!(env.health(&user).ltv(), 9_000);
+   // 15_000 at 95% for 13_500 of debt
+   assert_eq!(env.user_state(&user).health_factor, 10_555);
+}
+
+#[test]
+fn users_in_emode_only_borrow_from_the_category() {
+   let mut env = TestEnv::new();
+   let Setup { market, user, .. } = setup(&mut env);
+
+   // A debt outside of the category has to be repaid before opting in
+   env.borrow(&user, &market.usdc, 1_000).unwrap();
+   assert_eq!(error_code(env.set_user_emode(&user, 1)), Some(ErrCode::EModeCategoryMismatch));
+   env.repay(&user, &market.usdc, 1_000).unwrap();
+
+   env.set_user_emode(&user, 1).unwrap();
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 1_000)), Some(ErrCode::EModeCategoryMismatch));
+   // Deposits outside of the category still count, with the parameters of their bank: 13_500 + 75% of 1_500
+   env.fund(&user, &market.usdc, 1_500);
+   env.deposit(&user, &market.usdc, 1_500).unwrap();
+   assert_eq!(error_code(env.borrow(&user, &market.sol, 98)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &market.sol, 97).unwrap();
+}
+
+#[test]
+fn leaving_emode_can_not_take_the_user_over_the_borrow_limit() {
+   let mut env = TestEnv::new();
+   let Setup { market, user, .. } = setup(&mut env);
+   env.set_user_emode(&user, 1).unwrap();
+   env.borrow(&user, &market.sol, 80).unwrap();
+
+   assert_eq!(error_code(env.set_user_emode(&user, 0)), Some(ErrCode::HealthDecreased));
+
+   env.repay(&user, &market.sol, 10).unwrap();
+   let meta = env.set_user_emode(&user, 0).unwrap();
+   let event = &events::<UserEModeChanged>(&meta)[0];
+   assert_eq!((event.emode_category_before, event.emode_category), (1, 0));
+   // 15_000 at 80% for 10_500 of debt
+   assert_eq!(event.health_factor, 11_428);
+   assert_eq!(env.user_state(&user).emode_category, 0);
+}
+
+#[test]
+fn a_bank_stays_in_its_category_while_users_in_emode_borrow_from_it() {
+   let mut env = TestEnv::new();
+   let Setup { market, user, .. } = setup(&mut env);
+   env.init_emode_category(2, 9_000, 9_500, 100).unwrap();
+   env.set_user_emode(&user, 1).unwrap();
+   env.borrow(&user, &market.sol, 80).unwrap();
+   assert_eq!(env.bank_state(&market.sol).emode_borrowed_shares, 80);
+
+   // The jitoSOL of the user would still be valued at 90% against a SOL debt outside of the category
+   assert_eq!(error_code(env.set_bank_emode_category(&market.sol, 0)), Some(ErrCode::EModeDebtOutstanding));
+   assert_eq!(error_code(env.set_bank_emode_category(&market.sol, 2)), Some(ErrCode::EModeDebtOutstanding));
+   env.set_bank_emode_category(&market.sol, 1).unwrap();
+
+   env.repay(&user, &market.sol, 80).unwrap();
+   assert_eq!(env.bank_state(&market.sol).emode_borrowed_shares, 0);
+   env.set_bank_emode_category(&market.sol, 0).unwrap();
+}
+
+#[test]
+fn the_debt_of_a_user_moves_in_and_out_of_emode_with_it() {
+   let mut env = TestEnv::new();
+   let Setup { market, user, .. } = setup(&mut env);
+   env.borrow(&user, &market.sol, 10).unwrap();
+   assert_eq!(env.bank_state(&market.sol).emode_borrowed_shares, 0);
+
+   env.set_user_emode(&user, 1).unwrap();
+   assert_eq!(env.bank_state(&market.sol).emode_borrowed_shares, 10);
+
+   // The banks the user borrows from are written, they can't be passed read-only
+   let ix = SetUserEModeBuilder::new(user.wallet, 0).positions(env.position_accounts(&user, &[])).instruction();
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::InvalidPositionAccount));
+
+   env.set_user_emode(&user, 0).unwrap();
+   assert_eq!(env.bank_state(&market.sol).emode_borrowed_shares, 0);
+   env.set_bank_emode_category(&market.sol, 0).unwrap();
+}
+
+#[test]
+fn liquidations_in_emode_use_the_threshold_and_bonus_of_the_category() {
+   let mut env = TestEnv::new();
+   let Setup { market, jitosol, user } = setup(&mut env);
+   env.set_user_emode(&user, 1).unwrap();
+   env.borrow(&user, &market.sol, 90).unwrap();
+
+   // At 143, 95% of the collateral still covers the 13_500 of debt
+   env.set_oracle_price(jitosol.oracle, 143);
+   let liquidator = env.user();
+   env.fund(&liquidator, &market.sol, 45);
+   assert_eq!(error_code(env.liquidate(&liquidator, &user, &jitosol, &market.sol, 45)), Some(ErrCode::NotUndercollateralized));
+
+   // At 140 it doesn't, and 45 SOL with a 1% bonus are 6_817 of value, 48 jitoSOL
+   env.set_oracle_price(jitosol.oracle, 140);
+   env.liquidate(&liquidator, &user, &jitosol, &market.sol, 45).unwrap();
+   assert_eq!(env.wallet_balance(&liquidator, &jitosol), 48);
+}
+
+#[test]
+fn the_category_account_has_to_be_the_one_of_the_user() {
+   let mut env = TestEnv::new();
+   let Setup { market, user, .. } = setup(&mut env);
+   env.init_emode_category(2, 9_000, 9_500, 100).unwrap();
+   env.set_user_emode(&user, 1).unwrap();
+
+   let mut ix = env.borrow_ix(&user, &market.sol, 10);
+   let category = ix.accounts.iter_mut().find(|meta| meta.pubkey == emode_category_address(1)).unwrap();
+   category.pubkey = emode_category_address(2);
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::InvalidEModeCategory));
+}
+
+#[test]
+fn only_the_upgrade_authority_creates_categories() {
+   let mut env = TestEnv::new();
+   let stranger = env.user().wallet;
+   let config = EModeConfig { max_ltv: 9_000, liquidation_threshold: 9_500, liquidation_bonus: 100 };
+
+   let ix = InitEModeCategoryBuilder::new(stranger, 1, config).instruction();
+   assert_eq!(error_code(env.process(ix, &[stranger])), Some(ErrCode::Unauthorized));
+
+   // The creator stays the authority, only the admin can raise the parameters for the banks that joined
+   env.init_emode_category(1, 9_000, 9_500, 100).unwrap();
+   let category: lending::state::EModeCategory = env.read_account(&emode_category_address(1));
+   assert_eq!(category.authority, env.admin);
+   let raised = EModeConfig { max_ltv: 10_000, liquidation_threshold: 10_000, liquidation_bonus: 0 };
+   assert_eq!(error_code(env.update_emode_category(1, &stranger, raised)), Some(ErrCode::Unauthorized));
+}
+
+#[test]
+fn the_bonus_of_a_category_is_bounded_like_the_one_of_a_bank() {
+   let mut env = TestEnv::new();
+   let admin = env.admin;
+   // 9_500 * 106% is more than the collateral
+   assert_eq!(error_code(env.init_emode_category(1, 9_000, 9_500, 600)), Some(ErrCode::InvalidBankConfig));
+   assert_eq!(error_code(env.init_emode_category(1, 5_000, 5_000, MAX_LIQUIDATION_BONUS + 1)), Some(ErrCode::InvalidBankConfig));
+   env.init_emode_category(1, 9_000, 9_500, 500).unwrap();
+
+   let config = EModeConfig { max_ltv: 9_000, liquidation_threshold: 9_500, liquidation_bonus: 600 };
+   assert_eq!(error_code(env.update_emode_category(1, &admin, config)), Some(ErrCode::InvalidBankConfig));
+}
+
+#[test]
+fn categories_are_configured_by_their_authority() {
+   let mut env = TestEnv::new();
+   let Setup { market, .. } = setup(&mut env);
+   let admin = env.admin;
+   let stranger = env.user().wallet;
+
+   assert_eq!(error_code(env.init_emode_category(0, 9_000, 9_500, 100)), Some(ErrCode::InvalidEModeCategory));
+   assert_eq!(error_code(env.init_emode_category(3, 9_600, 9_500, 100)), Some(ErrCode::InvalidBankConfig));
+
+   let config = EModeConfig { max_ltv: 9_300, liquidation_threshold: 9_700, liquidation_bonus: 200 };
+   assert_eq!(error_code(env.update_emode_category(1, &stranger, config)), Some(ErrCode::Unauthorized));
+   env.update_emode_category(1, &admin, config).unwrap();
+   let category: lending::state::EModeCategory = env.read_account(&emode_category_address(1));
+   assert_eq!((category.max_ltv, category.liquidation_threshold, category.liquidation_bonus), (9_300, 9_700, 200));
+
+   // A bank leaves its category with no category account
+   env.set_bank_emode_category(&market.sol, 0).unwrap();
+   assert_eq!(env.bank_state(&market.sol).emode_category, 0);
+}
@@ -0,0 +1,73 @@
+#![cfg(feature = "devnet-faucet")]
+
+use anchor_lang::prelude::Pubkey;
+use lending::error::ErrCode;
+use lending_client::{faucet_address, InitFaucetBuilder, MintTokensBuilder, UpdateFaucetBuilder};
+use lending_tests::*;
+
+// A mint of the admin handed over to a faucet that gives up to 1_000 tokens an hour to each wallet
+fn faucet(env: &mut TestEnv) -> Pubkey {
+   let mint = env.create_mint(6);
+   let admin = env.admin;
+   env.process(InitFaucetBuilder::new(admin, mint, 1_000, 3_600).instruction(), &[admin]).unwrap();
+   mint
+}
+
+fn claim(env: &mut TestEnv, user: &TestUser, mint: &Pubkey, quantity: u64) -> TxResult {
+   let token_account = env.token_account(&user.wallet, mint);
+   let ix = MintTokensBuilder::new(user.wallet, *mint, quantity).user_token_account(token_account).instruction();
+   env.process(ix, &[user.wallet])
+}
+
+#[test]
+fn the_faucet_takes_over_the_mint_authority() {
+   let mut env = TestEnv::new();
+   let mint = env.create_mint(6);
+   let stranger = env.user().wallet;
+
+   // Only the mint authority can hand it over (an Anchor constraint error)
+   let ix = InitFaucetBuilder::new(stranger, mint, 1_000, 3_600).instruction();
+   assert!(env.process(ix, &[stranger]).is_err());
+
+   let admin = env.admin;
+   env.process(InitFaucetBuilder::new(admin, mint, 1_000, 3_600).instruction(), &[admin]).unwrap();
+   assert_eq!(env.mint_authority(&mint), Some(faucet_address(&mint)));
+}
+
+#[test]
+fn wallets_claim_up_to_the_limit_once_per_cooldown() {
+   let mut env = TestEnv::new();
+   let mint = faucet(&mut env);
+   let (user, other) = (env.user(), env.user());
+
+   assert_eq!(error_code(claim(&mut env, &user, &mint, 0)), Some(ErrCode::InvalidAmount));
+   assert_eq!(error_code(claim(&mut env, &user, &mint, 1_001)), Some(ErrCode::FaucetAmountExceeded));
+   claim(&mut env, &user, &mint, 1_000).unwrap();
+   let token_account = env.token_account(&user.wallet, &mint);
+   assert_eq!(env.balance(&token_account), 1_000);
+   assert_eq!(env.supply(&mint), 1_000);
+
+   // The cooldown is per wallet
+   env.svm.warp(3_599);
+   assert_eq!(error_code(claim(&mut env, &user, &mint, 1)), Some(ErrCode::FaucetCooldown));
+   claim(&mut env, &other, &mint, 1).unwrap();
+   env.svm.warp(1);
+   claim(&mut env, &user, &mint, 1).unwrap();
+   assert_eq!(env.balance(&token_account), 1_001);
+}
+
+#[test]
+fn the_authority_of_the_faucet_changes_its_limits() {
+   let mut env = TestEnv::new();
+   let mint = faucet(&mut env);
+   let user = env.user();
+   claim(&mut env, &user, &mint, 1_000).unwrap();
+
+   let ix = UpdateFaucetBuilder::new(user.wallet, mint, 5_000, 0).instruction();
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::Unauthorized));
+
+   let admin = env.admin;
+   env.process(UpdateFaucetBuilder::new(admin, mint, 5_000, 0).instruction(), &[admin]).unwrap();
+   claim(&mut env, &user, &mint, 5_000).unwrap();
+   assert_eq!(error_code(claim(&mut env, &user, &mint, 5_001)), Some(ErrCode::FaucetAmountExceeded));
+}
@@ -0,0 +1,232 @@
+use lending::error::ErrCode;
+use lending::events::{BadDebtSocialized, BankConfigUpdated, InsuranceStakeAction, InsuranceStakeEvent, InterestAccruedEvent};
+use lending_tests::*;
+
+const ONE_YEAR: i64 = 365 * 24 * 60 * 60;
+const COOLDOWN: u64 = 7 * 24 * 60 * 60;
+
+// A market whose USDC bank keeps `fee_bps` of its interest for the insurance fund, and a staker with `stake` USDC in it
+fn insured(fee_bps: u64, stake: u64) -> (TestEnv, Market, TestUser) {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+   env.set_bank_insurance(&market.usdc, fee_bps, COOLDOWN).unwrap();
+
+   let staker = env.user();
+   env.fund(&staker, &market.usdc, stake);
+   if stake > 0 {
+      env.stake_insurance(&staker, &market.usdc, stake).unwrap();
+   }
+   (env, market, staker)
+}
+
+#[test]
+fn insurance_fee_is_taken_from_the_interest_of_the_depositors() {
+   let (mut env, market, _) = insured(1_000, 0);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+
+   env.warp(ONE_YEAR);
+   let meta = env.refresh_bank(&market.usdc).unwrap();
+
+   // 10% of the 51_270 of interest, the depositors get the rest
+   let event = &events::<InterestAccruedEvent>(&meta)[0];
+   assert_eq!((event.interest, event.insurance_fees), (51_270, 5_127));
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!((bank.total_borrowed, bank.total_deposits, bank.insurance_fees), (1_051_270, 2_046_143, 5_127));
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user]).unwrap();
+}
+
+#[test]
+fn insurance_fee_is_configured_by_the_authority() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+
+   assert_eq!(error_code(env.set_bank_insurance(&market.usdc, 10_001, 0)), Some(ErrCode::InvalidBankConfig));
+   let meta = env.set_bank_insurance(&market.usdc, 2_000, COOLDOWN).unwrap();
+   let event = &events::<BankConfigUpdated>(&meta)[0];
+   assert_eq!((event.insurance_fee_bps, event.insurance_cooldown), (2_000, COOLDOWN));
+}
+
+#[test]
+fn unstake_waits_for_the_cooldown() {
+   let (mut env, market, staker) = insured(0, 1_000);
+   let stake = env.insurance_stake(&staker, &market.usdc);
+   assert_eq!((stake.staker, stake.bank, stake.shares), (staker.wallet, market.usdc.bank, 1_000));
+   assert_eq!(env.balance(&market.usdc.insurance_vault), 1_000);
+
+   assert_eq!(error_code(env.unstake(&staker, &market.usdc)), Some(ErrCode::NoUnstakeRequested));
+   assert_eq!(error_code(env.request_unstake(&staker, &market.usdc, 1_001)), Some(ErrCode::InsufficientFunds));
+   env.request_unstake(&staker, &market.usdc, 400).unwrap();
+
+   env.warp(COOLDOWN as i64 - 1);
+   assert_eq!(error_code(env.unstake(&staker, &market.usdc)), Some(ErrCode::UnstakeCooldown));
+   env.warp(1);
+   let meta = env.unstake(&staker, &market.usdc).unwrap();
+
+   let event = &events::<InsuranceStakeEvent>(&meta)[0];
+   assert_eq!((event.action, event.amount, event.shares, event.stake_shares), (InsuranceStakeAction::Unstake, 400, 400, 600));
+   assert_eq!(env.wallet_balance(&staker, &market.usdc), 400);
+   assert_eq!(env.balance(&market.usdc.insurance_vault), 600);
+   assert_eq!(env.insurance_stake(&staker, &market.usdc).unstake_shares, 0);
+   assert_eq!(error_code(env.unstake(&staker, &market.usdc)), Some(ErrCode::NoUnstakeRequested));
+}
+
+#[test]
+fn stakers_earn_the_insurance_fees() {
+   let (mut env, market, staker) = insured(1_000, 10_000);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+
+   env.warp(ONE_YEAR);
+   env.request_unstake(&staker, &market.usdc, 10_000).unwrap();
+   env.warp(COOLDOWN as i64);
+   env.unstake(&staker, &market.usdc).unwrap();
+
+   // The stake and every fee accrued until the unstake, swept from the treasury
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!((bank.insurance_fees, bank.insurance_funds, bank.insurance_shares), (0, 0, 0));
+   assert!(env.wallet_balance(&staker, &market.usdc) > 10_000 + 5_127);
+   assert_eq!(env.balance(&market.usdc.insurance_vault), 0);
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user]).unwrap();
+}
+
+#[test]
+fn later_stakers_do_not_share_the_earlier_fees() {
+   let (mut env, market, staker) = insured(1_000, 10_000);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+   env.warp(ONE_YEAR);
+
+   // The fund is worth 15_127 for 10_000 shares
+   let late = env.user();
+   env.fund(&late, &market.usdc, 15_127);
+   env.stake_insurance(&late, &market.usdc, 15_127).unwrap();
+   assert_eq!(env.insurance_stake(&late, &market.usdc).shares, 10_000);
+   assert_eq!(env.insurance_stake(&staker, &market.usdc).shares, 10_000);
+}
+
+#[test]
+fn the_first_staker_does_not_get_the_fees_booked_before() {
+   let (mut env, market, _) = insured(1_000, 0);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+   env.warp(ONE_YEAR);
+
+   let staker = env.user();
+   env.fund(&staker, &market.usdc, 10_000);
+   env.stake_insurance(&staker, &market.usdc, 10_000).unwrap();
+
+   // The 5_127 of fees are worth 5_127 shares nobody owns
+   let bank = env.bank_state(&market.usdc);
+   let shares = env.insurance_stake(&staker, &market.usdc).shares;
+   assert_eq!((shares, bank.insurance_shares), (10_000, 15_127));
+   assert_eq!(bank.stake_shares_to_amount(shares).unwrap(), 10_000);
+}
+
+#[test]
+fn withdrawals_and_borrows_leave_the_insurance_fees_in_the_treasury() {
+   let (mut env, market, _) = insured(1_000, 0);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+   env.warp(ONE_YEAR);
+   env.refresh_bank(&market.usdc).unwrap();
+
+   // 1_000_000 in the treasury: 994_873 of deposits that aren't lent out, and the 5_127 owed to the fund
+   assert_eq!(env.balance(&market.usdc.treasury), 1_000_000);
+   let other = env.borrower(&market, 10_000);
+   assert_eq!(error_code(env.borrow(&other, &market.usdc, 994_874)), Some(ErrCode::InsufficientLiquidity));
+   assert_eq!(error_code(env.withdraw(&market.lender, &market.usdc, 994_874)), Some(ErrCode::InsufficientLiquidity));
+
+   env.withdraw(&market.lender, &market.usdc, 994_873).unwrap();
+   assert_eq!(env.balance(&market.usdc.treasury), 5_127);
+   assert_eq!(env.bank_state(&market.usdc).insurance_fees, 5_127);
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user, other]).unwrap();
+}
+
+/*
+   A user with 100 SOL and 11_000 USDC of debt after SOL falls to $50, liquidated down to its last SOL,
+   which leaves 6_200 USDC of bad debt. Nothing accrues, so the insurance fund is its stake.
+*/
+fn bad_debt(env: &mut TestEnv, market: &Market) -> TestUser {
+   let user = env.borrower(market, 100);
+   env.borrow(&user, &market.usdc, 11_000).unwrap();
+   env.set_oracle_price(market.sol.oracle, 50);
+
+   let liquidator = env.user();
+   env.fund(&liquidator, &market.usdc, 4_800);
+   env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 4_800).unwrap();
+   user
+}
+
+#[test]
+fn bad_debt_is_paid_by_the_insurance_fund_first() {
+   let (mut env, market, staker) = insured(1_000, 10_000);
+   let user = bad_debt(&mut env, &market);
+
+   let meta = env.socialize_loss(&market.usdc, &user).unwrap();
+   let event = &events::<BadDebtSocialized>(&meta)[0];
+   assert_eq!((event.amount, event.insurance_amount, event.total_deposits), (6_200, 6_200, 2_000_000));
+
+   // The vault pays the treasury back, the stakers' shares are worth what is left
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!(bank.insurance_funds, 3_800);
+   assert_eq!(env.balance(&market.usdc.insurance_vault), 3_800);
+   assert_eq!(bank.stake_shares_to_amount(env.insurance_stake(&staker, &market.usdc).shares).unwrap(), 3_800);
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user]).unwrap();
+}
+
+#[test]
+fn depositors_pay_what_the_insurance_fund_can_not() {
+   let (mut env, market, _) = insured(1_000, 1_000);
+   let user = bad_debt(&mut env, &market);
+
+   let meta = env.socialize_loss(&market.usdc, &user).unwrap();
+   let event = &events::<BadDebtSocialized>(&meta)[0];
+   assert_eq!((event.amount, event.insurance_amount, event.total_deposits), (6_200, 1_000, 1_994_800));
+
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!((bank.insurance_funds, bank.insurance_fees), (0, 0));
+   assert_eq!(env.balance(&market.usdc.insurance_vault), 0);
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user]).unwrap();
+   env.withdraw(&market.lender, &market.usdc, 1_994_800).unwrap();
+}
+
+#[test]
+fn a_fund_a_loss_took_whole_takes_stakes_once_it_earns_fees() {
+   let (mut env, market, staker) = insured(1_000, 1_000);
+   let user = bad_debt(&mut env, &market);
+   env.socialize_loss(&market.usdc, &user).unwrap();
+
+   // The 1_000 shares left are worth nothing
+   let late = env.user();
+   env.fund(&late, &market.usdc, 2_000);
+   assert_eq!(error_code(env.stake_insurance(&late, &market.usdc, 1_000)), Some(ErrCode::InsuranceFundWrittenOff));
+
+   // SOL is at $50, 10_000 SOL borrow 300_000
+   let borrower = env.borrower(&market, 10_000);
+   env.borrow(&borrower, &market.usdc, 300_000).unwrap();
+   env.warp(ONE_YEAR);
+   env.stake_insurance(&late, &market.usdc, 1_000).unwrap();
+
+   // The fees went to the stakers that took the loss, the late stake is worth what it put in
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!(bank.stake_shares_to_amount(env.insurance_stake(&staker, &market.usdc).shares).unwrap(), bank.insurance_fees);
+   assert_eq!(bank.stake_shares_to_amount(env.insurance_stake(&late, &market.usdc).shares).unwrap(), 999);
+}
+
+#[test]
+fn insurance_fees_are_spent_before_the_vault() {
+   let (mut env, market, _) = insured(10_000, 1_000);
+   let user = bad_debt(&mut env, &market);
+   // All of the interest goes to the fund, the deposits don't move
+   env.warp(ONE_YEAR);
+   env.refresh_bank(&market.usdc).unwrap();
+   let fees = env.bank_state(&market.usdc).insurance_fees;
+   assert!(fees > 0);
+
+   let meta = env.socialize_loss(&market.usdc, &user).unwrap();
+   let event = &events::<BadDebtSocialized>(&meta)[0];
+   assert_eq!(event.insurance_amount, fees + 1_000);
+   assert_eq!(event.total_deposits, 2_000_000 - (event.amount - fees - 1_000));
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user]).unwrap();
+}
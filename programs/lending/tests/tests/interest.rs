@@ -0,0 +1,111 @@
+use lending::error::ErrCode;
+use lending::events::{BankRefreshed, InterestAccruedEvent};
+use lending_client::{BankAccounts, RefreshBankBuilder};
+use lending_tests::*;
+
+const ONE_YEAR: i64 = 365 * 24 * 60 * 60;
+
+#[test]
+fn interest_of_the_borrowers_is_earned_by_the_depositors() {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+
+   env.warp(ONE_YEAR);
+   env.fund(&user, &market.usdc, 1);
+   let meta = env.repay(&user, &market.usdc, 1).unwrap();
+
+   // The same numbers as the unit test of Bank::accrue_interest
+   let accrued = events::<InterestAccruedEvent>(&meta);
+   assert_eq!(accrued.len(), 1);
+   assert_eq!(accrued[0].interest, 51_270);
+   assert_eq!(accrued[0].timestamp, env.now());
+
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!(bank.total_borrowed, 1_051_269);
+   assert_eq!(bank.total_deposits, 2_051_270);
+
+   let lender_shares = env.user_state(&market.lender).position(&market.usdc.bank).unwrap().deposit_shares;
+   assert_eq!(bank.deposit_shares_to_amount(lender_shares).unwrap(), 2_051_270);
+}
+
+#[test]
+fn later_depositors_get_fewer_shares() {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+
+   env.warp(ONE_YEAR);
+   let late = env.user();
+   env.fund(&late, &market.usdc, 2_051_270);
+   env.deposit(&late, &market.usdc, 2_051_270).unwrap();
+
+   assert_eq!(env.user_state(&late).position(&market.usdc.bank).unwrap().deposit_shares, 2_000_000);
+}
+
+#[test]
+fn nothing_accrues_without_borrowers() {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+
+   env.warp(ONE_YEAR);
+   let meta = env.withdraw(&market.lender, &market.usdc, 2_000_000).unwrap();
+   assert!(events::<InterestAccruedEvent>(&meta).is_empty());
+   assert_eq!(env.bank_state(&market.usdc).last_updated, env.now());
+}
+
+#[test]
+fn anyone_can_refresh_a_bank() {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+
+   env.warp(ONE_YEAR);
+   let meta = env.refresh_bank(&market.usdc).unwrap();
+
+   // The same interest a repay after one year accrues
+   assert_eq!(events::<InterestAccruedEvent>(&meta)[0].interest, 51_270);
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!((bank.total_borrowed, bank.last_updated), (1_051_270, env.now()));
+   assert_eq!((bank.last_price, bank.last_price_updated), (100_000_000, env.now()));
+
+   let refreshed = events::<BankRefreshed>(&meta);
+   assert_eq!((refreshed[0].price, refreshed[0].total_deposits), (100_000_000, 2_051_270));
+
+   // Idle banks too, paused or not
+   env.set_bank_paused(&market.sol, true).unwrap();
+   let meta = env.refresh_bank(&market.sol).unwrap();
+   assert!(events::<InterestAccruedEvent>(&meta).is_empty());
+   assert_eq!(env.bank_state(&market.sol).last_price, 150 * 100_000_000);
+}
+
+#[test]
+fn refresh_bank_needs_the_oracle_of_the_bank_and_a_fresh_price() {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+
+   let ix = RefreshBankBuilder::new(&BankAccounts { oracle: market.sol.oracle, ..market.usdc.accounts() }).instruction();
+   assert_eq!(error_code(env.process(ix, &[env.admin])), Some(ErrCode::InvalidOracle));
+
+   env.svm.warp(lending::constants::DEFAULT_ORACLE_MAX_AGE as i64 + 1);
+   assert_eq!(error_code(env.refresh_bank(&market.usdc)), Some(ErrCode::StaleOracle));
+   assert_eq!(env.bank_state(&market.usdc).last_price, 0);
+}
+
+#[test]
+fn health_counts_the_interest_of_banks_nobody_refreshed() {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_000_000).unwrap();
+
+   // The USDC bank is only read from the remaining accounts, its debt is still 1_000_000 in the account.
+   // With the 51_270 of interest the borrow limit of $112.5 per SOL needs 9_345 SOL (8_889 without it)
+   env.warp(ONE_YEAR);
+   assert_eq!(error_code(env.withdraw(&user, &market.sol, 656)), Some(ErrCode::WithdrawExceedsBorrowLimit));
+   env.withdraw(&user, &market.sol, 655).unwrap();
+   assert_eq!(env.bank_state(&market.usdc).total_borrowed, 1_000_000);
+}
@@ -0,0 +1,239 @@
+use lending::constants::BPS;
+use lending::health::Health;
+use lending::math::value_to_amount;
+use lending_tests::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/*
+   Property based tests of the accounting of the program.
+   Every case is a random sequence of deposits, withdrawals, borrows, repayments, liquidations,
+   interest accrual and price moves of a few users in a few banks, run against the program,
+   and after every step the invariants below have to hold.
+
+   Actions describe amounts as a fraction of what makes sense in the current state (a part of the
+   deposit, of the remaining borrow limit...), so a sequence replays the same way from the same seed
+   and still makes sense after removing steps from it. When a case fails it is shrunk by removing
+   steps while it keeps failing, and the seed and the minimal sequence are printed.
+
+   INVARIANT_CASES, INVARIANT_STEPS and INVARIANT_SEED change the number of cases, their length and the first seed.
+*/
+
+const USERS: usize = 4;
+const PRICES: [u64; 3] = [150, 1, 2_000];
+const INTEREST_RATE: u64 = 20;
+
+#[derive(Clone, Copy, Debug)]
+enum Action {
+   Deposit { user: usize, bank: usize, amount: u64 },
+   // fraction (in bps) of the deposit of the user
+   Withdraw { user: usize, bank: usize, fraction: u64 },
+   // fraction of what the user can still borrow
+   Borrow { user: usize, bank: usize, fraction: u64 },
+   // fraction of the debt of the user
+   Repay { user: usize, bank: usize, fraction: u64 },
+   // fraction of what the close factor allows
+   Liquidate { user: usize, collateral: usize, borrowed: usize, fraction: u64 },
+   Accrue { seconds: i64 },
+   // only goes through for a user whose collateral was all seized
+   SocializeLoss { user: usize, bank: usize },
+   // new price as a fraction of the initial price of the bank
+   SetPrice { bank: usize, fraction: u64 },
+}
+
+fn generate(seed: u64, steps: usize) -> Vec<Action> {
+   let mut rng = StdRng::seed_from_u64(seed);
+   let banks = PRICES.len();
+   (0..steps)
+      .map(|_| {
+         let user = rng.gen_range(0..USERS);
+         let bank = rng.gen_range(0..banks);
+         let fraction = rng.gen_range(1..=BPS);
+         match rng.gen_range(0..100) {
+            0..=24 => Action::Deposit { user, bank, amount: rng.gen_range(1..=1_000_000) },
+            25..=34 => Action::Withdraw { user, bank, fraction },
+            35..=54 => Action::Borrow { user, bank, fraction },
+            55..=64 => Action::Repay { user, bank, fraction },
+            65..=74 => Action::Liquidate { user, collateral: bank, borrowed: rng.gen_range(0..banks), fraction },
+            75..=81 => Action::Accrue { seconds: rng.gen_range(1..=90 * 24 * 60 * 60) },
+            82..=84 => Action::SocializeLoss { user, bank },
+            _ => Action::SetPrice { bank, fraction: rng.gen_range(3_000..=15_000) },
+         }
+      })
+      .collect()
+}
+
+struct World {
+   env: TestEnv,
+   banks: Vec<TestBank>,
+   users: Vec<TestUser>,
+   liquidator: TestUser,
+}
+
+impl World {
+   fn new() -> Self {
+      let mut env = TestEnv::new();
+      let banks = PRICES.iter().map(|price| env.bank().price(*price).interest_rate(INTEREST_RATE).build()).collect();
+      let users = (0..USERS).map(|_| env.user()).collect();
+      // Liquidators use their own wallet, so their positions are not mixed with the ones being checked
+      let liquidator = env.user();
+      World { env, banks, users, liquidator }
+   }
+
+   fn deposited(&self, user: usize, bank: usize) -> u64 {
+      let priced = self.env.priced_bank(&self.banks[bank].bank);
+      let shares = self.env.user_state(&self.users[user]).position(&priced.key).map_or(0, |position| position.deposit_shares);
+      priced.bank.deposit_shares_to_amount(shares).unwrap()
+   }
+
+   fn debt(&self, user: usize, bank: usize) -> u64 {
+      let priced = self.env.priced_bank(&self.banks[bank].bank);
+      let shares = self.env.user_state(&self.users[user]).position(&priced.key).map_or(0, |position| position.borrowed_shares);
+      priced.bank.borrowed_shares_to_amount(shares).unwrap()
+   }
+
+   // Runs the action, a transaction rejected by the program is fine as long as it leaves no trace.
+   // Returns the user the action was about, whose own health may get worse.
+   fn apply(&mut self, action: Action) -> Option<usize> {
+      let env = &mut self.env;
+      match action {
+         Action::Deposit { user, bank, amount } => {
+            env.fund(&self.users[user], &self.banks[bank], amount);
+            let _ = env.deposit(&self.users[user], &self.banks[bank], amount);
+            Some(user)
+         }
+         Action::Withdraw { user, bank, fraction } => {
+            let amount = part(self.deposited(user, bank), fraction);
+            let _ = self.env.withdraw(&self.users[user], &self.banks[bank], amount);
+            Some(user)
+         }
+         Action::Borrow { user, bank, fraction } => {
+            let health = env.health(&self.users[user]);
+            let priced = env.priced_bank(&self.banks[bank].bank);
+            let room = value_to_amount(health.borrow_limit.saturating_sub(health.weighted_debt), priced.price, priced.bank.decimals).unwrap_or(u64::MAX);
+            let amount = part(room, fraction).min(env.balance(&self.banks[bank].treasury));
+            let _ = env.borrow(&self.users[user], &self.banks[bank], amount);
+            Some(user)
+         }
+         Action::Repay { user, bank, fraction } => {
+            let amount = part(self.debt(user, bank), fraction);
+            self.env.fund(&self.users[user], &self.banks[bank], amount);
+            let _ = self.env.repay(&self.users[user], &self.banks[bank], amount);
+            Some(user)
+         }
+         Action::Liquidate { user, collateral, borrowed, fraction } => {
+            let close_factor = self.env.bank_state(&self.banks[borrowed]).liquidation_close_factor;
+            let amount = part(part(self.debt(user, borrowed), close_factor), fraction);
+            let liquidator = self.liquidator;
+            self.env.fund(&liquidator, &self.banks[borrowed], amount);
+            let _ = self.env.liquidate(&liquidator, &self.users[user], &self.banks[collateral], &self.banks[borrowed], amount);
+            Some(user)
+         }
+         Action::Accrue { seconds } => {
+            env.warp(seconds);
+            None
+         }
+         // The loss lowers every deposit of the bank, like a price move it can make other users liquidatable
+         Action::SocializeLoss { user, bank } => {
+            let _ = env.socialize_loss(&self.banks[bank], &self.users[user]);
+            None
+         }
+         Action::SetPrice { bank, fraction } => {
+            let price = part(PRICES[bank], fraction);
+            env.set_oracle_price(self.banks[bank].oracle, price);
+            None
+         }
+      }
+   }
+
+   fn total_borrowed(&self) -> u128 {
+      self.banks.iter().map(|bank| self.env.bank_state(bank).total_borrowed as u128).sum()
+   }
+
+   fn healths(&self) -> Vec<Health> {
+      self.users.iter().map(|user| self.env.health(user)).collect()
+   }
+
+   fn check_accounting(&self) -> Result<(), String> {
+      let users: Vec<TestUser> = self.users.iter().copied().chain([self.liquidator]).collect();
+      check_accounting(&self.env, &self.banks, &users)
+   }
+}
+
+fn part(amount: u64, fraction: u64) -> u64 {
+   (amount as u128 * fraction as u128 / BPS as u128).max(1) as u64
+}
+
+// Runs the actions on a new world, returns the failing step and what went wrong
+fn run(actions: &[Action]) -> Result<(), (usize, String)> {
+   let mut world = World::new();
+   for (step, action) in actions.iter().enumerate() {
+      let before = world.healths();
+      let subject = world.apply(*action);
+      world.check_accounting().map_err(|message| (step, message))?;
+
+      // Price moves and time can make anyone liquidatable, actions of other users never should
+      if let Some(subject) = subject {
+         let after = world.healths();
+         for user in (0..USERS).filter(|user| *user != subject) {
+            if !before[user].is_liquidatable() && after[user].is_liquidatable() {
+               return Err((step, format!("user {user} became liquidatable: {:?} -> {:?}", before[user], after[user])));
+            }
+         }
+      }
+   }
+   Ok(())
+}
+
+fn shrink(mut actions: Vec<Action>) -> Vec<Action> {
+   loop {
+      let mut shrunk = false;
+      for index in (0..actions.len()).rev() {
+         let mut candidate = actions.clone();
+         candidate.remove(index);
+         if run(&candidate).is_err() {
+            actions = candidate;
+            shrunk = true;
+         }
+      }
+      if !shrunk {
+         return actions;
+      }
+   }
+}
+
+fn env_var(name: &str, default: u64) -> u64 {
+   std::env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+#[test]
+fn accounting_invariants_hold_for_random_action_sequences() {
+   let cases = env_var("INVARIANT_CASES", 24);
+   let steps = env_var("INVARIANT_STEPS", 40) as usize;
+   let first_seed = env_var("INVARIANT_SEED", 0x1e_4d_13);
+
+   for seed in first_seed..first_seed + cases {
+      let actions = generate(seed, steps);
+      if let Err((step, message)) = run(&actions) {
+         let minimal = shrink(actions[..=step].to_vec());
+         let (_, message_minimal) = run(&minimal).unwrap_err();
+         panic!("seed {seed}: {message}\nminimal sequence ({message_minimal}):\n{minimal:#?}");
+      }
+   }
+}
+
+#[test]
+fn random_sequences_exercise_every_action() {
+   // The generator has to produce sequences where the actions actually go through, not only rejections
+   let mut world = World::new();
+   let mut borrowed = false;
+   let mut liquidated = false;
+   for action in generate(env_var("INVARIANT_SEED", 0x1e_4d_13), 400) {
+      let before = world.total_borrowed();
+      world.apply(action);
+      let after = world.total_borrowed();
+      borrowed |= matches!(action, Action::Borrow { .. }) && after > before;
+      liquidated |= matches!(action, Action::Liquidate { .. }) && after < before;
+   }
+   assert!(borrowed, "no borrow went through");
+   assert!(liquidated, "no liquidation went through");
+}
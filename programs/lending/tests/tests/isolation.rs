@@ -0,0 +1,191 @@
+use anchor_lang::prelude::Pubkey;
+use lending::constants::STABLE_PRICE;
+use lending::error::ErrCode;
+use lending::events::BankConfigUpdated;
+use lending_client::SocializeLossBuilder;
+use lending_tests::*;
+
+// What the ceiling counts `amount` base units of a stable with the 6 decimals of the test banks for
+fn stable(amount: u64) -> u64 {
+   amount * STABLE_PRICE / 1_000_000
+}
+
+// BONK at $10, isolated with a ceiling of 5_000 USDC, and a user with 1_000 BONK (10_000) of collateral
+struct Setup {
+   market: Market,
+   bonk: TestBank,
+   user: TestUser,
+}
+
+fn setup(env: &mut TestEnv) -> Setup {
+   let market = env.market(50_000);
+   let bonk = env.bank().price(10).build();
+   env.set_bank_isolation(&bonk, true, stable(5_000), false).unwrap();
+   env.set_bank_isolation(&market.usdc, false, 0, true).unwrap();
+
+   let user = env.user();
+   env.fund(&user, &bonk, 1_000);
+   env.deposit(&user, &bonk, 1_000).unwrap();
+   Setup { market, bonk, user }
+}
+
+#[test]
+fn isolated_collateral_only_borrows_stable_banks_up_to_the_ceiling() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, user } = setup(&mut env);
+   env.fund(&market.lender, &market.sol, 100);
+   env.deposit(&market.lender, &market.sol, 100).unwrap();
+
+   assert_eq!(error_code(env.borrow(&user, &market.sol, 1)), Some(ErrCode::NotBorrowableInIsolation));
+   // 5_001 is within the borrow limit of 7_500, not within the ceiling
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 5_001)), Some(ErrCode::DebtCeilingExceeded));
+   env.borrow(&user, &market.usdc, 4_000).unwrap();
+   assert_eq!(env.bank_state(&bonk).isolated_debt, stable(4_000));
+
+   // The ceiling is shared by every user of the isolated bank
+   let other = env.user();
+   env.fund(&other, &bonk, 1_000);
+   env.deposit(&other, &bonk, 1_000).unwrap();
+   assert_eq!(error_code(env.borrow(&other, &market.usdc, 1_001)), Some(ErrCode::DebtCeilingExceeded));
+   env.borrow(&other, &market.usdc, 1_000).unwrap();
+   assert_eq!(env.bank_state(&bonk).isolated_debt, stable(5_000));
+}
+
+#[test]
+fn isolated_collateral_can_not_be_combined_with_other_collateral() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, user } = setup(&mut env);
+
+   env.fund(&user, &market.sol, 10);
+   assert_eq!(error_code(env.deposit(&user, &market.sol, 10)), Some(ErrCode::IsolatedCollateralMixed));
+
+   let borrower = env.borrower(&market, 10);
+   env.fund(&borrower, &bonk, 100);
+   assert_eq!(error_code(env.deposit(&borrower, &bonk, 100)), Some(ErrCode::IsolatedCollateralMixed));
+
+   // Nor can it be swapped into other collateral, or its debt into another debt
+   let pool = env.swap_pool(&bonk, &market.sol, 1, 15, 100);
+   assert_eq!(error_code(env.swap_collateral(&user, &bonk, &market.sol, &pool, 100, 1)), Some(ErrCode::IsolatedSwap));
+   let usdt = env.bank().price(1).build();
+   env.set_bank_isolation(&usdt, false, 0, true).unwrap();
+   env.borrow(&user, &market.usdc, 1_000).unwrap();
+   let pool = env.swap_pool(&usdt, &market.usdc, 1, 1, 1_000);
+   assert_eq!(error_code(env.swap_debt(&user, &market.usdc, &usdt, &pool, 1_000, 1_000)), Some(ErrCode::IsolatedSwap));
+}
+
+#[test]
+fn repay_and_liquidation_release_the_ceiling() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, user } = setup(&mut env);
+   env.borrow(&user, &market.usdc, 5_000).unwrap();
+
+   env.repay(&user, &market.usdc, 1_000).unwrap();
+   assert_eq!(env.bank_state(&bonk).isolated_debt, stable(4_000));
+
+   // At $4 the 1_000 BONK are 3_200 of weighted collateral for 4_000 of debt
+   env.set_oracle_price(bonk.oracle, 4);
+   let liquidator = env.user();
+   env.fund(&liquidator, &market.usdc, 1_000);
+   env.liquidate(&liquidator, &user, &bonk, &market.usdc, 1_000).unwrap();
+   assert_eq!(env.bank_state(&bonk).isolated_debt, stable(3_000));
+}
+
+#[test]
+fn the_ceiling_counts_stables_of_any_decimals_at_one() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, user } = setup(&mut env);
+   let dai = env.bank().price(1).decimals(9).build();
+   env.set_bank_isolation(&dai, false, 0, true).unwrap();
+   env.fund(&market.lender, &dai, 10_000_000);
+   env.deposit(&market.lender, &dai, 10_000_000).unwrap();
+
+   // 1_000_000 base units of DAI are worth 1_000 of USDC, the last 1_000 under the ceiling
+   env.borrow(&user, &market.usdc, 4_000).unwrap();
+   assert_eq!(error_code(env.borrow(&user, &dai, 1_000_001)), Some(ErrCode::DebtCeilingExceeded));
+   env.borrow(&user, &dai, 1_000_000).unwrap();
+   assert_eq!(env.bank_state(&bonk).isolated_debt, stable(5_000));
+
+   env.repay(&user, &dai, 1_000_000).unwrap();
+   assert_eq!(env.bank_state(&bonk).isolated_debt, stable(4_000));
+}
+
+#[test]
+fn written_off_debt_releases_the_ceiling() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, user } = setup(&mut env);
+   env.borrow(&user, &market.usdc, 5_000).unwrap();
+   assert_eq!(env.user_state(&user).isolated_bank, bonk.bank);
+
+   // At $2 the liquidator takes all of the BONK for less than half of the debt
+   env.set_oracle_price(bonk.oracle, 2);
+   let liquidator = env.user();
+   env.fund(&liquidator, &market.usdc, 2_000);
+   env.liquidate(&liquidator, &user, &bonk, &market.usdc, 2_000).unwrap();
+   assert!(env.bank_state(&bonk).isolated_debt > 0);
+
+   // The rest is written off the ceiling with the debt, which needs the isolated bank
+   let ix = SocializeLossBuilder::new(&market.usdc.accounts(), user.wallet).instruction();
+   assert_eq!(error_code(env.process(ix, &[Pubkey::new_unique()])), Some(ErrCode::MissingIsolatedBank));
+   env.socialize_loss(&market.usdc, &user).unwrap();
+   assert_eq!(env.bank_state(&bonk).isolated_debt, 0);
+   assert_eq!(env.user_state(&user).isolated_bank, Pubkey::default());
+}
+
+#[test]
+fn leverage_on_isolated_collateral_counts_against_the_ceiling() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, user } = setup(&mut env);
+   let buy = env.swap_pool(&market.usdc, &bonk, 1, 10, 10_000);
+
+   // 10_000 USDC would take the LTV to 50%, that is above the ceiling
+   assert_eq!(error_code(env.leverage(&user, &bonk, &market.usdc, &buy, 5_000, 1)), Some(ErrCode::DebtCeilingExceeded));
+   env.leverage(&user, &bonk, &market.usdc, &buy, 3_000, 1).unwrap();
+   assert_eq!(env.bank_state(&bonk).isolated_debt, stable(4_285));
+}
+
+#[test]
+fn leverage_into_an_isolated_bank_is_isolated_from_the_start() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, .. } = setup(&mut env);
+   let buy = env.swap_pool(&market.usdc, &bonk, 1, 10, 10_000);
+
+   // Without a position in the isolated bank there is nothing to loop yet
+   let fresh = env.user();
+   assert_eq!(error_code(env.leverage(&fresh, &bonk, &market.usdc, &buy, 7_500, 1)), Some(ErrCode::InvalidTargetLtv));
+
+   // And the deposit the loop opens in the isolated bank can't sit next to other collateral,
+   // even though the SOL alone could borrow 7_500 of USDC, past the ceiling
+   let borrower = env.borrower(&market, 50);
+   assert_eq!(error_code(env.leverage(&borrower, &bonk, &market.usdc, &buy, 5_000, 1)), Some(ErrCode::IsolatedCollateralMixed));
+   assert!(env.user_state(&borrower).position(&bonk.bank).is_none());
+   assert_eq!(env.bank_state(&bonk).isolated_debt, 0);
+}
+
+#[test]
+fn the_isolated_bank_has_to_be_passed() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, user } = setup(&mut env);
+
+   // Without it (the program id stands for a missing optional account)
+   let mut ix = env.borrow_ix(&user, &market.usdc, 1_000);
+   let isolated_bank = ix.accounts.iter_mut().find(|meta| meta.pubkey == bonk.bank).unwrap();
+   isolated_bank.pubkey = lending::ID;
+   isolated_bank.is_writable = false;
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::MissingIsolatedBank));
+}
+
+#[test]
+fn isolation_only_changes_while_the_bank_has_no_deposits() {
+   let mut env = TestEnv::new();
+   let Setup { market, bonk, .. } = setup(&mut env);
+
+   assert_eq!(error_code(env.set_bank_isolation(&market.usdc, true, 0, false)), Some(ErrCode::InvalidBankConfig));
+   assert_eq!(error_code(env.set_bank_isolation(&bonk, false, 0, false)), Some(ErrCode::InvalidBankConfig));
+   let empty = env.bank().build();
+   assert_eq!(error_code(env.set_bank_isolation(&empty, true, 0, true)), Some(ErrCode::InvalidBankConfig));
+
+   // The ceiling can move at any time
+   let meta = env.set_bank_isolation(&bonk, true, stable(8_000), false).unwrap();
+   let event = &events::<BankConfigUpdated>(&meta)[0];
+   assert_eq!((event.isolated, event.debt_ceiling, event.borrowable_in_isolation), (true, stable(8_000), false));
+}
@@ -0,0 +1,211 @@
+use anchor_lang::{prelude::Pubkey, solana_program::instruction::Instruction};
+use anchor_spl::token::spl_token;
+use lending::state::LiquidationMode;
+use lending_keeper::{bad_debt_instructions, refresh_instructions, FlashLoan, Keeper, Liquidation, Snapshot};
+use lending_tests::*;
+
+// The keeper reads the accounts of the runtime as they are now
+fn snapshot(env: &TestEnv) -> Snapshot {
+   let mut snapshot = Snapshot::new(env.now());
+   for (key, account) in env.svm.accounts() {
+      snapshot.insert(*key, account.owner, account.data.clone());
+   }
+   snapshot
+}
+
+// A user with 50 SOL and 4 ETH of collateral and 11_000 USDC of debt after SOL drops to $100
+fn undercollateralized() -> (TestEnv, Market, TestBank, TestUser) {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let eth = env.bank().price(2_000).liquidation(1_000, 5_000).build();
+
+   let user = env.borrower(&market, 50);
+   env.fund(&user, &eth, 4);
+   env.deposit(&user, &eth, 4).unwrap();
+   env.borrow(&user, &market.usdc, 11_000).unwrap();
+
+   // (50 * $100 + 4 * $2_000) * 80% = $10_400 < $11_000
+   env.set_oracle_price(market.sol.oracle, 100);
+   (env, market, eth, user)
+}
+
+#[test]
+fn keeper_liquidates_the_most_profitable_pair() {
+   let (mut env, market, eth, user) = undercollateralized();
+   let liquidator = env.user();
+   let usdc_account = env.fund(&liquidator, &market.usdc, 20_000);
+   let eth_account = env.token_account(&liquidator.wallet, &eth.mint);
+
+   let keeper = Keeper::new(snapshot(&env), liquidator.wallet)
+      .token_account(market.usdc.mint, usdc_account)
+      .token_account(eth.mint, eth_account);
+   let plans = keeper.scan().unwrap();
+
+   // The lender and the liquidator are healthy. ETH pays a 10% bonus, SOL 5% and runs out before half of the debt
+   assert_eq!(plans.len(), 1);
+   let liquidation = &plans[0].liquidation;
+   assert_eq!(liquidation.owner, user.wallet);
+   assert_eq!((liquidation.collateral_bank, liquidation.borrowed_bank), (eth.bank, market.usdc.bank));
+   // The profit is a value, 500 base units of the 6 decimals USDC mint
+   assert_eq!((liquidation.repay_amount, liquidation.seized_amount, liquidation.profit), (5_500, 3, 500 * 100_000_000 / 1_000_000));
+
+   env.svm.process_transaction(&plans[0].instructions, &[liquidator.wallet]).unwrap();
+   assert_eq!(env.balance(&eth_account), 3);
+   assert_eq!(env.balance(&usdc_account), 20_000 - 5_500);
+   assert!(!env.health(&user).is_liquidatable());
+
+   let keeper = Keeper::new(snapshot(&env), liquidator.wallet);
+   assert!(keeper.scan().unwrap().is_empty());
+}
+
+#[test]
+fn keeper_repays_what_the_liquidator_holds() {
+   let (mut env, market, eth, user) = undercollateralized();
+   let liquidator = env.user();
+   let usdc_account = env.fund(&liquidator, &market.usdc, 2_000);
+   let sol_account = env.token_account(&liquidator.wallet, &market.sol.mint);
+   let eth_account = env.token_account(&liquidator.wallet, &eth.mint);
+
+   let keeper = |env: &TestEnv| {
+      Keeper::new(snapshot(env), liquidator.wallet)
+         .token_account(market.usdc.mint, usdc_account)
+         .token_account(market.sol.mint, sol_account)
+         .token_account(eth.mint, eth_account)
+   };
+   let plans = keeper(&env).scan().unwrap();
+   // 2_000 USDC buy 21 SOL but only 1 ETH (rounded down from 1.1), so SOL is the better pair now
+   let liquidation = &plans[0].liquidation;
+   assert_eq!(liquidation.collateral_bank, market.sol.bank);
+   assert_eq!((liquidation.repay_amount, liquidation.seized_amount), (2_000, 21));
+
+   env.svm.process_transaction(&plans[0].instructions, &[liquidator.wallet]).unwrap();
+   assert_eq!((env.balance(&usdc_account), env.balance(&sol_account)), (0, 21));
+   // Still liquidatable, the next scan has nothing to repay with
+   assert!(env.health(&user).is_liquidatable());
+   assert!(keeper(&env).scan().unwrap().is_empty());
+}
+
+// Stands for a flash loan provider: mints the repaid tokens into the liquidator account before the liquidation
+struct MintingFlashLoan {
+   mint: Pubkey,
+   authority: Pubkey,
+}
+
+impl FlashLoan for MintingFlashLoan {
+   fn wrap(&self, liquidation: &Liquidation, liquidator_token_account: &Pubkey, instructions: Vec<Instruction>) -> Vec<Instruction> {
+      let lend = spl_token::instruction::mint_to(
+         &spl_token::ID,
+         &self.mint,
+         liquidator_token_account,
+         &self.authority,
+         &[],
+         liquidation.repay_amount,
+      )
+      .unwrap();
+      std::iter::once(lend).chain(instructions).collect()
+   }
+}
+
+#[test]
+fn keeper_wraps_liquidations_in_a_flash_loan() {
+   let (mut env, market, eth, user) = undercollateralized();
+   let liquidator = env.user();
+   let usdc_account = env.token_account(&liquidator.wallet, &market.usdc.mint);
+   let eth_account = env.token_account(&liquidator.wallet, &eth.mint);
+
+   let keeper = || {
+      Keeper::new(snapshot(&env), liquidator.wallet)
+         .token_account(market.usdc.mint, usdc_account)
+         .token_account(eth.mint, eth_account)
+   };
+   // No USDC to repay with
+   assert!(keeper().scan().unwrap().is_empty());
+
+   let plans = keeper().flash_loan(MintingFlashLoan { mint: market.usdc.mint, authority: env.admin }).scan().unwrap();
+   assert_eq!(plans[0].liquidation.repay_amount, 5_500);
+   assert_eq!(plans[0].instructions.len(), 2);
+
+   let admin = env.admin;
+   env.svm.process_transaction(&plans[0].instructions, &[liquidator.wallet, admin]).unwrap();
+   assert_eq!(env.balance(&eth_account), 3);
+   assert!(!env.health(&user).is_liquidatable());
+}
+
+#[test]
+fn keeper_flags_users_to_start_their_dutch_auction() {
+   let (mut env, market, eth, user) = undercollateralized();
+   env.set_liquidation_mode(&market.sol, LiquidationMode::DutchAuction, 0, 100).unwrap();
+   env.set_liquidation_mode(&eth, LiquidationMode::DutchAuction, 0, 100).unwrap();
+   let liquidator = env.user();
+   let usdc_account = env.fund(&liquidator, &market.usdc, 20_000);
+   let eth_account = env.token_account(&liquidator.wallet, &eth.mint);
+   let keeper = |env: &TestEnv| Keeper::new(snapshot(env), liquidator.wallet).token_account(market.usdc.mint, usdc_account).token_account(eth.mint, eth_account);
+
+   // A liquidation now would start the auctions without any bonus
+   assert!(keeper(&env).scan().unwrap().is_empty());
+   let flags = keeper(&env).unflagged().unwrap();
+   assert_eq!(flags.iter().map(|(owner, _)| *owner).collect::<Vec<_>>(), vec![user.wallet]);
+   for (_, instruction) in flags {
+      env.process(instruction, &[env.admin]).unwrap();
+   }
+   assert_eq!(env.user_state(&user).unhealthy_since, env.now());
+   assert!(keeper(&env).unflagged().unwrap().is_empty());
+
+   // At the end of the auctions the bonuses are the ones of the fixed mode
+   env.warp(100);
+   let plans = keeper(&env).scan().unwrap();
+   assert_eq!(plans[0].liquidation.collateral_bank, eth.bank);
+   env.svm.process_transaction(&plans[0].instructions, &[liquidator.wallet]).unwrap();
+   assert_eq!(env.balance(&eth_account), 3);
+}
+
+#[test]
+fn crank_refreshes_the_idle_banks() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000_000);
+   let user = env.borrower(&market, 100_000);
+   env.borrow(&user, &market.usdc, 5_000_000).unwrap();
+
+   // SOL is used again after an hour, USDC stays idle for a day
+   env.warp(60 * 60);
+   env.fund(&market.lender, &market.sol, 1);
+   env.deposit(&market.lender, &market.sol, 1).unwrap();
+   env.warp(24 * 60 * 60 - 60 * 60);
+
+   let refreshes = refresh_instructions(&snapshot(&env), 23 * 60 * 60 + 1).unwrap();
+   assert_eq!(refreshes.iter().map(|(bank, _)| *bank).collect::<Vec<_>>(), vec![market.usdc.bank]);
+
+   let refreshes = refresh_instructions(&snapshot(&env), 0).unwrap();
+   assert_eq!(refreshes.iter().map(|(bank, _)| *bank).collect::<Vec<_>>(), vec![market.usdc.bank, market.sol.bank]);
+   let total_borrowed = env.bank_state(&market.usdc).total_borrowed;
+   for (_, instruction) in refreshes {
+      env.process(instruction, &[env.admin]).unwrap();
+   }
+
+   let usdc = env.bank_state(&market.usdc);
+   assert!(usdc.total_borrowed > total_borrowed);
+   assert_eq!((usdc.last_updated, usdc.last_price), (env.now(), 100_000_000));
+   assert_eq!(env.bank_state(&market.sol).last_price, 150 * 100_000_000);
+}
+
+#[test]
+fn crank_socializes_the_debt_of_users_without_collateral() {
+   let (mut env, market, eth, user) = undercollateralized();
+   assert!(bad_debt_instructions(&snapshot(&env)).unwrap().is_empty());
+
+   // ETH and SOL crash, two liquidations seize everything and leave USDC debt behind
+   env.set_oracle_price(market.sol.oracle, 10);
+   env.set_oracle_price(eth.oracle, 100);
+   let liquidator = env.user();
+   env.fund(&liquidator, &market.usdc, 2_000);
+   env.liquidate(&liquidator, &user, &eth, &market.usdc, 400).unwrap();
+   env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 500).unwrap();
+
+   let instructions = bad_debt_instructions(&snapshot(&env)).unwrap();
+   assert_eq!(instructions.iter().map(|(owner, _)| *owner).collect::<Vec<_>>(), vec![user.wallet]);
+   for (_, instruction) in instructions {
+      env.process(instruction, &[env.admin]).unwrap();
+   }
+   assert_eq!(env.bank_state(&market.usdc).total_borrowed, 0);
+   assert!(bad_debt_instructions(&snapshot(&env)).unwrap().is_empty());
+}
@@ -0,0 +1,92 @@
+use lending::error::ErrCode;
+use lending::events::{LeverageEvent, RepayWithCollateralEvent};
+use lending_tests::*;
+
+// 100 SOL of collateral and no debt, with pools between SOL and USDC at the oracle price
+fn setup(env: &mut TestEnv) -> (Market, TestUser, SwapPool, SwapPool) {
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   let buy = env.swap_pool(&market.usdc, &market.sol, 1, 150, 1_000);
+   let sell = env.swap_pool(&market.sol, &market.usdc, 150, 1, 100_000);
+   (market, user, buy, sell)
+}
+
+#[test]
+fn leverage_borrows_and_deposits_up_to_the_target_ltv() {
+   let mut env = TestEnv::new();
+   let (market, user, buy, _) = setup(&mut env);
+
+   // 15_000 borrowed and deposited again: 15_000 of debt for 30_000 of collateral
+   let meta = env.leverage(&user, &market.sol, &market.usdc, &buy, 5_000, 100).unwrap();
+
+   let user_state = env.user_state(&user);
+   assert_eq!(user_state.position(&market.sol.bank).unwrap().deposit_shares, 200);
+   assert_eq!(user_state.position(&market.usdc.bank).unwrap().borrowed_shares, 15_000);
+   assert_eq!(env.balance(&market.sol.treasury), 200);
+   assert_eq!(env.balance(&market.usdc.treasury), 35_000);
+   assert_eq!(env.wallet_balance(&user, &market.sol), 0);
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 0);
+   assert_eq!(env.health(&user).ltv(), 5_000);
+
+   let event = &events::<LeverageEvent>(&meta)[0];
+   assert_eq!((event.borrowed_amount, event.deposited_amount), (15_000, 100));
+   assert_eq!((event.target_ltv, event.ltv), (5_000, 5_000));
+   assert_eq!(event.health_factor, user_state.health_factor);
+}
+
+#[test]
+fn the_target_ltv_is_bounded_by_the_max_ltv() {
+   let mut env = TestEnv::new();
+   let (market, user, buy, _) = setup(&mut env);
+
+   assert_eq!(error_code(env.leverage(&user, &market.sol, &market.usdc, &buy, 7_501, 0)), Some(ErrCode::InvalidTargetLtv));
+   assert_eq!(error_code(env.leverage(&user, &market.sol, &market.usdc, &buy, 0, 0)), Some(ErrCode::InvalidTargetLtv));
+
+   // Already above a target of 30%
+   env.borrow(&user, &market.usdc, 6_000).unwrap();
+   assert_eq!(error_code(env.leverage(&user, &market.sol, &market.usdc, &buy, 3_000, 0)), Some(ErrCode::InvalidTargetLtv));
+   env.leverage(&user, &market.sol, &market.usdc, &buy, 7_500, 0).unwrap();
+   assert_eq!(env.health(&user).ltv(), 7_500);
+}
+
+#[test]
+fn a_bad_swap_fails_the_leverage() {
+   let mut env = TestEnv::new();
+   let (market, user, buy, _) = setup(&mut env);
+   // 45_000 borrowed at the max LTV buys 281 SOL instead of 300
+   env.set_swap_rate(&buy, 1, 160);
+
+   assert_eq!(error_code(env.leverage(&user, &market.sol, &market.usdc, &buy, 7_500, 300)), Some(ErrCode::SlippageExceeded));
+   assert_eq!(error_code(env.leverage(&user, &market.sol, &market.usdc, &buy, 7_500, 0)), Some(ErrCode::BorrowLimitExceeded));
+   assert_eq!(env.balance(&market.usdc.treasury), 50_000);
+}
+
+#[test]
+fn leverage_needs_both_banks_running() {
+   let mut env = TestEnv::new();
+   let (market, user, buy, _) = setup(&mut env);
+
+   env.set_bank_paused(&market.usdc, true).unwrap();
+   assert_eq!(error_code(env.leverage(&user, &market.sol, &market.usdc, &buy, 5_000, 0)), Some(ErrCode::BankPaused));
+}
+
+#[test]
+fn deleverage_unwinds_the_loop() {
+   let mut env = TestEnv::new();
+   let (market, user, buy, sell) = setup(&mut env);
+   env.leverage(&user, &market.sol, &market.usdc, &buy, 5_000, 0).unwrap();
+   assert_eq!(error_code(env.deleverage(&user, &market.sol, &market.usdc, &sell, 6_000, 0)), Some(ErrCode::InvalidTargetLtv));
+
+   // 67 SOL (66.7 rounded up) repay 10_050 of the 15_000
+   let meta = env.deleverage(&user, &market.sol, &market.usdc, &sell, 2_500, 0).unwrap();
+   let event = &events::<RepayWithCollateralEvent>(&meta)[0];
+   assert_eq!((event.withdrawn_amount, event.repaid_amount), (67, 10_050));
+   assert_eq!(env.health(&user).ltv(), 2_481);
+
+   // A target of 0 closes it
+   env.deleverage(&user, &market.sol, &market.usdc, &sell, 0, 4_950).unwrap();
+   let user_state = env.user_state(&user);
+   assert!(user_state.position(&market.usdc.bank).is_none());
+   assert_eq!(user_state.position(&market.sol.bank).unwrap().deposit_shares, 100);
+   assert_eq!(env.bank_state(&market.usdc).total_borrowed, 0);
+}
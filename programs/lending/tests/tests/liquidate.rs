@@ -0,0 +1,134 @@
+use lending::error::ErrCode;
+use lending::events::{BankConfigUpdated, LiquidationEvent, UserRefreshed};
+use lending::state::LiquidationMode;
+use lending_tests::*;
+
+// A user with 100 SOL of collateral and 11_000 USDC of debt, and a liquidator with USDC
+fn undercollateralized() -> (TestEnv, Market, TestUser, TestUser) {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 11_000).unwrap();
+
+   // 100 SOL * $130 * 80% = $10_400 < $11_000
+   env.set_oracle_price(market.sol.oracle, 130);
+
+   let liquidator = env.user();
+   env.fund(&liquidator, &market.usdc, 20_000);
+   (env, market, user, liquidator)
+}
+
+#[test]
+fn liquidator_repays_debt_and_seizes_collateral_with_a_bonus() {
+   let (mut env, market, user, liquidator) = undercollateralized();
+
+   let meta = env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 5_000).unwrap();
+
+   // $5_000 + 5% bonus at $130 per SOL
+   assert_eq!(env.wallet_balance(&liquidator, &market.sol), 40);
+   assert_eq!(env.wallet_balance(&liquidator, &market.usdc), 15_000);
+
+   let user_state = env.user_state(&user);
+   assert_eq!(user_state.position(&market.sol.bank).unwrap().deposit_shares, 60);
+   assert_eq!(user_state.position(&market.usdc.bank).unwrap().borrowed_shares, 6_000);
+   // 60 SOL * $130 * 80% / $6_000
+   assert_eq!(user_state.health_factor, 10_400);
+
+   let event = &events::<LiquidationEvent>(&meta)[0];
+   assert_eq!((event.repaid_amount, event.seized_amount, event.liquidation_bonus), (5_000, 40, 500));
+   assert!(event.health_factor_before < 10_000);
+   assert_eq!(event.health_factor, 10_400);
+}
+
+#[test]
+fn liquidation_is_limited_by_the_close_factor() {
+   let (mut env, market, user, liquidator) = undercollateralized();
+
+   assert_eq!(
+      error_code(env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 5_501)),
+      Some(ErrCode::OverLiquidation)
+   );
+   env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 5_500).unwrap();
+}
+
+#[test]
+fn healthy_users_can_not_be_liquidated() {
+   let (mut env, market, user, liquidator) = undercollateralized();
+   env.set_oracle_price(market.sol.oracle, 150);
+
+   assert_eq!(
+      error_code(env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 1_000)),
+      Some(ErrCode::NotUndercollateralized)
+   );
+}
+
+#[test]
+fn liquidation_works_while_the_banks_are_paused() {
+   let (mut env, market, user, liquidator) = undercollateralized();
+   env.set_bank_paused(&market.sol, true).unwrap();
+   env.set_bank_paused(&market.usdc, true).unwrap();
+
+   env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 1_000).unwrap();
+}
+
+#[test]
+fn liquidation_needs_two_different_banks() {
+   let (mut env, market, user, liquidator) = undercollateralized();
+
+   assert_eq!(
+      error_code(env.liquidate(&liquidator, &user, &market.usdc, &market.usdc, 1_000)),
+      Some(ErrCode::SameLiquidationBank)
+   );
+}
+
+#[test]
+fn dutch_auction_bonus_grows_while_the_user_stays_unhealthy() {
+   let (mut env, market, user, liquidator) = undercollateralized();
+   env.set_liquidation_mode(&market.sol, LiquidationMode::DutchAuction, 100, 600).unwrap();
+
+   // Anyone can flag the user, the auction starts at 1%
+   let meta = env.refresh_user(&user).unwrap();
+   let event = &events::<UserRefreshed>(&meta)[0];
+   assert_eq!((event.owner, event.unhealthy_since), (user.wallet, env.now()));
+   assert!(event.health_factor < 10_000);
+
+   // Half way it is 3%: $5_000 + 3% at $130 per SOL is 39.6 SOL
+   env.warp(300);
+   let meta = env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 5_000).unwrap();
+   let event = &events::<LiquidationEvent>(&meta)[0];
+   assert_eq!((event.seized_amount, event.liquidation_bonus), (39, 300));
+
+   // The user is healthy again, which ends the auction
+   assert_eq!(env.user_state(&user).unhealthy_since, 0);
+}
+
+#[test]
+fn dutch_auction_restarts_after_the_user_recovered() {
+   let (mut env, market, user, liquidator) = undercollateralized();
+   env.set_liquidation_mode(&market.sol, LiquidationMode::DutchAuction, 100, 600).unwrap();
+   env.refresh_user(&user).unwrap();
+   env.warp(600);
+
+   env.set_oracle_price(market.sol.oracle, 150);
+   env.refresh_user(&user).unwrap();
+   assert_eq!(env.user_state(&user).unhealthy_since, 0);
+
+   // Nobody flagged the user since it became unhealthy again, the liquidation does it and gets the minimum
+   env.set_oracle_price(market.sol.oracle, 130);
+   let meta = env.liquidate(&liquidator, &user, &market.sol, &market.usdc, 1_000).unwrap();
+   assert_eq!(events::<LiquidationEvent>(&meta)[0].liquidation_bonus, 100);
+   assert_eq!(env.user_state(&user).unhealthy_since, env.now());
+}
+
+#[test]
+fn dutch_auction_needs_a_duration_and_a_minimum_below_the_bonus() {
+   let (mut env, market, _, _) = undercollateralized();
+
+   assert_eq!(error_code(env.set_liquidation_mode(&market.sol, LiquidationMode::DutchAuction, 100, 0)), Some(ErrCode::InvalidBankConfig));
+   assert_eq!(error_code(env.set_liquidation_mode(&market.sol, LiquidationMode::DutchAuction, 501, 600)), Some(ErrCode::InvalidBankConfig));
+
+   let meta = env.set_liquidation_mode(&market.sol, LiquidationMode::DutchAuction, 500, 600).unwrap();
+   let event = &events::<BankConfigUpdated>(&meta)[0];
+   assert_eq!((event.liquidation_mode, event.min_liquidation_bonus, event.liquidation_auction_duration), (LiquidationMode::DutchAuction, 500, 600));
+   env.set_liquidation_mode(&market.sol, LiquidationMode::Fixed, 0, 0).unwrap();
+}
@@ -0,0 +1,128 @@
+use anchor_lang::prelude::Pubkey;
+use lending::constants::DEFAULT_ORACLE_MAX_AGE;
+use lending::error::ErrCode;
+use lending::oracle::{PythPrice, PYTH_STATUS_TRADING};
+use lending::events::BankConfigUpdated;
+use lending_client::DepositBuilder;
+use lending_tests::*;
+
+// SOL is priced by its Pyth oracle at $150, a Switchboard-style feed at $140 and an AMM TWAP at $144
+fn three_sources(tolerance_bps: u64) -> (TestEnv, Market, [Pubkey; 2]) {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let secondary_oracles = [env.create_switchboard_oracle(140), env.create_twap_oracle(144)];
+   env.set_bank_oracles(&market.sol, &secondary_oracles, tolerance_bps).unwrap();
+   (env, market, secondary_oracles)
+}
+
+#[test]
+fn secondary_oracles_are_configured_by_the_authority() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+   let secondary_oracle = env.create_switchboard_oracle(150);
+
+   // The same source twice, an account that isn't an oracle and a tolerance above 100%
+   assert_eq!(error_code(env.set_bank_oracles(&market.sol, &[market.sol.oracle], 0)), Some(ErrCode::InvalidBankConfig));
+   assert_eq!(error_code(env.set_bank_oracles(&market.sol, &[secondary_oracle, secondary_oracle], 0)), Some(ErrCode::InvalidBankConfig));
+   assert_eq!(error_code(env.set_bank_oracles(&market.sol, &[market.usdc.bank], 0)), Some(ErrCode::InvalidOracle));
+   assert_eq!(error_code(env.set_bank_oracles(&market.sol, &[secondary_oracle], 10_001)), Some(ErrCode::InvalidBankConfig));
+
+   let meta = env.set_bank_oracles(&market.sol, &[secondary_oracle], 300).unwrap();
+   let event = &events::<BankConfigUpdated>(&meta)[0];
+   assert_eq!((event.secondary_oracles, event.oracle_tolerance_bps), ([secondary_oracle, Pubkey::default()], 300));
+   assert_eq!(env.bank_state(&market.sol).oracles(), vec![market.sol.oracle, secondary_oracle]);
+
+   env.set_bank_oracles(&market.sol, &[], 0).unwrap();
+   assert_eq!(env.bank_state(&market.sol).oracles(), vec![market.sol.oracle]);
+}
+
+#[test]
+fn price_is_the_median_of_the_oracles() {
+   let (mut env, market, _) = three_sources(0);
+   env.refresh_bank(&market.sol).unwrap();
+   assert_eq!(env.bank_state(&market.sol).last_price, 144 * 100_000_000);
+
+   // 100 SOL * $144 * 75%, SOL is read from the remaining accounts with its secondary oracles
+   let user = env.borrower(&market, 100);
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 10_801)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &market.usdc, 10_800).unwrap();
+}
+
+#[test]
+fn secondary_oracles_take_over_when_the_primary_is_stale() {
+   let (mut env, market, secondary_oracles) = three_sources(0);
+   let user = env.borrower(&market, 100);
+   env.publish_oracle(market.sol.oracle, env.now() - DEFAULT_ORACLE_MAX_AGE as i64 - 1);
+
+   // The average of $140 and $144
+   env.refresh_bank(&market.sol).unwrap();
+   let bank = env.bank_state(&market.sol);
+   assert_eq!((bank.last_price, bank.last_price_updated), (142 * 100_000_000, env.now()));
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 10_651)), Some(ErrCode::BorrowLimitExceeded));
+   env.borrow(&user, &market.usdc, 10_650).unwrap();
+
+   // Without any fresh source the price is as stale as the primary oracle
+   for oracle in secondary_oracles {
+      env.publish_oracle(oracle, env.now() - DEFAULT_ORACLE_MAX_AGE as i64 - 1);
+   }
+   assert_eq!(error_code(env.refresh_bank(&market.sol)), Some(ErrCode::StaleOracle));
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 1)), Some(ErrCode::StaleOracle));
+}
+
+#[test]
+fn diverging_oracles_are_rejected() {
+   // $150 and $140 are within 5% of $144
+   let (mut env, market, [_, twap]) = three_sources(500);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 1_000).unwrap();
+
+   // The median moves to $150 and $140 is 6.7% away from it
+   env.set_oracle_price(twap, 160);
+   assert_eq!(error_code(env.refresh_bank(&market.sol)), Some(ErrCode::OracleDivergence));
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 1_000)), Some(ErrCode::OracleDivergence));
+   assert_eq!(error_code(env.withdraw(&user, &market.sol, 1)), Some(ErrCode::OracleDivergence));
+}
+
+#[test]
+fn instructions_need_every_oracle_of_the_bank_in_order() {
+   let (mut env, market, [switchboard, twap]) = three_sources(0);
+   let user = env.user();
+   let user_token_account = env.fund(&user, &market.sol, 100);
+
+   let deposit = |accounts| DepositBuilder::new(user.wallet, &accounts, 100).user_token_account(user_token_account).instruction();
+   let ix = deposit(market.sol.accounts());
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::MissingSecondaryOracle));
+   let ix = deposit(market.sol.accounts().with_secondary_oracles([Some(twap), Some(switchboard)]));
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::InvalidOracle));
+
+   let ix = deposit(env.bank_accounts(&market.sol));
+   env.process(ix, &[user.wallet]).unwrap();
+}
+
+#[test]
+fn a_price_too_small_for_price_decimals_is_invalid() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+   let user = env.borrower(&market, 1);
+
+   // 5e-12 would read as 0 with 8 decimals, and a debt worth 0 has no limit
+   let now = env.now();
+   env.set_oracle(market.usdc.oracle, PythPrice { price: 5, conf: 0, expo: -12, publish_time: now, status: PYTH_STATUS_TRADING });
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 1_000)), Some(ErrCode::InvalidOracle));
+}
+
+#[test]
+fn a_price_with_a_wide_confidence_interval_is_rejected() {
+   let mut env = TestEnv::new();
+   let market = env.market(1_000);
+   let user = env.borrower(&market, 1);
+
+   // $1 +- 3 cents
+   let now = env.now();
+   let price = PythPrice { price: 100_000_000, conf: 3_000_000, expo: -8, publish_time: now, status: PYTH_STATUS_TRADING };
+   env.set_oracle(market.usdc.oracle, price);
+   assert_eq!(error_code(env.borrow(&user, &market.usdc, 10)), Some(ErrCode::OracleConfidenceTooWide));
+
+   env.set_oracle(market.usdc.oracle, PythPrice { conf: 2_000_000, ..price });
+   env.borrow(&user, &market.usdc, 10).unwrap();
+}
@@ -0,0 +1,95 @@
+use lending::constants::DEFAULT_ORACLE_MAX_AGE;
+use lending::error::ErrCode;
+use lending::events::RepayEvent;
+use lending_client::RepayBuilder;
+use lending_tests::*;
+
+const ONE_YEAR: i64 = 365 * 24 * 60 * 60;
+
+#[test]
+fn repay_removes_the_debt_shares() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 10_000).unwrap();
+
+   let meta = env.repay(&user, &market.usdc, 4_000).unwrap();
+
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 6_000);
+   assert_eq!(env.balance(&market.usdc.treasury), 44_000);
+   assert_eq!(env.user_state(&user).position(&market.usdc.bank).unwrap().borrowed_shares, 6_000);
+   assert_eq!(events::<RepayEvent>(&meta)[0].shares, 4_000);
+}
+
+#[test]
+fn repaying_the_whole_debt_includes_the_interest() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 10_000).unwrap();
+
+   env.warp(ONE_YEAR);
+   env.fund(&user, &market.usdc, 1_000);
+
+   // 5% compounded over a year
+   assert_eq!(error_code(env.repay(&user, &market.usdc, 10_513)), Some(ErrCode::OverRepay));
+   env.repay(&user, &market.usdc, 10_512).unwrap();
+
+   assert!(env.user_state(&user).position(&market.usdc.bank).is_none());
+   let bank = env.bank_state(&market.usdc);
+   assert_eq!((bank.total_borrowed, bank.total_borrowed_shares), (0, 0));
+   assert_eq!(bank.total_deposits, 50_512);
+}
+
+#[test]
+fn repay_works_while_the_bank_is_paused() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 10_000).unwrap();
+
+   env.set_bank_paused(&market.usdc, true).unwrap();
+   env.repay(&user, &market.usdc, 10_000).unwrap();
+}
+
+#[test]
+fn repay_does_not_need_prices() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 10_000).unwrap();
+   let health_factor = env.user_state(&user).health_factor;
+
+   // Without fresh oracles the debt is repaid and the health cached on the user is left as it was
+   let stale = env.now() - DEFAULT_ORACLE_MAX_AGE as i64 - 1;
+   env.publish_oracle(market.sol.oracle, stale);
+   env.publish_oracle(market.usdc.oracle, stale);
+   let meta = env.repay(&user, &market.usdc, 4_000).unwrap();
+   let event = &events::<RepayEvent>(&meta)[0];
+   assert_eq!((event.price, event.health_factor), (0, health_factor));
+   assert_eq!(env.user_state(&user).health_factor, health_factor);
+
+   // Nor without the accounts of the other positions
+   let now = env.now();
+   env.publish_oracle(market.sol.oracle, now);
+   env.publish_oracle(market.usdc.oracle, now);
+   let user_token_account = env.token_account(&user.wallet, &market.usdc.mint);
+   let ix = RepayBuilder::new(user.wallet, &market.usdc.accounts(), 1_000).user_token_account(user_token_account).instruction();
+   env.process(ix, &[user.wallet]).unwrap();
+   assert_eq!(env.user_state(&user).position(&market.usdc.bank).unwrap().borrowed_shares, 5_000);
+
+   // With them the health is refreshed
+   env.repay(&user, &market.usdc, 1_000).unwrap();
+   assert!(env.user_state(&user).health_factor > health_factor);
+}
+
+#[test]
+fn repay_without_debt_is_rejected() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.fund(&user, &market.usdc, 100);
+
+   assert_eq!(error_code(env.repay(&user, &market.usdc, 1)), Some(ErrCode::OverRepay));
+   assert_eq!(error_code(env.repay(&user, &market.usdc, 0)), Some(ErrCode::InvalidAmount));
+}
@@ -0,0 +1,148 @@
+use anchor_lang::prelude::Pubkey;
+use lending::error::ErrCode;
+use lending::events::RepayWithCollateralEvent;
+use lending_client::RepayWithCollateralBuilder;
+use lending_tests::*;
+
+// A borrower of 10_000 USDC against 100 SOL, and a pool that sells SOL at the oracle price
+fn setup(env: &mut TestEnv) -> (Market, TestUser, SwapPool) {
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 10_000).unwrap();
+   let pool = env.swap_pool(&market.sol, &market.usdc, 150, 1, 100_000);
+   (market, user, pool)
+}
+
+#[test]
+fn repay_with_collateral_swaps_the_collateral_into_the_repayment() {
+   let mut env = TestEnv::new();
+   let (market, user, pool) = setup(&mut env);
+   // Another position, its accounts go before the ones of the swap
+   let eth = env.bank().price(2_000).build();
+   env.fund(&user, &eth, 1);
+   env.deposit(&user, &eth, 1).unwrap();
+
+   let meta = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 20, 2_900).unwrap();
+
+   let user_state = env.user_state(&user);
+   assert_eq!(user_state.position(&market.sol.bank).unwrap().deposit_shares, 80);
+   assert_eq!(user_state.position(&market.usdc.bank).unwrap().borrowed_shares, 7_000);
+   assert_eq!(env.balance(&market.sol.treasury), 80);
+   assert_eq!(env.balance(&market.usdc.treasury), 43_000);
+   assert_eq!(env.balance(&pool.input_vault), 20);
+   // The borrowed tokens never moved, and nothing is left from the swap
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 10_000);
+   assert_eq!(env.wallet_balance(&user, &market.sol), 0);
+
+   let event = &events::<RepayWithCollateralEvent>(&meta)[0];
+   assert_eq!((event.withdrawn_amount, event.swapped_amount, event.repaid_amount), (20, 3_000, 3_000));
+   assert_eq!(event.swap_program, MOCK_SWAP_ID);
+   assert!(event.health_factor > event.health_factor_before);
+   assert_eq!(user_state.health_factor, event.health_factor);
+}
+
+#[test]
+fn what_the_debt_does_not_need_stays_with_the_user() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 1_000).unwrap();
+   let pool = env.swap_pool(&market.sol, &market.usdc, 150, 1, 100_000);
+
+   let meta = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 10, 1_500).unwrap();
+
+   assert!(env.user_state(&user).position(&market.usdc.bank).is_none());
+   assert_eq!(env.bank_state(&market.usdc).total_borrowed, 0);
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 1_500);
+   assert_eq!(events::<RepayWithCollateralEvent>(&meta)[0].repaid_amount, 1_000);
+}
+
+#[test]
+fn repay_with_collateral_checks_the_minimum_out() {
+   let mut env = TestEnv::new();
+   let (market, user, pool) = setup(&mut env);
+   env.set_swap_rate(&pool, 140, 1);
+
+   let result = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 20, 2_900);
+   assert_eq!(error_code(result), Some(ErrCode::SlippageExceeded));
+
+   env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 20, 2_800).unwrap();
+   assert_eq!(env.user_state(&user).position(&market.usdc.bank).unwrap().borrowed_shares, 7_200);
+}
+
+#[test]
+fn a_swap_that_leaves_the_user_above_the_borrow_limit_is_rejected() {
+   let mut env = TestEnv::new();
+   let (market, user, pool) = setup(&mut env);
+   // 30 SOL sold for 30 USDC: 70 SOL (7_875 borrow limit) for 9_970 of debt
+   env.set_swap_rate(&pool, 1, 1);
+
+   let result = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 30, 0);
+   assert_eq!(error_code(result), Some(ErrCode::HealthDecreased));
+}
+
+#[test]
+fn an_unhealthy_user_can_unwind_if_it_gets_healthier() {
+   let mut env = TestEnv::new();
+   let (market, user, pool) = setup(&mut env);
+   // 12_000 of collateral for 10_000 of debt: above the borrow limit (9_000) and liquidatable
+   env.set_oracle_price(market.sol.oracle, 120);
+   env.set_swap_rate(&pool, 120, 1);
+   assert_eq!(error_code(env.withdraw(&user, &market.sol, 1)), Some(ErrCode::WithdrawExceedsBorrowLimit));
+
+   // Still above the limit after it (7_600 of debt for 7_200), but no longer liquidatable
+   env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 20, 2_400).unwrap();
+   assert!(!env.health(&user).is_liquidatable());
+
+   // A worse price would have made it less healthy
+   env.set_swap_rate(&pool, 60, 1);
+   let result = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 20, 0);
+   assert_eq!(error_code(result), Some(ErrCode::HealthDecreased));
+}
+
+#[test]
+fn an_adapter_that_takes_more_than_the_collateral_is_rejected() {
+   let mut env = TestEnv::new();
+   let (market, user, pool) = setup(&mut env);
+   env.fund(&user, &market.sol, 5);
+   let state = env.mock_pool(&pool);
+   env.set_mock_pool(&pool, MockPool { extra_in: 1, ..state });
+
+   let result = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 20, 0);
+   assert_eq!(error_code(result), Some(ErrCode::InvalidSwapAdapter));
+   assert_eq!(env.wallet_balance(&user, &market.sol), 5);
+}
+
+#[test]
+fn repay_with_collateral_rejects_invalid_banks_and_adapters() {
+   let mut env = TestEnv::new();
+   let (market, user, pool) = setup(&mut env);
+
+   let result = env.repay_with_collateral(&user, &market.sol, &market.sol, &pool, 20, 0);
+   assert_eq!(error_code(result), Some(ErrCode::SameSwapBank));
+   let result = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 101, 0);
+   assert_eq!(error_code(result), Some(ErrCode::InsufficientFunds));
+   let result = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 0, 0);
+   assert_eq!(error_code(result), Some(ErrCode::InvalidAmount));
+
+   // The program can't be its own adapter
+   let collateral_token_account = env.token_account(&user.wallet, &market.sol.mint);
+   let borrowed_token_account = env.token_account(&user.wallet, &market.usdc.mint);
+   let ix = RepayWithCollateralBuilder::new(user.wallet, &market.sol.accounts(), &market.usdc.accounts(), 20, 0, lending::ID)
+      .user_token_accounts(collateral_token_account, borrowed_token_account)
+      .swap_accounts(pool.accounts())
+      .instruction();
+   assert_eq!(error_code(env.process(ix, &[user.wallet])), Some(ErrCode::InvalidSwapAdapter));
+
+   // Withdrawing from a paused bank is not allowed
+   env.set_bank_paused(&market.sol, true).unwrap();
+   let result = env.repay_with_collateral(&user, &market.sol, &market.usdc, &pool, 20, 0);
+   assert_eq!(error_code(result), Some(ErrCode::BankPaused));
+
+   // Nor is a program that isn't one
+   env.set_bank_paused(&market.sol, false).unwrap();
+   let ix = RepayWithCollateralBuilder::new(user.wallet, &market.sol.accounts(), &market.usdc.accounts(), 20, 0, Pubkey::new_unique())
+      .user_token_accounts(collateral_token_account, borrowed_token_account)
+      .instruction();
+   assert!(env.process(ix, &[user.wallet]).is_err());
+}
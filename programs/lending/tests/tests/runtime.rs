@@ -0,0 +1,126 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{entrypoint::ProgramResult, instruction::Instruction, system_program};
+use lending_client::{RefreshBankBuilder, SwapCollateralBuilder};
+use lending_tests::svm::{Account, TransactionError};
+use lending_tests::*;
+
+/*
+   The test runtime has to refuse what the validator refuses, or the tests of the program would pass
+   on things that fail on-chain. A small program breaks one rule per instruction, picked by its first byte,
+   on the accounts it gets: 0 writes the data of the first one, 1 moves a lamport from the first to the
+   second one, 2 adds a lamport to the first one.
+*/
+const RULE_BREAKER_ID: Pubkey = pubkey!("RuLeBreaker11111111111111111111111111111111");
+
+fn process_rule_breaker(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+   match data[0] {
+      0 => accounts[0].try_borrow_mut_data()?[0] ^= 1,
+      1 => {
+         **accounts[0].try_borrow_mut_lamports()? -= 1;
+         **accounts[1].try_borrow_mut_lamports()? += 1;
+      }
+      _ => **accounts[0].try_borrow_mut_lamports()? += 1,
+   }
+   Ok(())
+}
+
+fn break_rule(env: &mut TestEnv, rule: u8, accounts: &[Pubkey]) -> std::result::Result<(), TransactionError> {
+   env.svm.add_program(RULE_BREAKER_ID, process_rule_breaker);
+   let metas = accounts.iter().map(|key| AccountMeta::new(*key, false)).collect();
+   let payer = Pubkey::new_unique();
+   env.process(Instruction { program_id: RULE_BREAKER_ID, accounts: metas, data: vec![rule] }, &[payer]).map(|_| ())
+}
+
+fn account(env: &mut TestEnv, owner: Pubkey) -> Pubkey {
+   let key = Pubkey::new_unique();
+   env.svm.set_account(key, Account { lamports: 1_000_000, data: vec![0; 8], owner, executable: false });
+   key
+}
+
+fn logged(result: std::result::Result<(), TransactionError>, message: &str) -> bool {
+   result.unwrap_err().logs.iter().any(|log| log.contains(message))
+}
+
+#[test]
+fn programs_only_write_the_data_of_accounts_they_own() {
+   let mut env = TestEnv::new();
+   let mine = account(&mut env, RULE_BREAKER_ID);
+   let theirs = account(&mut env, system_program::ID);
+
+   break_rule(&mut env, 0, &[mine]).unwrap();
+   assert!(logged(break_rule(&mut env, 0, &[theirs]), "modified data of an account it does not own"));
+}
+
+#[test]
+fn programs_only_spend_the_lamports_of_accounts_they_own() {
+   let mut env = TestEnv::new();
+   let mine = account(&mut env, RULE_BREAKER_ID);
+   let theirs = account(&mut env, system_program::ID);
+
+   // Anyone can be paid, only the owner pays
+   break_rule(&mut env, 1, &[mine, theirs]).unwrap();
+   assert!(logged(break_rule(&mut env, 1, &[theirs, mine]), "spent from the balance of an account it does not own"));
+}
+
+#[test]
+fn lamports_are_neither_created_nor_destroyed() {
+   let mut env = TestEnv::new();
+   let mine = account(&mut env, RULE_BREAKER_ID);
+
+   assert!(logged(break_rule(&mut env, 2, &[mine]), "sum of account balances before and after instruction do not match"));
+}
+
+#[test]
+fn accounts_stay_rent_exempt() {
+   let mut env = TestEnv::new();
+   let minimum = Rent::default().minimum_balance(8);
+   let exact = account(&mut env, RULE_BREAKER_ID);
+   env.svm.set_account(exact, Account { lamports: minimum, data: vec![0; 8], owner: RULE_BREAKER_ID, executable: false });
+   let other = account(&mut env, RULE_BREAKER_ID);
+
+   // Paying the exact account keeps it exempt, paying from it doesn't
+   break_rule(&mut env, 1, &[other, exact]).unwrap();
+   break_rule(&mut env, 1, &[exact, other]).unwrap();
+   let result = break_rule(&mut env, 1, &[exact, other]);
+   assert_eq!(result.as_ref().unwrap_err().instruction, None);
+   assert!(logged(result, &format!("Transaction results in an account ({exact}) with insufficient funds for rent")));
+}
+
+#[test]
+fn transactions_fit_in_a_packet() {
+   let mut env = TestEnv::new();
+   let mine = account(&mut env, RULE_BREAKER_ID);
+   let payer = Pubkey::new_unique();
+   env.svm.add_program(RULE_BREAKER_ID, process_rule_breaker);
+   // Rule 0 with a payload, the rest of the data is ignored
+   let ix = |len: usize| Instruction { program_id: RULE_BREAKER_ID, accounts: vec![AccountMeta::new(mine, false)], data: vec![0; len] };
+
+   env.process(ix(1_000), &[payer]).unwrap();
+   assert!(logged(env.process(ix(1_200), &[payer]).map(|_| ()), "encoded transaction too large"));
+}
+
+#[test]
+fn swap_adapters_cannot_call_back_into_the_lending_program() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+   let pool = env.swap_pool(&market.sol, &market.usdc, 150, 1, 100_000);
+
+   // The same swap goes through when the adapter doesn't call back
+   let swap = |env: &mut TestEnv, callback: Vec<AccountMeta>| {
+      let from_token_account = env.token_account(&user.wallet, &market.sol.mint);
+      let to_token_account = env.token_account(&user.wallet, &market.usdc.mint);
+      let ix = SwapCollateralBuilder::new(user.wallet, &env.bank_accounts(&market.sol), &env.bank_accounts(&market.usdc), 10, 1_500, MOCK_SWAP_ID)
+         .user_token_accounts(from_token_account, to_token_account)
+         .positions(env.position_accounts(&user, &[&market.sol, &market.usdc]))
+         .swap_accounts([pool.accounts(), callback].concat())
+         .instruction();
+      env.process(ix, &[user.wallet]).map(|_| ())
+   };
+   let callback = RefreshBankBuilder::new(&env.bank_accounts(&market.sol)).instruction().accounts;
+   assert!(logged(swap(&mut env, callback), "Cross-program invocation reentrancy not allowed for this instruction"));
+   assert_eq!(env.user_state(&user).position(&market.sol.bank).unwrap().deposit_shares, 100);
+
+   swap(&mut env, Vec::new()).unwrap();
+   assert_eq!(env.user_state(&user).position(&market.sol.bank).unwrap().deposit_shares, 90);
+}
@@ -0,0 +1,123 @@
+use lending::error::ErrCode;
+use lending::events::{BankConfigUpdated, SelfLiquidationEvent};
+use lending_tests::*;
+
+// A user with 10_000 SOL and 1_100_000 USDC of debt, self liquidation of SOL below a health factor of 1.1 for 1%,
+// and a pool that sells SOL at the oracle price
+fn near_liquidation(sol_price: u64) -> (TestEnv, Market, TestUser, SwapPool) {
+   let mut env = TestEnv::new();
+   let market = env.market(2_000_000);
+   let user = env.borrower(&market, 10_000);
+   env.borrow(&user, &market.usdc, 1_100_000).unwrap();
+   env.set_self_liquidation(&market.sol, 11_000, 100).unwrap();
+   env.set_oracle_price(market.sol.oracle, sol_price);
+   let pool = env.swap_pool(&market.sol, &market.usdc, sol_price, 1, 1_000_000);
+   (env, market, user, pool)
+}
+
+#[test]
+fn user_repays_with_its_collateral_for_a_small_penalty() {
+   // 10_000 SOL * $140 * 80% / $1_100_000 = 1.018
+   let (mut env, market, user, pool) = near_liquidation(140);
+
+   let meta = env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 420_000, 420_000).unwrap();
+
+   // $420_000 is 3_000 SOL, 30 more are the penalty
+   let event = &events::<SelfLiquidationEvent>(&meta)[0];
+   assert_eq!((event.repaid_amount, event.seized_amount, event.penalty_amount), (420_000, 3_030, 30));
+   assert_eq!((event.swapped_amount, event.swap_program), (420_000, MOCK_SWAP_ID));
+   assert_eq!(event.health_factor_before, 10_181);
+   // The 3_000 SOL were sold for the repayment, the wallet didn't pay anything
+   assert_eq!(env.balance(&pool.input_vault), 3_000);
+   assert_eq!(env.wallet_balance(&user, &market.sol), 0);
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 1_100_000);
+
+   // 6_970 SOL * $140 * 80% / $680_000
+   let user_state = env.user_state(&user);
+   assert_eq!(user_state.position(&market.sol.bank).unwrap().deposit_shares, 6_970);
+   assert_eq!(user_state.position(&market.usdc.bank).unwrap().borrowed_shares, 680_000);
+   assert_eq!((user_state.health_factor, event.health_factor), (11_480, 11_480));
+
+   // The penalty stays in the treasury for the insurance fund of the SOL bank
+   let sol_bank = env.bank_state(&market.sol);
+   assert_eq!((sol_bank.total_deposits, sol_bank.insurance_fees), (6_970, 30));
+   assert_eq!(env.balance(&market.sol.treasury), 7_000);
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user]).unwrap();
+}
+
+#[test]
+fn liquidatable_users_can_self_liquidate_as_well() {
+   // 10_000 SOL * $130 * 80% / $1_100_000 = 0.945
+   let (mut env, market, user, pool) = near_liquidation(130);
+   env.refresh_user(&user).unwrap();
+   assert!(env.user_state(&user).unhealthy_since > 0);
+
+   env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 400_000, 0).unwrap();
+
+   let user_state = env.user_state(&user);
+   assert!(user_state.health_factor > 10_000);
+   assert_eq!(user_state.unhealthy_since, 0);
+}
+
+#[test]
+fn self_liquidation_needs_a_health_factor_below_the_soft_threshold() {
+   // 10_000 SOL * $150 * 80% / $1_100_000 = 1.09
+   let (mut env, market, user, pool) = near_liquidation(150);
+   env.set_self_liquidation(&market.sol, 10_500, 100).unwrap();
+   assert_eq!(error_code(env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 1_000, 0)), Some(ErrCode::NotSoftLiquidatable));
+
+   env.set_self_liquidation(&market.sol, 0, 0).unwrap();
+   env.set_oracle_price(market.sol.oracle, 130);
+   assert_eq!(error_code(env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 1_000, 0)), Some(ErrCode::NotSoftLiquidatable));
+}
+
+#[test]
+fn self_liquidation_is_limited_by_the_close_factor() {
+   let (mut env, market, user, pool) = near_liquidation(140);
+
+   assert_eq!(error_code(env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 550_001, 0)), Some(ErrCode::OverLiquidation));
+   env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 550_000, 0).unwrap();
+}
+
+#[test]
+fn the_wallet_covers_what_the_swap_returns_below_the_repayment() {
+   let (mut env, market, user, _) = near_liquidation(140);
+   // 3_000 SOL only get 414_000 USDC from this pool
+   let pool = env.swap_pool(&market.sol, &market.usdc, 138, 1, 1_000_000);
+
+   let result = env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 420_000, 420_000);
+   assert_eq!(error_code(result), Some(ErrCode::SlippageExceeded));
+
+   let meta = env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 420_000, 414_000).unwrap();
+   assert_eq!(events::<SelfLiquidationEvent>(&meta)[0].swapped_amount, 414_000);
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 1_094_000);
+   assert_eq!(env.user_state(&user).position(&market.usdc.bank).unwrap().borrowed_shares, 680_000);
+
+   // A better price than the oracle leaves the difference with the user, at 1.148 it needs a higher threshold
+   env.set_self_liquidation(&market.sol, 12_000, 100).unwrap();
+   let pool = env.swap_pool(&market.sol, &market.usdc, 141, 1, 1_000_000);
+   env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 140_000, 140_000).unwrap();
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 1_095_000);
+   check_accounting(&env, &[market.sol, market.usdc], &[market.lender, user]).unwrap();
+}
+
+#[test]
+fn self_liquidation_can_not_make_the_user_less_healthy() {
+   // 10_000 SOL * $90 * 80% / $1_100_000 = 0.65, below (1 + 5%) * 80% every repayment lowers it
+   let (mut env, market, user, pool) = near_liquidation(90);
+   env.set_self_liquidation(&market.sol, 11_000, 500).unwrap();
+
+   assert_eq!(error_code(env.self_liquidate(&user, &market.sol, &market.usdc, &pool, 100_000, 0)), Some(ErrCode::HealthDecreased));
+}
+
+#[test]
+fn self_liquidation_penalty_is_at_most_the_liquidation_bonus() {
+   let (mut env, market, _, _) = near_liquidation(150);
+
+   assert_eq!(error_code(env.set_self_liquidation(&market.sol, 9_999, 100)), Some(ErrCode::InvalidBankConfig));
+   assert_eq!(error_code(env.set_self_liquidation(&market.sol, 11_000, 501)), Some(ErrCode::InvalidBankConfig));
+
+   let meta = env.set_self_liquidation(&market.sol, 10_500, 200).unwrap();
+   let event = &events::<BankConfigUpdated>(&meta)[0];
+   assert_eq!((event.soft_liquidation_health, event.self_liquidation_penalty), (10_500, 200));
+}
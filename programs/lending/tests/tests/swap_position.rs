@@ -0,0 +1,166 @@
+use lending::error::ErrCode;
+use lending::events::{CollateralSwapEvent, DebtSwapEvent};
+use lending_tests::*;
+
+// A borrower of 10_000 USDC against 100 SOL, and an ETH bank at $2_000 with 100 ETH to lend
+fn setup(env: &mut TestEnv) -> (Market, TestBank, TestUser) {
+   let market = env.market(50_000);
+   let eth = env.bank().price(2_000).build();
+   env.fund(&market.lender, &eth, 100);
+   env.deposit(&market.lender, &eth, 100).unwrap();
+
+   let user = env.borrower(&market, 100);
+   env.borrow(&user, &market.usdc, 10_000).unwrap();
+   (market, eth, user)
+}
+
+#[test]
+fn swap_collateral_moves_the_deposit_into_another_bank() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   let pool = env.swap_pool(&market.sol, &eth, 3, 40, 100);
+
+   // 40 SOL for 3 ETH
+   let meta = env.swap_collateral(&user, &market.sol, &eth, &pool, 40, 3).unwrap();
+
+   let user_state = env.user_state(&user);
+   assert_eq!(user_state.position(&market.sol.bank).unwrap().deposit_shares, 60);
+   assert_eq!(user_state.position(&eth.bank).unwrap().deposit_shares, 3);
+   assert_eq!(env.balance(&market.sol.treasury), 60);
+   assert_eq!(env.balance(&eth.treasury), 103);
+   assert_eq!(env.wallet_balance(&user, &market.sol), 0);
+   assert_eq!(env.wallet_balance(&user, &eth), 0);
+
+   let event = &events::<CollateralSwapEvent>(&meta)[0];
+   assert_eq!((event.withdrawn_amount, event.deposited_amount), (40, 3));
+   assert_eq!((event.from_deposit_before, event.from_deposit_after), (100, 60));
+   assert_eq!((event.to_deposit_before, event.to_deposit_after), (0, 3));
+   assert_eq!(event.health_factor, user_state.health_factor);
+}
+
+#[test]
+fn swap_collateral_has_to_keep_the_user_above_the_liquidation_threshold() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   // 40 SOL for a single ETH: 11_000 of collateral (8_800 weighted) for 10_000 of debt
+   let pool = env.swap_pool(&market.sol, &eth, 1, 40, 100);
+
+   assert_eq!(error_code(env.swap_collateral(&user, &market.sol, &eth, &pool, 40, 3)), Some(ErrCode::SlippageExceeded));
+   assert_eq!(error_code(env.swap_collateral(&user, &market.sol, &eth, &pool, 40, 0)), Some(ErrCode::LiquidatableAfterSwap));
+
+   // Not liquidatable isn't enough either: 2 ETH give 13_000 (10_400 weighted) but a borrow limit of 9_750
+   env.set_swap_rate(&pool, 2, 40);
+   assert_eq!(error_code(env.swap_collateral(&user, &market.sol, &eth, &pool, 40, 0)), Some(ErrCode::HealthDecreased));
+}
+
+#[test]
+fn a_user_above_the_borrow_limit_can_only_swap_collateral_into_a_healthier_position() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   // 100 SOL at $128: 12_800 of collateral, a borrow limit of 9_600 for 10_000 of debt, liquidated below 10_240
+   env.set_oracle_price(market.sol.oracle, 128);
+   env.set_oracle_price(eth.oracle, 130);
+   let pool = env.swap_pool(&market.sol, &eth, 9, 10, 100);
+   let health_before = env.health(&user).health_factor();
+
+   // 10 SOL (1_280) for 9 ETH (1_170)
+   assert_eq!(error_code(env.swap_collateral(&user, &market.sol, &eth, &pool, 10, 0)), Some(ErrCode::HealthDecreased));
+
+   // 10 SOL for 11 ETH (1_430): 12_950 of collateral, still above the borrow limit
+   env.set_swap_rate(&pool, 11, 10);
+   env.swap_collateral(&user, &market.sol, &eth, &pool, 10, 0).unwrap();
+   assert!(!env.health(&user).is_within_borrow_limit());
+   assert!(env.health(&user).health_factor() > health_before);
+}
+
+#[test]
+fn a_swap_adapter_that_keeps_the_collateral_cannot_withdraw_past_the_borrow_limit() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   // A single ETH for 28 SOL, the adapter of the user keeps 2_200 of collateral:
+   // 72 * 150 + 2_000 = 12_800 can't be liquidated (10_240 weighted) but is above the limit (9_600)
+   let pool = env.swap_pool(&market.sol, &eth, 1, 28, 100);
+
+   assert_eq!(error_code(env.swap_collateral(&user, &market.sol, &eth, &pool, 28, 0)), Some(ErrCode::HealthDecreased));
+   assert_eq!(env.user_state(&user).position(&market.sol.bank).unwrap().deposit_shares, 100);
+}
+
+#[test]
+fn swap_collateral_withdraws_so_it_stops_while_paused() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   let pool = env.swap_pool(&market.sol, &eth, 3, 40, 100);
+
+   env.set_bank_paused(&market.sol, true).unwrap();
+   assert_eq!(error_code(env.swap_collateral(&user, &market.sol, &eth, &pool, 40, 0)), Some(ErrCode::BankPaused));
+   env.set_bank_paused(&market.sol, false).unwrap();
+   env.set_bank_paused(&eth, true).unwrap();
+   assert_eq!(error_code(env.swap_collateral(&user, &market.sol, &eth, &pool, 40, 0)), Some(ErrCode::BankPaused));
+   assert_eq!(error_code(env.swap_collateral(&user, &market.sol, &market.sol, &pool, 40, 0)), Some(ErrCode::SameSwapBank));
+}
+
+#[test]
+fn swap_debt_refinances_the_debt_in_another_bank() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   let pool = env.swap_pool(&eth, &market.usdc, 2_000, 1, 100_000);
+   // Repaying works while paused, so does refinancing out of the bank
+   env.set_bank_paused(&market.usdc, true).unwrap();
+
+   let meta = env.swap_debt(&user, &market.usdc, &eth, &pool, 5, 10_000).unwrap();
+
+   let user_state = env.user_state(&user);
+   assert!(user_state.position(&market.usdc.bank).is_none());
+   assert_eq!(user_state.position(&eth.bank).unwrap().borrowed_shares, 5);
+   assert_eq!(env.balance(&market.usdc.treasury), 50_000);
+   assert_eq!(env.balance(&eth.treasury), 95);
+   // The 10_000 USDC borrowed at first are still in the wallet
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 10_000);
+
+   let event = &events::<DebtSwapEvent>(&meta)[0];
+   assert_eq!((event.borrowed_amount, event.repaid_amount), (5, 10_000));
+   assert_eq!((event.from_debt_before, event.from_debt_after), (10_000, 0));
+   assert_eq!((event.to_debt_before, event.to_debt_after), (0, 5));
+}
+
+#[test]
+fn what_the_old_debt_does_not_need_stays_with_the_user() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   let pool = env.swap_pool(&eth, &market.usdc, 2_100, 1, 100_000);
+
+   env.swap_debt(&user, &market.usdc, &eth, &pool, 5, 0).unwrap();
+
+   assert!(env.user_state(&user).position(&market.usdc.bank).is_none());
+   assert_eq!(env.wallet_balance(&user, &market.usdc), 10_500);
+}
+
+#[test]
+fn swap_debt_cannot_borrow_past_the_borrow_limit() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   // An adapter of the user that pays nothing for the ETH: 10_000 of USDC debt stay, 1 ETH (2_000) is added,
+   // 12_000 of debt is below the liquidation threshold (12_000) but past the limit of 11_250
+   let pool = env.swap_pool(&eth, &market.usdc, 0, 1, 100_000);
+
+   assert_eq!(error_code(env.swap_debt(&user, &market.usdc, &eth, &pool, 1, 0)), Some(ErrCode::HealthDecreased));
+   assert!(env.user_state(&user).position(&eth.bank).is_none());
+
+   // Whatever the adapter returns, the new debt has to fit within the borrow limit as a plain borrow would
+   env.set_swap_rate(&pool, 2_000, 1);
+   env.swap_debt(&user, &market.usdc, &eth, &pool, 1, 0).unwrap();
+   assert!(env.health(&user).is_within_borrow_limit());
+}
+
+#[test]
+fn swap_debt_has_to_keep_the_user_above_the_liquidation_threshold() {
+   let mut env = TestEnv::new();
+   let (market, eth, user) = setup(&mut env);
+   // 5 ETH (10_000) only buy 2_500 USDC: 17_500 of debt for 12_000 of weighted collateral
+   let pool = env.swap_pool(&eth, &market.usdc, 500, 1, 100_000);
+
+   assert_eq!(error_code(env.swap_debt(&user, &market.usdc, &eth, &pool, 5, 0)), Some(ErrCode::LiquidatableAfterSwap));
+
+   env.set_bank_paused(&eth, true).unwrap();
+   assert_eq!(error_code(env.swap_debt(&user, &market.usdc, &eth, &pool, 5, 0)), Some(ErrCode::BankPaused));
+}
@@ -0,0 +1,63 @@
+use lending::error::ErrCode;
+use lending::events::WithdrawEvent;
+use lending_tests::*;
+
+#[test]
+fn withdrawing_everything_burns_all_the_shares_and_frees_the_position() {
+   let mut env = TestEnv::new();
+   let usdc = env.bank().build();
+   let user = env.user();
+   env.fund(&user, &usdc, 1_000);
+   env.deposit(&user, &usdc, 1_000).unwrap();
+
+   env.withdraw(&user, &usdc, 400).unwrap();
+   assert_eq!(env.wallet_balance(&user, &usdc), 400);
+   assert_eq!(env.user_state(&user).position(&usdc.bank).unwrap().deposit_shares, 600);
+
+   let meta = env.withdraw(&user, &usdc, 600).unwrap();
+   assert_eq!(env.wallet_balance(&user, &usdc), 1_000);
+   assert_eq!(env.balance(&usdc.treasury), 0);
+   assert!(env.user_state(&user).position(&usdc.bank).is_none());
+
+   let bank = env.bank_state(&usdc);
+   assert_eq!((bank.total_deposits, bank.total_deposit_shares), (0, 0));
+   assert_eq!(events::<WithdrawEvent>(&meta)[0].shares, 600);
+}
+
+#[test]
+fn withdraw_more_than_deposited_is_rejected() {
+   let mut env = TestEnv::new();
+   let usdc = env.bank().build();
+   let user = env.user();
+   env.fund(&user, &usdc, 1_000);
+   env.deposit(&user, &usdc, 1_000).unwrap();
+
+   assert_eq!(error_code(env.withdraw(&user, &usdc, 1_001)), Some(ErrCode::InsufficientFunds));
+}
+
+#[test]
+fn withdraw_has_to_keep_the_debt_under_the_borrow_limit() {
+   let mut env = TestEnv::new();
+   let market = env.market(50_000);
+   let user = env.borrower(&market, 100);
+
+   // 100 SOL * $150 * 75% = $11_250 of borrow limit
+   env.borrow(&user, &market.usdc, 10_000).unwrap();
+
+   // 80 SOL would only allow $9_000
+   assert_eq!(error_code(env.withdraw(&user, &market.sol, 20)), Some(ErrCode::WithdrawExceedsBorrowLimit));
+   // 95 SOL still allow $10_687
+   env.withdraw(&user, &market.sol, 5).unwrap();
+}
+
+#[test]
+fn withdraw_is_rejected_while_the_bank_is_paused() {
+   let mut env = TestEnv::new();
+   let usdc = env.bank().build();
+   let user = env.user();
+   env.fund(&user, &usdc, 1_000);
+   env.deposit(&user, &usdc, 1_000).unwrap();
+
+   env.set_bank_paused(&usdc, true).unwrap();
+   assert_eq!(error_code(env.withdraw(&user, &usdc, 100)), Some(ErrCode::BankPaused));
+}
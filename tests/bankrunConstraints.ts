@@ -0,0 +1,417 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Lending } from "../target/types/lending";
+import { BankrunProvider } from "anchor-bankrun";
+import { expect } from "chai";
+import { BanksClient, ProgramTestContext, startAnchor } from "solana-bankrun";
+import { createMint } from "spl-token-bankrun";
+import { PublicKey, Keypair, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import * as token from "@solana/spl-token"
+import { createAssociatedTokenAccount, createMockOracle, mintTo, setUpgradeAuthority } from "./utils";
+
+const IDL = require("../target/idl/lending.json");
+
+/*
+   Every instruction is sent with one account swapped for another that is valid on its own
+   (a bank of another mint, the treasury of another bank, the user account of someone else...)
+   and has to be rejected by the constraint that binds that account to the others.
+*/
+describe("Bankrun Lending constraints", () => {
+   let context: ProgramTestContext;
+   let provider: BankrunProvider;
+   let program: anchor.Program<Lending>;
+   let banksClient: BanksClient;
+
+   let payer: Keypair;
+   let attacker: Keypair;
+   let mintSol: PublicKey;
+   let mintUsdc: PublicKey;
+   let oracleSol: PublicKey;
+   let oracleUsdc: PublicKey;
+   let payerSolAta: PublicKey;
+   let payerUsdcAta: PublicKey;
+   let attackerUsdcAta: PublicKey;
+
+   const liquidationThreshold = new anchor.BN(8_000);
+   const maxLtv = new anchor.BN(7_500);
+   const interestRate = new anchor.BN(5);
+   const amount = 100_000;
+
+   function bankPda(mint: PublicKey): PublicKey {
+      return PublicKey.findProgramAddressSync([mint.toBuffer()], program.programId)[0];
+   }
+
+   function treasuryPda(mint: PublicKey): PublicKey {
+      return PublicKey.findProgramAddressSync([Buffer.from("treasury"), mint.toBuffer()], program.programId)[0];
+   }
+
+   function userPda(owner: PublicKey): PublicKey {
+      return PublicKey.findProgramAddressSync([owner.toBuffer()], program.programId)[0];
+   }
+
+   async function tokenBalance(account: PublicKey): Promise<bigint> {
+      const info = await banksClient.getAccount(account);
+      return token.AccountLayout.decode(info.data).amount;
+   }
+
+   function pair(mint: PublicKey, oracle: PublicKey): anchor.web3.AccountMeta[] {
+      return [
+         { pubkey: bankPda(mint), isSigner: false, isWritable: false },
+         { pubkey: oracle, isSigner: false, isWritable: false },
+      ];
+   }
+
+   // The transaction has to fail with the given Anchor error name (e.g. "ConstraintSeeds" or "MintMismatch")
+   async function expectRejected(tx: Promise<unknown>, code: string) {
+      try {
+         await tx;
+      } catch (err) {
+         const anchorCode = err?.error?.errorCode?.code;
+         const text = `${anchorCode ?? ""} ${err} ${(err?.logs ?? []).join("\n")}`;
+         expect(text).to.contain(code);
+         return;
+      }
+      expect.fail(`transaction was accepted, expected ${code}`);
+   }
+
+   function depositAccounts(mint: PublicKey, oracle: PublicKey, owner: PublicKey, userTokenAccount: PublicKey) {
+      return {
+         signer: owner,
+         mint,
+         bank: bankPda(mint),
+         bankTokenAccount: treasuryPda(mint),
+         oracle,
+         userAccount: userPda(owner),
+         userTokenAccount,
+         tokenProgram: token.TOKEN_PROGRAM_ID,
+      };
+   }
+
+   before(async() => {
+      context = await startAnchor("", [], []);
+      provider = new BankrunProvider(context);
+      anchor.setProvider(provider);
+      program = new anchor.Program<Lending>(IDL as Lending, provider);
+      banksClient = context.banksClient;
+      payer = provider.wallet.payer;
+      setUpgradeAuthority(context, program.programId, payer.publicKey);
+
+      attacker = Keypair.generate();
+      context.setAccount(attacker.publicKey, {
+         lamports: 10 * LAMPORTS_PER_SOL,
+         data: Buffer.alloc(0),
+         owner: anchor.web3.SystemProgram.programId,
+         executable: false,
+      });
+
+      mintSol = await createMint(banksClient, payer, payer.publicKey, payer.publicKey, 9);
+      mintUsdc = await createMint(banksClient, payer, payer.publicKey, payer.publicKey, 6);
+      oracleSol = await createMockOracle(context, 150);
+      oracleUsdc = await createMockOracle(context, 1);
+
+      for (const [mint, oracle] of [[mintSol, oracleSol], [mintUsdc, oracleUsdc]]) {
+         await program.methods
+            .initBank(liquidationThreshold, maxLtv, interestRate)
+            .accounts({ signer: payer.publicKey, mint, oracle, tokenProgram: token.TOKEN_PROGRAM_ID })
+            .signers([payer])
+            .rpc();
+      }
+
+      await program.methods.initUser().accounts({ signer: payer.publicKey }).signers([payer]).rpc();
+      await program.methods.initUser().accounts({ signer: attacker.publicKey }).signers([attacker]).rpc();
+
+      payerSolAta = await createAssociatedTokenAccount(banksClient, payer, mintSol, payer.publicKey);
+      payerUsdcAta = await createAssociatedTokenAccount(banksClient, payer, mintUsdc, payer.publicKey);
+      attackerUsdcAta = await createAssociatedTokenAccount(banksClient, payer, mintUsdc, attacker.publicKey);
+      await mintTo(banksClient, payer, mintSol, payerSolAta, payer, amount);
+      await mintTo(banksClient, payer, mintUsdc, payerUsdcAta, payer, amount);
+      await mintTo(banksClient, payer, mintUsdc, attackerUsdcAta, payer, amount);
+
+      // The payer ends up with a USDC deposit and a SOL deposit
+      await program.methods
+         .deposit(new anchor.BN(amount / 2))
+         .accountsPartial(depositAccounts(mintUsdc, oracleUsdc, payer.publicKey, payerUsdcAta))
+         .signers([payer])
+         .rpc();
+      await program.methods
+         .deposit(new anchor.BN(amount / 2))
+         .accountsPartial(depositAccounts(mintSol, oracleSol, payer.publicKey, payerSolAta))
+         .remainingAccounts(pair(mintUsdc, oracleUsdc))
+         .signers([payer])
+         .rpc();
+   })
+
+   it("Init bank rejects an oracle that isn't a price account", async() => {
+      const mint = await createMint(banksClient, payer, payer.publicKey, payer.publicKey, 6);
+      await expectRejected(
+         program.methods
+            .initBank(liquidationThreshold, maxLtv, interestRate)
+            .accounts({ signer: payer.publicKey, mint, oracle: payerUsdcAta, tokenProgram: token.TOKEN_PROGRAM_ID })
+            .signers([payer])
+            .rpc(),
+         "InvalidOracle",
+      );
+   })
+
+   it("Init bank rejects a max ltv above the liquidation threshold", async() => {
+      const mint = await createMint(banksClient, payer, payer.publicKey, payer.publicKey, 6);
+      await expectRejected(
+         program.methods
+            .initBank(maxLtv, liquidationThreshold, interestRate)
+            .accounts({ signer: payer.publicKey, mint, oracle: oracleUsdc, tokenProgram: token.TOKEN_PROGRAM_ID })
+            .signers([payer])
+            .rpc(),
+         "InvalidBankConfig",
+      );
+   })
+
+   it("Deposit rejects a zero amount", async() => {
+      await expectRejected(
+         program.methods
+            .deposit(new anchor.BN(0))
+            .accountsPartial(depositAccounts(mintUsdc, oracleUsdc, payer.publicKey, payerUsdcAta))
+            .remainingAccounts(pair(mintSol, oracleSol))
+            .signers([payer])
+            .rpc(),
+         "InvalidAmount",
+      );
+   })
+
+   it("Deposit rejects the bank of another mint", async() => {
+      await expectRejected(
+         program.methods
+            .deposit(new anchor.BN(1))
+            .accountsPartial({ ...depositAccounts(mintUsdc, oracleUsdc, payer.publicKey, payerUsdcAta), bank: bankPda(mintSol) })
+            .signers([payer])
+            .rpc(),
+         "MintMismatch",
+      );
+   })
+
+   it("Deposit rejects the treasury of another bank", async() => {
+      await expectRejected(
+         program.methods
+            .deposit(new anchor.BN(1))
+            .accountsPartial({ ...depositAccounts(mintUsdc, oracleUsdc, payer.publicKey, payerUsdcAta), bankTokenAccount: treasuryPda(mintSol) })
+            .signers([payer])
+            .rpc(),
+         "ConstraintSeeds",
+      );
+   })
+
+   it("Deposit rejects an oracle that isn't the one of the bank", async() => {
+      await expectRejected(
+         program.methods
+            .deposit(new anchor.BN(1))
+            .accountsPartial(depositAccounts(mintUsdc, oracleSol, payer.publicKey, payerUsdcAta))
+            .signers([payer])
+            .rpc(),
+         "InvalidOracle",
+      );
+   })
+
+   it("Deposit rejects the user account of someone else", async() => {
+      await expectRejected(
+         program.methods
+            .deposit(new anchor.BN(1))
+            .accountsPartial({ ...depositAccounts(mintUsdc, oracleUsdc, attacker.publicKey, attackerUsdcAta), userAccount: userPda(payer.publicKey) })
+            .signers([attacker])
+            .rpc(),
+         "Unauthorized",
+      );
+   })
+
+   it("Withdraw rejects a destination token account of someone else", async() => {
+      await expectRejected(
+         program.methods
+            .withdraw(new anchor.BN(1))
+            .accountsPartial(depositAccounts(mintUsdc, oracleUsdc, payer.publicKey, attackerUsdcAta))
+            .remainingAccounts(pair(mintSol, oracleSol))
+            .signers([payer])
+            .rpc(),
+         "ConstraintTokenOwner",
+      );
+   })
+
+   it("Withdraw rejects missing position accounts", async() => {
+      await expectRejected(
+         program.methods
+            .withdraw(new anchor.BN(1))
+            .accountsPartial(depositAccounts(mintUsdc, oracleUsdc, payer.publicKey, payerUsdcAta))
+            .signers([payer])
+            .rpc(),
+         "MissingPositionAccounts",
+      );
+   })
+
+   it("Withdraw from the attacker can't touch the deposit of the payer", async() => {
+      await expectRejected(
+         program.methods
+            .withdraw(new anchor.BN(1))
+            .accountsPartial({ ...depositAccounts(mintUsdc, oracleUsdc, attacker.publicKey, attackerUsdcAta), userAccount: userPda(payer.publicKey) })
+            .signers([attacker])
+            .rpc(),
+         "Unauthorized",
+      );
+   })
+
+   it("Borrow is signed by the treasury and pays the borrower", async() => {
+      const borrowed = 1_000;
+      const before = await tokenBalance(payerUsdcAta);
+      await program.methods
+         .borrow(new anchor.BN(borrowed))
+         .accountsPartial({
+            signer: payer.publicKey,
+            mint: mintUsdc,
+            bank: bankPda(mintUsdc),
+            bankTokenAccount: treasuryPda(mintUsdc),
+            oracle: oracleUsdc,
+            user: userPda(payer.publicKey),
+            userTokenAccount: payerUsdcAta,
+            tokenProgram: token.TOKEN_PROGRAM_ID,
+         })
+         .remainingAccounts(pair(mintSol, oracleSol))
+         .signers([payer])
+         .rpc();
+      const after = await tokenBalance(payerUsdcAta);
+      expect(Number(after - before)).to.be.equal(borrowed);
+   })
+
+   it("Borrow rejects the treasury of another bank", async() => {
+      await expectRejected(
+         program.methods
+            .borrow(new anchor.BN(1))
+            .accountsPartial({
+               signer: payer.publicKey,
+               mint: mintUsdc,
+               bank: bankPda(mintUsdc),
+               bankTokenAccount: treasuryPda(mintSol),
+               oracle: oracleUsdc,
+               user: userPda(payer.publicKey),
+               userTokenAccount: payerUsdcAta,
+               tokenProgram: token.TOKEN_PROGRAM_ID,
+            })
+            .remainingAccounts(pair(mintSol, oracleSol))
+            .signers([payer])
+            .rpc(),
+         "ConstraintSeeds",
+      );
+   })
+
+   it("Borrow against the collateral of someone else is rejected", async() => {
+      await expectRejected(
+         program.methods
+            .borrow(new anchor.BN(1))
+            .accountsPartial({
+               signer: attacker.publicKey,
+               mint: mintUsdc,
+               bank: bankPda(mintUsdc),
+               bankTokenAccount: treasuryPda(mintUsdc),
+               oracle: oracleUsdc,
+               user: userPda(payer.publicKey),
+               userTokenAccount: attackerUsdcAta,
+               tokenProgram: token.TOKEN_PROGRAM_ID,
+            })
+            .remainingAccounts(pair(mintSol, oracleSol))
+            .signers([attacker])
+            .rpc(),
+         "Unauthorized",
+      );
+   })
+
+   it("Repay rejects the bank of another mint", async() => {
+      await expectRejected(
+         program.methods
+            .repay(new anchor.BN(1))
+            .accountsPartial({ ...depositAccounts(mintUsdc, oracleUsdc, payer.publicKey, payerUsdcAta), bank: bankPda(mintSol) })
+            .remainingAccounts(pair(mintSol, oracleSol))
+            .signers([payer])
+            .rpc(),
+         "MintMismatch",
+      );
+   })
+
+   it("Liquidate rejects the same bank as collateral and debt", async() => {
+      await expectRejected(
+         program.methods
+            .liquidate(new anchor.BN(1))
+            .accountsPartial({
+               liquidator: attacker.publicKey,
+               collateralMint: mintUsdc,
+               borrowedMint: mintUsdc,
+               collateralBank: bankPda(mintUsdc),
+               borrowedBank: bankPda(mintUsdc),
+               collateralBankTokenAccount: treasuryPda(mintUsdc),
+               borrowedBankTokenAccount: treasuryPda(mintUsdc),
+               collateralOracle: oracleUsdc,
+               borrowedOracle: oracleUsdc,
+               userAccount: userPda(payer.publicKey),
+               liquidatorCollateralTokenAccount: attackerUsdcAta,
+               liquidatorBorrowedTokenAccount: attackerUsdcAta,
+               tokenProgram: token.TOKEN_PROGRAM_ID,
+            })
+            .remainingAccounts(pair(mintSol, oracleSol))
+            .signers([attacker])
+            .rpc(),
+         "SameLiquidationBank",
+      );
+   })
+
+   it("Liquidate rejects a user account that isn't the PDA of its owner", async() => {
+      // A copy of the payer's user account at another address, owned by the program
+      const fakeUser = Keypair.generate().publicKey;
+      const real = await banksClient.getAccount(userPda(payer.publicKey));
+      context.setAccount(fakeUser, { ...real, data: Buffer.from(real.data) });
+
+      const attackerSolAta = await createAssociatedTokenAccount(banksClient, payer, mintSol, attacker.publicKey);
+      await expectRejected(
+         program.methods
+            .liquidate(new anchor.BN(1))
+            .accountsPartial({
+               liquidator: attacker.publicKey,
+               collateralMint: mintSol,
+               borrowedMint: mintUsdc,
+               collateralBank: bankPda(mintSol),
+               borrowedBank: bankPda(mintUsdc),
+               collateralBankTokenAccount: treasuryPda(mintSol),
+               borrowedBankTokenAccount: treasuryPda(mintUsdc),
+               collateralOracle: oracleSol,
+               borrowedOracle: oracleUsdc,
+               userAccount: fakeUser,
+               liquidatorCollateralTokenAccount: attackerSolAta,
+               liquidatorBorrowedTokenAccount: attackerUsdcAta,
+               tokenProgram: token.TOKEN_PROGRAM_ID,
+            })
+            .signers([attacker])
+            .rpc(),
+         "ConstraintSeeds",
+      );
+   })
+
+   it("Only the authority can update or pause a bank", async() => {
+      await expectRejected(
+         program.methods
+            .updateBankConfig({
+               liquidationThreshold: new anchor.BN(10_000),
+               liquidationBonus: new anchor.BN(5_000),
+               liquidationCloseFactor: new anchor.BN(10_000),
+               maxLtv: new anchor.BN(10_000),
+               interestRate: new anchor.BN(0),
+               oracleMaxAge: new anchor.BN(60),
+               depositCap: new anchor.BN(0),
+               borrowCap: new anchor.BN(0),
+            })
+            .accountsPartial({ authority: attacker.publicKey, bank: bankPda(mintUsdc) })
+            .signers([attacker])
+            .rpc(),
+         "Unauthorized",
+      );
+      await expectRejected(
+         program.methods
+            .setBankPaused(true)
+            .accountsPartial({ authority: attacker.publicKey, bank: bankPda(mintUsdc) })
+            .signers([attacker])
+            .rpc(),
+         "Unauthorized",
+      );
+   })
+})
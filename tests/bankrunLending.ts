@@ -8,7 +8,7 @@ import {
 } from "solana-bankrun";
 
 import {
-   createAccount, createMint, mintTo, createAssociatedTokenAccount
+   createAccount, createMint
 } from "spl-token-bankrun";
 
 import {
@@ -24,6 +24,7 @@ import { publicKey } from "@coral-xyz/anchor/dist/cjs/utils";
 // } from "@solana/spl-token";
 
 import * as token from "@solana/spl-token"
+import { createAssociatedTokenAccount, mintTo, setUpgradeAuthority } from "./utils";
 
 const IDL = require("../target/idl/lending.json");
 
@@ -38,80 +39,6 @@ const MINIMUM_USDC_BALANCE = 100_000_000_000; // 100k USDC
 const networks = ['http://localhost:8899', clusterApiUrl('testnet'), 'https://api.devnet.solana.com', 'mainnet-beta']
 const connection = new Connection((networks[0]), 'confirmed');
 
-// ---------- Some utility functions that bankrun doesn't contain ---------- (https://github.com/ochaloup/spl-token-bankrun/blob/main/README.md)
-async function createAssociatedTokenAccount(
-   banksClient: BanksClient,
-   payer: Signer,
-   mint: PublicKey,
-   owner: PublicKey,
-   programId = token.TOKEN_PROGRAM_ID,
-   associatedTokenProgramId = token.ASSOCIATED_TOKEN_PROGRAM_ID
- ): Promise<PublicKey> {
-   const associatedToken = token.getAssociatedTokenAddressSync(
-     mint,
-     owner,
-     true,
-     programId,
-     associatedTokenProgramId
-   );
- 
-   const tx = new Transaction().add(
-     token.createAssociatedTokenAccountInstruction(
-       payer.publicKey,
-       associatedToken,
-       owner,
-       mint,
-       programId,
-       associatedTokenProgramId
-     )
-   );
- 
-   [tx.recentBlockhash] = (await banksClient.getLatestBlockhash())!;
-   tx.sign(payer);
- 
-   await banksClient.processTransaction(tx);
- 
-   return associatedToken;
- }
-
- async function mintTo(
-   banksClient: BanksClient,
-   payer: Signer,
-   mint: PublicKey,
-   destination: PublicKey,
-   authority: Signer | PublicKey,
-   amount: number | bigint,
-   multiSigners: Signer[] = [],
-   programId = token.TOKEN_PROGRAM_ID
- ): Promise<BanksTransactionMeta> {
-   const [authorityPublicKey, signers] = getSigners(authority, multiSigners);
- 
-   const tx = new Transaction().add(
-     token.createMintToInstruction(
-       mint,
-       destination,
-       authorityPublicKey,
-       amount,
-       multiSigners,
-       programId
-     )
-   );
-   [tx.recentBlockhash] = (await banksClient.getLatestBlockhash())!;
-   tx.sign(payer, ...signers);
- 
-   return await banksClient.processTransaction(tx);
- }
-
- function getSigners(
-   signerOrMultisig: Signer | PublicKey,
-   multiSigners: Signer[]
- ): [PublicKey, Signer[]] {
-   return signerOrMultisig instanceof PublicKey
-     ? [signerOrMultisig, multiSigners]
-     : [signerOrMultisig.publicKey, [signerOrMultisig]];
- }
-
-
 describe("Bankrun Lending", () => {
    // Boiler plate variables (types definitions)
    let context: ProgramTestContext;
@@ -128,10 +55,13 @@ describe("Bankrun Lending", () => {
    let usdcBankTokenAccountPda: anchor.web3.PublicKey;
    let userAccountPda: anchor.web3.PublicKey;
    let userPda: anchor.web3.PublicKey;
+   let oracleSol: anchor.web3.PublicKey;
+   let oracleUsdc: anchor.web3.PublicKey;
 
    // Input params
-   let liquidationThreshold = new anchor.BN(2);
-   let maxLtv = new anchor.BN(1);
+   // Ratios are in basis points (10_000 = 100%)
+   let liquidationThreshold = new anchor.BN(8_000);
+   let maxLtv = new anchor.BN(7_500);
    let interest_rate = new anchor.BN(5); // 5%
    let amount = 100_000;
    let amountBN = new anchor.BN(amount)
@@ -155,6 +85,41 @@ describe("Bankrun Lending", () => {
       return pdaAccount;
    }
 
+   // Writes a Pyth style price account (only the fields read by the program) with the given price and 8 decimals
+   async function createMockOracle(price: number): Promise<anchor.web3.PublicKey> {
+      const oracle = Keypair.generate().publicKey;
+      const clock = await banksClient.getClock();
+      const data = Buffer.alloc(240);
+      data.writeUInt32LE(0xa1b2c3d4, 0);   // magic
+      data.writeUInt32LE(2, 4);            // version
+      data.writeUInt32LE(3, 8);            // price account
+      data.writeInt32LE(-8, 20);           // expo
+      data.writeBigInt64LE(clock.unixTimestamp, 96);  // publish time
+      data.writeBigInt64LE(BigInt(price) * BigInt(100_000_000), 208);  // price
+      data.writeUInt32LE(1, 224);          // trading
+      context.setAccount(oracle, {
+         lamports: LAMPORTS_PER_SOL,
+         data,
+         owner: anchor.web3.SystemProgram.programId,
+         executable: false,
+      });
+      return oracle;
+   }
+
+   // Every other position of the user has to be passed as (bank, oracle) remaining accounts
+   async function positionAccounts(actedMint: anchor.web3.PublicKey): Promise<anchor.web3.AccountMeta[]> {
+      const actedBank = await getBankPda(actedMint, "bankAccountPda");
+      const userInfo = await program.account.user.fetch(getUserPda());
+      const metas: anchor.web3.AccountMeta[] = [];
+      for (const position of userInfo.positions) {
+         if (position.bank.equals(PublicKey.default) || position.bank.equals(actedBank)) continue;
+         const bankInfo = await program.account.bank.fetch(position.bank);
+         metas.push({ pubkey: position.bank, isSigner: false, isWritable: false });
+         metas.push({ pubkey: bankInfo.oracle, isSigner: false, isWritable: false });
+      }
+      return metas;
+   }
+
    function getUserPda(): anchor.web3.PublicKey {
       const [pdaAccount] = anchor.web3.PublicKey.findProgramAddressSync(
          [payer.publicKey.toBuffer()],
@@ -174,6 +139,7 @@ describe("Bankrun Lending", () => {
       banksClient = context.banksClient;
    
       payer = provider.wallet.payer;   // richPerson = Keypair.generate();
+      setUpgradeAuthority(context, program.programId, payer.publicKey);
       console.log("payer: ", payer.publicKey.toBase58());
 
       // let usdcAccount: AccountInfoBytes;
@@ -199,6 +165,10 @@ describe("Bankrun Lending", () => {
       )
       console.log("mintUsdc: ", mintUsdc.toBase58());
 
+      /***** Create oracles *****/
+      oracleSol = await createMockOracle(150);
+      oracleUsdc = await createMockOracle(1);
+
       
       /***** Derive PDAs *****/
 
@@ -211,6 +181,7 @@ describe("Bankrun Lending", () => {
 
    it("Init Bank with mintSol", async() => {
       const mint = mintSol;
+      const oracle = oracleSol;
       const bankAccountPda = await getBankPda(mint, "bankAccountPda");
       const bankTokenAccountPda = await getBankPda(mint, "bankTokenAccountPda");
       await program.methods
@@ -220,6 +191,7 @@ describe("Bankrun Lending", () => {
             mint: mint,
             bank: bankAccountPda,
             bankTokenAccount: bankTokenAccountPda, 
+            oracle: oracle,
             tokenProgram: token.TOKEN_PROGRAM_ID,
          })
          .signers([payer])
@@ -233,6 +205,7 @@ describe("Bankrun Lending", () => {
 
    it("Init Bank with mintUsdc", async() => {
       const mint = mintUsdc;
+      const oracle = oracleUsdc;
       const bankAccountPda = await getBankPda(mint, "bankAccountPda");
       const bankTokenAccountPda = await getBankPda(mint, "bankTokenAccountPda");
       await program.methods
@@ -242,32 +215,31 @@ describe("Bankrun Lending", () => {
             mint: mint,
             bank: bankAccountPda,
             bankTokenAccount: bankTokenAccountPda,
+            oracle: oracle,
             tokenProgram: token.TOKEN_PROGRAM_ID,
          })
          .signers([payer])
          .rpc()
    })
 
-   it("Init user with mintUsdc", async() => {
-      const mint = mintUsdc
+   it("Init user", async() => {
       const userAccountPda = getUserPda();
       await program.methods
-         .initUser(mint)
+         .initUser()
          .accounts({
             signer: payer.publicKey,
-            mint: mint, // q Thinking in business logic, is it neccessary to include mint account to initialized user
             userAccount: userAccountPda,
-            tokenProgram: token.TOKEN_PROGRAM_ID,
          })
          .signers([payer])
          .rpc()
       const userInfo = await program.account.user.fetch(userAccountPda)
       expect(userInfo.owner.toBase58()).to.be.equal(payer.publicKey.toBase58())
-      expect(userInfo.usdcAddress.toBase58()).to.be.equal(mintUsdc.toBase58())
+      expect(userInfo.positions.every((position) => position.bank.equals(PublicKey.default))).to.be.true
    })
 
    it("Deposit mintUsdc", async() => {
       const mint = mintUsdc;
+      const oracle = oracleUsdc;
       const bankAccountPda = await getBankPda(mint, "bankAccountPda");
       const bankTokenAccountPda = await getBankPda(mint, "bankTokenAccountPda");
       const userAccountPda = getUserPda();
@@ -287,16 +259,19 @@ describe("Bankrun Lending", () => {
             mint: mint,
             bank: bankAccountPda,
             bankTokenAccount: bankTokenAccountPda,
+            oracle: oracle,
             userAccount: userAccountPda,
             userTokenAccount: userAssociatedTokenAccount,
             tokenProgram: token.TOKEN_PROGRAM_ID,
          })
+         .remainingAccounts(await positionAccounts(mint))
          .signers([payer])
          .rpc()
    })
 
    it("Deposit mintSol", async() => {
       const mint = mintSol;
+      const oracle = oracleSol;
       const bankAccountPda = await getBankPda(mint, "bankAccountPda");
       const bankTokenAccountPda = await getBankPda(mint, "bankTokenAccountPda");
       const userAccountPda = getUserPda();
@@ -316,10 +291,12 @@ describe("Bankrun Lending", () => {
             mint: mint,
             bank: bankAccountPda,
             bankTokenAccount: bankTokenAccountPda,
+            oracle: oracle,
             userAccount: userAccountPda,
             userTokenAccount: userAssociatedTokenAccount,
             tokenProgram: token.TOKEN_PROGRAM_ID,
          })
+         .remainingAccounts(await positionAccounts(mint))
          .signers([payer])
          .rpc()
    })
@@ -0,0 +1,112 @@
+import * as anchor from "@coral-xyz/anchor";
+import { BanksClient, BanksTransactionMeta, ProgramTestContext } from "solana-bankrun";
+import { PublicKey, Keypair, Transaction, Signer, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import * as token from "@solana/spl-token"
+
+// ---------- Some utility functions that bankrun doesn't contain ---------- (https://github.com/ochaloup/spl-token-bankrun/blob/main/README.md)
+export async function createAssociatedTokenAccount(
+   banksClient: BanksClient,
+   payer: Signer,
+   mint: PublicKey,
+   owner: PublicKey,
+   programId = token.TOKEN_PROGRAM_ID,
+   associatedTokenProgramId = token.ASSOCIATED_TOKEN_PROGRAM_ID
+ ): Promise<PublicKey> {
+   const associatedToken = token.getAssociatedTokenAddressSync(
+     mint,
+     owner,
+     true,
+     programId,
+     associatedTokenProgramId
+   );
+ 
+   const tx = new Transaction().add(
+     token.createAssociatedTokenAccountInstruction(
+       payer.publicKey,
+       associatedToken,
+       owner,
+       mint,
+       programId,
+       associatedTokenProgramId
+     )
+   );
+ 
+   [tx.recentBlockhash] = (await banksClient.getLatestBlockhash())!;
+   tx.sign(payer);
+ 
+   await banksClient.processTransaction(tx);
+ 
+   return associatedToken;
+ }
+
+ export async function mintTo(
+   banksClient: BanksClient,
+   payer: Signer,
+   mint: PublicKey,
+   destination: PublicKey,
+   authority: Signer | PublicKey,
+   amount: number | bigint,
+   multiSigners: Signer[] = [],
+   programId = token.TOKEN_PROGRAM_ID
+ ): Promise<BanksTransactionMeta> {
+   const [authorityPublicKey, signers] = getSigners(authority, multiSigners);
+ 
+   const tx = new Transaction().add(
+     token.createMintToInstruction(
+       mint,
+       destination,
+       authorityPublicKey,
+       amount,
+       multiSigners,
+       programId
+     )
+   );
+   [tx.recentBlockhash] = (await banksClient.getLatestBlockhash())!;
+   tx.sign(payer, ...signers);
+ 
+   return await banksClient.processTransaction(tx);
+ }
+
+ function getSigners(
+   signerOrMultisig: Signer | PublicKey,
+   multiSigners: Signer[]
+ ): [PublicKey, Signer[]] {
+   return signerOrMultisig instanceof PublicKey
+     ? [signerOrMultisig, multiSigners]
+     : [signerOrMultisig.publicKey, [signerOrMultisig]];
+ }
+
+
+// Writes a Pyth style price account (only the fields read by the program) with the given price and 8 decimals
+export async function createMockOracle(context: ProgramTestContext, price: number): Promise<PublicKey> {
+   const oracle = Keypair.generate().publicKey;
+   const clock = await context.banksClient.getClock();
+   const data = Buffer.alloc(240);
+   data.writeUInt32LE(0xa1b2c3d4, 0);   // magic
+   data.writeUInt32LE(2, 4);            // version
+   data.writeUInt32LE(3, 8);            // price account
+   data.writeInt32LE(-8, 20);           // expo
+   data.writeBigInt64LE(clock.unixTimestamp, 96);  // publish time
+   data.writeBigInt64LE(BigInt(price) * BigInt(100_000_000), 208);  // price
+   data.writeUInt32LE(1, 224);          // trading
+   context.setAccount(oracle, {
+      lamports: LAMPORTS_PER_SOL,
+      data,
+      owner: anchor.web3.SystemProgram.programId,
+      executable: false,
+   });
+   return oracle;
+}
+
+// bankrun loads the program without the upgradeable loader, so its ProgramData account (that names the upgrade
+// authority, the only one allowed to create banks) is written by hand: UpgradeableLoaderState::ProgramData in bincode
+export function setUpgradeAuthority(context: ProgramTestContext, programId: PublicKey, authority: PublicKey) {
+   const loader = new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111");
+   const [programData] = PublicKey.findProgramAddressSync([programId.toBuffer()], loader);
+   const data = Buffer.alloc(45);
+   data.writeUInt32LE(3, 0);            // ProgramData
+   data.writeBigUInt64LE(BigInt(0), 4); // slot
+   data.writeUInt8(1, 12);              // Some(upgrade authority)
+   authority.toBuffer().copy(data, 13);
+   context.setAccount(programData, { lamports: LAMPORTS_PER_SOL, data, owner: loader, executable: false });
+}